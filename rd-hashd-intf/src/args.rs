@@ -14,8 +14,9 @@ lazy_static::lazy_static! {
              -s, --size=[SIZE]             'Max memory footprint, affects testfiles size (default: {dfl_size:.2}G)'
              -f, --file-max=[FRAC]         'Max fraction of page cache, affects testfiles size (default: {dfl_file_max_frac:.2})'
              -c, --compressibility=[FRAC]  'File and anon data compressibility (default: 0)
-             -p, --params=[FILE]           'Runtime updatable parameters, will be created if non-existent'
+             -p, --params=[FILE]           'Runtime updatable parameters, will be created if non-existent, - or URL to load once (needs the url feature and curl)'
              -r, --report=[FILE]           'Runtime report file, FILE.staging will be used for staging'
+             -S, --snapshot=[FILE]         'Resume PID controller states from FILE if present and keep it updated every {snapshot_intv}s'
              -l, --log-dir=[PATH]          'Record hash results to the files in PATH'
              -L, --log-size=[SIZE]         'Maximum log retention (default: {dfl_log_size:.2}G)'
              -i, --interval=[SECS]         'Summary report interval, 0 to disable (default: {dfl_intv}s)'
@@ -25,6 +26,8 @@ lazy_static::lazy_static! {
                  --clear-testfiles         'Clear testfiles before preparing them'
                  --prepare-config          'Prepare config files and exit'
                  --prepare                 'Prepare config files and testfiles and exit'
+                 --lint                    'Report adjustments loading the params file would make and exit'
+                 --lint-fix                'Like --lint but also rewrite the params file normalized'
                  --bench                   'Benchmark and record results in args and params file'
                  --bench-cpu-single        'Benchmark hash/chunk sizes instead of taking from params'
                  --bench-cpu               'Benchmark cpu, implied by --bench'
@@ -48,7 +51,8 @@ lazy_static::lazy_static! {
             dfl_log_bps=to_mb(dfl_args.bench_log_bps),
             dfl_preload_cache=to_mb(dfl_args.bench_preload_cache_size()),
             dfl_file_frac=Params::default().file_frac,
-            dfl_intv=dfl_args.interval)
+            dfl_intv=dfl_args.interval,
+            snapshot_intv=Args::SNAPSHOT_INTV_SECS)
     };
 
     static ref HELP_BODY: Mutex<&'static str> = Mutex::new("");
@@ -72,6 +76,7 @@ pub struct Args {
     pub compressibility: f64,
     pub params: Option<String>,
     pub report: Option<String>,
+    pub snapshot: Option<String>,
     pub log_dir: Option<String>,
     pub log_size: u64,
     pub interval: u32,
@@ -86,6 +91,10 @@ pub struct Args {
     #[serde(skip)]
     pub prepare_and_exit: bool,
     #[serde(skip)]
+    pub lint: bool,
+    #[serde(skip)]
+    pub lint_fix: bool,
+    #[serde(skip)]
     pub bench_cpu_single: bool,
     #[serde(skip)]
     pub bench_cpu: bool,
@@ -116,6 +125,7 @@ pub struct Args {
 impl Args {
     pub const DFL_SIZE_MULT: u64 = 4;
     pub const DFL_FILE_MAX_FRAC: f64 = 0.25;
+    pub const SNAPSHOT_INTV_SECS: u64 = 10;
 
     pub fn set_help_body(help: &'static str) {
         *HELP_BODY.lock().unwrap() = help;
@@ -130,6 +140,7 @@ impl Args {
             compressibility: 0.0,
             params: None,
             report: None,
+            snapshot: None,
             log_dir: None,
             log_size: mem_size as u64 / 2,
             interval: 10,
@@ -139,6 +150,8 @@ impl Args {
             bench_preload_cache: None,
             prepare_testfiles: true,
             prepare_and_exit: false,
+            lint: false,
+            lint_fix: false,
             bench_cpu_single: false,
             bench_cpu: false,
             bench_mem: false,
@@ -271,6 +284,14 @@ impl JsonArgs for Args {
             };
             updated_base = true;
         }
+        if let Some(v) = matches.value_of("snapshot") {
+            self.snapshot = if !v.is_empty() {
+                Some(v.to_string())
+            } else {
+                None
+            };
+            updated_base = true;
+        }
         if let Some(v) = matches.value_of("log-dir") {
             self.log_dir = if v.len() > 0 {
                 Some(v.to_string())
@@ -320,6 +341,9 @@ impl JsonArgs for Args {
             self.prepare_and_exit = true;
         }
 
+        self.lint_fix = matches.is_present("lint-fix");
+        self.lint = self.lint_fix || matches.is_present("lint");
+
         if !self.prepare_and_exit {
             self.bench_cpu_single = matches.is_present("bench-cpu-single");
             self.bench_cpu = matches.is_present("bench-cpu");
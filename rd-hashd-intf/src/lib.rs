@@ -2,10 +2,20 @@
 pub mod args;
 pub mod params;
 pub mod report;
+pub mod sampler;
+pub mod snapshot;
+pub mod trace;
 
 pub use args::Args;
-pub use params::Params;
-pub use report::{Latencies, Phase, Report, Stat};
+pub use params::{
+    AssertField, AssertOp, ConvergenceReport, ConvergenceTracker, FileAddrDist, FileSizeDist,
+    FileSync, HashAlgo, HistogramDelta, IoMix, LintReport, ParamAssertion, ParamField, Params,
+    ParamsBuilder, ParamsPatch, ParamsProfile, ParamsProfiles, ParamsSummary, ParamsView, PidReset,
+    Provenance, ResourcePlan, Source, StressSchedule, WorkingSet, WorkloadClass,
+};
+pub use report::{AccessDist, Latencies, LatencyHistogram, Phase, Report, Stat};
+pub use snapshot::{PidState, Snapshot};
+pub use trace::{AccessTrace, TraceRecord};
 
 use rd_util::*;
 
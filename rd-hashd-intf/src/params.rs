@@ -1,14 +1,463 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use log::{debug, warn};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "url")]
+use std::process::Command;
 
+use super::sampler::{ClampedNormal, ClassSampler, FileSizeSampler, SleepSampler, WeightedSampler};
+use super::trace::AccessTrace;
+use super::LatencyHistogram;
 use rd_util::*;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Complementary error function, Abramowitz and Stegun 7.1.26. The absolute
+/// error is below 1.5e-7.
+pub(crate) fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.327_591_1 * z);
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let v = poly * (-z * z).exp();
+    match x.is_sign_negative() {
+        true => 2.0 - v,
+        false => v,
+    }
+}
+
+fn is_infinite(v: &f64) -> bool {
+    v.is_infinite()
+}
+
+fn is_single_or_empty<T>(v: &[T]) -> bool {
+    v.len() <= 1
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PidParams {
     pub kp: f64,
     pub ki: f64,
     pub kd: f64,
+    #[serde(skip_serializing_if = "is_infinite")]
+    pub i_min: f64,
+    #[serde(skip_serializing_if = "is_infinite")]
+    pub i_max: f64,
+    #[serde(skip_serializing_if = "is_infinite")]
+    pub out_min: f64,
+    #[serde(skip_serializing_if = "is_infinite")]
+    pub out_max: f64,
+    pub d_on_measurement: bool,
+    pub p_enabled: bool,
+    pub i_enabled: bool,
+    pub d_enabled: bool,
+}
+
+impl Default for PidParams {
+    fn default() -> Self {
+        Self {
+            kp: 0.0,
+            ki: 0.0,
+            kd: 0.0,
+            i_min: -f64::INFINITY,
+            i_max: f64::INFINITY,
+            out_min: -f64::INFINITY,
+            out_max: f64::INFINITY,
+            d_on_measurement: true,
+            p_enabled: true,
+            i_enabled: true,
+            d_enabled: true,
+        }
+    }
+}
+
+impl PidParams {
+    /// Copy with the P, I and D gains multiplied by `scale`.
+    pub fn scaled_gains(&self, scale: f64) -> Self {
+        Self {
+            kp: self.kp * scale,
+            ki: self.ki * scale,
+            kd: self.kd * scale,
+            ..self.clone()
+        }
+    }
+
+    /// Reject gains which would silently poison the controller, i.e.
+    /// non-finite or negative ones, and NaN or inverted clamp ranges. `name`
+    /// identifies the controller in the error.
+    fn validate(&self, name: &str) -> Result<()> {
+        for (term, v) in &[("kp", self.kp), ("ki", self.ki), ("kd", self.kd)] {
+            if !v.is_finite() {
+                bail!("{}.{} {} should be finite", name, term, v);
+            }
+            if *v < 0.0 {
+                bail!("{}.{} {} should be non-negative", name, term, v);
+            }
+        }
+        for (term, min, max) in &[
+            ("i", self.i_min, self.i_max),
+            ("out", self.out_min, self.out_max),
+        ] {
+            if min.is_nan() || max.is_nan() {
+                bail!(
+                    "{}.{}_min/max ({}, {}) shouldn't be NaN",
+                    name,
+                    term,
+                    min,
+                    max
+                );
+            }
+            if min > max {
+                bail!(
+                    "{}.{}_min {} shouldn't be greater than {}_max {}",
+                    name,
+                    term,
+                    min,
+                    term,
+                    max
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Distribution of file access sizes. `Normal` is clamped to twice the mean
+/// while `LogNormal` has a long tail of large accesses. Both are shaped by
+/// `file_size_mean` and `file_size_stdev_ratio`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileSizeDist {
+    #[default]
+    Normal,
+    LogNormal,
+}
+
+/// Distribution of file access addresses. `Normal` spreads accesses around
+/// the start of the testfiles according to `file_addr_stdev_ratio`. `Zipf`
+/// picks the testfile ranked `k` with probability proportional to `1/k^s`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum FileAddrDist {
+    #[default]
+    Normal,
+    Zipf {
+        s: f64,
+    },
+}
+
+/// How file writes are synced. `Fdatasync` and `Fsync` force writeback of
+/// the written file after every `file_sync_every` writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileSync {
+    #[default]
+    None,
+    Fdatasync,
+    Fsync,
+}
+
+/// A workload class in `Params::classes`. Each request picks a class with
+/// probability proportional to `weight` and uses its `file_size_mean` and
+/// `file_frac` in place of the top-level ones.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadClass {
+    pub weight: f64,
+    #[serde(deserialize_with = "de_size", serialize_with = "ser_size")]
+    pub file_size_mean: usize,
+    pub file_frac: f64,
+    pub rps_target: u32,
+}
+
+/// `Params::stress_schedule` steps as `(elapsed_secs, rps_target)` pairs in
+/// ascending order of `elapsed_secs`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct StressSchedule {
+    pub steps: Vec<(f64, u32)>,
+}
+
+impl StressSchedule {
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// The rps_target of the last step started by `elapsed`. None before
+    /// the first step.
+    pub fn rps_target_at(&self, elapsed: f64) -> Option<u32> {
+        let nr_started = self.steps.partition_point(|(at, _)| *at <= elapsed);
+        match nr_started {
+            0 => None,
+            n => Some(self.steps[n - 1].1),
+        }
+    }
+
+    fn verify(&self) -> Result<()> {
+        let mut last = 0.0;
+        for (at, _) in self.steps.iter() {
+            if at.is_nan() || *at < last {
+                bail!("stress_schedule step at {} is negative or out of order", at);
+            }
+            last = *at;
+        }
+        Ok(())
+    }
+}
+
+/// Update to a single `anon_histogram` slot. See
+/// `Params::apply_histogram_delta()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistogramDelta {
+    pub index: usize,
+    pub weight: u64,
+}
+
+/// Digest calculated by hash workers. Per-byte CPU cost goes up roughly in
+/// the listed order. `Crc32` still reads every accessed byte but costs
+/// little CPU, which decouples memory and IO pressure from CPU usage on
+/// small machines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgo {
+    Crc32,
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DurationRepr {
+    Secs(f64),
+    Str(String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeRepr {
+    Bytes(u64),
+    Str(String),
+}
+
+fn duration_from_repr<E: serde::de::Error>(repr: DurationRepr) -> Result<f64, E> {
+    match repr {
+        DurationRepr::Secs(v) => Ok(v),
+        DurationRepr::Str(v) => {
+            parse_duration(&v).map_err(|e| E::custom(format!("invalid duration {:?} ({})", &v, &e)))
+        }
+    }
+}
+
+fn size_from_repr<E: serde::de::Error>(repr: SizeRepr) -> Result<u64, E> {
+    match repr {
+        SizeRepr::Bytes(v) => Ok(v),
+        SizeRepr::Str(v) => {
+            parse_size(&v).map_err(|e| E::custom(format!("invalid size {:?} ({})", &v, &e)))
+        }
+    }
+}
+
+/// Canonical string for `dur` in the largest of s, ms, us and ns which
+/// represents it exactly. `None` if there's no such unit.
+fn duration_to_str(dur: f64) -> Option<String> {
+    if dur.is_nan() || dur <= 0.0 {
+        return None;
+    }
+    let nsecs = (dur * 1_000_000_000.0).round();
+    for (unit, suffix) in &[(1e9, "s"), (1e6, "ms"), (1e3, "us"), (1.0, "ns")] {
+        if nsecs % unit == 0.0 {
+            let formatted = format!("{}{}", nsecs / unit, suffix);
+            return match parse_duration(&formatted) {
+                Ok(v) if v == dur => Some(formatted),
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
+/// Canonical string for `size` in the largest of T, G, M and K which divides
+/// it. `None` if there's no such unit.
+fn size_to_str(size: u64) -> Option<String> {
+    for (shift, suffix) in &[(40, "T"), (30, "G"), (20, "M"), (10, "K")] {
+        if size != 0 && size % (1 << shift) == 0 {
+            return Some(format!("{}{}", size >> shift, suffix));
+        }
+    }
+    None
+}
+
+fn de_duration<'de, D: Deserializer<'de>>(de: D) -> Result<f64, D::Error> {
+    duration_from_repr(DurationRepr::deserialize(de)?)
+}
+
+fn de_opt_duration<'de, D: Deserializer<'de>>(de: D) -> Result<Option<f64>, D::Error> {
+    match Option::<DurationRepr>::deserialize(de)? {
+        Some(repr) => Ok(Some(duration_from_repr(repr)?)),
+        None => Ok(None),
+    }
+}
+
+fn de_nullable<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    de: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(de).map(Some)
+}
+
+fn de_nullable_size<'de, D: Deserializer<'de>, T: std::convert::TryFrom<u64>>(
+    de: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    de_opt_size(de).map(Some)
+}
+
+fn ser_duration<S: Serializer>(dur: &f64, ser: S) -> Result<S::Ok, S::Error> {
+    match duration_to_str(*dur) {
+        Some(v) => ser.serialize_str(&v),
+        None => ser.serialize_f64(*dur),
+    }
+}
+
+fn de_size<'de, D: Deserializer<'de>, T: std::convert::TryFrom<u64>>(de: D) -> Result<T, D::Error> {
+    let size = size_from_repr(SizeRepr::deserialize(de)?)?;
+    T::try_from(size).map_err(|_| serde::de::Error::custom(format!("size {} too large", size)))
+}
+
+fn de_opt_size<'de, D: Deserializer<'de>, T: std::convert::TryFrom<u64>>(
+    de: D,
+) -> Result<Option<T>, D::Error> {
+    match Option::<SizeRepr>::deserialize(de)? {
+        Some(repr) => {
+            let size = size_from_repr(repr)?;
+            Ok(Some(T::try_from(size).map_err(|_| {
+                serde::de::Error::custom(format!("size {} too large", size))
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+fn ser_size<S: Serializer, T: Copy + std::convert::TryInto<u64>>(
+    size: &T,
+    ser: S,
+) -> Result<S::Ok, S::Error> {
+    let size: u64 = (*size).try_into().unwrap_or(u64::MAX);
+    match size_to_str(size) {
+        Some(v) => ser.serialize_str(&v),
+        None => ser.serialize_u64(size),
+    }
+}
+
+fn diff_value_str(v: Option<&Value>) -> String {
+    match v {
+        None => "-".to_string(),
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(vals)) if vals.len() > 16 => format!(
+            "[{} slots, sum {}]",
+            vals.len(),
+            vals.iter().filter_map(|v| v.as_f64()).sum::<f64>()
+        ),
+        Some(v) => v.to_string(),
+    }
+}
+
+fn diff_values(
+    prefix: &str,
+    old: &serde_json::Map<String, Value>,
+    new: &serde_json::Map<String, Value>,
+    diffs: &mut Vec<(String, String, String)>,
+) {
+    let mut keys: Vec<&String> = old.keys().collect();
+    keys.extend(new.keys().filter(|k| !old.contains_key(*k)));
+
+    for key in keys {
+        let name = format!("{}{}", prefix, key);
+        match (old.get(key), new.get(key)) {
+            (Some(ov), Some(nv)) if ov == nv => {}
+            (Some(Value::Object(om)), Some(Value::Object(nm))) => {
+                diff_values(&format!("{}.", name), om, nm, diffs)
+            }
+            (ov, nv) => diffs.push((name, diff_value_str(ov), diff_value_str(nv))),
+        }
+    }
+}
+
+/// Interpolate `a` and `b` at `t`. Numbers are interpolated linearly and
+/// stay integers if both are. Arrays of numbers are interpolated element-wise
+/// with the shorter one padded with zeros, other arrays and objects
+/// recursively if their shapes match. Everything else snaps to `b` at `t >=
+/// 0.5`. `None` means "take the snapped side as-is".
+fn lerp_value(a: &Value, b: &Value, t: f64) -> Option<Value> {
+    let snap = |a: &Value, b: &Value| match t >= 0.5 {
+        true => b.clone(),
+        false => a.clone(),
+    };
+    match (a, b) {
+        (Value::Number(an), Value::Number(bn)) => {
+            let v = an.as_f64()? + (bn.as_f64()? - an.as_f64()?) * t;
+            Some(
+                match (an.is_u64() || an.is_i64(), bn.is_u64() || bn.is_i64()) {
+                    (true, true) if v >= 0.0 => json!(v.round() as u64),
+                    (true, true) => json!(v.round() as i64),
+                    _ => json!(v),
+                },
+            )
+        }
+        (Value::Array(av), Value::Array(bv))
+            if av.iter().chain(bv.iter()).all(|v| v.is_number()) =>
+        {
+            let zero = json!(0);
+            let nr = av.len().max(bv.len());
+            let mut vals = vec![];
+            for i in 0..nr {
+                let (x, y) = (av.get(i).unwrap_or(&zero), bv.get(i).unwrap_or(&zero));
+                vals.push(lerp_value(x, y, t)?);
+            }
+            Some(Value::Array(vals))
+        }
+        (Value::Array(av), Value::Array(bv)) if av.len() == bv.len() => {
+            let mut vals = vec![];
+            for (x, y) in av.iter().zip(bv.iter()) {
+                vals.push(lerp_value(x, y, t).unwrap_or_else(|| snap(x, y)));
+            }
+            Some(Value::Array(vals))
+        }
+        (Value::Object(am), Value::Object(bm))
+            if am.len() == bm.len() && am.keys().all(|k| bm.contains_key(k)) =>
+        {
+            let mut map = serde_json::Map::new();
+            for (k, x) in am.iter() {
+                let y = &bm[k];
+                map.insert(k.clone(), lerp_value(x, y, t).unwrap_or_else(|| snap(x, y)));
+            }
+            Some(Value::Object(map))
+        }
+        _ => None,
+    }
+}
+
+/// Rebuild `v` with object keys in sorted order and negative zeros, which
+/// compare equal to zeros, as zeros.
+fn canonical_value(v: Value) -> Value {
+    match v {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k, canonical_value(v)))
+                    .collect(),
+            )
+        }
+        Value::Array(vals) => Value::Array(vals.into_iter().map(canonical_value).collect()),
+        Value::Number(n) if n.as_f64() == Some(0.0) && n.is_f64() => json!(0.0),
+        v => v,
+    }
 }
 
 const PARAMS_DOC: &str = "\
@@ -22,7 +471,8 @@ const PARAMS_DOC: &str = "\
 // determined using truncated normal distributions which gradually transforms
 // to uniform distributions as their standard deviations increase.
 //
-// All durations are in seconds and memory bytes. A _frac field should be <=
+// All durations are in seconds and memory bytes. Both can also be specified
+// as strings with units, e.g. \"20ms\" or \"1.2M\". A _frac field should be <=
 // 1.0 and specifies a sub-proportion of some other value. A _ratio field is
 // similar but may be greater than 1.0.
 //
@@ -30,6 +480,15 @@ const PARAMS_DOC: &str = "\
 // specified latency and RPS so that neither is exceeded. The total number
 // of concurrent threads is limited by `concurrency_max`.
 //
+// Multiple latency targets can be specified as [percentile, target] pairs in
+// `lat_targets`, in which case the most violated one drives the latency PID
+// controller. `lat_target_pct` and `lat_target` always reflect the first
+// entry and are used on their own if `lat_targets` is empty.
+//
+// `concurrency`, `lat_pct`, `lat` and `rps` are accepted as shorthands for
+// `concurrency_max`, `lat_target_pct`, `lat_target` and `rps_target` when
+// loading. The full names are always used on save.
+//
 // The total size of testfiles is set up during startup and can't be changed
 // online. However, the portion which is actively used by rd-hashd can be
 // scaled down with `file_total_frac`.
@@ -37,140 +496,6680 @@ const PARAMS_DOC: &str = "\
 // Anonymous memory total and access sizes are configured as proportions to
 // file access sizes.
 //
+// If `trace_path` is set, file accesses replay the recorded trace in order,
+// wrapping around at the end. Each request takes the next record's chunk
+// index, size and write flag, which take precedence over `file_histogram`,
+// `file_addr_dist`, the file size distribution, `classes` and
+// `file_write_frac`. Anonymous accesses are still sampled as configured.
+//
 // The total footprint for file accesses is scaled between
 // `file_addr_rps_base_frac` and 1.0 linearly if the current RPS is lower than
 // `rps_max`. If `rps_max` is 0, access footprint scaling is disabled. Anon
 // footprint is scaled the same way between 'anon_addr_rps_base_frac' and 1.0.
 //
+// If `mem_pressure_target` is non-zero, a third PID controller scales the
+// memory footprint down from `mem_frac` while the memory pressure of
+// rd-hashd's cgroup is above the target and back up as it subsides.
+//
 // Worker threads will sleep according to the sleep duration distribution and
 // their CPU consumption can be scaled up and down using `cpu_ratio`.
 //
+// A request's latency is measured from when its worker starts until the
+// completion, so by default all of the sleep counts towards it. If
+// `service_mean` is non-zero, the sleep becomes client think time which the
+// worker spends before the request starts and outside the latency, and the
+// service time sampled from `service_mean` and `service_stdev_ratio` takes its
+// place inside. Each worker then models a closed-loop client and Little's Law
+// gives `concurrency = rps * (think time + latency)`.
+//
+//  version: Format version - files without it are upgraded and stamped on save
 //  control_period: PID control period, best left alone
-//  concurrency_max: Maximum number of worker threads
-//  lat_target_pct: Latency target percentile
+//  control_period_adaptive: Shorten the control period down to
+//                           control_period_min while the latency error is
+//                           changing quickly and return to control_period as
+//                           it flattens out - the PID gains stay per
+//                           control_period, shorter steps scale their effect
+//  control_period_min: Lower bound of the adaptive control period - capped at
+//                      control_period
+//  concurrency_max: Maximum number of worker threads - clamped to the system
+//                   thread limit (RLIMIT_NPROC and kernel.threads-max)
+//  concurrency_min: Minimum number of worker threads regardless of the
+//                   controllers - if a latency blowout could drive concurrency
+//                   to zero, no requests would complete and there'd be no
+//                   measurements left to recover from, at least 1
+//  lat_target_pct: Latency target percentile - [0.5, 0.9999]
 //  lat_target: Latency target
-//  rps_target: Request-per-second target
+//  lat_targets: Additional [percentile, target] pairs - the first one overrides lat_target_pct/lat_target
+//  lat_tail_weight: Blend this proportion of the p99.9 latency into the latency
+//                   controller's input so that the deep tail can't run wild -
+//                   at 1.0, p99.9 is held to lat_target - [0.0, 1.0]
+//  lat_target_ramp: [start, end, secs] - overrides lat_target with a linear ramp
+//                   from start to end over secs after being set, then stays at end
+//  rps_target: Request-per-second target - 0 pauses, no new requests are issued
+//              while the memory footprint, worker threads and PID controller
+//              states are retained, and resuming picks up where it left off
 //  rps_max: Reference maximum RPS, used to scale the amount of used memory
+//  concurrency_ramp_max: Maximum number of workers to add per control_period -
+//                        0 is unlimited
+//  queue_max: Model a dispatch queue of this size - requests arrive at the
+//             rps target and wait for a free worker, and arrivals beyond the
+//             capacity are dropped and counted. The wait is part of each
+//             request's latency, adding up to about queue_max / rps under
+//             overload. Think time doesn't apply. 0 disables the queue and
+//             launches a request whenever a worker frees up
+//  warmup_secs: Ramp the effective rps_target linearly from zero over this many
+//               seconds after startup - lat_pid and rps_pid gains are scaled
+//               down while warming up so that cold-cache latency spikes don't
+//               crash concurrency_max - 0 disables
+//  burst_rps: Jump the effective rps_target to this every burst_period_secs to
+//             model traffic spikes - 0 disables
+//  burst_secs: Duration of each burst
+//  burst_period_secs: Interval between the starts of bursts, the first one
+//                     starting this many seconds after startup
+//  stress_schedule: [elapsed_secs, rps_target] steps in ascending order to
+//                   escalate load until the latency target breaks - each
+//                   target holds until the next step and the last one stays,
+//                   rps_target applies before the first step, overrides the
+//                   warmup ramp and the first SLO break is logged with the
+//                   rps_target it happened at
+//  classes: Workload classes, each {weight, file_size_mean, file_frac, rps_target} -
+//           requests pick a class by weight and the classes' rps_targets add
+//           up to the effective one, a top-level rps_target of 0 still
+//           pauses and any other value is ignored
 //  chunk_pages: Memory access chunk size in pages
 //  mem_frac: Memory footprint scaling factor - [0.0, 1.0]
+//  mem_frac_ramp_secs: Transition linearly to a new mem_frac over this many
+//                      seconds starting from the current one - 0 is instant,
+//                      reloads which don't change mem_frac keep the ramp going
+//  mem_balloon_bytes: Keep this much extra anon memory resident but idle by
+//                     touching all of it once every 60s - on top of the
+//                     mem_frac footprint and never accessed by requests,
+//                     rounded up to 32M, 0 disables
+//  mem_total_override: System memory total in bytes assumed by the derived
+//                      footprint and rate estimates - queried from the system
+//                      if omitted, doesn't affect the workload itself
 //  file_frac: Page cache proportion of memory footprint - [0.0, 1.0]
+//  file_frac_ramp_secs: Transition linearly to a new file_frac over this many
+//                       seconds starting from the current one - 0 is instant,
+//                       reloads which don't change file_frac keep the ramp going
+//  file_frac_min: Floor for file_frac and the classes' file_frac - 0.0 allows
+//                 all-anon workloads without any file accesses
+//  file_total_frac: Proportion of testfiles actively used - [0.0, 1.0]
+//  anon_frac: Anon footprint as a proportion of memory - [0.0, 1.0], takes
+//             effect only together with file_cache_frac
+//  file_cache_frac: Page cache footprint as a proportion of memory -
+//                   [0.0, 1.0], if both this and anon_frac are above 0,
+//                   mem_frac and file_frac are derived from the two and
+//                   their configured values are ignored - the two can't
+//                   add up to more than 1.0
 //  file_size_mean: File access size average
 //  file_size_stdev_ratio: Standard deviation of file access sizes
+//  file_size_dist: File access size distribution - Normal or LogNormal
+//  file_size_percentiles: [percentile, size] points of an empirical file access
+//                         size distribution, interpolated linearly in between -
+//                         overrides file_size_mean, file_size_stdev_ratio and
+//                         file_size_dist if non-empty, classes use their own
 //  file_addr_stdev_ratio: Standard deviation of file access addresses
+//  file_addr_dist: File access address distribution - \"Normal\" or {\"Zipf\": {\"s\": S}}
 //  file_addr_rps_base_frac: Memory scaling starting point for file accesses
 //  file_write_frac: The proportion of writes in file accesses
+//  file_sync: How file writes are synced - None, Fdatasync or Fsync
+//  file_sync_every: Sync after every N file writes, at least 1
+//  write_cost_ratio: Cost of a write access in read-equivalents - each file write
+//                    counts this many times towards file_sync_every and io_mix()
+//                    scales the write bandwidths by it
+//  file_direct: Read testfiles with O_DIRECT bypassing page cache - file_frac
+//               has no effect on reads and writes still go through page cache
+//  verify_reads: Check file reads against the per-page checksums recorded while
+//                preparing testfiles and count mismatches in the report -
+//                ignored if testfiles weren't prepared in this run
+//  file_histogram: File access weights for consecutive chunk_pages-sized regions
+//                  from the start of testfiles - overrides file_addr_dist and
+//                  file_addr_stdev_ratio if non-empty, can't be all zeros
+//  trace_path: Access trace to replay for file accesses - CHUNK_INDEX SIZE
+//              IS_WRITE lines, overrides the file access distributions
 //  anon_size_ratio: Anon access size average - 1.0 means equal as file accesses
+//  anon_size_mean: Anon access size average in bytes - overrides anon_size_ratio
+//                  if set, anon_histogram still determines the addresses
 //  anon_size_stdev_ratio: Standard deviation of anon access sizes
 //  anon_addr_stdev_ratio: Standard deviation of anon access addresses
+//  addr_trunc_stdevs: Truncate the normal address distributions this many stdevs
+//                     from the mean in addition to the area bounds - 0 disables
+//  size_trunc_stdevs: Ditto for the normal file and anon size distributions
+//                     in addition to [page size, 2 * mean] - 0 disables
+//  dist_uniform_knee: Address stdev_ratio at which half of the accesses are
+//                     uniform, the rest following the normal distribution -
+//                     the default matches clamped-off samples becoming uniform
+//  addr_drift_rate: Advance the center of the normal and Zipf address
+//                   distributions by this many chunks per second, wrapping
+//                   around the area, to simulate a drifting working set -
+//                   0 keeps them stationary, histograms aren't affected
 //  anon_addr_rps_base_frac: Memory scaling starting point for anon accesses
 //  anon_write_frac: The proportion of writes in anon accesses
+//  anon_histogram: Anon access weights for consecutive chunk_pages-sized regions
+//                  from the start of the anon area - overrides
+//                  anon_addr_stdev_ratio if non-empty, can't be all zeros
+//  anon_histogram_rle: anon_histogram as [count, weight] runs, expanded into
+//                      anon_histogram on load and saved dense
 //  sleep_mean: Worker sleep duration average
 //  sleep_stdev_ratio: Standard deviation of sleep duration distribution
+//  sleep_modes: [weight, mean, stdev_ratio] sleep distribution modes - overrides
+//               sleep_mean and sleep_stdev_ratio with the mixture if non-empty
+//  sleep_jitter_frac: Add uniform random jitter of up to this proportion of
+//                     sleep_mean to each sleep - [0.0, 1.0]
+//  service_mean: Worker service time average - counted in latency, turns the
+//                sleep into think time outside latency if non-zero
+//  service_stdev_ratio: Standard deviation of service time distribution
 //  cpu_ratio: CPU usage scaling - 1.0 hashes the same number of bytes as accessed
+//  cpu_ratio_min: Back cpu_ratio off towards this as CPUs get thermally
+//                 throttled, reaching it when all are - no backoff if omitted
+//  cpu_time_per_byte: Seconds to hash a byte as measured by the CPU bench -
+//                     0 if not benchmarked
 //  log_bps: Log write bps at rps_max
-//  fake_cpu_load: Sleep equivalent time durations instead of calculating SHA1s
-//  acc_dist_slots: Access distribution report slots - 0 disables
+//  log_max_bytes: Rotate the request log once it grows to this size, rotated
+//                 files are kept up to --log-size - 0 never rotates
+//  log_fsync_secs: Sync the request log at most this often to model durable
+//                  log writes - 0 never syncs
+//  fake_cpu_load: Sleep instead of calculating digests - the duration is the
+//                 accessed bytes times cpu_time_per_byte scaled by cpu_ratio
+//  hash_algo: Digest algorithm - Crc32, Sha1, Sha256 or Sha512, in increasing
+//             per-byte CPU cost, cpu_ratio still scales the number of hashed
+//             bytes - Crc32 reads all the same bytes at a fraction of the CPU
+//             cost so that memory pressure builds before CPU saturates
+//  acc_dist_slots: Access distribution report slots - 0 disables, at most 4096
+//  lat_hist_buckets: Latency histogram buckets, log-spaced between 100us and
+//                    10s - 0 disables
+//  cpu_affinity: CPUs to pin worker threads to round-robin - unrestricted if omitted
+//  rng_seed: Seed for access pattern sampling - the same seed and params
+//            generate the same per-request sizes, addresses and sleeps but
+//            thread scheduling and feedback control still vary across runs
 //  lat_pid: PID controller parameters for latency convergence
 //  rps_pid: PID controller parameters for RPS convergence
+//  mem_pressure_target: Memory pressure (some avg10) target in [0.0, 1.0] - 0 disables
+//  mem_pid: PID controller parameters for memory pressure convergence
+//  pid_reset: Reset PID controller states on this update - not saved, the dispatcher also resets on large target jumps
+//  converge_rps_frac: Converged if RPS is within this proportion of rps_target
+//  converge_lat_frac: Converged if latency is at most this proportion above lat_target
+//  converge_timeout_secs: Give up on converging after this many seconds - 0 waits forever
+//  converge_enter_frac: ConvergenceTracker enters converged state when within
+//                       this proportion of the converge tolerances - <= 1.0
+//  converge_exit_frac: ConvergenceTracker leaves converged state when outside
+//                      this proportion of the converge tolerances - >= 1.0
+//  assertions: Invariants checked every control period, each {field, op,
+//              threshold, of_target} - e.g. {\"field\": \"Rps\", \"op\": \">=\",
+//              \"threshold\": 0.9, \"of_target\": true} counts the periods
+//              where rps fell below 90% of rps_target, see Stat
+//  strict: Fail loading on conflicting settings instead of warning, e.g.
+//          non-default anon_addr_* ratios which anon_histogram overrides
+//  *_pid.kp, ki, kd: Proportional, integral and derivative gains
+//  *_pid.i_min, i_max: Integral term is clamped to this range - unbounded if omitted
+//  *_pid.out_min, out_max: Controller output is clamped to this range - unbounded if omitted
+//  *_pid.p_enabled, i_enabled, d_enabled: Compute the term - a disabled term
+//                                          is zero and keeps no state
+//  *_pid.d_on_measurement: Take the derivative of the measurement instead of the
+//                          error - the default, avoids output spikes on target
+//                          changes but the D term no longer reacts to them
 //
 ";
 
 /// Dispatch and hash parameters, can be adjusted dynamially.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Params {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(deserialize_with = "de_duration", serialize_with = "ser_duration")]
     pub control_period: f64,
+    pub control_period_adaptive: bool,
+    #[serde(deserialize_with = "de_duration", serialize_with = "ser_duration")]
+    pub control_period_min: f64,
+    #[serde(alias = "concurrency")]
     pub concurrency_max: u32,
+    pub concurrency_min: u32,
+    #[serde(alias = "lat_pct")]
     pub lat_target_pct: f64,
+    #[serde(
+        alias = "lat",
+        deserialize_with = "de_duration",
+        serialize_with = "ser_duration"
+    )]
     pub lat_target: f64,
+    #[serde(skip_serializing_if = "is_single_or_empty")]
+    pub lat_targets: Vec<(f64, f64)>,
+    pub lat_tail_weight: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lat_target_ramp: Option<(f64, f64, f64)>,
+    #[serde(alias = "rps")]
     pub rps_target: u32,
     pub rps_max: u32,
+    pub concurrency_ramp_max: u32,
+    pub queue_max: u32,
+    pub warmup_secs: f64,
+    pub burst_rps: u32,
+    pub burst_secs: f64,
+    pub burst_period_secs: f64,
+    #[serde(skip_serializing_if = "StressSchedule::is_empty")]
+    pub stress_schedule: StressSchedule,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub classes: Vec<WorkloadClass>,
     pub mem_frac: f64,
+    pub mem_frac_ramp_secs: f64,
+    #[serde(deserialize_with = "de_size", serialize_with = "ser_size")]
+    pub mem_balloon_bytes: u64,
+    #[serde(
+        deserialize_with = "de_opt_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub mem_total_override: Option<u64>,
     pub chunk_pages: usize,
     pub file_frac: f64,
+    pub file_frac_ramp_secs: f64,
+    pub file_frac_min: f64,
+    pub file_total_frac: f64,
+    pub anon_frac: f64,
+    pub file_cache_frac: f64,
+    #[serde(deserialize_with = "de_size", serialize_with = "ser_size")]
     pub file_size_mean: usize,
     pub file_size_stdev_ratio: f64,
+    pub file_size_dist: FileSizeDist,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub file_size_percentiles: Vec<(f64, usize)>,
     pub file_addr_stdev_ratio: f64,
+    pub file_addr_dist: FileAddrDist,
     pub file_addr_rps_base_frac: f64,
     pub file_write_frac: f64,
+    pub file_sync: FileSync,
+    pub file_sync_every: u32,
+    pub write_cost_ratio: f64,
+    pub file_direct: bool,
+    pub verify_reads: bool,
+    pub file_histogram: Vec<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_path: Option<PathBuf>,
     pub anon_size_ratio: f64,
+    #[serde(
+        deserialize_with = "de_opt_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub anon_size_mean: Option<usize>,
     pub anon_size_stdev_ratio: f64,
     pub anon_addr_stdev_ratio: f64,
     pub anon_addr_rps_base_frac: f64,
     pub anon_write_frac: f64,
+    pub anon_histogram: Vec<u64>,
+    #[serde(skip_serializing)]
+    pub anon_histogram_rle: Vec<(usize, u64)>,
+    pub addr_trunc_stdevs: f64,
+    pub size_trunc_stdevs: f64,
+    pub dist_uniform_knee: f64,
+    pub addr_drift_rate: f64,
+    #[serde(deserialize_with = "de_duration", serialize_with = "ser_duration")]
     pub sleep_mean: f64,
     pub sleep_stdev_ratio: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sleep_modes: Vec<(f64, f64, f64)>,
+    pub sleep_jitter_frac: f64,
+    #[serde(deserialize_with = "de_duration", serialize_with = "ser_duration")]
+    pub service_mean: f64,
+    pub service_stdev_ratio: f64,
     pub cpu_ratio: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_ratio_min: Option<f64>,
+    pub cpu_time_per_byte: f64,
+    #[serde(deserialize_with = "de_size", serialize_with = "ser_size")]
     pub log_bps: u64,
+    #[serde(deserialize_with = "de_size", serialize_with = "ser_size")]
+    pub log_max_bytes: u64,
+    pub log_fsync_secs: f64,
     pub fake_cpu_load: bool,
+    pub hash_algo: HashAlgo,
     pub acc_dist_slots: usize,
+    pub lat_hist_buckets: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_affinity: Option<Vec<usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rng_seed: Option<u64>,
     pub lat_pid: PidParams,
     pub rps_pid: PidParams,
+    pub mem_pressure_target: f64,
+    pub mem_pid: PidParams,
+    #[serde(skip_serializing)]
+    pub pid_reset: PidReset,
+    pub converge_rps_frac: f64,
+    pub converge_lat_frac: f64,
+    pub converge_timeout_secs: f64,
+    pub converge_enter_frac: f64,
+    pub converge_exit_frac: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub assertions: Vec<ParamAssertion>,
+    pub strict: bool,
+    #[serde(skip)]
+    pub provenance: Provenance,
+    #[serde(skip)]
+    pub degraded: Degraded,
 }
 
 impl Params {
+    pub const VERSION: u32 = 1;
     pub const FILE_FRAC_MIN: f64 = 0.001;
+    /// Fraction clamps smaller than this are rounding noise and don't mark
+    /// the params `degraded`.
+    pub const CLAMP_COSMETIC_MAX: f64 = 0.000_001;
+    pub const LAT_TARGET_PCT_MIN: f64 = 0.5;
+    pub const LAT_TARGET_PCT_MAX: f64 = 0.9999;
+    pub const PID_RESET_JUMP_RATIO: f64 = 2.0;
+    pub const AUTOTUNE_RELAY_AMP: f64 = 0.1;
+    pub const FILE_DIRECT_FILE_FRAC_WARN: f64 = 0.5;
+    pub const CONTROL_PERIOD_MIN: f64 = 0.01;
+    pub const FAKE_CPU_LOAD_TIME_PER_BYTE: f64 = 4.0 / 1_000_000_000.0;
+    pub const CONTROL_PERIOD_LAT_RATIO_WARN: f64 = 1.0;
+    pub const CONTROL_PERIOD_ERROR_STEP: f64 = 0.1;
+    pub const ACC_DIST_SLOTS_MAX: usize = 4096;
+    pub const HISTOGRAM_RLE_SLOTS_MAX: usize = 1 << 24;
+    // A standard normal sample is within this many stdevs of the mean half
+    // of the time.
+    const NORMAL_QUARTILE: f64 = 0.674_489_750_196_081_7;
+    pub const DIST_UNIFORM_KNEE_DFL: f64 = 1.0 / Self::NORMAL_QUARTILE;
+    pub const WARMUP_PID_GAIN_SCALE: f64 = 0.5;
+    pub const LAT_HIST_MIN: f64 = 0.0001;
+    pub const LAT_HIST_MAX: f64 = 10.0;
+    pub const MEM_BALLOON_TOUCH_SECS: f64 = 60.0;
 
-    pub fn log_padding(&self) -> u64 {
-        if self.rps_max > 0 {
-            (self.log_bps as f64 / self.rps_max as f64).round() as u64
-        } else {
-            0
+    /// Upgrade older representations to `VERSION`. A missing `version` key
+    /// deserializes to 0.
+    fn migrate(&mut self) -> Result<()> {
+        if self.version > Self::VERSION {
+            bail!(
+                "params version {} is newer than {} supported by this binary, update rd-hashd",
+                self.version,
+                Self::VERSION
+            );
         }
+        if self.version < Self::VERSION {
+            debug!(
+                "params: Migrating from version {} to {}",
+                self.version,
+                Self::VERSION
+            );
+        }
+        // v0 predates the version field and is otherwise identical to v1.
+        // Future format changes should convert step by step here.
+        self.version = Self::VERSION;
+        Ok(())
     }
-}
 
-impl Default for Params {
-    fn default() -> Self {
-        Self {
-            control_period: 1.0,
-            concurrency_max: 65536,
-            lat_target_pct: 0.95,
-            lat_target: 75.0 * MSEC,
-            rps_target: 65536,
-            rps_max: 0,
-            chunk_pages: 25,
-            mem_frac: 0.80,
-            file_frac: 0.25,
-            file_size_mean: 1258291,
-            file_size_stdev_ratio: 0.45,
-            file_addr_stdev_ratio: 0.215,
-            file_addr_rps_base_frac: 0.5,
-            file_write_frac: 0.0,
-            anon_size_ratio: 2.3,
-            anon_size_stdev_ratio: 0.45,
-            anon_addr_stdev_ratio: 0.235,
-            anon_addr_rps_base_frac: 0.5,
-            anon_write_frac: 0.3,
-            sleep_mean: 20.0 * MSEC,
-            sleep_stdev_ratio: 0.33,
-            cpu_ratio: 0.93,
-            log_bps: 1100794,
-            fake_cpu_load: false,
-            acc_dist_slots: 0,
-            lat_pid: PidParams {
-                kp: 0.1,
-                ki: 0.01,
-                kd: 0.01,
-            },
-            rps_pid: PidParams {
-                kp: 0.25,
-                ki: 0.01,
-                kd: 0.01,
-            },
+    fn verify_cpu_affinity(cpus: &[usize]) -> Result<()> {
+        if cpus.is_empty() {
+            bail!("cpu_affinity is empty, omit it to leave workers unpinned");
+        }
+        let online = online_cpus()?;
+        for cpu in cpus.iter() {
+            if !online.contains(cpu) {
+                bail!("cpu_affinity: CPU {} is not online ({:?})", cpu, &online);
+            }
         }
+        Ok(())
     }
-}
 
-impl JsonLoad for Params {
-    fn loaded(&mut self, _prev: Option<&mut Self>) -> Result<()> {
-        self.file_frac = self.file_frac.max(Self::FILE_FRAC_MIN);
+    /// Verify `classes` and clamp their fracs. `rps_target` is left as
+    /// configured, see `base_rps_target()`.
+    fn verify_classes(&mut self) -> Result<()> {
+        if self.classes.is_empty() {
+            return Ok(());
+        }
+        let mut weight_sum = 0.0;
+        for class in self.classes.iter_mut() {
+            if class.weight.is_nan() || class.weight < 0.0 {
+                bail!("classes weight {} is negative", class.weight);
+            }
+            weight_sum += class.weight;
+            Self::clamp_frac(
+                "classes file_frac",
+                &mut class.file_frac,
+                &mut self.degraded.0,
+            );
+            class.file_frac = class.file_frac.max(self.file_frac_min);
+        }
+        if weight_sum == 0.0 {
+            bail!(
+                "classes has {} entries but all weights are zero, leave it empty to disable",
+                self.classes.len()
+            );
+        }
         Ok(())
     }
-}
 
-impl JsonSave for Params {
-    fn preamble() -> Option<String> {
-        Some(PARAMS_DOC.to_string())
+    fn verify_lat_target_ramp(&self) -> Result<()> {
+        if let Some((start, end, secs)) = self.lat_target_ramp {
+            if start.is_nan() || start <= 0.0 || end.is_nan() || end <= 0.0 {
+                bail!(
+                    "lat_target_ramp targets {} and {} should be positive",
+                    start,
+                    end
+                );
+            }
+            if secs.is_nan() || secs < 0.0 {
+                bail!("lat_target_ramp duration {} is negative", secs);
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify that `file_size_percentiles` are within [0.0, 1.0] and both the
+    /// percentiles and sizes are non-decreasing.
+    fn verify_file_size_percentiles(&self) -> Result<()> {
+        let mut last = (0.0, 0);
+        for &(pct, size) in self.file_size_percentiles.iter() {
+            if !(0.0..=1.0).contains(&pct) {
+                bail!(
+                    "file_size_percentiles percentile {} out of range [0.0, 1.0]",
+                    pct
+                );
+            }
+            if pct < last.0 || size < last.1 {
+                bail!(
+                    "file_size_percentiles [{}, {}] is lower than the preceding [{}, {}]",
+                    pct,
+                    size,
+                    last.0,
+                    last.1
+                );
+            }
+            last = (pct, size);
+        }
+        Ok(())
+    }
+
+    /// Verify `sleep_modes` and scale the weights to sum to 1.0.
+    fn normalize_sleep_modes(&mut self) -> Result<()> {
+        let mut sum = 0.0;
+        for (weight, mean, stdev_ratio) in self.sleep_modes.iter() {
+            if weight.is_nan() || *weight < 0.0 {
+                bail!("sleep_modes weight {} is negative", weight);
+            }
+            if mean.is_nan() || *mean < 0.0 {
+                bail!("sleep_modes mean {} is negative", mean);
+            }
+            if stdev_ratio.is_nan() || *stdev_ratio < 0.0 {
+                bail!("sleep_modes stdev_ratio {} is negative", stdev_ratio);
+            }
+            sum += weight;
+        }
+        if !self.sleep_modes.is_empty() && sum == 0.0 {
+            bail!(
+                "sleep_modes has {} modes but all weights are zero, leave it empty to disable",
+                self.sleep_modes.len()
+            );
+        }
+        for mode in self.sleep_modes.iter_mut() {
+            mode.0 /= sum;
+        }
+        Ok(())
+    }
+
+    fn floor_control_period(&mut self) {
+        for (name, v) in [
+            ("control_period", &mut self.control_period),
+            ("control_period_min", &mut self.control_period_min),
+        ] {
+            if v.is_nan() || *v < Self::CONTROL_PERIOD_MIN {
+                let msg = format!(
+                    "{} {} is lower than {}, clamping",
+                    name,
+                    v,
+                    Self::CONTROL_PERIOD_MIN
+                );
+                warn!("params: {}", msg);
+                self.degraded.0.push(msg);
+                *v = Self::CONTROL_PERIOD_MIN;
+            }
+        }
+    }
+
+    /// The control period to use next when the relative latency error is
+    /// changing by `error_slope` per second. The adaptive period is the one
+    /// over which the error would change by `CONTROL_PERIOD_ERROR_STEP`,
+    /// bounded to `[control_period_min, control_period]`, so a steep slope
+    /// shortens it and a flat one lengthens it. Without
+    /// `control_period_adaptive`, always `control_period`.
+    pub fn next_control_period(&self, error_slope: f64) -> f64 {
+        let slope = error_slope.abs();
+        if !self.control_period_adaptive || slope.is_nan() || slope == 0.0 {
+            return self.control_period;
+        }
+        (Self::CONTROL_PERIOD_ERROR_STEP / slope).clamp(
+            self.control_period_min.min(self.control_period),
+            self.control_period,
+        )
+    }
+
+    /// Clamp `frac` into [0.0, 1.0]. Unless the adjustment is within
+    /// `CLAMP_COSMETIC_MAX`, the reason is appended to `reasons`.
+    fn clamp_frac(name: &str, frac: &mut f64, reasons: &mut Vec<String>) {
+        let clamped = frac.max(0.0).min(1.0);
+        if clamped != *frac {
+            let msg = format!(
+                "{} {} out of range [0.0, 1.0], clamping to {}",
+                name, frac, clamped
+            );
+            if (clamped - *frac).abs() <= Self::CLAMP_COSMETIC_MAX {
+                debug!("params: {}", msg);
+            } else {
+                warn!("params: {}", msg);
+                reasons.push(msg);
+            }
+            *frac = clamped;
+        }
+    }
+
+    /// Check cross-field invariants and return a description of each
+    /// violation. Nothing is modified.
+    pub fn validate(&self) -> Vec<String> {
+        let mut warns = vec![];
+
+        if self.concurrency_max == 0 {
+            warns.push("concurrency_max is 0, no worker can run".to_string());
+        } else if self.concurrency_min > self.concurrency_max {
+            warns.push(format!(
+                "concurrency_min {} is higher than concurrency_max {}, capping",
+                self.concurrency_min, self.concurrency_max
+            ));
+        }
+        for (_, target) in self.lat_target_list().iter() {
+            if target.is_nan() || *target <= 0.0 {
+                warns.push(format!("lat_target {} is not positive", target));
+            }
+        }
+        let rps_target = self.base_rps_target();
+        if self.rps_max != 0 && rps_target > self.rps_max {
+            warns.push(format!(
+                "rps_target {} is higher than rps_max {}",
+                rps_target, self.rps_max
+            ));
+        }
+        let max_rps = self.max_achievable_rps();
+        if self.concurrency_max > 0 && rps_target > max_rps {
+            warns.push(format!(
+                "rps_target {} is unreachable with concurrency_max {}, expected maximum is {}",
+                rps_target, self.concurrency_max, max_rps
+            ));
+        }
+        if !self.classes.is_empty()
+            && self.rps_target != 0
+            && self.rps_target != rps_target
+            && self.provenance().get("rps_target") != Some(&Source::Default)
+        {
+            warns.push(format!(
+                "rps_target {} is ignored, the classes' rps_targets add up to {}",
+                self.rps_target, rps_target
+            ));
+        }
+        if self.converge_enter_frac > self.converge_exit_frac {
+            warns.push(format!(
+                "converge_enter_frac {} is larger than converge_exit_frac {}, the converged state may flap",
+                self.converge_enter_frac, self.converge_exit_frac
+            ));
+        }
+        if let FileAddrDist::Zipf { s } = self.file_addr_dist {
+            if s.is_nan() || s < 0.0 {
+                warns.push(format!("file_addr_dist Zipf exponent {} is negative", s));
+            }
+        }
+        if self.chunk_bytes() as f64 > self.file_size_mean_bytes() {
+            warns.push(format!(
+                "chunk_pages {} is larger than the file access size average {}",
+                self.chunk_pages,
+                format_size(self.file_size_mean_bytes())
+            ));
+        }
+        if self.file_direct && self.file_frac > Self::FILE_DIRECT_FILE_FRAC_WARN {
+            warns.push(format!(
+                "file_frac {} is ineffective for reads with file_direct",
+                self.file_frac
+            ));
+        }
+        if !self.has_decoupled_footprints() && self.anon_frac + self.file_cache_frac > 0.0 {
+            warns.push(format!(
+                "anon_frac {} and file_cache_frac {} are ignored unless both are set",
+                self.anon_frac, self.file_cache_frac
+            ));
+        }
+        // A control loop slower than the latency it controls lags behind
+        // it. The default period is left alone as it's what the controller
+        // gains are tuned for.
+        if self.lat_target > 0.0
+            && self.control_period != Self::default().control_period
+            && self.control_period > self.lat_target * Self::CONTROL_PERIOD_LAT_RATIO_WARN
+        {
+            warns.push(format!(
+                "control_period {} is too slow for lat_target {}",
+                self.control_period, self.lat_target
+            ));
+        }
+
+        warns
+    }
+
+    /// The value `frac` of the way through a linear ramp from `start` to
+    /// `target`.
+    pub fn frac_ramp(start: f64, target: f64, frac: f64) -> f64 {
+        start + (target - start) * frac.max(0.0).min(1.0)
+    }
+
+    /// The mem_frac `elapsed` seconds into the `mem_frac_ramp_secs` ramp
+    /// from `start`, or `mem_frac` if ramping is disabled. A ramp starts
+    /// from the mem_frac the dispatcher is running, which may be mid-ramp
+    /// or adjusted by `mem_pid`, so the dispatcher keeps `start` rather
+    /// than `loaded()` deriving it from the previous params.
+    pub fn mem_frac_at(&self, start: f64, elapsed: f64) -> f64 {
+        match self.mem_frac_ramp_secs > 0.0 {
+            true => Self::frac_ramp(start, self.mem_frac, elapsed / self.mem_frac_ramp_secs),
+            false => self.mem_frac,
+        }
+    }
+
+    /// Ditto for file_frac and `file_frac_ramp_secs`. Never lower than
+    /// `file_frac_min`. `start` is the file_frac the dispatcher is running,
+    /// the previous params can't tell where a running ramp has got to.
+    pub fn file_frac_at(&self, start: f64, elapsed: f64) -> f64 {
+        match self.file_frac_ramp_secs > 0.0 {
+            true => Self::frac_ramp(start, self.file_frac, elapsed / self.file_frac_ramp_secs)
+                .max(self.file_frac_min),
+            false => self.file_frac,
+        }
+    }
+
+    /// Seconds to hash a byte, `cpu_time_per_byte` from the CPU bench or
+    /// `FAKE_CPU_LOAD_TIME_PER_BYTE` if not benchmarked.
+    pub fn hash_time_per_byte(&self) -> f64 {
+        match self.cpu_time_per_byte > 0.0 {
+            true => self.cpu_time_per_byte,
+            false => Self::FAKE_CPU_LOAD_TIME_PER_BYTE,
+        }
+    }
+
+    /// Seconds `fake_cpu_load` sleeps in place of hashing `bytes` accessed
+    /// bytes. Like the real path, `bytes * cpu_ratio` bytes are "hashed",
+    /// each taking `hash_time_per_byte()`.
+    pub fn fake_cpu_sleep(&self, bytes: usize) -> f64 {
+        bytes as f64 * self.cpu_ratio * self.hash_time_per_byte()
+    }
+
+    /// `cpu_ratio` backed off linearly towards `cpu_ratio_min` by
+    /// `throttle_frac`, the fraction of CPUs being thermally throttled.
+    /// `cpu_ratio_min` above `cpu_ratio` is treated as `cpu_ratio`.
+    pub fn cpu_ratio_under_throttle(&self, throttle_frac: f64) -> f64 {
+        let min = match self.cpu_ratio_min {
+            Some(v) => v.min(self.cpu_ratio),
+            None => return self.cpu_ratio,
+        };
+        let frac = throttle_frac.max(0.0).min(1.0);
+        self.cpu_ratio * (1.0 - frac) + min * frac
+    }
+
+    /// The latency target `elapsed` seconds after `lat_target_ramp` was set,
+    /// or `lat_target` if there's no ramp.
+    pub fn lat_target_at(&self, elapsed: f64) -> f64 {
+        match self.lat_target_ramp {
+            Some((start, end, secs)) if elapsed < secs => {
+                Self::frac_ramp(start, end, elapsed / secs)
+            }
+            Some((_, end, _)) => end,
+            None => self.lat_target,
+        }
+    }
+
+    /// The proportion of accesses which are uniform over the area for a
+    /// normal distribution with `stdev_ratio`, the rest following the normal
+    /// distribution within the area. This is the chance of a normal sample
+    /// falling outside the area, with the stdev scaled so that the blend is
+    /// 0.5 at `dist_uniform_knee`.
+    pub fn uniform_blend(&self, stdev_ratio: f64) -> f64 {
+        if stdev_ratio.is_nan() || stdev_ratio <= 0.0 {
+            return 0.0;
+        }
+        let stdevs = Self::NORMAL_QUARTILE * self.dist_uniform_knee / stdev_ratio;
+        erfc(stdevs / std::f64::consts::SQRT_2)
+    }
+
+    /// `uniform_blend()` for the address distributions. `None` at the
+    /// default `dist_uniform_knee`, where the plain clamping of
+    /// `ClampedNormal` produces the same blend with a single draw.
+    pub fn addr_uniform_blend(&self, stdev_ratio: f64) -> Option<f64> {
+        match self.dist_uniform_knee == Self::DIST_UNIFORM_KNEE_DFL {
+            true => None,
+            false => Some(self.uniform_blend(stdev_ratio)),
+        }
+    }
+
+    /// The chunk the address distributions are centered on `elapsed` seconds
+    /// in, advancing by `addr_drift_rate` and wrapping around the `span`
+    /// chunks of the area. Always 0 without drift.
+    pub fn addr_center_at(&self, elapsed: f64, span: usize) -> usize {
+        if span == 0 || self.addr_drift_rate <= 0.0 {
+            return 0;
+        }
+        let chunks = (elapsed.max(0.0) * self.addr_drift_rate) as u64;
+        (chunks % span as u64) as usize
+    }
+
+    /// `n` file access sizes in bytes drawn from the distribution rd-hashd
+    /// uses, including `classes`. The same `seed` yields the same samples.
+    pub fn sample_file_sizes(&self, n: usize, seed: u64) -> Vec<usize> {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let base = FileSizeSampler::for_params(self);
+        let classes = ClassSampler::new(self);
+        (0..n)
+            .map(|_| {
+                let sampler = match classes.as_ref() {
+                    Some(cs) => cs.pick(&mut rng).0,
+                    None => &base,
+                };
+                sampler.sample(&mut rng).round() as usize
+            })
+            .collect()
+    }
+
+    /// `n` worker sleep durations in seconds, see `sample_file_sizes()`.
+    pub fn sample_sleep(&self, n: usize, seed: u64) -> Vec<f64> {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let sampler = SleepSampler::new(self);
+        (0..n).map(|_| sampler.sample(&mut rng)).collect()
+    }
+
+    /// `n` file access addresses as slot indices in `[0, span)`, see
+    /// `sample_file_sizes()`. `file_histogram` slots are scaled to `span`,
+    /// Zipf ranks map to slots in order and the normal distribution is laid
+    /// out like rd-hashd does, alternating sides of the center from slot 0.
+    /// Drift isn't applied.
+    pub fn sample_file_addrs(&self, n: usize, span: usize, seed: u64) -> Vec<usize> {
+        if span == 0 {
+            return vec![];
+        }
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let hist_len = self.file_histogram.len();
+
+        if self.file_histogram.iter().any(|&w| w > 0) {
+            let hist = WeightedSampler::new(self.file_histogram.iter().map(|&w| w as f64));
+            return (0..n)
+                .map(|_| (hist.sample(&mut rng) * span / hist_len).min(span - 1))
+                .collect();
+        }
+        if let FileAddrDist::Zipf { s } = self.file_addr_dist {
+            let zipf = WeightedSampler::zipf(span, s);
+            return (0..n).map(|_| zipf.sample(&mut rng)).collect();
+        }
+
+        let normal = ClampedNormal::truncated(
+            0.0,
+            self.file_addr_stdev_ratio,
+            -1.0,
+            1.0,
+            self.addr_trunc_stdevs,
+        )
+        .with_uniform_frac(self.addr_uniform_blend(self.file_addr_stdev_ratio));
+        (0..n)
+            .map(|_| {
+                let rel = normal.sample(&mut rng);
+                let mut idx = ((span / 2) as f64 * rel.abs()) as usize * 2;
+                if rel.is_sign_negative() {
+                    idx += 1;
+                }
+                idx.min(span - 1)
+            })
+            .collect()
+    }
+
+    /// The floor of the worker thread count, `concurrency_min` but at least
+    /// 1 and at most `conc_max`.
+    pub fn effective_concurrency_min(&self, conc_max: u32) -> u32 {
+        self.concurrency_min.max(1).min(conc_max.max(1))
+    }
+
+    /// `concurrency_max` limited to `sys_limit` worker threads.
+    pub fn effective_concurrency_max(&self, sys_limit: u32) -> u32 {
+        self.concurrency_max.min(sys_limit)
+    }
+
+    /// The effective rps_target `elapsed` seconds after startup, ramping up
+    /// from zero during `warmup_secs`.
+    pub fn warmup_rps_target(&self, elapsed: f64) -> u32 {
+        let rps_target = self.base_rps_target();
+        if elapsed < self.warmup_secs {
+            (rps_target as f64 * elapsed.max(0.0) / self.warmup_secs).round() as u32
+        } else {
+            rps_target
+        }
+    }
+
+    /// Whether file accesses replay `trace_path` instead of sampling.
+    pub fn has_trace(&self) -> bool {
+        self.trace_path.is_some()
+    }
+
+    /// Whether workers split their time into think time outside latency
+    /// and service time inside, see `think_service_split()`.
+    pub fn has_service_time(&self) -> bool {
+        self.service_mean > 0.0
+    }
+
+    /// Split a worker's sampled `sleep` and `service` durations into `(think
+    /// time, sleep counted in latency)`. Without service time, all of the
+    /// sleep is counted in latency.
+    pub fn think_service_split(&self, sleep: f64, service: f64) -> (f64, f64) {
+        match self.has_service_time() {
+            true => (sleep, service),
+            false => (0.0, sleep),
+        }
+    }
+
+    /// Estimate the highest RPS `concurrency_max` workers can sustain. Each
+    /// request takes at least `sleep_mean` plus `service_mean`, or
+    /// `lat_target` if the workers don't sleep. CPU time isn't accounted
+    /// for, so this is an upper bound.
+    pub fn max_achievable_rps(&self) -> u32 {
+        let req_dur = if self.sleep_mean + self.service_mean > 0.0 {
+            self.sleep_mean + self.service_mean
+        } else {
+            self.lat_target
+        };
+        if req_dur.is_nan() || req_dur <= 0.0 {
+            return u32::MAX;
+        }
+        (self.concurrency_max as f64 / req_dur).min(u32::MAX as f64) as u32
+    }
+
+    /// Ask the next update to reset the PID controller states.
+    pub fn request_pid_reset(&mut self) {
+        self.pid_reset.0 = true;
+    }
+
+    pub fn is_pid_reset_requested(&self) -> bool {
+        self.pid_reset.0
+    }
+
+    /// Return whether PID controller state reset is requested and clear the
+    /// request.
+    pub fn take_pid_reset(&mut self) -> bool {
+        std::mem::replace(&mut self.pid_reset.0, false)
+    }
+
+    /// The `stress_schedule` step's rps_target `elapsed` seconds after
+    /// startup, `base_rps_target()` before the first step or without a
+    /// schedule.
+    pub fn scheduled_rps_target(&self, elapsed: f64) -> u32 {
+        self.stress_schedule
+            .rps_target_at(elapsed)
+            .unwrap_or_else(|| self.base_rps_target())
+    }
+
+    /// Whether `burst_rps`, `burst_secs` and `burst_period_secs` are all set.
+    pub fn has_bursts(&self) -> bool {
+        self.burst_rps > 0 && self.burst_secs > 0.0 && self.burst_period_secs > 0.0
+    }
+
+    /// The effective rps_target `elapsed` seconds after startup. This is
+    /// `burst_rps` for `burst_secs` from every multiple of
+    /// `burst_period_secs`, `scheduled_rps_target()` with a `stress_schedule`
+    /// and `warmup_rps_target()` otherwise. Neither applies while paused.
+    pub fn effective_rps_target(&self, elapsed: f64) -> u32 {
+        if self.has_bursts()
+            && !self.is_paused()
+            && elapsed >= self.burst_period_secs
+            && elapsed % self.burst_period_secs < self.burst_secs
+        {
+            return self.burst_rps;
+        }
+        if !self.stress_schedule.is_empty() && !self.is_paused() {
+            return self.scheduled_rps_target(elapsed);
+        }
+        self.warmup_rps_target(elapsed)
+    }
+
+    /// Whether `rps_target` is 0 and the workload is paused.
+    pub fn is_paused(&self) -> bool {
+        self.rps_target == 0
+    }
+
+    /// The configured rps target before warmup, bursts and schedules. With
+    /// `classes`, it's what their `rps_target`s add up to unless the
+    /// top-level `rps_target` is 0 to pause.
+    pub fn base_rps_target(&self) -> u32 {
+        match (self.classes.is_empty(), self.rps_target) {
+            (true, v) | (false, v @ 0) => v,
+            (false, _) => self
+                .classes
+                .iter()
+                .fold(0u32, |sum, c| sum.saturating_add(c.rps_target)),
+        }
+    }
+
+    /// Whether the latency or rps target jumped far enough from `prev` that
+    /// the PID controller states are no longer relevant. Pausing and
+    /// resuming aren't jumps. The dispatcher checks this against the params
+    /// it's running rather than `loaded()` against the previous file, which
+    /// may lack env overrides and patches applied since and doesn't see
+    /// params handed to the dispatcher directly.
+    pub fn is_setpoint_jump(&self, prev: &Params) -> bool {
+        let is_jump = |prev: f64, next: f64| {
+            let ratio = next / prev;
+            !(1.0 / Self::PID_RESET_JUMP_RATIO..=Self::PID_RESET_JUMP_RATIO).contains(&ratio)
+        };
+        is_jump(prev.lat_target, self.lat_target)
+            || (!self.is_paused()
+                && !prev.is_paused()
+                && is_jump(prev.base_rps_target() as f64, self.base_rps_target() as f64))
+    }
+
+    /// Set `lat_pid` gains using the classic Ziegler-Nichols rules from the
+    /// result of a relay test. The relay swings the control output by
+    /// `AUTOTUNE_RELAY_AMP` and the latency oscillates with `period` seconds
+    /// and peak `amplitude` relative to `lat_target`. As the controller is
+    /// stepped once per `control_period`, the integral and derivative gains
+    /// are scaled accordingly.
+    pub fn autotune_lat(&mut self, period: f64, amplitude: f64) -> Result<()> {
+        if !(period > 0.0 && amplitude > 0.0) {
+            bail!(
+                "invalid relay oscillation period {} and amplitude {}",
+                period,
+                amplitude
+            );
+        }
+
+        let ku = 4.0 * Self::AUTOTUNE_RELAY_AMP / (std::f64::consts::PI * amplitude);
+        let kp = 0.6 * ku;
+        let ti = period / 2.0;
+        let td = period / 8.0;
+
+        self.lat_pid.kp = kp;
+        self.lat_pid.ki = kp * self.control_period / ti;
+        self.lat_pid.kd = kp * td / self.control_period;
+        Ok(())
+    }
+
+    /// The latency the latency controller acts on, `p_target` measured at
+    /// the target percentile blended with `p_tail` at p99.9 by
+    /// `lat_tail_weight`.
+    pub fn lat_tail_blend(&self, p_target: f64, p_tail: f64) -> f64 {
+        match self.lat_tail_weight > 0.0 {
+            true => p_target * (1.0 - self.lat_tail_weight) + p_tail * self.lat_tail_weight,
+            false => p_target,
+        }
+    }
+
+    /// The relative latency error the latency controller sees, positive
+    /// when below `lat_target`. See `lat_tail_blend()`.
+    pub fn lat_error(&self, p_target: f64, p_tail: f64) -> f64 {
+        (self.lat_target - self.lat_tail_blend(p_target, p_tail)) / self.lat_target
+    }
+
+    /// All the latency targets as `(percentile, target)` pairs.
+    pub fn lat_target_list(&self) -> Vec<(f64, f64)> {
+        if self.lat_targets.is_empty() {
+            vec![(self.lat_target_pct, self.lat_target)]
+        } else {
+            self.lat_targets.clone()
+        }
+    }
+
+    /// Copy `lat_target_pct` and `lat_target` into the first of
+    /// `lat_targets`, which `loaded()` restores them from. Must be called
+    /// after changing either directly.
+    pub fn sync_lat_targets(&mut self) {
+        if let Some(first) = self.lat_targets.first_mut() {
+            *first = (self.lat_target_pct, self.lat_target);
+        }
+    }
+
+    /// The memory total the derived estimates assume, `mem_total_override`
+    /// if set or the system's otherwise. Pass it as the `mem_total` of
+    /// `footprints()`, `working_set_bytes()` and the like, e.g.
+    /// `p.working_set_bytes(rps, p.mem_total())`.
+    pub fn mem_total(&self) -> u64 {
+        self.mem_total_override
+            .unwrap_or_else(|| total_memory() as u64)
+    }
+
+    /// Whether the footprints are configured through `anon_frac` and
+    /// `file_cache_frac`, which requires both to be above 0.
+    pub fn has_decoupled_footprints(&self) -> bool {
+        self.anon_frac > 0.0 && self.file_cache_frac > 0.0
+    }
+
+    /// The anon and file footprints in bytes on a machine with `mem_total`
+    /// bytes of memory, `mem_frac` of the memory split by `file_frac`. If
+    /// `has_decoupled_footprints()`, `loaded()` derives the two from
+    /// `anon_frac` and `file_cache_frac`. Either way, these are the values
+    /// after clamping and `file_frac_min`, which the daemon runs with.
+    pub fn footprints(&self, mem_total: u64) -> (u64, u64) {
+        let resident_bytes = mem_total as f64 * self.mem_frac;
+        (
+            (resident_bytes * (1.0 - self.file_frac)).round() as u64,
+            (resident_bytes * self.file_frac).round() as u64,
+        )
+    }
+
+    /// Testfile bytes needed to back the file footprint on a machine with
+    /// `mem_total` bytes of memory, rounded up to the page size.
+    pub fn required_testfile_bytes(&self, mem_total: u64) -> u64 {
+        let (_, file_bytes) = self.footprints(mem_total);
+        let bytes = (file_bytes as f64 * self.file_total_frac).ceil() as u64;
+        let page_size = *PAGE_SIZE as u64;
+        (bytes + page_size - 1) / page_size * page_size
+    }
+
+    /// Copy of `self` tuned on a machine with `from_mem` bytes of memory
+    /// adjusted for one with `to_mem`. The footprints already scale through
+    /// the `_frac` fields, which are left alone along with the latency and
+    /// rps targets. What changes:
+    ///
+    /// * `file_histogram` and `anon_histogram` slots cover fixed-size
+    ///   regions, so they're stretched or merged to keep covering the same
+    ///   proportions of the testfiles and anon area.
+    ///
+    /// * `anon_size_mean`, if set, is scaled proportionally.
+    ///
+    /// `file_size_mean` and `log_bps` are per-request and per-rps costs
+    /// calibrated against the CPU and IO device rather than the memory size
+    /// and are kept as-is.
+    pub fn scaled_for_memory(&self, from_mem: u64, to_mem: u64) -> Params {
+        let mut params = self.clone();
+        if from_mem == 0 || from_mem == to_mem {
+            return params;
+        }
+        let ratio = to_mem as f64 / from_mem as f64;
+        params.file_histogram = Self::scale_histogram(&self.file_histogram, ratio);
+        params.anon_histogram = Self::scale_histogram(&self.anon_histogram, ratio);
+        params.anon_size_mean = self
+            .anon_size_mean
+            .map(|v| (v as f64 * ratio).round() as usize);
+        params
+    }
+
+    /// Stretch `hist` by `ratio` repeating each slot, or shrink it summing
+    /// the slots which land on the same one.
+    fn scale_histogram(hist: &[u64], ratio: f64) -> Vec<u64> {
+        if hist.is_empty() {
+            return vec![];
+        }
+        let len = ((hist.len() as f64 * ratio).round() as usize).max(1);
+        let mut scaled = vec![0; len];
+        if ratio >= 1.0 {
+            for (idx, slot) in scaled.iter_mut().enumerate() {
+                *slot = hist[((idx as f64 / ratio) as usize).min(hist.len() - 1)];
+            }
+        } else {
+            for (idx, w) in hist.iter().enumerate() {
+                scaled[((idx as f64 * ratio) as usize).min(len - 1)] += w;
+            }
+        }
+        scaled
+    }
+
+    /// Estimate the resources needed on a machine with `mem_total` bytes of
+    /// memory and `ncpus` CPUs. The peak worker count is `concurrency_max`
+    /// capped by the number of requests in flight at `rps_max` when each
+    /// takes `lat_target`, or by `ncpus` if that's higher or `rps_max` is 0.
+    pub fn resource_plan(&self, mem_total: u64, ncpus: usize) -> ResourcePlan {
+        let (anon_bytes, file_bytes) = self.footprints(mem_total);
+        let in_flight = (self.rps_max as f64 * self.lat_target).ceil() as u64;
+        let worker_cap = in_flight.max(ncpus as u64).min(u32::MAX as u64) as u32;
+
+        ResourcePlan {
+            resident_bytes: anon_bytes + file_bytes,
+            file_bytes,
+            anon_bytes,
+            peak_workers: self.concurrency_max.min(worker_cap),
+            log_bps: self.log_bps_at_rps(self.rps_max),
+        }
+    }
+
+    /// Estimate the file and anon read and write rates at `cur_rps` on a
+    /// machine with `mem_total` bytes of memory. Each request accesses whole
+    /// chunks and each chunk is a write with the respective `_write_frac`.
+    /// The write bandwidths are in read-equivalent bytes, i.e. scaled by
+    /// `write_cost_ratio`.
+    /// The footprints are scaled by `file_addr_frac()` and `anon_addr_frac()`
+    /// and are full if `rps_max` is 0. Workload classes are weighted by
+    /// their shares of the requests.
+    pub fn io_mix(&self, cur_rps: u32, mem_total: u64) -> IoMix {
+        let chunk_bytes = self.chunk_bytes().max(1) as f64;
+        let chunked =
+            |size: f64, min: u64| (size / chunk_bytes).ceil().max(min as f64) * chunk_bytes;
+
+        let file_req_bytes = match self.classes.is_empty() {
+            true => chunked(self.file_size_mean_bytes(), 1),
+            false => {
+                let weight_sum: f64 = self.classes.iter().map(|c| c.weight).sum();
+                self.classes
+                    .iter()
+                    .map(|c| c.weight * chunked(c.file_size_mean as f64, 1))
+                    .sum::<f64>()
+                    / weight_sum
+            }
+        };
+        let anon_req_bytes = chunked(self.anon_size_mean_bytes(), 0);
+        let file_bps = file_req_bytes * cur_rps as f64;
+        let anon_bps = anon_req_bytes * cur_rps as f64;
+        let ws = self.working_set_bytes(cur_rps, mem_total);
+
+        IoMix {
+            file_read_bps: (file_bps * (1.0 - self.file_write_frac)) as u64,
+            file_write_bps: (file_bps * self.file_write_frac * self.write_cost_ratio) as u64,
+            anon_read_bps: (anon_bps * (1.0 - self.anon_write_frac)) as u64,
+            anon_write_bps: (anon_bps * self.anon_write_frac * self.write_cost_ratio) as u64,
+            file_bytes: ws.file_bytes,
+            anon_bytes: ws.anon_bytes,
+        }
+    }
+
+    /// The file and anon footprints accessed at `cur_rps` on a machine with
+    /// `mem_total` bytes of memory. The `footprints()` are scaled by
+    /// `file_addr_frac()` and `anon_addr_frac()` and the file side is further
+    /// limited by `file_total_frac`.
+    pub fn working_set_bytes(&self, cur_rps: u32, mem_total: u64) -> WorkingSet {
+        let (anon_bytes, file_bytes) = self.footprints(mem_total);
+        let file_bytes = file_bytes as f64 * self.file_total_frac;
+        let anon_bytes = anon_bytes as f64;
+
+        WorkingSet {
+            file_bytes: (file_bytes * self.file_addr_frac(cur_rps)) as u64,
+            anon_bytes: (anon_bytes * self.anon_addr_frac(cur_rps)) as u64,
+        }
+    }
+
+    /// File access size average, `file_size_mean` or the mean of the
+    /// `file_size_percentiles` inverse CDF if set. Like the sampler, the CDF
+    /// is linear between the points and flat beyond the ends.
+    pub fn file_size_mean_bytes(&self) -> f64 {
+        let pts = &self.file_size_percentiles;
+        let (first, last) = match (pts.first(), pts.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return self.file_size_mean as f64,
+        };
+        let mut mean = first.0 * first.1 as f64 + (1.0 - last.0) * last.1 as f64;
+        for w in pts.windows(2) {
+            mean += (w[1].0 - w[0].0) * (w[0].1 + w[1].1) as f64 / 2.0;
+        }
+        mean
+    }
+
+    /// Anon access size average, `anon_size_mean` if set or
+    /// `file_size_mean_bytes()` scaled by `anon_size_ratio` otherwise.
+    pub fn anon_size_mean_bytes(&self) -> f64 {
+        match self.anon_size_mean {
+            Some(v) => v as f64,
+            None => (self.file_size_mean_bytes() * self.anon_size_ratio).max(0.0),
+        }
+    }
+
+    /// Compare the measured `rps` and `lat` at `lat_target_pct` against the
+    /// targets. Converged if the RPS is within `converge_rps_frac` of the
+    /// target and the latency doesn't exceed the target by more than
+    /// `converge_lat_frac`. Latency well below the target is fine.
+    pub fn convergence(&self, rps: f64, lat: f64, mem_frac: f64) -> ConvergenceReport {
+        self.convergence_scaled(rps, lat, mem_frac, 1.0)
+    }
+
+    /// `convergence()` with the tolerances scaled by `scale`.
+    fn convergence_scaled(
+        &self,
+        rps: f64,
+        lat: f64,
+        mem_frac: f64,
+        scale: f64,
+    ) -> ConvergenceReport {
+        let rps_ok = match self.base_rps_target() {
+            0 => true,
+            target => (rps - target as f64).abs() / target as f64 <= self.converge_rps_frac * scale,
+        };
+        let lat_ok = lat <= self.lat_target * (1.0 + self.converge_lat_frac * scale);
+
+        ConvergenceReport {
+            rps_target: self.base_rps_target(),
+            rps,
+            lat_target_pct: self.lat_target_pct,
+            lat_target: self.lat_target,
+            lat,
+            mem_frac,
+            converged: rps_ok && lat_ok,
+        }
+    }
+
+    /// Whether to give up on converging `elapsed` seconds in. True once
+    /// `converge_timeout_secs` has passed and `report` still isn't
+    /// converged. Never true if the timeout is 0 or `report` is converged,
+    /// so a run which converges late still counts.
+    pub fn converge_deadline_exceeded(&self, elapsed: f64, report: &ConvergenceReport) -> bool {
+        self.converge_timeout_secs > 0.0
+            && elapsed >= self.converge_timeout_secs
+            && !report.converged
+    }
+
+    fn rps_scaled_frac(&self, base: f64, cur_rps: u32) -> f64 {
+        if self.rps_max == 0 {
+            return 1.0;
+        }
+        (base + (1.0 - base) * (cur_rps as f64 / self.rps_max as f64)).min(1.0)
+    }
+
+    /// Proportion of the file footprint accessed at `cur_rps`, scaled
+    /// linearly from `file_addr_rps_base_frac` at 0 to 1.0 at `rps_max`.
+    pub fn file_addr_frac(&self, cur_rps: u32) -> f64 {
+        self.rps_scaled_frac(self.file_addr_rps_base_frac, cur_rps)
+    }
+
+    /// Ditto for anon accesses with `anon_addr_rps_base_frac`.
+    pub fn anon_addr_frac(&self, cur_rps: u32) -> f64 {
+        self.rps_scaled_frac(self.anon_addr_rps_base_frac, cur_rps)
+    }
+
+    /// `hist` as probabilities summing to 1.0. `None` if the histogram is
+    /// empty or all zeros. Summed in f64 so that large weights can't
+    /// overflow.
+    fn histogram_normalized(hist: &[u64]) -> Option<Vec<f64>> {
+        let sum: f64 = hist.iter().map(|&w| w as f64).sum();
+        if sum == 0.0 {
+            return None;
+        }
+        Some(hist.iter().map(|&w| w as f64 / sum).collect())
+    }
+
+    /// `anon_histogram` as probabilities summing to 1.0. `None` if the
+    /// histogram is empty or all zeros.
+    pub fn anon_histogram_normalized(&self) -> Option<Vec<f64>> {
+        Self::histogram_normalized(&self.anon_histogram)
+    }
+
+    /// Ditto for `file_histogram`.
+    pub fn file_histogram_normalized(&self) -> Option<Vec<f64>> {
+        Self::histogram_normalized(&self.file_histogram)
+    }
+
+    /// The anon address fields which are set to non-default values but
+    /// ignored because `anon_histogram` determines the addresses. The size
+    /// fields still apply.
+    pub fn anon_histogram_conflicts(&self) -> Vec<&'static str> {
+        if self.anon_histogram.is_empty() {
+            return vec![];
+        }
+        let dfl = Self::default();
+        let mut fields = vec![];
+        if self.anon_addr_stdev_ratio != dfl.anon_addr_stdev_ratio {
+            fields.push("anon_addr_stdev_ratio");
+        }
+        if self.anon_addr_rps_base_frac != dfl.anon_addr_rps_base_frac {
+            fields.push("anon_addr_rps_base_frac");
+        }
+        fields
+    }
+
+    fn verify_histogram(name: &str, hist: &[u64]) -> Result<()> {
+        if !hist.is_empty() && hist.iter().all(|&w| w == 0) {
+            bail!(
+                "{} has {} slots but all are zero, leave it empty to disable",
+                name,
+                hist.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Expand `anon_histogram_rle` into `anon_histogram`. The runs are capped
+    /// at `HISTOGRAM_RLE_SLOTS_MAX` slots in total so that a short input
+    /// can't blow up memory usage.
+    fn expand_anon_histogram_rle(&mut self) -> Result<()> {
+        if self.anon_histogram_rle.is_empty() {
+            return Ok(());
+        }
+        if !self.anon_histogram.is_empty() {
+            bail!("anon_histogram and anon_histogram_rle can't be both set");
+        }
+        let nr_slots = self
+            .anon_histogram_rle
+            .iter()
+            .fold(0usize, |acc, (cnt, _)| acc.saturating_add(*cnt));
+        if nr_slots > Self::HISTOGRAM_RLE_SLOTS_MAX {
+            bail!(
+                "anon_histogram_rle expands to {} slots, over the maximum {}",
+                nr_slots,
+                Self::HISTOGRAM_RLE_SLOTS_MAX
+            );
+        }
+        let mut hist = Vec::with_capacity(nr_slots);
+        for (cnt, weight) in self.anon_histogram_rle.drain(..) {
+            hist.resize(hist.len() + cnt, weight);
+        }
+        self.anon_histogram = hist;
+        Ok(())
+    }
+
+    /// Set the `anon_histogram` slots in `deltas` in order, growing the
+    /// histogram with zero slots as needed. Nothing is changed if the result
+    /// fails the same check as in `loaded()`.
+    pub fn apply_histogram_delta(&mut self, deltas: &[HistogramDelta]) -> Result<()> {
+        let mut hist = self.anon_histogram.clone();
+        for delta in deltas.iter() {
+            if delta.index >= hist.len() {
+                hist.resize(delta.index + 1, 0);
+            }
+            hist[delta.index] = delta.weight;
+        }
+        Self::verify_histogram("anon_histogram", &hist)?;
+        self.anon_histogram = hist;
+        Ok(())
+    }
+
+    /// Empty latency histogram laid out according to `lat_hist_buckets`.
+    pub fn lat_histogram(&self) -> LatencyHistogram {
+        LatencyHistogram::log_spaced(
+            self.lat_hist_buckets,
+            Self::LAT_HIST_MIN,
+            Self::LAT_HIST_MAX,
+        )
+    }
+
+    /// Size of a memory access chunk in bytes.
+    pub fn chunk_bytes(&self) -> usize {
+        self.chunk_bytes_with_page_size(*PAGE_SIZE)
+    }
+
+    fn chunk_bytes_with_page_size(&self, page_size: usize) -> usize {
+        self.chunk_pages * page_size
+    }
+
+    /// Verify that `anon_histogram` fits in an anon area of `anon_bytes`.
+    /// Each slot covers `chunk_pages` pages from the start of the area and
+    /// slots past the end would never be accessed. `loaded()` can't check
+    /// this as the anon area size depends on the total memory.
+    pub fn check_anon_histogram(&self, anon_bytes: u64) -> Result<()> {
+        let slot_bytes = (self.chunk_pages.max(1) * *PAGE_SIZE) as u64;
+        let nr_slots = self.anon_histogram.len() as u64;
+        let max_slots = (anon_bytes + slot_bytes - 1) / slot_bytes;
+        if nr_slots > max_slots {
+            bail!(
+                "anon_histogram has {} slots but {} anon area with chunk_pages {} only fits {}",
+                nr_slots,
+                format_size(anon_bytes),
+                self.chunk_pages,
+                max_slots
+            );
+        }
+        Ok(())
+    }
+
+    /// Verify that `mem_balloon_bytes` fits in the `mem_frac` share of
+    /// `mem_total`. The balloon comes on top of the access footprint, so a
+    /// larger one pushes the total past what `mem_frac` budgets for.
+    pub fn check_mem_balloon(&self, mem_total: u64) -> Result<()> {
+        let budget = (mem_total as f64 * self.mem_frac) as u64;
+        if self.mem_balloon_bytes > budget {
+            bail!(
+                "mem_balloon_bytes {} exceeds the {} footprint budget of mem_frac {:.2}",
+                format_size(self.mem_balloon_bytes),
+                format_size(budget),
+                self.mem_frac
+            );
+        }
+        Ok(())
+    }
+
+    /// 64bit FNV-1a hash of the serialized params. Fields are serialized in
+    /// declaration order, so the result is stable across runs and equal
+    /// params always produce the same value. Runtime-only fields which
+    /// aren't serialized, such as `pid_reset`, are ignored.
+    pub fn fingerprint(&self) -> u64 {
+        let json = serde_json::to_string(self).expect("Params serialization failed");
+        json.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    /// Params `t` of the way from `a` to `b`, e.g. to ramp between two
+    /// configurations. Numeric fields, including those inside the PID
+    /// parameters and workload classes, are interpolated linearly and the
+    /// histograms element-wise with the shorter one padded with zeros.
+    /// Booleans, enums and fields set on only one side switch to `b` at `t
+    /// >= 0.5`. `version` and `rng_seed` are never interpolated and `t` is
+    /// clamped to [0.0, 1.0]. The result is clamped and validated like a
+    /// loaded file. If that fails, the endpoint the non-numeric fields
+    /// snapped to is returned instead.
+    pub fn lerp(a: &Params, b: &Params, t: f64) -> Params {
+        // The endpoints are exact, e.g. without zero padded histograms.
+        if t.is_nan() || t <= 0.0 {
+            return a.clone();
+        } else if t >= 1.0 {
+            return b.clone();
+        }
+        let base = match t >= 0.5 {
+            true => b,
+            false => a,
+        };
+        let as_patch = |p: &Params| -> Option<Value> {
+            let patch: ParamsPatch = serde_json::from_value(serde_json::to_value(p).ok()?).ok()?;
+            serde_json::to_value(patch).ok()
+        };
+        let lerped = match (as_patch(a), as_patch(b)) {
+            (Some(Value::Object(am)), Some(Value::Object(bm))) => {
+                let mut map = serde_json::Map::new();
+                for (k, x) in am.iter() {
+                    let y = match bm.get(k) {
+                        Some(y) => y,
+                        None => continue,
+                    };
+                    if k == "version" || k == "rng_seed" {
+                        continue;
+                    }
+                    if let Some(v) = lerp_value(x, y, t) {
+                        map.insert(k.clone(), v);
+                    }
+                }
+                serde_json::from_value::<ParamsPatch>(Value::Object(map)).ok()
+            }
+            _ => None,
+        };
+
+        let mut params = base.clone();
+        match lerped.map(|patch| params.apply_patch(&patch)) {
+            Some(Ok(())) => params,
+            Some(Err(e)) => {
+                warn!(
+                    "params: Interpolated params are invalid ({:#}), snapping",
+                    &e
+                );
+                base.clone()
+            }
+            None => base.clone(),
+        }
+    }
+
+    /// `self` as canonical JSON for diffing saved params, e.g. in git. Fields
+    /// are sorted by name, floats take their shortest round-trippable form
+    /// and histograms are saved dense. Equal params produce byte-identical
+    /// output which loads back to the same params.
+    pub fn to_canonical_json(&self) -> String {
+        let v = serde_json::to_value(self).expect("Params serialization failed");
+        let mut json =
+            serde_json::to_string_pretty(&canonical_value(v)).expect("Params serialization failed");
+        json.push('\n');
+        json
+    }
+
+    /// `self` as a single-line JSON object for log pipelines, tagged with
+    /// `timestamp`, the update sequence number `seq` and the hex
+    /// `fingerprint()`, e.g. `{"fingerprint":"..","params":{..},"seq":3,..}`.
+    pub fn as_log_line(&self, seq: u64, timestamp: DateTime<Local>) -> String {
+        json!({
+            "timestamp": timestamp,
+            "seq": seq,
+            "fingerprint": format!("{:016x}", self.fingerprint()),
+            "params": self,
+        })
+        .to_string()
+    }
+
+    /// Like `as_log_line()` but with the compact `ParamsSummary` under
+    /// `"summary"` in place of the full params.
+    pub fn as_summary_log_line(&self, seq: u64, timestamp: DateTime<Local>) -> String {
+        json!({
+            "timestamp": timestamp,
+            "seq": seq,
+            "fingerprint": format!("{:016x}", self.fingerprint()),
+            "summary": ParamsSummary::from(self),
+        })
+        .to_string()
+    }
+
+    /// The targets alongside `measured_rps`, `measured_lat`, the latency at
+    /// `lat_target_pct`, and `measured_concurrency`, the current concurrency
+    /// the controller settled on, as OpenMetrics text exposition. Latencies
+    /// are in seconds and the metric names are stable.
+    pub fn prometheus_metrics(
+        &self,
+        measured_rps: f64,
+        measured_lat: f64,
+        measured_concurrency: f64,
+    ) -> String {
+        let metrics: [(&str, &str, f64); 8] = [
+            (
+                "rps_target",
+                "Target requests per second",
+                self.base_rps_target() as f64,
+            ),
+            ("rps", "Measured requests per second", measured_rps),
+            (
+                "lat_target_pct",
+                "Latency percentile being controlled",
+                self.lat_target_pct,
+            ),
+            (
+                "lat_target_seconds",
+                "Target latency at lat_target_pct",
+                self.lat_target,
+            ),
+            (
+                "lat_seconds",
+                "Measured latency at lat_target_pct",
+                measured_lat,
+            ),
+            (
+                "mem_frac",
+                "Fraction of memory the hasher uses",
+                self.mem_frac,
+            ),
+            (
+                "concurrency_max",
+                "Maximum number of concurrent workers",
+                self.concurrency_max as f64,
+            ),
+            (
+                "concurrency",
+                "Current number of concurrent workers",
+                measured_concurrency,
+            ),
+        ];
+
+        let mut buf = String::new();
+        for (name, help, v) in metrics.iter() {
+            let v = match v {
+                v if v.is_nan() => "NaN".to_string(),
+                v if v.is_infinite() && v.is_sign_positive() => "+Inf".to_string(),
+                v if v.is_infinite() => "-Inf".to_string(),
+                v => format!("{}", v),
+            };
+            buf += &format!(
+                "# HELP rd_hashd_{name} {help}.\n# TYPE rd_hashd_{name} gauge\nrd_hashd_{name} {v}\n",
+                name = name,
+                help = help,
+                v = v
+            );
+        }
+        buf += "# EOF\n";
+        buf
+    }
+
+    /// List the fields which differ between `self` and `other` as `(name,
+    /// old, new)`. Nested fields are dot-separated, e.g. `lat_pid.kp`, and
+    /// long histograms are summarized. "-" marks an omitted field such as an
+    /// unbounded PID clamp.
+    pub fn diff(&self, other: &Params) -> Vec<(String, String, String)> {
+        let mut diffs = vec![];
+        if let (Ok(Value::Object(old)), Ok(Value::Object(new))) =
+            (serde_json::to_value(self), serde_json::to_value(other))
+        {
+            diff_values("", &old, &new, &mut diffs);
+        }
+        diffs
+    }
+
+    /// Load params from `r`, e.g. stdin, the same way as from a params
+    /// file. Comment lines are allowed and the result is verified with
+    /// `loaded()`.
+    pub fn load_from_reader<R: Read>(mut r: R) -> Result<Params> {
+        let mut buf = String::new();
+        r.read_to_string(&mut buf)?;
+        let (_, body) = split_json_preamble(&buf);
+        let mut params = Self::from_json_body(&body)?;
+        params.loaded(None)?;
+        Ok(params)
+    }
+
+    /// Deserialize a params file body and mark the fields it sets as
+    /// coming from `Source::File`.
+    fn from_json_body(body: &str) -> Result<Params> {
+        let mut params = serde_json::from_str::<Params>(body)?;
+        if let Value::Object(map) = serde_json::from_str::<Value>(body)? {
+            for (key, val) in map.into_iter() {
+                // Resolve aliases such as "rps" through ParamsPatch.
+                let single = json!({ key.clone(): val });
+                let field = serde_json::from_value::<ParamsPatch>(single)
+                    .ok()
+                    .and_then(|patch| patch.fields().pop())
+                    .unwrap_or(key);
+                params.provenance.0.insert(field, Source::File);
+            }
+        }
+        Ok(params)
+    }
+
+    /// Where the current value of each field came from. See `Source`.
+    pub fn provenance(&self) -> &HashMap<String, Source> {
+        &self.provenance.0
+    }
+
+    /// Whether `loaded()` had to clamp away fidelity, see
+    /// `degraded_reasons()`.
+    pub fn degraded(&self) -> bool {
+        !self.degraded.0.is_empty()
+    }
+
+    /// The fidelity-affecting clamps, one message per field.
+    pub fn degraded_reasons(&self) -> &[String] {
+        &self.degraded.0
+    }
+
+    /// Whether `s` is an http(s) URL which `load_from_url()` can fetch.
+    pub fn is_url(s: &str) -> bool {
+        s.starts_with("http://") || s.starts_with("https://")
+    }
+
+    /// Fetch params from the http(s) `url` and load them with
+    /// `load_from_reader()`. Only available with the `url` feature, which
+    /// runs the `curl` binary to fetch and needs it installed.
+    pub fn load_from_url(url: &str) -> Result<Params> {
+        if !Self::is_url(url) {
+            bail!("{:?} is not an http(s) URL", url);
+        }
+        let body = Self::fetch_url(url)?;
+        Self::load_from_reader(&body[..]).with_context(|| format!("loading {:?}", url))
+    }
+
+    #[cfg(not(feature = "url"))]
+    fn fetch_url(url: &str) -> Result<Vec<u8>> {
+        bail!(
+            "can't fetch {:?}, built without the \"url\" feature which loads params with curl",
+            url
+        )
+    }
+
+    #[cfg(feature = "url")]
+    fn fetch_url(url: &str) -> Result<Vec<u8>> {
+        let output = Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--location", url])
+            .output()
+            .context("running curl")?;
+        if !output.status.success() {
+            bail!(
+                "failed to fetch {:?} ({}): {}",
+                url,
+                &output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output.stdout)
+    }
+
+    /// Load the params file at `path` and report what the clamping and
+    /// normalization in `loaded()` change and what `validate()` warns
+    /// about. Only the fields which the file sets are reported, the ones
+    /// `loaded()` fills in from defaults such as `version` aren't. If `fix`,
+    /// the normalized params are saved back to `path`. Errors which would
+    /// fail loading are returned as is.
+    pub fn lint(path: &Path, fix: bool) -> Result<LintReport> {
+        let raw = <Params as JsonLoad>::load(path)?;
+        let mut normalized = raw.clone();
+        normalized.loaded(None)?;
+
+        let in_file = |name: &str| {
+            let field = name.split('.').next().unwrap();
+            raw.provenance().get(field) == Some(&Source::File)
+        };
+        let mut report = LintReport {
+            adjustments: raw
+                .diff(&normalized)
+                .into_iter()
+                .filter(|(name, _, _)| in_file(name))
+                .collect(),
+            warnings: normalized.validate(),
+            fixed: false,
+        };
+        if fix {
+            normalized.save(path)?;
+            report.fixed = true;
+        }
+        Ok(report)
+    }
+
+    /// Field descriptions from `PARAMS_DOC` keyed by field name.
+    fn field_docs() -> Vec<(String, String)> {
+        let mut docs: Vec<(String, String)> = vec![];
+        for line in PARAMS_DOC.lines() {
+            let body = match line.strip_prefix("//  ") {
+                Some(v) => v,
+                None => continue,
+            };
+            if body.starts_with(' ') {
+                if let Some(last) = docs.last_mut() {
+                    last.1 += " ";
+                    last.1 += body.trim();
+                }
+            } else if let Some((name, desc)) = body.split_once(": ") {
+                docs.push((name.to_string(), desc.to_string()));
+            }
+        }
+        docs
+    }
+
+    fn pid_json_schema(dfl: &PidParams) -> Value {
+        let mut props = serde_json::Map::new();
+        for (name, v) in &[("kp", dfl.kp), ("ki", dfl.ki), ("kd", dfl.kd)] {
+            props.insert(name.to_string(), json!({ "type": "number", "default": v }));
+        }
+        for name in &["i_min", "i_max", "out_min", "out_max"] {
+            props.insert(name.to_string(), json!({ "type": "number" }));
+        }
+        for (name, v) in &[
+            ("d_on_measurement", dfl.d_on_measurement),
+            ("p_enabled", dfl.p_enabled),
+            ("i_enabled", dfl.i_enabled),
+            ("d_enabled", dfl.d_enabled),
+        ] {
+            props.insert(name.to_string(), json!({ "type": "boolean", "default": v }));
+        }
+        json!({ "type": "object", "properties": props })
+    }
+
+    /// JSON Schema describing the params file. Types and defaults come from
+    /// `Params::default()`, descriptions from `PARAMS_DOC` and the bounds
+    /// from what `loaded()` and `validate()` enforce.
+    pub fn json_schema() -> Value {
+        let dfl = Params::default();
+        let docs = Self::field_docs();
+        let dfl_val = match serde_json::to_value(&dfl) {
+            Ok(Value::Object(map)) => map,
+            _ => panic!("Params didn't serialize into an object"),
+        };
+
+        let mut props = serde_json::Map::new();
+        for (name, v) in dfl_val.iter() {
+            let mut prop = match v {
+                Value::Bool(_) => json!({ "type": "boolean" }),
+                Value::Number(n) if n.is_u64() => json!({ "type": "integer", "minimum": 0 }),
+                Value::Number(_) => json!({ "type": "number" }),
+                Value::Array(_) => json!({
+                    "type": "array",
+                    "items": { "type": "integer", "minimum": 0 }
+                }),
+                _ => json!({}),
+            };
+            prop["default"] = v.clone();
+            props.insert(name.clone(), prop);
+        }
+
+        for (name, pid) in &[
+            ("lat_pid", &dfl.lat_pid),
+            ("rps_pid", &dfl.rps_pid),
+            ("mem_pid", &dfl.mem_pid),
+        ] {
+            props.insert(name.to_string(), Self::pid_json_schema(pid));
+        }
+        props.insert(
+            "lat_targets".into(),
+            json!({
+                "type": "array",
+                "items": {
+                    "type": "array",
+                    "items": [
+                        {
+                            "type": "number",
+                            "minimum": Self::LAT_TARGET_PCT_MIN,
+                            "maximum": Self::LAT_TARGET_PCT_MAX
+                        },
+                        { "type": "number", "exclusiveMinimum": 0.0 }
+                    ],
+                    "minItems": 2,
+                    "maxItems": 2
+                },
+                "default": []
+            }),
+        );
+        props.insert(
+            "classes".into(),
+            json!({
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "weight": { "type": "number", "minimum": 0.0 },
+                        "file_size_mean": { "type": ["integer", "string"] },
+                        "file_frac": { "type": "number", "minimum": 0.0, "maximum": 1.0 },
+                        "rps_target": { "type": "integer", "minimum": 0 }
+                    },
+                    "required": ["weight", "file_size_mean", "file_frac", "rps_target"]
+                },
+                "default": []
+            }),
+        );
+        props.insert(
+            "stress_schedule".into(),
+            json!({
+                "type": "array",
+                "items": {
+                    "type": "array",
+                    "items": [
+                        { "type": "number", "minimum": 0.0 },
+                        { "type": "integer", "minimum": 0 }
+                    ],
+                    "minItems": 2,
+                    "maxItems": 2
+                },
+                "default": []
+            }),
+        );
+        props.insert(
+            "assertions".into(),
+            json!({
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "field": {
+                            "enum": ["Rps", "Lat", "LatP99", "Concurrency", "MemFrac"]
+                        },
+                        "op": { "enum": [">=", ">", "<=", "<"] },
+                        "threshold": { "type": "number" },
+                        "of_target": { "type": "boolean", "default": false }
+                    },
+                    "required": ["field", "op", "threshold"]
+                },
+                "default": []
+            }),
+        );
+        props.insert(
+            "file_size_percentiles".into(),
+            json!({
+                "type": "array",
+                "items": {
+                    "type": "array",
+                    "items": [
+                        { "type": "number", "minimum": 0.0, "maximum": 1.0 },
+                        { "type": "integer", "minimum": 0 }
+                    ],
+                    "minItems": 2,
+                    "maxItems": 2
+                },
+                "default": []
+            }),
+        );
+        props.insert(
+            "sleep_modes".into(),
+            json!({
+                "type": "array",
+                "items": {
+                    "type": "array",
+                    "items": [
+                        { "type": "number", "minimum": 0.0 },
+                        { "type": "number", "minimum": 0.0 },
+                        { "type": "number", "minimum": 0.0 }
+                    ],
+                    "minItems": 3,
+                    "maxItems": 3
+                },
+                "default": []
+            }),
+        );
+        props.insert(
+            "anon_histogram_rle".into(),
+            json!({
+                "type": "array",
+                "items": {
+                    "type": "array",
+                    "items": [
+                        { "type": "integer", "minimum": 0 },
+                        { "type": "integer", "minimum": 0 }
+                    ],
+                    "minItems": 2,
+                    "maxItems": 2
+                },
+                "default": []
+            }),
+        );
+        props.insert(
+            "cpu_affinity".into(),
+            json!({
+                "type": ["array", "null"],
+                "items": { "type": "integer", "minimum": 0 },
+                "default": null
+            }),
+        );
+        props.insert(
+            "cpu_ratio_min".into(),
+            json!({ "type": ["number", "null"], "minimum": 0.0, "default": null }),
+        );
+        props.insert(
+            "trace_path".into(),
+            json!({ "type": ["string", "null"], "default": null }),
+        );
+        props.insert(
+            "mem_total_override".into(),
+            json!({ "type": ["integer", "string", "null"], "minimum": 0, "default": null }),
+        );
+        props.insert(
+            "anon_size_mean".into(),
+            json!({ "type": ["integer", "string", "null"], "minimum": 0, "default": null }),
+        );
+        props.insert(
+            "lat_target_ramp".into(),
+            json!({
+                "type": ["array", "null"],
+                "items": [
+                    { "type": "number", "exclusiveMinimum": 0.0 },
+                    { "type": "number", "exclusiveMinimum": 0.0 },
+                    { "type": "number", "minimum": 0.0 }
+                ],
+                "minItems": 3,
+                "maxItems": 3,
+                "default": null
+            }),
+        );
+        props.insert(
+            "rng_seed".into(),
+            json!({ "type": ["integer", "null"], "minimum": 0, "default": null }),
+        );
+        props.insert(
+            "pid_reset".into(),
+            json!({ "type": "boolean", "default": false }),
+        );
+        props.insert(
+            "file_sync".into(),
+            json!({ "enum": ["None", "Fdatasync", "Fsync"], "default": "None" }),
+        );
+        props.insert(
+            "hash_algo".into(),
+            json!({ "enum": ["Crc32", "Sha1", "Sha256", "Sha512"], "default": "Sha1" }),
+        );
+        props.insert(
+            "file_size_dist".into(),
+            json!({ "enum": ["Normal", "LogNormal"], "default": "Normal" }),
+        );
+        props.insert(
+            "file_addr_dist".into(),
+            json!({
+                "oneOf": [
+                    { "enum": ["Normal"] },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "Zipf": {
+                                "type": "object",
+                                "properties": { "s": { "type": "number", "minimum": 0.0 } },
+                                "required": ["s"]
+                            }
+                        },
+                        "required": ["Zipf"]
+                    }
+                ],
+                "default": "Normal"
+            }),
+        );
+
+        for (name, prop) in props.iter_mut() {
+            if name.ends_with("_frac") || name == "mem_pressure_target" {
+                prop["minimum"] = json!(0.0);
+                prop["maximum"] = json!(1.0);
+            }
+        }
+        for name in &[
+            "control_period",
+            "control_period_min",
+            "lat_target",
+            "sleep_mean",
+            "service_mean",
+        ] {
+            props[*name]["type"] = json!(["number", "string"]);
+        }
+        for name in &[
+            "file_size_mean",
+            "log_bps",
+            "log_max_bytes",
+            "mem_balloon_bytes",
+        ] {
+            props[*name]["type"] = json!(["integer", "string"]);
+        }
+        props["version"]["maximum"] = json!(Self::VERSION);
+        props["concurrency_max"]["minimum"] = json!(1);
+        props["concurrency_min"]["minimum"] = json!(1);
+        props["chunk_pages"]["minimum"] = json!(1);
+        props["addr_trunc_stdevs"]["minimum"] = json!(0.0);
+        props["size_trunc_stdevs"]["minimum"] = json!(0.0);
+        props["dist_uniform_knee"]["exclusiveMinimum"] = json!(0.0);
+        props["addr_drift_rate"]["minimum"] = json!(0.0);
+        props["lat_tail_weight"]["minimum"] = json!(0.0);
+        props["lat_tail_weight"]["maximum"] = json!(1.0);
+        props["warmup_secs"]["minimum"] = json!(0.0);
+        props["burst_secs"]["minimum"] = json!(0.0);
+        props["burst_period_secs"]["minimum"] = json!(0.0);
+        props["converge_timeout_secs"]["minimum"] = json!(0.0);
+        props["converge_enter_frac"]["minimum"] = json!(0.0);
+        props["converge_exit_frac"]["minimum"] = json!(0.0);
+        props["log_fsync_secs"]["minimum"] = json!(0.0);
+        props["cpu_time_per_byte"]["minimum"] = json!(0.0);
+        props["write_cost_ratio"]["exclusiveMinimum"] = json!(0.0);
+        props["acc_dist_slots"]["maximum"] = json!(Self::ACC_DIST_SLOTS_MAX);
+        props["lat_target"]["exclusiveMinimum"] = json!(0.0);
+        props["lat_target_pct"]["minimum"] = json!(Self::LAT_TARGET_PCT_MIN);
+        props["lat_target_pct"]["maximum"] = json!(Self::LAT_TARGET_PCT_MAX);
+        props["file_frac"]["minimum"] = json!(0.0);
+        props["file_frac_min"]["minimum"] = json!(0.0);
+        props["file_frac_ramp_secs"]["minimum"] = json!(0.0);
+        props["file_frac_min"]["maximum"] = json!(1.0);
+        props["control_period"]["minimum"] = json!(Self::CONTROL_PERIOD_MIN);
+        props["control_period_min"]["minimum"] = json!(Self::CONTROL_PERIOD_MIN);
+        props["file_total_frac"]["minimum"] = json!(Self::FILE_FRAC_MIN);
+        for name in ["anon_frac", "file_cache_frac"] {
+            props[name]["minimum"] = json!(0.0);
+            props[name]["maximum"] = json!(1.0);
+        }
+
+        for (name, desc) in docs.iter() {
+            if let Some(prop) = props.get_mut(name) {
+                prop["description"] = json!(desc);
+            }
+        }
+
+        json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "rd-hashd runtime parameters",
+            "type": "object",
+            "properties": props,
+        })
+    }
+
+    pub fn log_padding(&self) -> u64 {
+        if self.rps_max > 0 {
+            (self.log_bps as f64 / self.rps_max as f64).round() as u64
+        } else {
+            0
+        }
+    }
+
+    /// Expected log write bandwidth at `cur_rps` with each request's log
+    /// entry padded to `log_padding()`. Entries longer than the padding
+    /// aren't accounted for.
+    pub fn log_bps_at_rps(&self, cur_rps: u32) -> u64 {
+        if self.rps_max > 0 {
+            self.log_padding() * cur_rps as u64
+        } else {
+            self.log_bps
+        }
+    }
+
+    /// Seconds the request log takes to grow to `log_max_bytes` and rotate
+    /// when writing at `log_bps_at_rps(cur_rps)`. None if it never rotates.
+    pub fn log_rotation_secs(&self, cur_rps: u32) -> Option<f64> {
+        match (self.log_max_bytes, self.log_bps_at_rps(cur_rps)) {
+            (0, _) | (_, 0) => None,
+            (max, bps) => Some(max as f64 / bps as f64),
+        }
+    }
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            control_period: 1.0,
+            control_period_adaptive: false,
+            control_period_min: 0.1,
+            concurrency_max: 65536,
+            concurrency_min: 1,
+            lat_target_pct: 0.95,
+            lat_target: 75.0 * MSEC,
+            lat_targets: vec![],
+            lat_tail_weight: 0.0,
+            lat_target_ramp: None,
+            rps_target: 65536,
+            rps_max: 0,
+            concurrency_ramp_max: 0,
+            queue_max: 0,
+            warmup_secs: 0.0,
+            burst_rps: 0,
+            burst_secs: 0.0,
+            burst_period_secs: 0.0,
+            stress_schedule: Default::default(),
+            classes: vec![],
+            chunk_pages: 25,
+            mem_frac: 0.80,
+            mem_frac_ramp_secs: 0.0,
+            mem_balloon_bytes: 0,
+            mem_total_override: None,
+            file_frac: 0.25,
+            file_frac_ramp_secs: 0.0,
+            file_frac_min: Self::FILE_FRAC_MIN,
+            file_total_frac: 1.0,
+            anon_frac: 0.0,
+            file_cache_frac: 0.0,
+            file_size_mean: 1258291,
+            file_size_stdev_ratio: 0.45,
+            file_size_dist: FileSizeDist::Normal,
+            file_size_percentiles: vec![],
+            file_addr_stdev_ratio: 0.215,
+            file_addr_dist: FileAddrDist::Normal,
+            file_addr_rps_base_frac: 0.5,
+            file_write_frac: 0.0,
+            file_sync: FileSync::None,
+            file_sync_every: 1,
+            write_cost_ratio: 1.0,
+            file_direct: false,
+            verify_reads: false,
+            file_histogram: vec![],
+            trace_path: None,
+            anon_size_ratio: 2.3,
+            anon_size_mean: None,
+            anon_size_stdev_ratio: 0.45,
+            anon_addr_stdev_ratio: 0.235,
+            addr_trunc_stdevs: 0.0,
+            size_trunc_stdevs: 0.0,
+            dist_uniform_knee: Self::DIST_UNIFORM_KNEE_DFL,
+            addr_drift_rate: 0.0,
+            anon_addr_rps_base_frac: 0.5,
+            anon_write_frac: 0.3,
+            anon_histogram: vec![],
+            anon_histogram_rle: vec![],
+            sleep_mean: 20.0 * MSEC,
+            sleep_stdev_ratio: 0.33,
+            sleep_modes: vec![],
+            sleep_jitter_frac: 0.0,
+            service_mean: 0.0,
+            service_stdev_ratio: 0.33,
+            cpu_ratio: 0.93,
+            cpu_ratio_min: None,
+            cpu_time_per_byte: 0.0,
+            log_bps: 1100794,
+            log_max_bytes: 1 << 30,
+            log_fsync_secs: 0.0,
+            fake_cpu_load: false,
+            hash_algo: HashAlgo::Sha1,
+            acc_dist_slots: 0,
+            lat_hist_buckets: 41,
+            cpu_affinity: None,
+            rng_seed: None,
+            lat_pid: PidParams {
+                kp: 0.1,
+                ki: 0.01,
+                kd: 0.01,
+                ..Default::default()
+            },
+            rps_pid: PidParams {
+                kp: 0.25,
+                ki: 0.01,
+                kd: 0.01,
+                ..Default::default()
+            },
+            mem_pressure_target: 0.0,
+            mem_pid: PidParams {
+                kp: 0.05,
+                ki: 0.005,
+                kd: 0.0,
+                ..Default::default()
+            },
+            pid_reset: Default::default(),
+            converge_rps_frac: 0.1,
+            converge_lat_frac: 0.1,
+            converge_timeout_secs: 0.0,
+            converge_enter_frac: 1.0,
+            converge_exit_frac: 1.0,
+            assertions: vec![],
+            strict: false,
+            provenance: Default::default(),
+            degraded: Default::default(),
+        }
+    }
+}
+
+impl JsonLoad for Params {
+    fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let buf = std::fs::read_to_string(path)?;
+        let (_, body) = split_json_preamble(&buf);
+        Self::from_json_body(&body)
+    }
+
+    fn loaded(&mut self, prev: Option<&mut Self>) -> Result<()> {
+        self.migrate()?;
+        Self::clamp_frac(
+            "file_frac_min",
+            &mut self.file_frac_min,
+            &mut self.degraded.0,
+        );
+        if let Some(&(pct, target)) = self.lat_targets.first() {
+            self.lat_target_pct = pct;
+            self.lat_target = target;
+        }
+        for (idx, (pct, _)) in self.lat_target_list().iter().enumerate() {
+            if !(Self::LAT_TARGET_PCT_MIN..=Self::LAT_TARGET_PCT_MAX).contains(pct) {
+                let name = match self.lat_targets.is_empty() {
+                    true => "lat_target_pct".to_string(),
+                    false => format!("lat_targets[{}] percentile", idx),
+                };
+                bail!(
+                    "{} {} out of range [{}, {}], should be a fraction, not a percentage",
+                    name,
+                    pct,
+                    Self::LAT_TARGET_PCT_MIN,
+                    Self::LAT_TARGET_PCT_MAX
+                );
+            }
+        }
+        self.expand_anon_histogram_rle()?;
+        Self::verify_histogram("anon_histogram", &self.anon_histogram)?;
+        Self::verify_histogram("file_histogram", &self.file_histogram)?;
+        let conflicts = self.anon_histogram_conflicts();
+        if !conflicts.is_empty() {
+            let msg = format!(
+                "anon_histogram overrides {}, which are ignored",
+                conflicts.join(", ")
+            );
+            if self.strict {
+                bail!("{}", msg);
+            }
+            warn!("params: {}", msg);
+        }
+        if self.file_sync_every == 0 {
+            bail!("file_sync_every should be at least 1");
+        }
+        if self.write_cost_ratio.is_nan() || self.write_cost_ratio <= 0.0 {
+            bail!(
+                "write_cost_ratio {} should be positive",
+                self.write_cost_ratio
+            );
+        }
+        if self.file_size_dist == FileSizeDist::LogNormal
+            && (self.file_size_stdev_ratio.is_nan() || self.file_size_stdev_ratio < 0.0)
+        {
+            bail!(
+                "file_size_stdev_ratio {} should be non-negative for LogNormal",
+                self.file_size_stdev_ratio
+            );
+        }
+        for (name, pid) in &[
+            ("lat_pid", &self.lat_pid),
+            ("rps_pid", &self.rps_pid),
+            ("mem_pid", &self.mem_pid),
+        ] {
+            pid.validate(name)?;
+        }
+        if let Some(v) = self.cpu_ratio_min {
+            if v.is_nan() || v < 0.0 {
+                bail!("cpu_ratio_min {} should be non-negative", v);
+            }
+        }
+        if self.chunk_pages == 0 {
+            bail!("chunk_pages should be at least 1");
+        }
+        if self.dist_uniform_knee.is_nan() || self.dist_uniform_knee <= 0.0 {
+            bail!(
+                "dist_uniform_knee {} should be positive",
+                self.dist_uniform_knee
+            );
+        }
+        if self.acc_dist_slots > Self::ACC_DIST_SLOTS_MAX {
+            bail!(
+                "acc_dist_slots {} is over the maximum {}",
+                self.acc_dist_slots,
+                Self::ACC_DIST_SLOTS_MAX
+            );
+        }
+        for (name, v) in &[
+            ("addr_trunc_stdevs", self.addr_trunc_stdevs),
+            ("size_trunc_stdevs", self.size_trunc_stdevs),
+            ("file_frac_ramp_secs", self.file_frac_ramp_secs),
+            ("warmup_secs", self.warmup_secs),
+            ("burst_secs", self.burst_secs),
+            ("burst_period_secs", self.burst_period_secs),
+            ("converge_timeout_secs", self.converge_timeout_secs),
+            ("converge_enter_frac", self.converge_enter_frac),
+            ("converge_exit_frac", self.converge_exit_frac),
+            ("addr_drift_rate", self.addr_drift_rate),
+            ("log_fsync_secs", self.log_fsync_secs),
+            ("service_mean", self.service_mean),
+            ("service_stdev_ratio", self.service_stdev_ratio),
+            ("cpu_time_per_byte", self.cpu_time_per_byte),
+        ] {
+            if v.is_nan() || *v < 0.0 {
+                bail!("{} {} is negative", name, v);
+            }
+        }
+        self.normalize_sleep_modes()?;
+        self.verify_file_size_percentiles()?;
+        self.verify_lat_target_ramp()?;
+        self.verify_classes()?;
+        self.stress_schedule.verify()?;
+        for assertion in self.assertions.iter() {
+            assertion.verify()?;
+        }
+        if let Some(cpus) = self.cpu_affinity.as_ref() {
+            Self::verify_cpu_affinity(cpus)?;
+        }
+        // Traces can be large, only verify a new one. The dispatcher loads
+        // it for replay when trace_path changes.
+        let trace_changed = prev
+            .as_ref()
+            .map_or(true, |prev| prev.trace_path != self.trace_path);
+        if let (true, Some(path)) = (trace_changed, self.trace_path.as_ref()) {
+            AccessTrace::load(path)?;
+        }
+        self.floor_control_period();
+        for (name, v) in [
+            ("anon_frac", &mut self.anon_frac),
+            ("file_cache_frac", &mut self.file_cache_frac),
+        ] {
+            Self::clamp_frac(name, v, &mut self.degraded.0);
+        }
+        if self.has_decoupled_footprints() && self.anon_frac + self.file_cache_frac > 1.0 {
+            bail!(
+                "anon_frac {} and file_cache_frac {} add up to more than 1.0",
+                self.anon_frac,
+                self.file_cache_frac
+            );
+        }
+        if self.has_decoupled_footprints() {
+            self.mem_frac = self.anon_frac + self.file_cache_frac;
+            self.file_frac = self.file_cache_frac / self.mem_frac;
+        }
+        for (name, v) in [
+            ("mem_frac", &mut self.mem_frac),
+            ("file_frac", &mut self.file_frac),
+            ("file_total_frac", &mut self.file_total_frac),
+            ("file_addr_rps_base_frac", &mut self.file_addr_rps_base_frac),
+            ("file_write_frac", &mut self.file_write_frac),
+            ("anon_addr_rps_base_frac", &mut self.anon_addr_rps_base_frac),
+            ("anon_write_frac", &mut self.anon_write_frac),
+            ("sleep_jitter_frac", &mut self.sleep_jitter_frac),
+            ("lat_tail_weight", &mut self.lat_tail_weight),
+            ("converge_rps_frac", &mut self.converge_rps_frac),
+            ("converge_lat_frac", &mut self.converge_lat_frac),
+        ] {
+            Self::clamp_frac(name, v, &mut self.degraded.0);
+        }
+
+        // Raising file_frac to file_frac_min is what the latter asks for and
+        // doesn't count as degradation.
+        self.file_frac = self.file_frac.max(self.file_frac_min);
+        if self.file_total_frac < Self::FILE_FRAC_MIN {
+            let msg = format!(
+                "file_total_frac {} is lower than {}, clamping",
+                self.file_total_frac,
+                Self::FILE_FRAC_MIN
+            );
+            warn!("params: {}", msg);
+            self.degraded.0.push(msg);
+            self.file_total_frac = Self::FILE_FRAC_MIN;
+        }
+
+        for msg in self.validate().iter() {
+            warn!("params: {}", msg);
+        }
+
+        if let Some(prev) = prev {
+            match (prev.is_paused(), self.is_paused()) {
+                (false, true) => debug!("params: rps_target is 0, pausing"),
+                (true, false) => {
+                    debug!("params: Resuming at rps_target {}", self.base_rps_target())
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl JsonSave for Params {
+    fn preamble() -> Option<String> {
+        Some(PARAMS_DOC.to_string())
+    }
+
+    /// `PARAMS_DOC` followed by the fields which differ from the defaults.
+    fn preamble_of(&self) -> Option<String> {
+        let mut pre = PARAMS_DOC.to_string();
+        let diffs = Params::default().diff(self);
+        if !diffs.is_empty() {
+            for (field, _, new) in diffs.iter() {
+                pre += &format!("// overridden: {}={}\n", field, new);
+            }
+            pre += "//\n";
+        }
+        Some(pre)
+    }
+}
+
+impl TomlLoad for Params {}
+impl TomlSave for Params {}
+
+/// Resources which `Params` will demand on a given machine. See
+/// `Params::resource_plan()`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ResourcePlan {
+    pub resident_bytes: u64,
+    pub file_bytes: u64,
+    pub anon_bytes: u64,
+    pub peak_workers: u32,
+    pub log_bps: u64,
+}
+
+/// Result of `Params::lint()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LintReport {
+    /// `(field, as written, normalized)` for each field `loaded()` changed.
+    pub adjustments: Vec<(String, String, String)>,
+    /// `validate()` warnings on the normalized params.
+    pub warnings: Vec<String>,
+    /// Whether the file was rewritten.
+    pub fixed: bool,
+}
+
+impl LintReport {
+    pub fn is_clean(&self) -> bool {
+        self.adjustments.is_empty() && self.warnings.is_empty()
+    }
+}
+
+/// Footprints accessed at a given RPS. See `Params::working_set_bytes()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct WorkingSet {
+    pub file_bytes: u64,
+    pub anon_bytes: u64,
+}
+
+impl WorkingSet {
+    pub fn total(&self) -> u64 {
+        self.file_bytes + self.anon_bytes
+    }
+}
+
+/// Estimated access rates and footprints. See `Params::io_mix()`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IoMix {
+    pub file_read_bps: u64,
+    pub file_write_bps: u64,
+    pub anon_read_bps: u64,
+    pub anon_write_bps: u64,
+    pub file_bytes: u64,
+    pub anon_bytes: u64,
+}
+
+impl IoMix {
+    pub fn read_bps(&self) -> u64 {
+        self.file_read_bps + self.anon_read_bps
+    }
+
+    pub fn write_bps(&self) -> u64 {
+        self.file_write_bps + self.anon_write_bps
+    }
+}
+
+/// How far the measurements are from the targets. See
+/// `Params::convergence()`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConvergenceReport {
+    pub rps_target: u32,
+    pub rps: f64,
+    pub lat_target_pct: f64,
+    pub lat_target: f64,
+    pub lat: f64,
+    pub mem_frac: f64,
+    pub converged: bool,
+}
+
+/// Converged state with hysteresis so that it doesn't flap while the
+/// measurements hover around the tolerances. Entering requires the
+/// measurements to be within the tolerances scaled by `converge_enter_frac`
+/// and, once converged, only falling outside the ones scaled by
+/// `converge_exit_frac` leaves. With both at 1.0, this is the same as
+/// `Params::convergence()`.
+#[derive(Clone, Debug, Default)]
+pub struct ConvergenceTracker {
+    converged: bool,
+}
+
+impl ConvergenceTracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_converged(&self) -> bool {
+        self.converged
+    }
+
+    /// Feed the next measurements and return the report with the updated
+    /// converged state.
+    pub fn update(
+        &mut self,
+        params: &Params,
+        rps: f64,
+        lat: f64,
+        mem_frac: f64,
+    ) -> ConvergenceReport {
+        let scale = match self.converged {
+            true => params.converge_exit_frac,
+            false => params.converge_enter_frac,
+        };
+        let report = params.convergence_scaled(rps, lat, mem_frac, scale);
+        self.converged = report.converged;
+        report
+    }
+}
+
+/// `Params::pid_reset` request, only settable through
+/// `Params::request_pid_reset()` or a params file or patch so that it can't
+/// be left set by accident.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct PidReset(bool);
+
+/// Live measurement checked by a `ParamAssertion`. `Lat` is the latency fed
+/// to the controller, see `Latencies::ctl`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AssertField {
+    Rps,
+    Lat,
+    LatP99,
+    Concurrency,
+    MemFrac,
+}
+
+impl AssertField {
+    /// The parameter which the measurement is driven towards, used to scale
+    /// `of_target` thresholds. `rps_target`, `lat_target`,
+    /// `concurrency_max` and `mem_frac` respectively.
+    pub fn target(&self, params: &Params) -> f64 {
+        match self {
+            Self::Rps => params.base_rps_target() as f64,
+            Self::Lat | Self::LatP99 => params.lat_target,
+            Self::Concurrency => params.concurrency_max as f64,
+            Self::MemFrac => params.mem_frac,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AssertOp {
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = "<")]
+    Lt,
+}
+
+/// An invariant in `Params::assertions`: the measured `field` compared
+/// with `op` against `threshold`, which is a multiplier of the field's
+/// `AssertField::target()` if `of_target`. rd-hashd checks each every
+/// control period and counts the violations in `Stat::assertion_violations`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParamAssertion {
+    pub field: AssertField,
+    pub op: AssertOp,
+    pub threshold: f64,
+    #[serde(default)]
+    pub of_target: bool,
+}
+
+impl ParamAssertion {
+    fn verify(&self) -> Result<()> {
+        if !self.threshold.is_finite() {
+            bail!(
+                "assertions {:?} threshold {} should be finite",
+                self.field,
+                self.threshold
+            );
+        }
+        Ok(())
+    }
+
+    /// The threshold in the units of the measurement.
+    pub fn threshold_for(&self, params: &Params) -> f64 {
+        match self.of_target {
+            true => self.threshold * self.field.target(params),
+            false => self.threshold,
+        }
+    }
+
+    /// Whether `measured` satisfies the assertion. NaN never does.
+    pub fn holds(&self, params: &Params, measured: f64) -> bool {
+        let threshold = self.threshold_for(params);
+        match self.op {
+            AssertOp::Ge => measured >= threshold,
+            AssertOp::Gt => measured > threshold,
+            AssertOp::Le => measured <= threshold,
+            AssertOp::Lt => measured < threshold,
+        }
+    }
+}
+
+/// The layer which set a `Params` field, see `Params::provenance()`. Each
+/// layer overrides the ones before it in the order listed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Patch,
+}
+
+/// Per-field `Source` of `Params`, keyed by the field names of
+/// `ParamsPatch`. It only describes how the values were arrived at and
+/// doesn't take part in comparing params.
+#[derive(Clone, Debug)]
+pub struct Provenance(HashMap<String, Source>);
+
+impl Default for Provenance {
+    fn default() -> Self {
+        Self(
+            ParamsPatch::all_fields()
+                .into_iter()
+                .map(|field| (field, Source::Default))
+                .collect(),
+        )
+    }
+}
+
+impl PartialEq for Provenance {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// The fidelity-affecting clamps made while loading `Params`, see
+/// `Params::degraded()`. Like `Provenance`, it doesn't take part in
+/// comparing params.
+#[derive(Clone, Debug, Default)]
+pub struct Degraded(Vec<String>);
+
+impl PartialEq for Degraded {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// Sparse update to `Params`. Only the fields which are set are applied by
+/// `Params::apply_patch()`. Omitted keys deserialize to `None`. The fields
+/// which are optional in `Params` are `Option<Option<_>>` and `null` sets
+/// them back to `None`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParamsPatch {
+    #[serde(deserialize_with = "de_opt_duration")]
+    pub control_period: Option<f64>,
+    pub control_period_adaptive: Option<bool>,
+    #[serde(deserialize_with = "de_opt_duration")]
+    pub control_period_min: Option<f64>,
+    #[serde(alias = "concurrency")]
+    pub concurrency_max: Option<u32>,
+    pub concurrency_min: Option<u32>,
+    #[serde(alias = "lat_pct")]
+    pub lat_target_pct: Option<f64>,
+    #[serde(alias = "lat", deserialize_with = "de_opt_duration")]
+    pub lat_target: Option<f64>,
+    pub lat_targets: Option<Vec<(f64, f64)>>,
+    pub lat_tail_weight: Option<f64>,
+    #[serde(
+        deserialize_with = "de_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub lat_target_ramp: Option<Option<(f64, f64, f64)>>,
+    #[serde(alias = "rps")]
+    pub rps_target: Option<u32>,
+    pub rps_max: Option<u32>,
+    pub concurrency_ramp_max: Option<u32>,
+    pub queue_max: Option<u32>,
+    pub warmup_secs: Option<f64>,
+    pub burst_rps: Option<u32>,
+    pub burst_secs: Option<f64>,
+    pub burst_period_secs: Option<f64>,
+    pub stress_schedule: Option<StressSchedule>,
+    pub classes: Option<Vec<WorkloadClass>>,
+    pub mem_frac: Option<f64>,
+    pub mem_frac_ramp_secs: Option<f64>,
+    #[serde(deserialize_with = "de_opt_size")]
+    pub mem_balloon_bytes: Option<u64>,
+    #[serde(
+        deserialize_with = "de_nullable_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub mem_total_override: Option<Option<u64>>,
+    pub chunk_pages: Option<usize>,
+    pub file_frac: Option<f64>,
+    pub file_frac_ramp_secs: Option<f64>,
+    pub file_frac_min: Option<f64>,
+    pub file_total_frac: Option<f64>,
+    pub anon_frac: Option<f64>,
+    pub file_cache_frac: Option<f64>,
+    #[serde(deserialize_with = "de_opt_size")]
+    pub file_size_mean: Option<usize>,
+    pub file_size_stdev_ratio: Option<f64>,
+    pub file_size_dist: Option<FileSizeDist>,
+    pub file_size_percentiles: Option<Vec<(f64, usize)>>,
+    pub file_addr_stdev_ratio: Option<f64>,
+    pub file_addr_dist: Option<FileAddrDist>,
+    pub file_addr_rps_base_frac: Option<f64>,
+    pub file_write_frac: Option<f64>,
+    pub file_sync: Option<FileSync>,
+    pub file_sync_every: Option<u32>,
+    pub write_cost_ratio: Option<f64>,
+    pub file_direct: Option<bool>,
+    pub verify_reads: Option<bool>,
+    pub file_histogram: Option<Vec<u64>>,
+    #[serde(
+        deserialize_with = "de_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub trace_path: Option<Option<PathBuf>>,
+    pub anon_size_ratio: Option<f64>,
+    #[serde(
+        deserialize_with = "de_nullable_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub anon_size_mean: Option<Option<usize>>,
+    pub anon_size_stdev_ratio: Option<f64>,
+    pub anon_addr_stdev_ratio: Option<f64>,
+    pub anon_addr_rps_base_frac: Option<f64>,
+    pub anon_write_frac: Option<f64>,
+    pub anon_histogram: Option<Vec<u64>>,
+    pub anon_histogram_rle: Option<Vec<(usize, u64)>>,
+    pub addr_trunc_stdevs: Option<f64>,
+    pub size_trunc_stdevs: Option<f64>,
+    pub dist_uniform_knee: Option<f64>,
+    pub addr_drift_rate: Option<f64>,
+    #[serde(deserialize_with = "de_opt_duration")]
+    pub sleep_mean: Option<f64>,
+    pub sleep_stdev_ratio: Option<f64>,
+    pub sleep_modes: Option<Vec<(f64, f64, f64)>>,
+    pub sleep_jitter_frac: Option<f64>,
+    #[serde(deserialize_with = "de_opt_duration")]
+    pub service_mean: Option<f64>,
+    pub service_stdev_ratio: Option<f64>,
+    pub cpu_ratio: Option<f64>,
+    #[serde(
+        deserialize_with = "de_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cpu_ratio_min: Option<Option<f64>>,
+    pub cpu_time_per_byte: Option<f64>,
+    #[serde(deserialize_with = "de_opt_size")]
+    pub log_bps: Option<u64>,
+    #[serde(deserialize_with = "de_opt_size")]
+    pub log_max_bytes: Option<u64>,
+    pub log_fsync_secs: Option<f64>,
+    pub fake_cpu_load: Option<bool>,
+    pub hash_algo: Option<HashAlgo>,
+    pub acc_dist_slots: Option<usize>,
+    pub lat_hist_buckets: Option<usize>,
+    #[serde(
+        deserialize_with = "de_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cpu_affinity: Option<Option<Vec<usize>>>,
+    #[serde(
+        deserialize_with = "de_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub rng_seed: Option<Option<u64>>,
+    pub lat_pid: Option<PidParams>,
+    pub rps_pid: Option<PidParams>,
+    pub mem_pressure_target: Option<f64>,
+    pub mem_pid: Option<PidParams>,
+    pub pid_reset: Option<bool>,
+    pub converge_rps_frac: Option<f64>,
+    pub converge_lat_frac: Option<f64>,
+    pub converge_timeout_secs: Option<f64>,
+    pub converge_enter_frac: Option<f64>,
+    pub converge_exit_frac: Option<f64>,
+    pub assertions: Option<Vec<ParamAssertion>>,
+    pub strict: Option<bool>,
+}
+
+impl JsonLoad for ParamsPatch {}
+
+impl ParamsPatch {
+    /// The `Option<Option<_>>` fields. They're omitted when unset, so they
+    /// count as set even when `null`.
+    const NULLABLE: &'static [&'static str] = &[
+        "lat_target_ramp",
+        "mem_total_override",
+        "trace_path",
+        "anon_size_mean",
+        "cpu_ratio_min",
+        "cpu_affinity",
+        "rng_seed",
+    ];
+
+    /// Names of all the fields, set or not.
+    fn all_fields() -> Vec<String> {
+        match serde_json::to_value(Self::default()) {
+            Ok(Value::Object(map)) => map
+                .into_iter()
+                .map(|(k, _)| k)
+                .chain(Self::NULLABLE.iter().map(|f| f.to_string()))
+                .collect(),
+            _ => panic!("ParamsPatch didn't serialize into an object"),
+        }
+    }
+
+    /// Names of the fields which are set.
+    pub fn fields(&self) -> Vec<String> {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(map)) => map
+                .into_iter()
+                .filter(|(k, v)| !v.is_null() || Self::NULLABLE.contains(&k.as_str()))
+                .map(|(k, _)| k)
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// A layer in `ParamsProfiles` - `patch` on top of the `inherits` profile,
+/// or the defaults if there's none. The file form is a `ParamsPatch` with
+/// an optional `"inherits": "<name>"` key.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParamsProfile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inherits: Option<String>,
+    #[serde(flatten)]
+    pub patch: ParamsPatch,
+}
+
+impl JsonLoad for ParamsProfile {}
+
+/// Named `ParamsProfile`s, e.g. a base config and the overlays building on
+/// it.
+#[derive(Clone, Debug, Default)]
+pub struct ParamsProfiles {
+    profiles: BTreeMap<String, ParamsProfile>,
+}
+
+impl ParamsProfiles {
+    /// Register `profile` as `name`, returning the one it replaced.
+    pub fn insert(&mut self, name: &str, profile: ParamsProfile) -> Option<ParamsProfile> {
+        self.profiles.insert(name.to_string(), profile)
+    }
+
+    /// Load the profile file at `path` and register it as `name`.
+    pub fn load<P: AsRef<Path>>(&mut self, name: &str, path: P) -> Result<()> {
+        let profile = ParamsProfile::load(path)?;
+        self.insert(name, profile);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ParamsProfile> {
+        self.profiles.get(name)
+    }
+
+    /// The `Params` for `name` - the defaults with each profile in the
+    /// inheritance chain applied in turn, the furthest ancestor first.
+    pub fn resolve(&self, name: &str) -> Result<Params> {
+        let mut chain: Vec<&str> = vec![];
+        let mut cur = name;
+        loop {
+            if chain.contains(&cur) {
+                chain.push(cur);
+                bail!("params profile inheritance cycle: {}", chain.join(" -> "));
+            }
+            let profile = match self.get(cur) {
+                Some(v) => v,
+                None if chain.is_empty() => bail!("unknown params profile {:?}", cur),
+                None => bail!(
+                    "params profile {:?} inherits unknown profile {:?}",
+                    chain.last().unwrap(),
+                    cur
+                ),
+            };
+            chain.push(cur);
+            match profile.inherits.as_ref() {
+                Some(parent) => cur = parent,
+                None => break,
+            }
+        }
+
+        let mut params = Params::default();
+        for name in chain.iter().rev() {
+            params.apply_patch(&self.profiles[*name].patch)?;
+        }
+        Ok(params)
+    }
+}
+
+impl Params {
+    /// Overwrite the fields set in `patch` and re-apply the same clamping and
+    /// validation as loading from a file. On failure, `self` is left
+    /// untouched.
+    pub fn apply_patch(&mut self, patch: &ParamsPatch) -> Result<()> {
+        self.apply_patch_from(patch, Source::Patch)
+    }
+
+    fn apply_patch_from(&mut self, patch: &ParamsPatch, source: Source) -> Result<()> {
+        let mut next = self.clone();
+        next.overwrite_from_patch(patch, source);
+        next.loaded(Some(self))?;
+        *self = next;
+        Ok(())
+    }
+
+    fn overwrite_from_patch(&mut self, patch: &ParamsPatch, source: Source) {
+        for field in patch.fields() {
+            // The patched value replaces whatever was clamped before.
+            let prefix = format!("{} ", field);
+            self.degraded.0.retain(|r| !r.starts_with(&prefix));
+            self.provenance.0.insert(field, source);
+        }
+        if let Some(v) = patch.control_period {
+            self.control_period = v;
+        }
+        if let Some(v) = patch.control_period_adaptive {
+            self.control_period_adaptive = v;
+        }
+        if let Some(v) = patch.control_period_min {
+            self.control_period_min = v;
+        }
+        if let Some(v) = patch.concurrency_max {
+            self.concurrency_max = v;
+        }
+        if let Some(v) = patch.concurrency_min {
+            self.concurrency_min = v;
+        }
+        if let Some(v) = patch.lat_target_pct {
+            self.lat_target_pct = v;
+        }
+        if let Some(v) = patch.lat_target {
+            self.lat_target = v;
+        }
+        // lat_targets in the same patch wins, see loaded().
+        match patch.lat_targets.as_ref() {
+            Some(v) => self.lat_targets = v.clone(),
+            None => self.sync_lat_targets(),
+        }
+        if let Some(v) = patch.lat_tail_weight {
+            self.lat_tail_weight = v;
+        }
+        if let Some(v) = patch.lat_target_ramp {
+            self.lat_target_ramp = v;
+        }
+        if let Some(v) = patch.rps_target {
+            self.rps_target = v;
+        }
+        if let Some(v) = patch.rps_max {
+            self.rps_max = v;
+        }
+        if let Some(v) = patch.concurrency_ramp_max {
+            self.concurrency_ramp_max = v;
+        }
+        if let Some(v) = patch.queue_max {
+            self.queue_max = v;
+        }
+        if let Some(v) = patch.warmup_secs {
+            self.warmup_secs = v;
+        }
+        if let Some(v) = patch.burst_rps {
+            self.burst_rps = v;
+        }
+        if let Some(v) = patch.burst_secs {
+            self.burst_secs = v;
+        }
+        if let Some(v) = patch.burst_period_secs {
+            self.burst_period_secs = v;
+        }
+        if let Some(v) = patch.stress_schedule.as_ref() {
+            self.stress_schedule = v.clone();
+        }
+        if let Some(v) = patch.classes.as_ref() {
+            self.classes = v.clone();
+        }
+        if let Some(v) = patch.mem_frac {
+            self.mem_frac = v;
+        }
+        if let Some(v) = patch.mem_frac_ramp_secs {
+            self.mem_frac_ramp_secs = v;
+        }
+        if let Some(v) = patch.mem_balloon_bytes {
+            self.mem_balloon_bytes = v;
+        }
+        if let Some(v) = patch.mem_total_override {
+            self.mem_total_override = v;
+        }
+        if let Some(v) = patch.chunk_pages {
+            self.chunk_pages = v;
+        }
+        if let Some(v) = patch.file_frac {
+            self.file_frac = v;
+        }
+        if let Some(v) = patch.file_frac_ramp_secs {
+            self.file_frac_ramp_secs = v;
+        }
+        if let Some(v) = patch.anon_frac {
+            self.anon_frac = v;
+        }
+        if let Some(v) = patch.file_cache_frac {
+            self.file_cache_frac = v;
+        }
+        if let Some(v) = patch.file_frac_min {
+            self.file_frac_min = v;
+        }
+        if let Some(v) = patch.file_total_frac {
+            self.file_total_frac = v;
+        }
+        if let Some(v) = patch.file_size_mean {
+            self.file_size_mean = v;
+        }
+        if let Some(v) = patch.file_size_stdev_ratio {
+            self.file_size_stdev_ratio = v;
+        }
+        if let Some(v) = patch.file_size_dist {
+            self.file_size_dist = v;
+        }
+        if let Some(v) = patch.file_size_percentiles.as_ref() {
+            self.file_size_percentiles = v.clone();
+        }
+        if let Some(v) = patch.file_addr_stdev_ratio {
+            self.file_addr_stdev_ratio = v;
+        }
+        if let Some(v) = patch.file_addr_dist {
+            self.file_addr_dist = v;
+        }
+        if let Some(v) = patch.file_addr_rps_base_frac {
+            self.file_addr_rps_base_frac = v;
+        }
+        if let Some(v) = patch.file_write_frac {
+            self.file_write_frac = v;
+        }
+        if let Some(v) = patch.file_sync {
+            self.file_sync = v;
+        }
+        if let Some(v) = patch.file_sync_every {
+            self.file_sync_every = v;
+        }
+        if let Some(v) = patch.write_cost_ratio {
+            self.write_cost_ratio = v;
+        }
+        if let Some(v) = patch.file_direct {
+            self.file_direct = v;
+        }
+        if let Some(v) = patch.verify_reads {
+            self.verify_reads = v;
+        }
+        if let Some(v) = patch.file_histogram.as_ref() {
+            self.file_histogram = v.clone();
+        }
+        if let Some(v) = patch.trace_path.as_ref() {
+            self.trace_path = v.clone();
+        }
+        if let Some(v) = patch.anon_size_ratio {
+            self.anon_size_ratio = v;
+        }
+        if let Some(v) = patch.anon_size_mean {
+            self.anon_size_mean = v;
+        }
+        if let Some(v) = patch.anon_size_stdev_ratio {
+            self.anon_size_stdev_ratio = v;
+        }
+        if let Some(v) = patch.anon_addr_stdev_ratio {
+            self.anon_addr_stdev_ratio = v;
+        }
+        if let Some(v) = patch.addr_trunc_stdevs {
+            self.addr_trunc_stdevs = v;
+        }
+        if let Some(v) = patch.size_trunc_stdevs {
+            self.size_trunc_stdevs = v;
+        }
+        if let Some(v) = patch.dist_uniform_knee {
+            self.dist_uniform_knee = v;
+        }
+        if let Some(v) = patch.addr_drift_rate {
+            self.addr_drift_rate = v;
+        }
+        if let Some(v) = patch.anon_addr_rps_base_frac {
+            self.anon_addr_rps_base_frac = v;
+        }
+        if let Some(v) = patch.anon_write_frac {
+            self.anon_write_frac = v;
+        }
+        if let Some(v) = patch.anon_histogram.as_ref() {
+            self.anon_histogram = v.clone();
+        }
+        if let Some(v) = patch.anon_histogram_rle.as_ref() {
+            self.anon_histogram.clear();
+            self.anon_histogram_rle = v.clone();
+        }
+        if let Some(v) = patch.sleep_mean {
+            self.sleep_mean = v;
+        }
+        if let Some(v) = patch.sleep_stdev_ratio {
+            self.sleep_stdev_ratio = v;
+        }
+        if let Some(v) = patch.sleep_modes.as_ref() {
+            self.sleep_modes = v.clone();
+        }
+        if let Some(v) = patch.sleep_jitter_frac {
+            self.sleep_jitter_frac = v;
+        }
+        if let Some(v) = patch.service_mean {
+            self.service_mean = v;
+        }
+        if let Some(v) = patch.service_stdev_ratio {
+            self.service_stdev_ratio = v;
+        }
+        if let Some(v) = patch.cpu_ratio {
+            self.cpu_ratio = v;
+        }
+        if let Some(v) = patch.cpu_ratio_min {
+            self.cpu_ratio_min = v;
+        }
+        if let Some(v) = patch.cpu_time_per_byte {
+            self.cpu_time_per_byte = v;
+        }
+        if let Some(v) = patch.log_bps {
+            self.log_bps = v;
+        }
+        if let Some(v) = patch.log_max_bytes {
+            self.log_max_bytes = v;
+        }
+        if let Some(v) = patch.log_fsync_secs {
+            self.log_fsync_secs = v;
+        }
+        if let Some(v) = patch.fake_cpu_load {
+            self.fake_cpu_load = v;
+        }
+        if let Some(v) = patch.hash_algo {
+            self.hash_algo = v;
+        }
+        if let Some(v) = patch.acc_dist_slots {
+            self.acc_dist_slots = v;
+        }
+        if let Some(v) = patch.lat_hist_buckets {
+            self.lat_hist_buckets = v;
+        }
+        if let Some(v) = patch.cpu_affinity.as_ref() {
+            self.cpu_affinity = v.clone();
+        }
+        if let Some(v) = patch.rng_seed {
+            self.rng_seed = v;
+        }
+        if let Some(v) = patch.lat_pid.as_ref() {
+            self.lat_pid = v.clone();
+        }
+        if let Some(v) = patch.rps_pid.as_ref() {
+            self.rps_pid = v.clone();
+        }
+        if let Some(v) = patch.mem_pressure_target {
+            self.mem_pressure_target = v;
+        }
+        if let Some(v) = patch.mem_pid.as_ref() {
+            self.mem_pid = v.clone();
+        }
+        if let Some(v) = patch.pid_reset {
+            self.pid_reset = PidReset(v);
+        }
+        if let Some(v) = patch.converge_rps_frac {
+            self.converge_rps_frac = v;
+        }
+        if let Some(v) = patch.converge_lat_frac {
+            self.converge_lat_frac = v;
+        }
+        if let Some(v) = patch.converge_timeout_secs {
+            self.converge_timeout_secs = v;
+        }
+        if let Some(v) = patch.converge_enter_frac {
+            self.converge_enter_frac = v;
+        }
+        if let Some(v) = patch.converge_exit_frac {
+            self.converge_exit_frac = v;
+        }
+        if let Some(v) = patch.assertions.as_ref() {
+            self.assertions = v.clone();
+        }
+        if let Some(v) = patch.strict {
+            self.strict = v;
+        }
+    }
+
+    /// Apply environment variables named `prefix` followed by an upper-cased
+    /// field name, e.g. `RD_HASHD_RPS_TARGET` for `rps_target` with prefix
+    /// `RD_HASHD_`, as a `ParamsPatch`. Values are parsed as JSON and taken
+    /// as strings if that fails, so both `RD_HASHD_LAT_TARGET=0.05` and
+    /// `RD_HASHD_LAT_TARGET=50ms` work. Fails naming the variable if it
+    /// doesn't match a field or its value can't be parsed.
+    pub fn apply_env_overrides(&mut self, prefix: &str) -> Result<()> {
+        let fields = ParamsPatch::all_fields();
+
+        let mut vars: Vec<(String, String)> = std::env::vars()
+            .filter(|(name, _)| name.starts_with(prefix) && name.len() > prefix.len())
+            .collect();
+        vars.sort();
+
+        let mut overrides = serde_json::Map::new();
+        for (name, val) in vars.iter() {
+            let field = name[prefix.len()..].to_lowercase();
+            if !fields.contains(&field) {
+                bail!("{}: unknown params field {:?}", name, &field);
+            }
+            let val = serde_json::from_str::<Value>(val).unwrap_or_else(|_| json!(val));
+            let single = json!({ field.clone(): val.clone() });
+            if let Err(e) = serde_json::from_value::<ParamsPatch>(single) {
+                bail!("{}: failed to parse {:?} ({})", name, &val, &e);
+            }
+            overrides.insert(field, val);
+        }
+
+        let patch: ParamsPatch = serde_json::from_value(Value::Object(overrides))?;
+        self.apply_patch_from(&patch, Source::Env)
+    }
+}
+
+/// Chainable construction of `Params` starting from the defaults. `build()`
+/// applies the same clamping and validation as loading from a file.
+#[derive(Clone, Debug, Default)]
+pub struct ParamsBuilder {
+    params: Params,
+}
+
+impl ParamsBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn rps_target(mut self, v: u32) -> Self {
+        self.params.rps_target = v;
+        self
+    }
+
+    pub fn rps_max(mut self, v: u32) -> Self {
+        self.params.rps_max = v;
+        self
+    }
+
+    pub fn lat_target(mut self, v: f64) -> Self {
+        self.params.lat_target = v;
+        self
+    }
+
+    pub fn lat_target_pct(mut self, v: f64) -> Self {
+        self.params.lat_target_pct = v;
+        self
+    }
+
+    pub fn mem_frac(mut self, v: f64) -> Self {
+        self.params.mem_frac = v;
+        self
+    }
+
+    pub fn file_frac(mut self, v: f64) -> Self {
+        self.params.file_frac = v;
+        self
+    }
+
+    pub fn concurrency_max(mut self, v: u32) -> Self {
+        self.params.concurrency_max = v;
+        self
+    }
+
+    pub fn build(self) -> Result<Params> {
+        let mut params = self.params;
+        params.loaded(None)?;
+        Ok(params)
+    }
+}
+
+/// Compact summary of the salient `Params` knobs for benchmark results.
+/// Unlike `Params`, it only grows deliberately, and bulky fields such as the
+/// histograms are represented by their lengths.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ParamsSummary {
+    pub version: u32,
+    pub rps_target: u32,
+    pub rps_max: u32,
+    pub lat_targets: Vec<(f64, f64)>,
+    pub concurrency_min: u32,
+    pub concurrency_max: u32,
+    pub mem_frac: f64,
+    pub file_frac: f64,
+    pub file_total_frac: f64,
+    pub file_write_frac: f64,
+    pub anon_write_frac: f64,
+    pub file_histogram_len: usize,
+    pub anon_histogram_len: usize,
+    pub classes_len: usize,
+}
+
+impl From<&Params> for ParamsSummary {
+    fn from(params: &Params) -> Self {
+        Self {
+            version: params.version,
+            rps_target: params.rps_target,
+            rps_max: params.rps_max,
+            lat_targets: params.lat_target_list(),
+            concurrency_min: params.concurrency_min,
+            concurrency_max: params.concurrency_max,
+            mem_frac: params.mem_frac,
+            file_frac: params.file_frac,
+            file_total_frac: params.file_total_frac,
+            file_write_frac: params.file_write_frac,
+            anon_write_frac: params.anon_write_frac,
+            file_histogram_len: params.file_histogram.len(),
+            anon_histogram_len: params.anon_histogram.len(),
+            classes_len: params.classes.len(),
+        }
+    }
+}
+
+/// Access to `Params` fields in human units for display and interactive
+/// editing. The setters convert back and store canonical units.
+pub struct ParamsView<'a> {
+    params: &'a mut Params,
+}
+
+impl<'a> ParamsView<'a> {
+    pub fn new(params: &'a mut Params) -> Self {
+        Self { params }
+    }
+
+    pub fn lat_target_ms(&self) -> f64 {
+        self.params.lat_target * TO_MSEC
+    }
+
+    /// Also updates the matching `lat_targets` entry so that the new target
+    /// survives reloading.
+    pub fn set_lat_target_ms(&mut self, v: f64) {
+        self.params.lat_target = v * MSEC;
+        self.params.sync_lat_targets();
+    }
+
+    pub fn file_size_mean_mib(&self) -> f64 {
+        to_mb(self.params.file_size_mean)
+    }
+
+    pub fn set_file_size_mean_mib(&mut self, v: f64) {
+        self.params.file_size_mean = (v * (1 << 20) as f64).round().max(0.0) as usize;
+    }
+
+    pub fn log_mbps(&self) -> f64 {
+        to_mb(self.params.log_bps)
+    }
+
+    pub fn set_log_mbps(&mut self, v: f64) {
+        self.params.log_bps = (v * (1 << 20) as f64).round().max(0.0) as u64;
+    }
+}
+
+/// Numeric `Params` fields for generic get and set tooling, e.g. UIs and
+/// CLIs which shouldn't hardcode the field names. Values are plain `f64`s in
+/// the canonical units, seconds and bytes. `set()` rounds for the integer
+/// fields and doesn't clamp or verify, follow up with `apply_patch()` or
+/// `loaded()` for that.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ParamField {
+    ControlPeriod,
+    ControlPeriodMin,
+    ConcurrencyMax,
+    ConcurrencyMin,
+    LatTargetPct,
+    LatTarget,
+    LatTailWeight,
+    RpsTarget,
+    RpsMax,
+    ConcurrencyRampMax,
+    QueueMax,
+    WarmupSecs,
+    BurstRps,
+    BurstSecs,
+    BurstPeriodSecs,
+    MemFrac,
+    MemFracRampSecs,
+    MemBalloonBytes,
+    ChunkPages,
+    FileFrac,
+    FileFracRampSecs,
+    FileFracMin,
+    FileTotalFrac,
+    AnonFrac,
+    FileCacheFrac,
+    FileSizeMean,
+    FileSizeStdevRatio,
+    FileAddrStdevRatio,
+    FileAddrRpsBaseFrac,
+    FileWriteFrac,
+    FileSyncEvery,
+    WriteCostRatio,
+    AnonSizeRatio,
+    AnonSizeStdevRatio,
+    AnonAddrStdevRatio,
+    AnonAddrRpsBaseFrac,
+    AnonWriteFrac,
+    AddrTruncStdevs,
+    SizeTruncStdevs,
+    DistUniformKnee,
+    AddrDriftRate,
+    SleepMean,
+    SleepStdevRatio,
+    SleepJitterFrac,
+    ServiceMean,
+    ServiceStdevRatio,
+    CpuRatio,
+    CpuTimePerByte,
+    LogBps,
+    LogMaxBytes,
+    LogFsyncSecs,
+    AccDistSlots,
+    LatHistBuckets,
+    MemPressureTarget,
+    ConvergeRpsFrac,
+    ConvergeLatFrac,
+    ConvergeTimeoutSecs,
+    ConvergeEnterFrac,
+    ConvergeExitFrac,
+}
+
+impl ParamField {
+    pub const ALL: &'static [ParamField] = &[
+        Self::ControlPeriod,
+        Self::ControlPeriodMin,
+        Self::ConcurrencyMax,
+        Self::ConcurrencyMin,
+        Self::LatTargetPct,
+        Self::LatTarget,
+        Self::LatTailWeight,
+        Self::RpsTarget,
+        Self::RpsMax,
+        Self::ConcurrencyRampMax,
+        Self::QueueMax,
+        Self::WarmupSecs,
+        Self::BurstRps,
+        Self::BurstSecs,
+        Self::BurstPeriodSecs,
+        Self::MemFrac,
+        Self::MemFracRampSecs,
+        Self::MemBalloonBytes,
+        Self::ChunkPages,
+        Self::FileFrac,
+        Self::FileFracRampSecs,
+        Self::FileFracMin,
+        Self::FileTotalFrac,
+        Self::AnonFrac,
+        Self::FileCacheFrac,
+        Self::FileSizeMean,
+        Self::FileSizeStdevRatio,
+        Self::FileAddrStdevRatio,
+        Self::FileAddrRpsBaseFrac,
+        Self::FileWriteFrac,
+        Self::FileSyncEvery,
+        Self::WriteCostRatio,
+        Self::AnonSizeRatio,
+        Self::AnonSizeStdevRatio,
+        Self::AnonAddrStdevRatio,
+        Self::AnonAddrRpsBaseFrac,
+        Self::AnonWriteFrac,
+        Self::AddrTruncStdevs,
+        Self::SizeTruncStdevs,
+        Self::DistUniformKnee,
+        Self::AddrDriftRate,
+        Self::SleepMean,
+        Self::SleepStdevRatio,
+        Self::SleepJitterFrac,
+        Self::ServiceMean,
+        Self::ServiceStdevRatio,
+        Self::CpuRatio,
+        Self::CpuTimePerByte,
+        Self::LogBps,
+        Self::LogMaxBytes,
+        Self::LogFsyncSecs,
+        Self::AccDistSlots,
+        Self::LatHistBuckets,
+        Self::MemPressureTarget,
+        Self::ConvergeRpsFrac,
+        Self::ConvergeLatFrac,
+        Self::ConvergeTimeoutSecs,
+        Self::ConvergeEnterFrac,
+        Self::ConvergeExitFrac,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ControlPeriod => "control_period",
+            Self::ControlPeriodMin => "control_period_min",
+            Self::ConcurrencyMax => "concurrency_max",
+            Self::ConcurrencyMin => "concurrency_min",
+            Self::LatTargetPct => "lat_target_pct",
+            Self::LatTarget => "lat_target",
+            Self::LatTailWeight => "lat_tail_weight",
+            Self::RpsTarget => "rps_target",
+            Self::RpsMax => "rps_max",
+            Self::ConcurrencyRampMax => "concurrency_ramp_max",
+            Self::QueueMax => "queue_max",
+            Self::WarmupSecs => "warmup_secs",
+            Self::BurstRps => "burst_rps",
+            Self::BurstSecs => "burst_secs",
+            Self::BurstPeriodSecs => "burst_period_secs",
+            Self::MemFrac => "mem_frac",
+            Self::MemFracRampSecs => "mem_frac_ramp_secs",
+            Self::MemBalloonBytes => "mem_balloon_bytes",
+            Self::ChunkPages => "chunk_pages",
+            Self::FileFrac => "file_frac",
+            Self::FileFracRampSecs => "file_frac_ramp_secs",
+            Self::FileFracMin => "file_frac_min",
+            Self::FileTotalFrac => "file_total_frac",
+            Self::AnonFrac => "anon_frac",
+            Self::FileCacheFrac => "file_cache_frac",
+            Self::FileSizeMean => "file_size_mean",
+            Self::FileSizeStdevRatio => "file_size_stdev_ratio",
+            Self::FileAddrStdevRatio => "file_addr_stdev_ratio",
+            Self::FileAddrRpsBaseFrac => "file_addr_rps_base_frac",
+            Self::FileWriteFrac => "file_write_frac",
+            Self::FileSyncEvery => "file_sync_every",
+            Self::WriteCostRatio => "write_cost_ratio",
+            Self::AnonSizeRatio => "anon_size_ratio",
+            Self::AnonSizeStdevRatio => "anon_size_stdev_ratio",
+            Self::AnonAddrStdevRatio => "anon_addr_stdev_ratio",
+            Self::AnonAddrRpsBaseFrac => "anon_addr_rps_base_frac",
+            Self::AnonWriteFrac => "anon_write_frac",
+            Self::AddrTruncStdevs => "addr_trunc_stdevs",
+            Self::SizeTruncStdevs => "size_trunc_stdevs",
+            Self::DistUniformKnee => "dist_uniform_knee",
+            Self::AddrDriftRate => "addr_drift_rate",
+            Self::SleepMean => "sleep_mean",
+            Self::SleepStdevRatio => "sleep_stdev_ratio",
+            Self::SleepJitterFrac => "sleep_jitter_frac",
+            Self::ServiceMean => "service_mean",
+            Self::ServiceStdevRatio => "service_stdev_ratio",
+            Self::CpuRatio => "cpu_ratio",
+            Self::CpuTimePerByte => "cpu_time_per_byte",
+            Self::LogBps => "log_bps",
+            Self::LogMaxBytes => "log_max_bytes",
+            Self::LogFsyncSecs => "log_fsync_secs",
+            Self::AccDistSlots => "acc_dist_slots",
+            Self::LatHistBuckets => "lat_hist_buckets",
+            Self::MemPressureTarget => "mem_pressure_target",
+            Self::ConvergeRpsFrac => "converge_rps_frac",
+            Self::ConvergeLatFrac => "converge_lat_frac",
+            Self::ConvergeTimeoutSecs => "converge_timeout_secs",
+            Self::ConvergeEnterFrac => "converge_enter_frac",
+            Self::ConvergeExitFrac => "converge_exit_frac",
+        }
+    }
+
+    pub fn get(&self, params: &Params) -> f64 {
+        match self {
+            Self::ControlPeriod => params.control_period,
+            Self::ControlPeriodMin => params.control_period_min,
+            Self::ConcurrencyMax => params.concurrency_max as f64,
+            Self::ConcurrencyMin => params.concurrency_min as f64,
+            Self::LatTargetPct => params.lat_target_pct,
+            Self::LatTarget => params.lat_target,
+            Self::LatTailWeight => params.lat_tail_weight,
+            Self::RpsTarget => params.rps_target as f64,
+            Self::RpsMax => params.rps_max as f64,
+            Self::ConcurrencyRampMax => params.concurrency_ramp_max as f64,
+            Self::QueueMax => params.queue_max as f64,
+            Self::WarmupSecs => params.warmup_secs,
+            Self::BurstRps => params.burst_rps as f64,
+            Self::BurstSecs => params.burst_secs,
+            Self::BurstPeriodSecs => params.burst_period_secs,
+            Self::MemFrac => params.mem_frac,
+            Self::MemFracRampSecs => params.mem_frac_ramp_secs,
+            Self::MemBalloonBytes => params.mem_balloon_bytes as f64,
+            Self::ChunkPages => params.chunk_pages as f64,
+            Self::FileFrac => params.file_frac,
+            Self::FileFracRampSecs => params.file_frac_ramp_secs,
+            Self::FileFracMin => params.file_frac_min,
+            Self::FileTotalFrac => params.file_total_frac,
+            Self::AnonFrac => params.anon_frac,
+            Self::FileCacheFrac => params.file_cache_frac,
+            Self::FileSizeMean => params.file_size_mean as f64,
+            Self::FileSizeStdevRatio => params.file_size_stdev_ratio,
+            Self::FileAddrStdevRatio => params.file_addr_stdev_ratio,
+            Self::FileAddrRpsBaseFrac => params.file_addr_rps_base_frac,
+            Self::FileWriteFrac => params.file_write_frac,
+            Self::FileSyncEvery => params.file_sync_every as f64,
+            Self::WriteCostRatio => params.write_cost_ratio,
+            Self::AnonSizeRatio => params.anon_size_ratio,
+            Self::AnonSizeStdevRatio => params.anon_size_stdev_ratio,
+            Self::AnonAddrStdevRatio => params.anon_addr_stdev_ratio,
+            Self::AnonAddrRpsBaseFrac => params.anon_addr_rps_base_frac,
+            Self::AnonWriteFrac => params.anon_write_frac,
+            Self::AddrTruncStdevs => params.addr_trunc_stdevs,
+            Self::SizeTruncStdevs => params.size_trunc_stdevs,
+            Self::DistUniformKnee => params.dist_uniform_knee,
+            Self::AddrDriftRate => params.addr_drift_rate,
+            Self::SleepMean => params.sleep_mean,
+            Self::SleepStdevRatio => params.sleep_stdev_ratio,
+            Self::SleepJitterFrac => params.sleep_jitter_frac,
+            Self::ServiceMean => params.service_mean,
+            Self::ServiceStdevRatio => params.service_stdev_ratio,
+            Self::CpuRatio => params.cpu_ratio,
+            Self::CpuTimePerByte => params.cpu_time_per_byte,
+            Self::LogBps => params.log_bps as f64,
+            Self::LogMaxBytes => params.log_max_bytes as f64,
+            Self::LogFsyncSecs => params.log_fsync_secs,
+            Self::AccDistSlots => params.acc_dist_slots as f64,
+            Self::LatHistBuckets => params.lat_hist_buckets as f64,
+            Self::MemPressureTarget => params.mem_pressure_target,
+            Self::ConvergeRpsFrac => params.converge_rps_frac,
+            Self::ConvergeLatFrac => params.converge_lat_frac,
+            Self::ConvergeTimeoutSecs => params.converge_timeout_secs,
+            Self::ConvergeEnterFrac => params.converge_enter_frac,
+            Self::ConvergeExitFrac => params.converge_exit_frac,
+        }
+    }
+
+    pub fn set(&self, params: &mut Params, v: f64) {
+        match self {
+            Self::ControlPeriod => params.control_period = v,
+            Self::ControlPeriodMin => params.control_period_min = v,
+            Self::ConcurrencyMax => params.concurrency_max = v.round() as u32,
+            Self::ConcurrencyMin => params.concurrency_min = v.round() as u32,
+            Self::LatTargetPct => {
+                params.lat_target_pct = v;
+                params.sync_lat_targets();
+            }
+            Self::LatTarget => {
+                params.lat_target = v;
+                params.sync_lat_targets();
+            }
+            Self::LatTailWeight => params.lat_tail_weight = v,
+            Self::RpsTarget => params.rps_target = v.round() as u32,
+            Self::RpsMax => params.rps_max = v.round() as u32,
+            Self::ConcurrencyRampMax => params.concurrency_ramp_max = v.round() as u32,
+            Self::QueueMax => params.queue_max = v.round() as u32,
+            Self::WarmupSecs => params.warmup_secs = v,
+            Self::BurstRps => params.burst_rps = v.round() as u32,
+            Self::BurstSecs => params.burst_secs = v,
+            Self::BurstPeriodSecs => params.burst_period_secs = v,
+            Self::MemFrac => params.mem_frac = v,
+            Self::MemFracRampSecs => params.mem_frac_ramp_secs = v,
+            Self::MemBalloonBytes => params.mem_balloon_bytes = v.round() as u64,
+            Self::ChunkPages => params.chunk_pages = v.round() as usize,
+            Self::FileFrac => params.file_frac = v,
+            Self::FileFracRampSecs => params.file_frac_ramp_secs = v,
+            Self::FileFracMin => params.file_frac_min = v,
+            Self::FileTotalFrac => params.file_total_frac = v,
+            Self::AnonFrac => params.anon_frac = v,
+            Self::FileCacheFrac => params.file_cache_frac = v,
+            Self::FileSizeMean => params.file_size_mean = v.round() as usize,
+            Self::FileSizeStdevRatio => params.file_size_stdev_ratio = v,
+            Self::FileAddrStdevRatio => params.file_addr_stdev_ratio = v,
+            Self::FileAddrRpsBaseFrac => params.file_addr_rps_base_frac = v,
+            Self::FileWriteFrac => params.file_write_frac = v,
+            Self::FileSyncEvery => params.file_sync_every = v.round() as u32,
+            Self::WriteCostRatio => params.write_cost_ratio = v,
+            Self::AnonSizeRatio => params.anon_size_ratio = v,
+            Self::AnonSizeStdevRatio => params.anon_size_stdev_ratio = v,
+            Self::AnonAddrStdevRatio => params.anon_addr_stdev_ratio = v,
+            Self::AnonAddrRpsBaseFrac => params.anon_addr_rps_base_frac = v,
+            Self::AnonWriteFrac => params.anon_write_frac = v,
+            Self::AddrTruncStdevs => params.addr_trunc_stdevs = v,
+            Self::SizeTruncStdevs => params.size_trunc_stdevs = v,
+            Self::DistUniformKnee => params.dist_uniform_knee = v,
+            Self::AddrDriftRate => params.addr_drift_rate = v,
+            Self::SleepMean => params.sleep_mean = v,
+            Self::SleepStdevRatio => params.sleep_stdev_ratio = v,
+            Self::SleepJitterFrac => params.sleep_jitter_frac = v,
+            Self::ServiceMean => params.service_mean = v,
+            Self::ServiceStdevRatio => params.service_stdev_ratio = v,
+            Self::CpuRatio => params.cpu_ratio = v,
+            Self::CpuTimePerByte => params.cpu_time_per_byte = v,
+            Self::LogBps => params.log_bps = v.round() as u64,
+            Self::LogMaxBytes => params.log_max_bytes = v.round() as u64,
+            Self::LogFsyncSecs => params.log_fsync_secs = v,
+            Self::AccDistSlots => params.acc_dist_slots = v.round() as usize,
+            Self::LatHistBuckets => params.lat_hist_buckets = v.round() as usize,
+            Self::MemPressureTarget => params.mem_pressure_target = v,
+            Self::ConvergeRpsFrac => params.converge_rps_frac = v,
+            Self::ConvergeLatFrac => params.converge_lat_frac = v,
+            Self::ConvergeTimeoutSecs => params.converge_timeout_secs = v,
+            Self::ConvergeEnterFrac => params.converge_enter_frac = v,
+            Self::ConvergeExitFrac => params.converge_exit_frac = v,
+        }
+    }
+}
+
+impl std::str::FromStr for ParamField {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Self::ALL.iter().find(|field| field.as_str() == s) {
+            Some(field) => Ok(*field),
+            None => bail!("unknown numeric params field {:?}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AssertField, AssertOp, ConvergenceReport, ConvergenceTracker, FileAddrDist, FileSizeDist,
+        FileSync, HashAlgo, HistogramDelta, IoMix, LintReport, ParamAssertion, ParamField, Params,
+        ParamsBuilder, ParamsPatch, ParamsProfile, ParamsProfiles, ParamsSummary, ParamsView,
+        PidParams, ResourcePlan, Source, StressSchedule, WorkingSet, WorkloadClass, PARAMS_DOC,
+    };
+    use rd_util::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn load_params_str(body: &str) -> anyhow::Result<Params> {
+        static SEQ: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "rd-hashd-intf-test-params-{}-{}.json",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, body).unwrap();
+        let result = JsonConfigFile::<Params>::load(&path).map(|f| f.data);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_json_comments() {
+        let p = load_params_str(
+            r#"// preamble
+            {
+                "rps_target": 800, // inline
+                /* block */ "mem_frac": 0.5,
+                "file_frac": /* spanning
+                   lines */ 0.3,
+                "lat_target": "50ms"
+            } // trailing
+            "#,
+        )
+        .unwrap();
+        assert_eq!((p.rps_target, p.mem_frac, p.file_frac), (800, 0.5, 0.3));
+        assert_eq!(p.lat_target, 0.05);
+
+        // A saved file with comments added by hand round-trips.
+        let json = Params {
+            rps_target: 321,
+            ..Default::default()
+        }
+        .as_json()
+        .unwrap()
+        .replace(
+            r#""rps_target": 321,"#,
+            r#""rps_target": 321, // tuned /* on the test box */"#,
+        );
+        assert!(json.contains("// tuned"));
+        assert_eq!(load_params_str(&json).unwrap().rps_target, 321);
+
+        // Comment markers in strings are kept and line numbers preserved.
+        let body = "{ \"a\": \"http://x/*y*/\", // c\n \"b\": \"\\\"//\" /* d\n */ }";
+        let stripped = strip_json_comments(body);
+        println!("{}", &stripped);
+        assert_eq!(stripped.len(), body.len());
+        assert_eq!(stripped.lines().count(), body.lines().count());
+        let v: serde_json::Value = serde_json::from_str(&stripped).unwrap();
+        assert_eq!(v["a"], "http://x/*y*/");
+        assert_eq!(v["b"], "\"//");
+
+        assert!(load_params_str(r#"{ "rps_target": 800 /* unterminated }"#).is_err());
+    }
+
+    #[test]
+    fn test_lat_target_pct_range() {
+        let e = load_params_str(r#"{ "lat_target_pct": 95.0 }"#).unwrap_err();
+        println!("lat_target_pct=95.0: {}", &e);
+        assert!(e.to_string().contains("lat_target_pct"));
+        assert!(e.to_string().contains("[0.5, 0.9999]"));
+
+        assert!(load_params_str(r#"{ "lat_target_pct": 0.0 }"#).is_err());
+        assert_eq!(
+            load_params_str(r#"{ "lat_target_pct": 0.99 }"#)
+                .unwrap()
+                .lat_target_pct,
+            0.99
+        );
+    }
+
+    #[test]
+    fn test_frac_clamping() {
+        let p = load_params_str(
+            r#"{
+                "mem_frac": 1.5,
+                "file_frac": 2.0,
+                "file_addr_rps_base_frac": -0.2,
+                "file_write_frac": 1.1,
+                "anon_addr_rps_base_frac": 3.0,
+                "anon_write_frac": -1.0
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(p.mem_frac, 1.0);
+        assert_eq!(p.file_frac, 1.0);
+        assert_eq!(p.file_addr_rps_base_frac, 0.0);
+        assert_eq!(p.file_write_frac, 1.0);
+        assert_eq!(p.anon_addr_rps_base_frac, 1.0);
+        assert_eq!(p.anon_write_frac, 0.0);
+
+        // The FILE_FRAC_MIN floor still applies after clamping.
+        let p = load_params_str(r#"{ "file_frac": -0.5 }"#).unwrap();
+        assert_eq!(p.file_frac, Params::FILE_FRAC_MIN);
+    }
+
+    #[test]
+    fn test_file_frac_min() {
+        let p = load_params_str(r#"{ "file_frac": 0.0 }"#).unwrap();
+        assert_eq!(p.file_frac_min, Params::FILE_FRAC_MIN);
+        assert_eq!(p.file_frac, Params::FILE_FRAC_MIN);
+
+        // A zero floor allows all-anon.
+        let p = load_params_str(r#"{ "file_frac": 0.0, "file_frac_min": 0.0 }"#).unwrap();
+        assert_eq!(p.file_frac, 0.0);
+
+        // A raised floor pulls file_frac and the classes' up.
+        let p = load_params_str(
+            r#"{
+                "file_frac": 0.1,
+                "file_frac_min": 0.4,
+                "classes": [
+                    { "weight": 1, "file_size_mean": 4096, "file_frac": 0.2, "rps_target": 10 },
+                    { "weight": 1, "file_size_mean": 4096, "file_frac": 0.6, "rps_target": 10 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(p.file_frac, 0.4);
+        assert_eq!(p.classes[0].file_frac, 0.4);
+        assert_eq!(p.classes[1].file_frac, 0.6);
+        let p = load_params_str(r#"{ "file_frac": 0.5, "file_frac_min": 0.4 }"#).unwrap();
+        assert_eq!(p.file_frac, 0.5);
+    }
+
+    #[test]
+    fn test_chunk_pages() {
+        assert!(load_params_str(r#"{ "chunk_pages": 0 }"#).is_err());
+        let p = load_params_str(r#"{ "chunk_pages": 16 }"#).unwrap();
+        assert_eq!(p.chunk_bytes_with_page_size(4096), 64 << 10);
+        assert_eq!(p.chunk_bytes_with_page_size(65536), 1 << 20);
+        assert_eq!(p.chunk_bytes(), 16 * *PAGE_SIZE);
+
+        let p = Params {
+            file_size_mean: p.chunk_bytes() - 1,
+            ..p
+        };
+        let warns = p.validate();
+        println!("{:#?}", &warns);
+        assert_eq!(warns.len(), 1);
+        assert!(warns[0].contains("chunk_pages 16"));
+    }
+
+    #[test]
+    fn test_lat_target_ramp() {
+        let p = load_params_str(r#"{ "lat_target": "75ms" }"#).unwrap();
+        assert_eq!(p.lat_target_at(0.0), 0.075);
+        assert_eq!(p.lat_target_at(1000.0), 0.075);
+
+        let p = load_params_str(r#"{ "lat_target_ramp": [0.1, 0.02, 60] }"#).unwrap();
+        assert_eq!(p.lat_target_ramp, Some((0.1, 0.02, 60.0)));
+        assert_eq!(p.lat_target_at(0.0), 0.1);
+        assert!((p.lat_target_at(30.0) - 0.06).abs() < 0.000_001);
+        assert_eq!(p.lat_target_at(60.0), 0.02);
+        // Stays at the end once done.
+        assert_eq!(p.lat_target_at(600.0), 0.02);
+
+        // A zero duration jumps straight to the end.
+        let p = load_params_str(r#"{ "lat_target_ramp": [0.1, 0.02, 0] }"#).unwrap();
+        assert_eq!(p.lat_target_at(0.0), 0.02);
+
+        assert!(load_params_str(r#"{ "lat_target_ramp": [0.0, 0.02, 60] }"#).is_err());
+        assert!(load_params_str(r#"{ "lat_target_ramp": [0.1, 0.02, -1] }"#).is_err());
+    }
+
+    #[test]
+    fn test_load_from_reader() {
+        let body = "// comment\n{ \"rps_target\": 123, \"lat_target\": 0.05 }\n";
+        let p = Params::load_from_reader(body.as_bytes()).unwrap();
+        assert_eq!(p.rps_target, 123);
+        assert_eq!(p.lat_target_list(), vec![(p.lat_target_pct, 0.05)]);
+        assert_eq!(
+            p,
+            load_params_str(body).unwrap(),
+            "reader and file loaders disagree"
+        );
+
+        for bad in &[
+            "",
+            "{ \"rps_target\": }",
+            "\"params\"",
+            "{ \"chunk_pages\": 0 }",
+        ] {
+            let e = Params::load_from_reader(bad.as_bytes()).unwrap_err();
+            println!("{:?}: {}", bad, &e);
+        }
+        assert!(Params::load_from_url("/tmp/params.json").is_err());
+        assert!(Params::is_url("https://example.com/params.json"));
+    }
+
+    #[cfg(not(feature = "url"))]
+    #[test]
+    fn test_load_from_url() {
+        let e = Params::load_from_url("http://127.0.0.1:1/params.json").unwrap_err();
+        assert!(e.to_string().contains("\"url\" feature"));
+        assert!(Params::load_from_url("/tmp/params.json").is_err());
+    }
+
+    // Needs curl, which the "url" feature depends on.
+    #[cfg(feature = "url")]
+    #[test]
+    fn test_load_from_url() {
+        use std::io::{Read, Write};
+
+        // Serve the params body and then a 404 from a local listener.
+        let body = "// comment\n{ \"rps_target\": 321, \"mem_frac\": 0.5 }\n";
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            for status in &["200 OK", "404 Not Found"] {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut req = vec![];
+                let mut buf = [0u8; 1024];
+                while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let len = conn.read(&mut buf).unwrap();
+                    if len == 0 {
+                        break;
+                    }
+                    req.extend_from_slice(&buf[..len]);
+                }
+                let resp_body = match *status {
+                    "200 OK" => body,
+                    _ => "",
+                };
+                write!(
+                    conn,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    resp_body.len(),
+                    resp_body
+                )
+                .unwrap();
+            }
+        });
+
+        let url = format!("http://{}/params.json", addr);
+        let p = Params::load_from_url(&url).unwrap();
+        assert_eq!(p.rps_target, 321);
+        assert_eq!(p, Params::load_from_reader(body.as_bytes()).unwrap());
+
+        let e = Params::load_from_url(&format!("http://{}/missing", addr)).unwrap_err();
+        println!("{:#}", &e);
+        assert!(e.to_string().contains("failed to fetch"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_anon_histogram_conflicts() {
+        // The ratios alone or the histogram with default ratios are fine.
+        let p = load_params_str(r#"{ "anon_addr_stdev_ratio": 0.2 }"#).unwrap();
+        assert!(p.anon_histogram_conflicts().is_empty());
+        let p = load_params_str(r#"{ "anon_histogram": [1, 2], "anon_size_ratio": 2.0 }"#).unwrap();
+        assert!(p.anon_histogram_conflicts().is_empty());
+
+        let body = r#"{ "anon_histogram": [1, 2], "anon_addr_stdev_ratio": 0.2,
+                        "anon_addr_rps_base_frac": 0.3 }"#;
+        let p = load_params_str(body).unwrap();
+        assert_eq!(
+            p.anon_histogram_conflicts(),
+            vec!["anon_addr_stdev_ratio", "anon_addr_rps_base_frac"]
+        );
+
+        let strict = body.replacen('{', r#"{ "strict": true,"#, 1);
+        let e = load_params_str(&strict).unwrap_err();
+        println!("{}", &e);
+        assert!(e
+            .to_string()
+            .contains("anon_addr_stdev_ratio, anon_addr_rps_base_frac"));
+        assert!(load_params_str(r#"{ "strict": true, "anon_histogram": [1, 2] }"#).is_ok());
+    }
+
+    #[test]
+    fn test_stress_schedule() {
+        let p = load_params_str(r#"{ "rps_target": 100 }"#).unwrap();
+        assert!(p.stress_schedule.is_empty());
+        assert_eq!(p.scheduled_rps_target(0.0), 100);
+        assert_eq!(p.scheduled_rps_target(1000.0), 100);
+
+        let p = load_params_str(
+            r#"{ "rps_target": 100, "stress_schedule": [[10, 200], [20, 400], [40, 800]] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            p.stress_schedule,
+            StressSchedule {
+                steps: vec![(10.0, 200), (20.0, 400), (40.0, 800)]
+            }
+        );
+        for (t, rps) in &[
+            (0.0, 100),
+            (9.9, 100),
+            (10.0, 200),
+            (19.9, 200),
+            (20.0, 400),
+            (39.0, 400),
+            (40.0, 800),
+            (3600.0, 800),
+        ] {
+            assert_eq!(p.scheduled_rps_target(*t), *rps, "elapsed={}", t);
+        }
+        assert_eq!(p.stress_schedule.rps_target_at(5.0), None);
+
+        // The schedule takes over from warmup but bursts still win.
+        let p = Params {
+            warmup_secs: 60.0,
+            burst_rps: 5000,
+            burst_secs: 1.0,
+            burst_period_secs: 30.0,
+            ..p
+        };
+        assert_eq!(p.effective_rps_target(20.0), 400);
+        assert_eq!(p.effective_rps_target(30.5), 5000);
+
+        // Saved as plain pairs and only if non-empty.
+        let json = serde_json::to_value(&p).unwrap();
+        assert_eq!(
+            json["stress_schedule"],
+            serde_json::json!([[10.0, 200], [20.0, 400], [40.0, 800]])
+        );
+        assert!(serde_json::to_value(Params::default()).unwrap()["stress_schedule"].is_null());
+
+        assert!(load_params_str(r#"{ "stress_schedule": [[20, 1], [10, 2]] }"#).is_err());
+        assert!(load_params_str(r#"{ "stress_schedule": [[-1, 1]] }"#).is_err());
+    }
+
+    #[test]
+    fn test_effective_rps_target() {
+        let p = load_params_str(r#"{ "rps_target": 1000 }"#).unwrap();
+        assert!(!p.has_bursts());
+        for t in &[0.0, 5.0, 60.0, 3600.0] {
+            assert_eq!(p.effective_rps_target(*t), 1000);
+        }
+
+        let p = load_params_str(
+            r#"{ "rps_target": 1000, "burst_rps": 3000, "burst_secs": 5,
+                 "burst_period_secs": 60 }"#,
+        )
+        .unwrap();
+        assert!(p.has_bursts());
+        for (t, rps) in &[
+            (0.0, 1000),
+            (4.0, 1000),
+            (59.9, 1000),
+            (60.0, 3000),
+            (64.9, 3000),
+            (65.0, 1000),
+            (119.0, 1000),
+            (122.0, 3000),
+            (600.0, 3000),
+            (606.0, 1000),
+        ] {
+            assert_eq!(p.effective_rps_target(*t), *rps, "elapsed={}", t);
+        }
+
+        // Ramping up still applies outside bursts.
+        let warm = Params {
+            warmup_secs: 120.0,
+            ..p.clone()
+        };
+        assert_eq!(warm.effective_rps_target(30.0), 250);
+        assert_eq!(warm.effective_rps_target(61.0), 3000);
+
+        let paused = Params {
+            rps_target: 0,
+            ..p.clone()
+        };
+        assert_eq!(paused.effective_rps_target(60.0), 0);
+
+        assert!(load_params_str(r#"{ "burst_secs": -1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_warmup_rps_target() {
+        let p = load_params_str(r#"{ "rps_target": 1000 }"#).unwrap();
+        assert_eq!(p.warmup_rps_target(0.0), 1000);
+        assert_eq!(p.warmup_rps_target(100.0), 1000);
+
+        let p = load_params_str(r#"{ "rps_target": 1000, "warmup_secs": 60 }"#).unwrap();
+        assert_eq!(p.warmup_rps_target(0.0), 0);
+        assert_eq!(p.warmup_rps_target(15.0), 250);
+        assert_eq!(p.warmup_rps_target(30.0), 500);
+        assert_eq!(p.warmup_rps_target(60.0), 1000);
+        assert_eq!(p.warmup_rps_target(600.0), 1000);
+
+        let gains = p.lat_pid.scaled_gains(Params::WARMUP_PID_GAIN_SCALE);
+        assert_eq!(gains.kp, p.lat_pid.kp * Params::WARMUP_PID_GAIN_SCALE);
+        assert_eq!(gains.i_max, p.lat_pid.i_max);
+
+        assert!(load_params_str(r#"{ "warmup_secs": -1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_log_line() {
+        let p = Params {
+            rps_target: 1234,
+            ..Default::default()
+        };
+        let line = p.as_log_line(7, chrono::Local::now());
+        assert!(!line.contains('\n'));
+
+        let v: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["seq"], 7);
+        assert!(v["timestamp"].is_string());
+        assert_eq!(
+            v["fingerprint"].as_str().unwrap(),
+            format!("{:016x}", p.fingerprint())
+        );
+        let parsed: Params = serde_json::from_value(v["params"].clone()).unwrap();
+        assert_eq!(parsed, p);
+
+        let line = p.as_summary_log_line(7, chrono::Local::now());
+        assert!(!line.contains('\n'));
+        let sv: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(sv["seq"], 7);
+        assert_eq!(sv["fingerprint"], v["fingerprint"]);
+        assert_eq!(sv["summary"]["rps_target"], 1234);
+        assert!(sv.get("params").is_none());
+        assert!(line.len() < v.to_string().len() / 4);
+    }
+
+    #[test]
+    fn test_mem_balloon() {
+        let p = load_params_str(r#"{ "mem_frac": 0.5, "mem_balloon_bytes": "2G" }"#).unwrap();
+        assert_eq!(p.mem_balloon_bytes, 2 << 30);
+        assert!(p
+            .as_json()
+            .unwrap()
+            .contains(r#""mem_balloon_bytes": "2G""#));
+
+        assert!(p.check_mem_balloon(8 << 30).is_ok());
+        assert!(p.check_mem_balloon(4 << 30).is_ok());
+        let e = p.check_mem_balloon(2 << 30).unwrap_err().to_string();
+        assert!(
+            e.contains("exceeds") && e.contains("mem_frac 0.50"),
+            "{}",
+            e
+        );
+
+        // Disabled never warns.
+        let p = Params {
+            mem_frac: 0.0,
+            ..Default::default()
+        };
+        assert!(p.check_mem_balloon(0).is_ok());
+    }
+
+    #[test]
+    fn test_lerp() {
+        let a = load_params_str(
+            r#"{
+                "rps_target": 1000,
+                "lat_target": "100ms",
+                "mem_frac": 0.4,
+                "file_size_mean": "1M",
+                "fake_cpu_load": false,
+                "hash_algo": "Sha1",
+                "anon_histogram": [4, 2]
+            }"#,
+        )
+        .unwrap();
+        let b = load_params_str(
+            r#"{
+                "rps_target": 3001,
+                "lat_target": "200ms",
+                "mem_frac": 0.8,
+                "file_size_mean": "3M",
+                "fake_cpu_load": true,
+                "hash_algo": "Sha256",
+                "anon_histogram": [0, 4, 8, 2],
+                "lat_pid": { "kp": 0.3, "ki": 0.01, "kd": 0.01 }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(Params::lerp(&a, &b, 0.0), a);
+        assert_eq!(Params::lerp(&a, &b, 1.0), b);
+        // Out of range is clamped.
+        assert_eq!(Params::lerp(&a, &b, -1.0), a);
+        assert_eq!(Params::lerp(&a, &b, 2.0), b);
+
+        let m = Params::lerp(&a, &b, 0.5);
+        assert_eq!(m.rps_target, 2001);
+        assert!((m.lat_target - 0.15).abs() < 0.000_001);
+        assert_eq!(m.lat_target_list().len(), 1);
+        assert!((m.lat_target_list()[0].1 - 0.15).abs() < 0.000_001);
+        assert!((m.mem_frac - 0.6).abs() < 0.000_001);
+        assert_eq!(m.file_size_mean, 2 << 20);
+        assert!((m.lat_pid.kp - (a.lat_pid.kp + 0.3) / 2.0).abs() < 0.000_001);
+        // Non-numeric fields snap to b at the mid point.
+        assert!(m.fake_cpu_load);
+        assert_eq!(m.hash_algo, HashAlgo::Sha256);
+        // The shorter histogram is padded with zeros.
+        assert_eq!(m.anon_histogram, vec![2, 3, 4, 1]);
+
+        let q = Params::lerp(&a, &b, 0.25);
+        assert_eq!(q.rps_target, 1500);
+        assert!(!q.fake_cpu_load);
+        assert_eq!(q.hash_algo, HashAlgo::Sha1);
+        assert_eq!(q.anon_histogram, vec![3, 3, 2, 1]);
+
+        // The result goes through the same clamping as loading.
+        let c = Params {
+            file_frac: 0.0,
+            ..b.clone()
+        };
+        let m = Params::lerp(&a, &c, 0.9);
+        assert!(m.file_frac >= m.file_frac_min);
+    }
+
+    #[test]
+    fn test_canonical_json() {
+        let a = Params {
+            rps_target: 1234,
+            lat_target: 0.075,
+            file_histogram: vec![3, 0, 1],
+            lat_pid: PidParams {
+                i_max: 0.25,
+                ..Params::default().lat_pid
+            },
+            ..Default::default()
+        };
+        let b = Params {
+            lat_pid: PidParams {
+                i_max: 0.25,
+                ..Params::default().lat_pid
+            },
+            file_histogram: vec![3, 0, 1],
+            lat_target: 0.075,
+            rps_target: 1234,
+            // -0.0 == 0.0 and shouldn't show up as a difference.
+            sleep_jitter_frac: -0.0,
+            ..Default::default()
+        };
+        assert_eq!(a, b);
+
+        let json = a.to_canonical_json();
+        assert_eq!(json, b.to_canonical_json());
+        assert!(json.ends_with("}\n"));
+
+        // Keys are sorted.
+        let top_keys: Vec<&str> = json
+            .lines()
+            .filter(|line| line.starts_with("  \"") && !line.starts_with("   "))
+            .map(|line| line.trim().split('"').nth(1).unwrap())
+            .collect();
+        let mut sorted = top_keys.clone();
+        sorted.sort();
+        assert!(top_keys.len() > 10);
+        assert_eq!(top_keys, sorted);
+
+        // Round-trips through loading, which also fills in lat_targets.
+        let mut normalized = a.clone();
+        normalized.loaded(None).unwrap();
+        let loaded = load_params_str(&json).unwrap();
+        assert_eq!(loaded, normalized);
+        assert_eq!(
+            load_params_str(&loaded.to_canonical_json()).unwrap(),
+            loaded
+        );
+    }
+
+    #[test]
+    fn test_prometheus_metrics() {
+        let p = Params {
+            rps_target: 1200,
+            lat_target_pct: 0.9,
+            lat_target: 0.075,
+            mem_frac: 0.5,
+            concurrency_max: 128,
+            ..Default::default()
+        };
+        let text = p.prometheus_metrics(1187.5, 0.082, 42.5);
+        assert!(text.ends_with("# EOF\n"));
+
+        let mut nr_types = 0;
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let toks: Vec<&str> = rest.split(' ').collect();
+                assert_eq!(toks.len(), 2, "{:?}", line);
+                assert!(toks[0].starts_with("rd_hashd_"), "{:?}", line);
+                assert_eq!(toks[1], "gauge");
+                nr_types += 1;
+            }
+        }
+        assert_eq!(nr_types, 8);
+
+        for sample in &[
+            "rd_hashd_rps_target 1200",
+            "rd_hashd_rps 1187.5",
+            "rd_hashd_lat_target_pct 0.9",
+            "rd_hashd_lat_target_seconds 0.075",
+            "rd_hashd_lat_seconds 0.082",
+            "rd_hashd_mem_frac 0.5",
+            "rd_hashd_concurrency_max 128",
+            "rd_hashd_concurrency 42.5",
+        ] {
+            assert!(text.lines().any(|line| line == *sample), "{}", sample);
+        }
+
+        assert!(p
+            .prometheus_metrics(f64::NAN, f64::INFINITY, 1.0)
+            .contains("rd_hashd_lat_seconds +Inf\n"));
+    }
+
+    #[test]
+    fn test_lint() {
+        let path = std::env::temp_dir().join(format!(
+            "rd-hashd-intf-test-lint-{}.json",
+            std::process::id()
+        ));
+        // A minimal valid file without an explicit version is clean.
+        std::fs::write(&path, r#"{ "rps_target": 1000 }"#).unwrap();
+        let report = Params::lint(&path, false).unwrap();
+        println!("{:?}", &report);
+        assert!(report.is_clean());
+
+        let body = r#"{
+            "control_period": 0.001,
+            "mem_frac": 1.5,
+            "file_write_frac": -0.2
+        }"#;
+        std::fs::write(&path, body).unwrap();
+
+        // Report only, the file is left alone.
+        let report = Params::lint(&path, false).unwrap();
+        let fields: Vec<&str> = report.adjustments.iter().map(|a| a.0.as_str()).collect();
+        println!("{:?}", &report);
+        assert_eq!(
+            fields,
+            vec!["control_period", "file_write_frac", "mem_frac"]
+        );
+        assert_eq!(
+            report.adjustments[2],
+            ("mem_frac".into(), "1.5".into(), "1.0".into())
+        );
+        assert_eq!(report.adjustments[0].1, "1ms");
+        assert!(!report.fixed && !report.is_clean());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), body);
+
+        // Fix rewrites the normalized form, after which it's clean.
+        let report = Params::lint(&path, true).unwrap();
+        assert_eq!(report.adjustments.len(), 3);
+        assert!(report.fixed);
+        let p = JsonConfigFile::<Params>::load(&path).unwrap().data;
+        assert_eq!(p.mem_frac, 1.0);
+        assert_eq!(p.file_write_frac, 0.0);
+        assert_eq!(p.control_period, Params::CONTROL_PERIOD_MIN);
+        assert_eq!(Params::lint(&path, false).unwrap(), LintReport::default());
+
+        // Errors which would fail loading are passed through.
+        std::fs::write(&path, r#"{ "chunk_pages": 0 }"#).unwrap();
+        assert!(Params::lint(&path, true).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_uniform_blend() {
+        let p = Params::default();
+        assert_eq!(p.uniform_blend(0.0), 0.0);
+        assert!(p.uniform_blend(0.1) < 0.000_001);
+        assert!(p.uniform_blend(100.0) > 0.99);
+        assert!((p.uniform_blend(Params::DIST_UNIFORM_KNEE_DFL) - 0.5).abs() < 0.000_001);
+
+        // The default is the chance of a normal sample landing outside
+        // [-1, 1], e.g. 4.55% for 2 stdevs.
+        assert!((p.uniform_blend(0.5) - 0.0455).abs() < 0.0001);
+        assert!((p.uniform_blend(1.0) - 0.3173).abs() < 0.0001);
+
+        let p = load_params_str(r#"{ "dist_uniform_knee": 0.3 }"#).unwrap();
+        assert!((p.uniform_blend(0.3) - 0.5).abs() < 0.000_001);
+        let mut last = 0.0;
+        for i in 1..100 {
+            let blend = p.uniform_blend(i as f64 * 0.05);
+            assert!(blend > last);
+            last = blend;
+        }
+        assert!(load_params_str(r#"{ "dist_uniform_knee": 0 }"#).is_err());
+    }
+
+    #[test]
+    fn test_effective_concurrency_max() {
+        let p = Params {
+            concurrency_max: 65536,
+            ..Default::default()
+        };
+        assert_eq!(p.effective_concurrency_max(4096), 4096);
+        assert_eq!(p.effective_concurrency_max(65536), 65536);
+        assert_eq!(p.effective_concurrency_max(1 << 20), 65536);
+        assert_eq!(p.effective_concurrency_max(u32::MAX), 65536);
+    }
+
+    #[test]
+    fn test_acc_dist_slots_max() {
+        let p = load_params_str(r#"{ "acc_dist_slots": 4096 }"#).unwrap();
+        assert_eq!(p.acc_dist_slots, Params::ACC_DIST_SLOTS_MAX);
+        assert!(load_params_str(r#"{ "acc_dist_slots": 4097 }"#).is_err());
+    }
+
+    #[test]
+    fn test_fake_cpu_sleep() {
+        let p = Params {
+            cpu_ratio: 1.0,
+            ..Default::default()
+        };
+        let base = p.fake_cpu_sleep(1 << 20);
+        assert_eq!(base, (1 << 20) as f64 * Params::FAKE_CPU_LOAD_TIME_PER_BYTE);
+        assert_eq!(p.fake_cpu_sleep(2 << 20), base * 2.0);
+        assert_eq!(p.fake_cpu_sleep(0), 0.0);
+
+        for ratio in &[0.25, 0.5, 2.0, 3.0] {
+            let p = Params {
+                cpu_ratio: *ratio,
+                ..p.clone()
+            };
+            assert!((p.fake_cpu_sleep(1 << 20) - base * ratio).abs() < 0.000_000_001);
+        }
+
+        // The bench-calibrated cost replaces the fallback.
+        let p = Params {
+            cpu_time_per_byte: 2.0 / 1_000_000_000.0,
+            ..p
+        };
+        assert_eq!(p.hash_time_per_byte(), p.cpu_time_per_byte);
+        assert!((p.fake_cpu_sleep(1 << 20) - base / 2.0).abs() < 0.000_000_001);
+        let p = Params {
+            cpu_ratio: 2.0,
+            ..p
+        };
+        assert!((p.fake_cpu_sleep(1 << 20) - base).abs() < 0.000_000_001);
+        assert!(load_params_str(r#"{ "cpu_time_per_byte": -1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_param_field() {
+        // Every numeric field in the schema has a ParamField.
+        let schema = Params::json_schema();
+        let mut nr_numeric = 0;
+        for (name, prop) in schema["properties"].as_object().unwrap().iter() {
+            let types = match &prop["type"] {
+                serde_json::Value::Array(types) => types.clone(),
+                ty => vec![ty.clone()],
+            };
+            let numeric = types.iter().any(|t| t == "number" || t == "integer");
+            if !numeric || types.iter().any(|t| t == "null") || name == "version" {
+                continue;
+            }
+            nr_numeric += 1;
+            let field = name.parse::<ParamField>().expect(name);
+            assert_eq!(field.as_str(), name);
+        }
+        assert_eq!(nr_numeric, ParamField::ALL.len());
+        assert!("file_sync".parse::<ParamField>().is_err());
+        assert!("no_such_field".parse::<ParamField>().is_err());
+
+        // Round-trips through get and set.
+        let dfl = Params::default();
+        let mut p = Params::default();
+        for (idx, field) in ParamField::ALL.iter().enumerate() {
+            let v = field.get(&dfl) + idx as f64 + 1.0;
+            field.set(&mut p, v);
+            assert_eq!(field.get(&p), v, "{}", field.as_str());
+        }
+        let diffs = dfl.diff(&p);
+        assert_eq!(diffs.len(), ParamField::ALL.len());
+
+        let mut p = Params::default();
+        ParamField::LatTarget.set(&mut p, 0.05);
+        assert_eq!(p.lat_target, 0.05);
+        ParamField::RpsTarget.set(&mut p, 99.6);
+        assert_eq!(p.rps_target, 100);
+        assert_eq!(ParamField::FileSizeMean.get(&p), p.file_size_mean as f64);
+    }
+
+    #[test]
+    fn test_next_control_period() {
+        // Static unless adaptive.
+        let p = Params::default();
+        for slope in &[0.0, 0.5, 100.0, f64::NAN] {
+            assert_eq!(p.next_control_period(*slope), p.control_period);
+        }
+
+        let p = load_params_str(
+            r#"{ "control_period": "1s", "control_period_adaptive": true,
+                 "control_period_min": "100ms" }"#,
+        )
+        .unwrap();
+        assert_eq!(p.control_period_min, 0.1);
+
+        // Flat error stays at the long end.
+        assert_eq!(p.next_control_period(0.0), 1.0);
+        assert_eq!(p.next_control_period(0.01), 1.0);
+        // Steep error goes to the short end, either direction.
+        assert_eq!(p.next_control_period(10.0), 0.1);
+        assert_eq!(p.next_control_period(-10.0), 0.1);
+        // In between, the error changes by CONTROL_PERIOD_ERROR_STEP.
+        let period = p.next_control_period(0.5);
+        assert!((period - Params::CONTROL_PERIOD_ERROR_STEP / 0.5).abs() < 0.000_001);
+        assert!(p.next_control_period(0.25) > period);
+        assert!(p.next_control_period(0.75) < period);
+
+        // control_period_min is floored and capped at control_period.
+        let p = load_params_str(
+            r#"{ "control_period": 0.05, "control_period_adaptive": true,
+                 "control_period_min": 0 }"#,
+        )
+        .unwrap();
+        assert_eq!(p.control_period_min, Params::CONTROL_PERIOD_MIN);
+        let p = Params {
+            control_period_min: 2.0,
+            ..p
+        };
+        assert_eq!(p.next_control_period(100.0), 0.05);
+    }
+
+    #[test]
+    fn test_control_period() {
+        // A zero period would divide by zero in the derivative gain.
+        let p = load_params_str(r#"{ "control_period": 0.0 }"#).unwrap();
+        assert_eq!(p.control_period, Params::CONTROL_PERIOD_MIN);
+        let p = load_params_str(r#"{ "control_period": -1.0 }"#).unwrap();
+        assert_eq!(p.control_period, Params::CONTROL_PERIOD_MIN);
+        let p = load_params_str(r#"{ "control_period": 0.5 }"#).unwrap();
+        assert_eq!(p.control_period, 0.5);
+
+        // The default period doesn't warn.
+        let p = Params {
+            lat_target: 0.005,
+            ..Default::default()
+        };
+        assert!(p.validate().is_empty());
+
+        // A period set longer than lat_target does.
+        let p = Params {
+            control_period: 0.1,
+            ..Default::default()
+        };
+        let warns = p.validate();
+        println!("{:#?}", &warns);
+        assert_eq!(warns.len(), 1);
+        assert!(warns[0].contains("control_period"));
+
+        let p = Params {
+            control_period: 0.05,
+            ..p
+        };
+        assert!(p.validate().is_empty());
+        let p = Params {
+            control_period: 0.075,
+            ..p
+        };
+        assert!(p.validate().is_empty());
+    }
+
+    #[test]
+    fn test_file_size_percentiles() {
+        // 90% tiny and 10% huge.
+        let p = load_params_str(
+            r#"{ "file_size_percentiles": [[0.9, 4096], [0.9, 1048576]], "file_size_mean": "64M" }"#,
+        )
+        .unwrap();
+        let sizes = p.sample_file_sizes(16384, 1);
+        let nr_tiny = sizes.iter().filter(|&&s| s == 4096).count();
+        let nr_huge = sizes.iter().filter(|&&s| s == 1 << 20).count();
+        let tiny_frac = nr_tiny as f64 / sizes.len() as f64;
+        println!("tiny_frac={:.3}", tiny_frac);
+        assert_eq!(nr_tiny + nr_huge, sizes.len());
+        assert!((tiny_frac - 0.9).abs() < 0.01);
+
+        // The estimates follow the percentiles rather than file_size_mean.
+        let mean = 0.9 * 4096.0 + 0.1 * (1 << 20) as f64;
+        assert!((p.file_size_mean_bytes() - mean).abs() < 0.001);
+        assert!((p.anon_size_mean_bytes() - mean * p.anon_size_ratio).abs() < 0.001);
+        let chunk_bytes = p.chunk_bytes() as f64;
+        let req_bytes = (mean / chunk_bytes).ceil() * chunk_bytes;
+        let io = p.io_mix(100, p.mem_total());
+        assert_eq!(
+            io.file_read_bps + io.file_write_bps,
+            ((req_bytes * 100.0 * (1.0 - p.file_write_frac)) as u64)
+                + ((req_bytes * 100.0 * p.file_write_frac * p.write_cost_ratio) as u64)
+        );
+
+        // Linear in between the points.
+        let p = Params {
+            file_size_percentiles: vec![(0.0, 1000), (0.5, 2000), (1.0, 10000)],
+            ..p
+        };
+        let sizes = p.sample_file_sizes(16384, 2);
+        assert!(sizes.iter().all(|&s| (1000..=10000).contains(&s)));
+        let below = sizes.iter().filter(|&&s| s <= 2000).count() as f64 / sizes.len() as f64;
+        let below_1500 = sizes.iter().filter(|&&s| s <= 1500).count() as f64 / sizes.len() as f64;
+        println!("below={:.3} below_1500={:.3}", below, below_1500);
+        assert!((below - 0.5).abs() < 0.02);
+        assert!((below_1500 - 0.25).abs() < 0.02);
+        let sampled_mean = sizes.iter().sum::<usize>() as f64 / sizes.len() as f64;
+        println!(
+            "mean={} sampled_mean={:.1}",
+            p.file_size_mean_bytes(),
+            sampled_mean
+        );
+        assert_eq!(p.file_size_mean_bytes(), 3750.0);
+        assert!((sampled_mean / 3750.0 - 1.0).abs() < 0.02);
+
+        // Saved only if set.
+        assert!(!Params::default().as_json().unwrap()[PARAMS_DOC.len()..]
+            .contains("file_size_percentiles"));
+
+        for bad in &[
+            r#"[[0.5, 4096], [0.4, 8192]]"#,
+            r#"[[0.4, 8192], [0.5, 4096]]"#,
+            r#"[[1.5, 4096]]"#,
+            r#"[[-0.1, 4096]]"#,
+        ] {
+            let body = format!(r#"{{ "file_size_percentiles": {} }}"#, bad);
+            assert!(load_params_str(&body).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_file_size_dist() {
+        // Configs without the field keep the normal distribution.
+        let p = load_params_str(r#"{ "file_size_mean": 4096 }"#).unwrap();
+        assert_eq!(p.file_size_dist, FileSizeDist::Normal);
+
+        let p = load_params_str(r#"{ "file_size_dist": "LogNormal" }"#).unwrap();
+        assert_eq!(p.file_size_dist, FileSizeDist::LogNormal);
+        assert!(load_params_str(r#"{ "file_size_dist": "Pareto" }"#).is_err());
+        assert!(load_params_str(
+            r#"{ "file_size_dist": "LogNormal", "file_size_stdev_ratio": -0.1 }"#
+        )
+        .is_err());
+
+        // A bad ratio which gets past loaded() falls back to the normal
+        // distribution instead of panicking.
+        let p = Params {
+            file_size_dist: FileSizeDist::LogNormal,
+            file_size_stdev_ratio: f64::NAN,
+            ..Default::default()
+        };
+        let sizes = p.sample_file_sizes(64, 1);
+        assert_eq!(sizes.len(), 64);
+    }
+
+    #[test]
+    fn test_file_sync() {
+        let p = load_params_str("{}").unwrap();
+        assert_eq!(p.file_sync, FileSync::None);
+        assert_eq!(p.file_sync_every, 1);
+
+        for (name, sync) in &[
+            ("None", FileSync::None),
+            ("Fdatasync", FileSync::Fdatasync),
+            ("Fsync", FileSync::Fsync),
+        ] {
+            let p = load_params_str(&format!(
+                r#"{{ "file_sync": "{}", "file_sync_every": 8 }}"#,
+                name
+            ))
+            .unwrap();
+            assert_eq!(p.file_sync, *sync);
+            assert_eq!(p.file_sync_every, 8);
+        }
+        assert!(load_params_str(r#"{ "file_sync": "Msync" }"#).is_err());
+        assert!(load_params_str(r#"{ "file_sync_every": 0 }"#).is_err());
+    }
+
+    #[test]
+    fn test_frac_ramp() {
+        let close = |a: f64, b: f64| (a - b).abs() < 0.000_001;
+        assert!(close(Params::frac_ramp(0.2, 0.8, 0.0), 0.2));
+        assert!(close(Params::frac_ramp(0.2, 0.8, 0.25), 0.35));
+        assert!(close(Params::frac_ramp(0.8, 0.2, 0.5), 0.5));
+        assert!(close(Params::frac_ramp(0.2, 0.8, 1.0), 0.8));
+        assert!(close(Params::frac_ramp(0.2, 0.8, 3.0), 0.8));
+        assert!(close(Params::frac_ramp(0.2, 0.8, -1.0), 0.2));
+    }
+
+    #[test]
+    fn test_file_frac_ramp() {
+        let close = |a: f64, b: f64| (a - b).abs() < 0.000_001;
+
+        // Without a ramp, the new file_frac applies right away.
+        let mut p = Params {
+            file_frac: 0.75,
+            ..Default::default()
+        };
+        p.loaded(None).unwrap();
+        assert_eq!(p.file_frac_at(0.25, 0.0), 0.75);
+
+        p.file_frac_ramp_secs = 20.0;
+        assert!(close(p.file_frac_at(0.25, 0.0), 0.25));
+        assert!(close(p.file_frac_at(0.25, 10.0), 0.5));
+        assert!(close(p.file_frac_at(0.25, 20.0), 0.75));
+        assert!(close(p.file_frac_at(0.25, 60.0), 0.75));
+
+        // Ramping up from an all-anon file_frac 0.0 stays above the floor.
+        let mut p = Params {
+            file_frac: 0.01,
+            file_frac_ramp_secs: 10.0,
+            ..Default::default()
+        };
+        p.loaded(None).unwrap();
+        for i in 0..=10 {
+            let frac = p.file_frac_at(0.0, i as f64);
+            assert!(frac >= Params::FILE_FRAC_MIN, "{} at {}s", frac, i);
+        }
+        assert_eq!(p.file_frac_at(0.0, 0.0), Params::FILE_FRAC_MIN);
+        assert!(close(p.file_frac_at(0.0, 5.0), 0.005));
+
+        assert!(load_params_str(r#"{ "file_frac_ramp_secs": -1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_sleep_modes() {
+        let p = load_params_str("{}").unwrap();
+        assert!(p.sleep_modes.is_empty());
+        assert!(!p.as_json().unwrap()[PARAMS_DOC.len()..].contains("sleep_modes"));
+
+        let p = load_params_str(r#"{ "sleep_modes": [[1, 0.001, 0.1], [3, 0.1, 0.2]] }"#).unwrap();
+        assert_eq!(p.sleep_modes, vec![(0.25, 0.001, 0.1), (0.75, 0.1, 0.2)]);
+        assert_eq!(load_params_str(&p.as_json().unwrap()).unwrap(), p);
+
+        assert!(load_params_str(r#"{ "sleep_modes": [[1, -0.001, 0.1]] }"#).is_err());
+        assert!(load_params_str(r#"{ "sleep_modes": [[-1, 0.001, 0.1]] }"#).is_err());
+        assert!(load_params_str(r#"{ "sleep_modes": [[0, 0.001, 0.1]] }"#).is_err());
+    }
+
+    #[test]
+    fn test_classes() {
+        let p = load_params_str("{}").unwrap();
+        assert!(p.classes.is_empty());
+        assert!(!p.as_json().unwrap()[PARAMS_DOC.len()..].contains("classes"));
+
+        let p = load_params_str(
+            r#"{
+                "classes": [
+                    { "weight": 3, "file_size_mean": "4K", "file_frac": 0.1, "rps_target": 900 },
+                    { "weight": 1, "file_size_mean": "1M", "file_frac": 1.5, "rps_target": 100 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            p.classes,
+            vec![
+                WorkloadClass {
+                    weight: 3.0,
+                    file_size_mean: 4096,
+                    file_frac: 0.1,
+                    rps_target: 900
+                },
+                WorkloadClass {
+                    weight: 1.0,
+                    file_size_mean: 1 << 20,
+                    file_frac: 1.0,
+                    rps_target: 100
+                },
+            ]
+        );
+        assert_eq!(p.rps_target, Params::default().rps_target);
+        assert_eq!(p.base_rps_target(), 1000);
+        assert!(p.validate().is_empty());
+
+        let json = p.as_json().unwrap();
+        assert!(json.contains(r#""file_size_mean": "1M""#));
+        // The clamp which degraded p is already baked into the json.
+        assert!(p.degraded());
+        assert_eq!(load_params_str(&json).unwrap(), p);
+
+        // The saved file and the loaded params can both be edited further.
+        let edited = json.replace(r#""rps_target": 100"#, r#""rps_target": 300"#);
+        assert_ne!(edited, json);
+        let q = load_params_str(&edited).unwrap();
+        assert_eq!(q.base_rps_target(), 1200);
+        let mut p = p;
+        p.apply_patch(&ParamsPatch {
+            classes: Some(q.classes.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(p.base_rps_target(), 1200);
+        assert_eq!(p.effective_rps_target(1000.0), 1200);
+
+        let class = r#""file_size_mean": 4096, "file_frac": 0.1, "rps_target": 1"#;
+        let two = format!(
+            r#"[{{ "weight": 1, {} }}, {{ "weight": 1, {} }}]"#,
+            class, class
+        );
+        // The classes override a top-level rps_target, which is kept as
+        // written and warned about, except for 0 which pauses.
+        for v in [500, 65536] {
+            let p = load_params_str(&format!(r#"{{ "rps_target": {}, "classes": {} }}"#, v, two))
+                .unwrap();
+            assert_eq!((p.rps_target, p.base_rps_target()), (v, 2));
+            assert!(p.validate().iter().any(|w| w.contains("is ignored")));
+        }
+        let p = load_params_str(&format!(r#"{{ "rps_target": 2, "classes": {} }}"#, two)).unwrap();
+        assert!(p.validate().is_empty());
+        let mut p =
+            load_params_str(&format!(r#"{{ "rps_target": 0, "classes": {} }}"#, two)).unwrap();
+        assert!(p.is_paused());
+        assert_eq!(p.base_rps_target(), 0);
+        p.apply_patch(&serde_json::from_str(r#"{ "rps_target": 3 }"#).unwrap())
+            .unwrap();
+        assert!(!p.is_paused());
+        assert_eq!((p.rps_target, p.base_rps_target()), (3, 2));
+
+        assert!(load_params_str(&format!(
+            r#"{{ "classes": [{{ "weight": -1, {} }}] }}"#,
+            class
+        ))
+        .is_err());
+        assert!(load_params_str(&format!(
+            r#"{{ "classes": [{{ "weight": 0, {} }}] }}"#,
+            class
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_aliases() {
+        let p = load_params_str(
+            r#"{ "concurrency": 64, "lat_pct": 0.95, "lat": "50ms", "rps": 1200 }"#,
+        )
+        .unwrap();
+        assert_eq!(p.concurrency_max, 64);
+        assert_eq!(p.lat_target_pct, 0.95);
+        assert_eq!(p.lat_target, 0.05);
+        assert_eq!(p.rps_target, 1200);
+        assert_eq!(p.lat_target_list(), vec![(0.95, 0.05)]);
+
+        let json = p.as_json().unwrap();
+        for canonical in &[
+            "concurrency_max",
+            "lat_target_pct",
+            "lat_target",
+            "rps_target",
+        ] {
+            assert!(json.contains(&format!("\"{}\":", canonical)));
+        }
+        for alias in &["concurrency", "lat_pct", "lat", "rps"] {
+            assert!(!json.contains(&format!("\"{}\":", alias)));
+        }
+
+        let patch: ParamsPatch = serde_json::from_str(r#"{ "rps": 300, "lat": 0.1 }"#).unwrap();
+        assert_eq!(patch.rps_target, Some(300));
+        assert_eq!(patch.lat_target, Some(0.1));
+    }
+
+    #[test]
+    fn test_convergence() {
+        let p = Params {
+            rps_target: 1000,
+            lat_target: 0.1,
+            ..Default::default()
+        };
+        assert_eq!(p.converge_rps_frac, 0.1);
+        assert_eq!(p.converge_lat_frac, 0.1);
+
+        let report = p.convergence(950.0, 0.105, 0.5);
+        assert_eq!(
+            report,
+            ConvergenceReport {
+                rps_target: 1000,
+                rps: 950.0,
+                lat_target_pct: p.lat_target_pct,
+                lat_target: 0.1,
+                lat: 0.105,
+                mem_frac: 0.5,
+                converged: true,
+            }
+        );
+        assert!(p.convergence(1080.0, 0.02, 0.5).converged);
+
+        // Too slow, too fast and too much latency.
+        assert!(!p.convergence(850.0, 0.05, 0.5).converged);
+        assert!(!p.convergence(1200.0, 0.05, 0.5).converged);
+        assert!(!p.convergence(1000.0, 0.12, 0.5).converged);
+
+        // Tolerances are configurable.
+        let p = Params {
+            converge_rps_frac: 0.2,
+            converge_lat_frac: 0.25,
+            ..p
+        };
+        assert!(p.convergence(850.0, 0.12, 0.5).converged);
+    }
+
+    #[test]
+    fn test_convergence_tracker() {
+        // Without hysteresis, tracks convergence() as is.
+        let p = Params {
+            rps_target: 1000,
+            lat_target: 0.1,
+            ..Default::default()
+        };
+        let mut tracker = ConvergenceTracker::new();
+        for rps in &[850.0, 950.0, 880.0, 1050.0, 1150.0] {
+            let report = tracker.update(&p, *rps, 0.05, 0.5);
+            assert_eq!(report, p.convergence(*rps, 0.05, 0.5));
+            assert_eq!(tracker.is_converged(), report.converged);
+        }
+
+        // Enter within 5%, leave outside 15%.
+        let p = load_params_str(
+            r#"{ "rps_target": 1000, "lat_target": 0.1,
+                 "converge_enter_frac": 0.5, "converge_exit_frac": 1.5 }"#,
+        )
+        .unwrap();
+        let mut tracker = ConvergenceTracker::new();
+        let states: Vec<bool> = [
+            880.0, 920.0, 1080.0, 940.0, 960.0, 890.0, 1090.0, 870.0, 1160.0, 880.0, 910.0,
+        ]
+        .iter()
+        .map(|rps| tracker.update(&p, *rps, 0.05, 0.5).converged)
+        .collect();
+        assert_eq!(
+            states,
+            vec![false, false, false, false, true, true, true, true, false, false, false]
+        );
+
+        // The latency band works the same way.
+        let mut tracker = ConvergenceTracker::new();
+        assert!(!tracker.update(&p, 1000.0, 0.108, 0.5).converged);
+        assert!(tracker.update(&p, 1000.0, 0.104, 0.5).converged);
+        assert!(tracker.update(&p, 1000.0, 0.114, 0.5).converged);
+        assert!(!tracker.update(&p, 1000.0, 0.116, 0.5).converged);
+
+        assert_eq!(
+            Params {
+                converge_enter_frac: 2.0,
+                ..p
+            }
+            .validate()
+            .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_addr_center_at() {
+        // Stationary by default.
+        let mut p = Params::default();
+        assert_eq!(p.addr_drift_rate, 0.0);
+        for elapsed in &[0.0, 1.0, 3600.0, 1e9] {
+            assert_eq!(p.addr_center_at(*elapsed, 1000), 0);
+        }
+
+        p.addr_drift_rate = 2.5;
+        assert_eq!(p.addr_center_at(0.0, 1000), 0);
+        assert_eq!(p.addr_center_at(1.0, 1000), 2);
+        assert_eq!(p.addr_center_at(100.0, 1000), 250);
+        assert_eq!(p.addr_center_at(399.9, 1000), 999);
+        // Wraps around the area.
+        assert_eq!(p.addr_center_at(400.0, 1000), 0);
+        assert_eq!(p.addr_center_at(500.0, 1000), 250);
+        assert_eq!(p.addr_center_at(100.0, 7), 250 % 7);
+        // An empty area has nowhere to drift to.
+        assert_eq!(p.addr_center_at(100.0, 0), 0);
+
+        assert!(load_params_str(r#"{ "addr_drift_rate": -1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_converge_deadline() {
+        let mut p = Params {
+            rps_target: 1000,
+            lat_target: 0.1,
+            ..Default::default()
+        };
+        let converged = p.convergence(1000.0, 0.1, 0.5);
+        let stuck = p.convergence(500.0, 0.3, 0.5);
+
+        // No timeout by default.
+        assert_eq!(p.converge_timeout_secs, 0.0);
+        assert!(!p.converge_deadline_exceeded(1e6, &stuck));
+
+        p.converge_timeout_secs = 60.0;
+        // Converged before the timeout and still converged after.
+        assert!(!p.converge_deadline_exceeded(30.0, &converged));
+        assert!(!p.converge_deadline_exceeded(90.0, &converged));
+        // Not converged yet but still within the deadline.
+        assert!(!p.converge_deadline_exceeded(59.9, &stuck));
+        // Timed out.
+        assert!(p.converge_deadline_exceeded(60.0, &stuck));
+        assert!(p.converge_deadline_exceeded(600.0, &stuck));
+
+        assert!(load_params_str(r#"{ "converge_timeout_secs": -1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_anon_histogram_rle() {
+        let dense = load_params_str(r#"{ "anon_histogram": [0, 0, 0, 5, 5, 0, 1] }"#).unwrap();
+        let rle = load_params_str(r#"{ "anon_histogram_rle": [[3, 0], [2, 5], [1, 0], [1, 1]] }"#)
+            .unwrap();
+        assert_eq!(rle.anon_histogram, vec![0, 0, 0, 5, 5, 0, 1]);
+        assert!(rle.anon_histogram_rle.is_empty());
+        assert_eq!(rle, dense);
+        // Saved dense.
+        let json = serde_json::to_string(&rle).unwrap();
+        assert!(json.contains("\"anon_histogram\":[0,0,0,5,5,0,1]"));
+        assert!(!json.contains("anon_histogram_rle"));
+
+        // Empty runs are fine, all zeros isn't.
+        let p = load_params_str(r#"{ "anon_histogram_rle": [[0, 7], [2, 1]] }"#).unwrap();
+        assert_eq!(p.anon_histogram, vec![1, 1]);
+        assert!(load_params_str(r#"{ "anon_histogram_rle": [[1000, 0]] }"#).is_err());
+        assert!(
+            load_params_str(r#"{ "anon_histogram": [1], "anon_histogram_rle": [[1, 1]] }"#)
+                .is_err()
+        );
+
+        // Oversized histograms are rejected the same way as dense ones.
+        let chunk = (dense.chunk_pages * *PAGE_SIZE) as u64;
+        let p = load_params_str(r#"{ "anon_histogram_rle": [[4, 1], [6, 0], [1, 1]] }"#).unwrap();
+        let dense =
+            load_params_str(r#"{ "anon_histogram": [1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 1] }"#).unwrap();
+        assert_eq!(p, dense);
+        assert!(p.check_anon_histogram(11 * chunk).is_ok());
+        assert!(p.check_anon_histogram(10 * chunk).is_err());
+        assert_eq!(
+            format!("{}", p.check_anon_histogram(10 * chunk).unwrap_err()),
+            format!("{}", dense.check_anon_histogram(10 * chunk).unwrap_err())
+        );
+
+        // Runs which would expand past the cap fail to load.
+        let body = format!(
+            r#"{{ "anon_histogram_rle": [[{}, 1], [{}, 1]] }}"#,
+            Params::HISTOGRAM_RLE_SLOTS_MAX,
+            usize::MAX
+        );
+        assert!(load_params_str(&body).is_err());
+
+        // And through patches.
+        let mut p = dense;
+        p.apply_patch(&ParamsPatch {
+            anon_histogram_rle: Some(vec![(2, 3)]),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(p.anon_histogram, vec![3, 3]);
+    }
+
+    #[test]
+    fn test_working_set_bytes() {
+        let p = Params {
+            mem_frac: 0.5,
+            file_frac: 0.25,
+            file_total_frac: 0.5,
+            file_addr_rps_base_frac: 0.4,
+            anon_addr_rps_base_frac: 0.2,
+            rps_max: 1000,
+            mem_total_override: Some(16 << 30),
+            ..Default::default()
+        };
+
+        // At rps_max, the whole footprints: 8G resident split 2G/6G with
+        // half of the file side in use.
+        let ws = p.working_set_bytes(1000, p.mem_total());
+        assert_eq!(
+            ws,
+            WorkingSet {
+                file_bytes: 1 << 30,
+                anon_bytes: 6 << 30,
+            }
+        );
+        assert_eq!(ws.total(), 7 << 30);
+        assert_eq!(p.working_set_bytes(5000, p.mem_total()), ws);
+
+        // At 0, only the base fracs.
+        let ws = p.working_set_bytes(0, p.mem_total());
+        assert_eq!(ws.file_bytes, (1u64 << 30) * 4 / 10);
+        assert_eq!(ws.anon_bytes, (6u64 << 30) * 2 / 10);
+
+        // Halfway in between.
+        let ws = p.working_set_bytes(500, p.mem_total());
+        assert_eq!(ws.file_bytes, (1u64 << 30) * 7 / 10);
+        assert_eq!(ws.anon_bytes, (6u64 << 30) * 6 / 10);
+
+        // Agrees with io_mix().
+        let mix = p.io_mix(500, p.mem_total());
+        assert_eq!(
+            (mix.file_bytes, mix.anon_bytes),
+            (ws.file_bytes, ws.anon_bytes)
+        );
+    }
+
+    #[test]
+    fn test_footprints() {
+        let gig = 1u64 << 30;
+
+        // Legacy, mem_frac split by file_frac.
+        let p = load_params_str(r#"{ "mem_frac": 0.5, "file_frac": 0.25 }"#).unwrap();
+        assert!(!p.has_decoupled_footprints());
+        assert_eq!(p.footprints(8 * gig), (3 * gig, gig));
+        assert!(p.validate().is_empty());
+
+        // Only one of the two doesn't change anything.
+        let q =
+            load_params_str(r#"{ "mem_frac": 0.5, "file_frac": 0.25, "anon_frac": 0.3 }"#).unwrap();
+        assert_eq!(q.footprints(8 * gig), (3 * gig, gig));
+        assert!(q.validate().iter().any(|w| w.contains("ignored")));
+
+        // Decoupled, mem_frac and file_frac follow.
+        let mut p = load_params_str(
+            r#"{ "mem_frac": 0.5, "file_frac": 0.25, "anon_frac": 0.125, "file_cache_frac": 0.5,
+                 "mem_total_override": "8G" }"#,
+        )
+        .unwrap();
+        assert!(p.has_decoupled_footprints());
+        assert_eq!(p.footprints(8 * gig), (gig, 4 * gig));
+        assert_eq!((p.mem_frac, p.file_frac), (0.625, 0.8));
+        let plan = p.resource_plan(p.mem_total(), 1);
+        assert_eq!(
+            (plan.anon_bytes, plan.file_bytes, plan.resident_bytes),
+            (gig, 4 * gig, 5 * gig)
+        );
+        assert_eq!(p.required_testfile_bytes(p.mem_total()), 4 * gig);
+
+        // They take precedence over patched legacy fields too.
+        p.apply_patch(&ParamsPatch {
+            mem_frac: Some(0.9),
+            file_cache_frac: Some(0.25),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(p.footprints(8 * gig), (gig, 2 * gig));
+        assert_eq!(p.mem_frac, 0.375);
+
+        // Clearing one falls back to the derived legacy fields.
+        p.apply_patch(&ParamsPatch {
+            anon_frac: Some(0.0),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!p.has_decoupled_footprints());
+        assert_eq!(p.footprints(8 * gig), (gig, 2 * gig));
+
+        // More than the whole memory can't be honored.
+        assert!(load_params_str(r#"{ "anon_frac": 0.9, "file_cache_frac": 0.9 }"#).is_err());
+        assert!(p
+            .apply_patch(&ParamsPatch {
+                anon_frac: Some(0.9),
+                file_cache_frac: Some(0.9),
+                ..Default::default()
+            })
+            .is_err());
+
+        // file_frac_min raises the derived file_frac and the footprints
+        // show what runs.
+        let p = load_params_str(
+            r#"{ "anon_frac": 0.5, "file_cache_frac": 0.01, "file_frac_min": 0.2 }"#,
+        )
+        .unwrap();
+        assert_eq!((p.mem_frac, p.file_frac), (0.51, 0.2));
+        let (anon_bytes, file_bytes) = p.footprints(8 * gig);
+        assert_eq!(
+            anon_bytes + file_bytes,
+            (8.0 * gig as f64 * 0.51).round() as u64
+        );
+        assert_eq!(file_bytes, (8.0 * gig as f64 * 0.51 * 0.2).round() as u64);
+    }
+
+    #[test]
+    fn test_mem_total_override() {
+        let p = Params::default();
+        assert_eq!(p.mem_total(), total_memory() as u64);
+        assert!(!p.as_json().unwrap()[PARAMS_DOC.len()..].contains("mem_total_override"));
+
+        let p = load_params_str(
+            r#"{ "mem_total_override": "8G", "mem_frac": 0.5, "file_frac": 0.25, "rps_max": 0 }"#,
+        )
+        .unwrap();
+        assert_eq!(p.mem_total(), 8 << 30);
+        assert_eq!(
+            p.working_set_bytes(100, p.mem_total()),
+            WorkingSet {
+                file_bytes: 1 << 30,
+                anon_bytes: 3 << 30,
+            }
+        );
+
+        // Only the interpretation changes, twice the memory, twice the bytes.
+        let p2 = Params {
+            mem_total_override: Some(16 << 30),
+            ..p.clone()
+        };
+        assert_eq!(
+            p2.working_set_bytes(100, p2.mem_total()).total(),
+            2 * p.working_set_bytes(100, p.mem_total()).total()
+        );
+    }
+
+    #[test]
+    fn test_io_mix() {
+        let chunk = 4 * *PAGE_SIZE as u64;
+        let p = Params {
+            chunk_pages: 4,
+            file_size_mean: 3 * chunk as usize,
+            anon_size_ratio: 2.0,
+            file_write_frac: 0.25,
+            anon_write_frac: 0.5,
+            mem_frac: 0.5,
+            file_frac: 0.25,
+            file_addr_rps_base_frac: 0.5,
+            anon_addr_rps_base_frac: 0.0,
+            rps_max: 1000,
+            mem_total_override: Some(16 << 30),
+            ..Default::default()
+        };
+        let mix = p.io_mix(100, p.mem_total());
+        assert_eq!(
+            mix,
+            IoMix {
+                file_read_bps: 100 * 3 * chunk * 3 / 4,
+                file_write_bps: 100 * 3 * chunk / 4,
+                anon_read_bps: 100 * 6 * chunk / 2,
+                anon_write_bps: 100 * 6 * chunk / 2,
+                file_bytes: (2 << 30) * 55 / 100,
+                anon_bytes: (6 << 30) / 10,
+            }
+        );
+        assert_eq!(mix.read_bps(), 100 * 3 * chunk * 7 / 4);
+        assert_eq!(mix.write_bps(), 100 * 3 * chunk * 5 / 4);
+
+        // Partial chunks are accessed whole.
+        let p2 = Params {
+            file_size_mean: 3 * chunk as usize - 1,
+            ..p.clone()
+        };
+        assert_eq!(
+            p2.io_mix(100, p2.mem_total()).file_write_bps,
+            mix.file_write_bps
+        );
+
+        // Writes cost more on write-amplified devices, reads don't change.
+        let p2 = Params {
+            write_cost_ratio: 4.0,
+            ..p.clone()
+        };
+        let amp = p2.io_mix(100, p2.mem_total());
+        assert_eq!(amp.file_write_bps, mix.file_write_bps * 4);
+        assert_eq!(amp.anon_write_bps, mix.anon_write_bps * 4);
+        assert_eq!(amp.file_read_bps, mix.file_read_bps);
+        assert_eq!(amp.anon_read_bps, mix.anon_read_bps);
+        assert!(load_params_str(r#"{ "write_cost_ratio": 0 }"#).is_err());
+
+        // Without rps_max, the footprints are full.
+        let p2 = Params { rps_max: 0, ..p };
+        let mix = p2.io_mix(100, p2.mem_total());
+        assert_eq!(mix.file_bytes, 2 << 30);
+        assert_eq!(mix.anon_bytes, 6 << 30);
+    }
+
+    #[test]
+    fn test_scaled_for_memory() {
+        let p = Params {
+            mem_frac: 0.6,
+            file_frac: 0.3,
+            lat_target: 0.05,
+            rps_target: 1000,
+            file_histogram: vec![1, 2],
+            anon_histogram: vec![4, 0, 2, 1],
+            anon_size_mean: Some(8 << 20),
+            ..Default::default()
+        };
+
+        let up = p.scaled_for_memory(32 << 30, 64 << 30);
+        assert_eq!(up.file_histogram, vec![1, 1, 2, 2]);
+        assert_eq!(up.anon_histogram, vec![4, 4, 0, 0, 2, 2, 1, 1]);
+        assert_eq!(up.anon_size_mean, Some(16 << 20));
+
+        let down = p.scaled_for_memory(32 << 30, 16 << 30);
+        assert_eq!(down.file_histogram, vec![3]);
+        assert_eq!(down.anon_histogram, vec![4, 3]);
+        assert_eq!(down.anon_size_mean, Some(4 << 20));
+
+        // Everything else is untouched.
+        for scaled in &[up, down] {
+            let mut scaled = scaled.clone();
+            scaled.file_histogram = p.file_histogram.clone();
+            scaled.anon_histogram = p.anon_histogram.clone();
+            scaled.anon_size_mean = p.anon_size_mean;
+            assert_eq!(scaled, p);
+        }
+
+        // Without histograms and anon_size_mean, nothing changes.
+        let p = Params::default();
+        assert_eq!(p.scaled_for_memory(32 << 30, 64 << 30), p);
+    }
+
+    #[test]
+    fn test_resource_plan() {
+        let p = Params {
+            mem_frac: 0.5,
+            file_frac: 0.25,
+            rps_max: 1000,
+            lat_target: 0.1,
+            log_bps: 4 << 20,
+            mem_total_override: Some(16 << 30),
+            ..Default::default()
+        };
+        let plan = p.resource_plan(p.mem_total(), 8);
+        assert_eq!(
+            plan,
+            ResourcePlan {
+                resident_bytes: 8 << 30,
+                file_bytes: 2 << 30,
+                anon_bytes: 6 << 30,
+                peak_workers: 100,
+                log_bps: 1000 * 4194,
+            }
+        );
+
+        // Without rps_max, the CPU count caps and the log rate isn't scaled.
+        let p = Params { rps_max: 0, ..p };
+        let plan = p.resource_plan(p.mem_total(), 8);
+        assert_eq!(plan.peak_workers, 8);
+        assert_eq!(plan.log_bps, 4 << 20);
+
+        // concurrency_max is the upper bound.
+        let p = Params {
+            concurrency_max: 4,
+            ..p
+        };
+        assert_eq!(p.resource_plan(p.mem_total(), 8).peak_workers, 4);
+    }
+
+    #[test]
+    fn test_log_bps_at_rps() {
+        let p = Params {
+            rps_max: 1000,
+            log_bps: 4 << 20,
+            ..Default::default()
+        };
+        let padding = p.log_padding();
+        assert_eq!(padding, 4194);
+        assert_eq!(p.log_bps_at_rps(0), 0);
+        assert_eq!(p.log_bps_at_rps(250), 250 * padding);
+        assert_eq!(p.log_bps_at_rps(1000), 1000 * padding);
+
+        let p = Params { rps_max: 0, ..p };
+        assert_eq!(p.log_bps_at_rps(0), 4 << 20);
+        assert_eq!(p.log_bps_at_rps(500), 4 << 20);
+    }
+
+    #[test]
+    fn test_log_rotation_secs() {
+        let p = load_params_str(r#"{ "rps_max": 1000, "log_bps": "1M", "log_max_bytes": "64M" }"#)
+            .unwrap();
+        assert_eq!(p.log_max_bytes, 64 << 20);
+        assert_eq!(p.log_fsync_secs, 0.0);
+
+        // 1M per second at rps_max, the padding rounds to 1049 bytes.
+        let secs = p.log_rotation_secs(1000).unwrap();
+        assert!((secs - (64 << 20) as f64 / 1_049_000.0).abs() < 0.001);
+        // Half the rps takes twice as long.
+        assert!((p.log_rotation_secs(500).unwrap() - secs * 2.0).abs() < 0.001);
+        // An hour at rps_max rotates this many times.
+        assert_eq!((3600.0 / secs) as u64, 56);
+
+        assert_eq!(p.log_rotation_secs(0), None);
+        let p = Params {
+            log_max_bytes: 0,
+            ..p
+        };
+        assert_eq!(p.log_rotation_secs(1000), None);
+
+        assert!(load_params_str(r#"{ "log_fsync_secs": -1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_hash_algo() {
+        let p = load_params_str("{}").unwrap();
+        assert_eq!(p.hash_algo, HashAlgo::Sha1);
+
+        for algo in &[
+            HashAlgo::Crc32,
+            HashAlgo::Sha1,
+            HashAlgo::Sha256,
+            HashAlgo::Sha512,
+        ] {
+            let p = load_params_str(&format!(r#"{{ "hash_algo": "{:?}" }}"#, algo)).unwrap();
+            assert_eq!(p.hash_algo, *algo);
+            assert_eq!(load_params_str(&p.as_json().unwrap()).unwrap(), p);
+        }
+        assert!(load_params_str(r#"{ "hash_algo": "Md5" }"#).is_err());
+    }
+
+    #[test]
+    fn test_file_direct() {
+        let p = load_params_str("{}").unwrap();
+        assert!(!p.file_direct);
+
+        let p = load_params_str(r#"{ "file_direct": true }"#).unwrap();
+        assert!(p.file_direct);
+        let json = p.as_json().unwrap();
+        assert!(json.contains("\"file_direct\": true"));
+        assert_eq!(load_params_str(&json).unwrap(), p);
+    }
+
+    #[test]
+    fn test_file_addr_dist() {
+        let p = load_params_str("{}").unwrap();
+        assert_eq!(p.file_addr_dist, FileAddrDist::Normal);
+
+        let p = load_params_str(r#"{ "file_addr_dist": { "Zipf": { "s": 1.2 } } }"#).unwrap();
+        assert_eq!(p.file_addr_dist, FileAddrDist::Zipf { s: 1.2 });
+    }
+
+    #[test]
+    fn test_anon_size_mean() {
+        // By default, anon accesses scale with file accesses.
+        let p = load_params_str(r#"{ "file_size_mean": "1M", "anon_size_ratio": 2.0 }"#).unwrap();
+        assert_eq!(p.anon_size_mean, None);
+        assert_eq!(p.anon_size_mean_bytes(), (2 << 20) as f64);
+
+        // An absolute size overrides the ratio.
+        let p = load_params_str(
+            r#"{ "file_size_mean": "1M", "anon_size_ratio": 2.0, "anon_size_mean": "256K" }"#,
+        )
+        .unwrap();
+        assert_eq!(p.anon_size_mean, Some(256 << 10));
+        assert_eq!(p.anon_size_mean_bytes(), (256 << 10) as f64);
+        assert!(p.as_json().unwrap().contains(r#""anon_size_mean": 262144"#));
+
+        // anon_histogram picks the addresses and leaves the size alone.
+        let p = load_params_str(
+            r#"{ "anon_size_ratio": 2.0, "anon_size_mean": "256K", "anon_histogram": [0, 1] }"#,
+        )
+        .unwrap();
+        assert_eq!(p.anon_size_mean_bytes(), (256 << 10) as f64);
+        assert_eq!(p.anon_histogram_normalized(), Some(vec![0.0, 1.0]));
+    }
+
+    #[test]
+    fn test_params_profiles() {
+        let profile = |body: &str| serde_json::from_str::<ParamsProfile>(body).unwrap();
+        let mut profiles = ParamsProfiles::default();
+        profiles.insert(
+            "base",
+            profile(r#"{ "rps_target": 1000, "lat_target": "100ms", "mem_frac": 0.6 }"#),
+        );
+        profiles.insert(
+            "throughput",
+            profile(r#"{ "inherits": "base", "rps_target": 4000, "concurrency_max": 2048 }"#),
+        );
+        profiles.insert(
+            "throughput-lat",
+            profile(r#"{ "inherits": "throughput", "lat_target": "50ms" }"#),
+        );
+
+        let p = profiles.resolve("throughput-lat").unwrap();
+        assert_eq!(p.rps_target, 4000);
+        assert_eq!(p.concurrency_max, 2048);
+        assert_eq!(p.lat_target, 0.05);
+        assert_eq!(p.mem_frac, 0.6);
+        assert_eq!(p.file_frac, Params::default().file_frac);
+
+        // The ancestors are unaffected.
+        let p = profiles.resolve("base").unwrap();
+        assert_eq!((p.rps_target, p.lat_target), (1000, 0.1));
+
+        assert!(profiles.resolve("nope").is_err());
+        profiles.insert("orphan", profile(r#"{ "inherits": "nope" }"#));
+        let e = profiles.resolve("orphan").unwrap_err().to_string();
+        assert!(e.contains("orphan") && e.contains("nope"), "{}", e);
+    }
+
+    #[test]
+    fn test_params_profiles_cycle() {
+        let profile = |parent: &str| ParamsProfile {
+            inherits: Some(parent.to_string()),
+            ..Default::default()
+        };
+        let mut profiles = ParamsProfiles::default();
+        profiles.insert("a", profile("b"));
+        profiles.insert("b", profile("c"));
+        profiles.insert("c", profile("a"));
+        let e = profiles.resolve("a").unwrap_err().to_string();
+        assert!(e.contains("cycle: a -> b -> c -> a"), "{}", e);
+
+        profiles.insert("self", profile("self"));
+        assert!(profiles.resolve("self").is_err());
+    }
+
+    #[test]
+    fn test_pid_validate() {
+        let err = |pid: PidParams| pid.validate("lat_pid").unwrap_err().to_string();
+
+        let e = err(PidParams {
+            kp: f64::NAN,
+            ..Default::default()
+        });
+        assert!(e.contains("lat_pid.kp") && e.contains("finite"), "{}", e);
+        let e = err(PidParams {
+            ki: f64::INFINITY,
+            ..Default::default()
+        });
+        assert!(e.contains("lat_pid.ki") && e.contains("finite"), "{}", e);
+        let e = err(PidParams {
+            i_min: 1.0,
+            i_max: -1.0,
+            ..Default::default()
+        });
+        assert!(e.contains("lat_pid.i_min"), "{}", e);
+
+        // Unbounded clamps are the default and fine.
+        assert!(PidParams::default().validate("lat_pid").is_ok());
+        assert!(Params::default().rps_pid.validate("rps_pid").is_ok());
+
+        // loaded() checks every controller.
+        let mut p = Params::default();
+        p.mem_pid.kd = f64::NEG_INFINITY;
+        let e = p.loaded(None).unwrap_err().to_string();
+        assert!(e.contains("mem_pid.kd"), "{}", e);
+
+        let e = load_params_str(r#"{ "rps_pid": { "kp": 0.1, "ki": -0.01 } }"#)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            e.contains("rps_pid.ki") && e.contains("non-negative"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_cpu_ratio_under_throttle() {
+        // Without cpu_ratio_min, throttling doesn't matter.
+        let mut p = Params {
+            cpu_ratio: 0.8,
+            ..Default::default()
+        };
+        assert_eq!(p.cpu_ratio_under_throttle(0.0), 0.8);
+        assert_eq!(p.cpu_ratio_under_throttle(1.0), 0.8);
+
+        // Nor does it when the floor equals cpu_ratio.
+        p.cpu_ratio_min = Some(0.8);
+        assert_eq!(p.cpu_ratio_under_throttle(1.0), 0.8);
+
+        p.cpu_ratio_min = Some(0.2);
+        assert_eq!(p.cpu_ratio_under_throttle(0.0), 0.8);
+        assert!((p.cpu_ratio_under_throttle(0.5) - 0.5).abs() < 0.000_001);
+        assert_eq!(p.cpu_ratio_under_throttle(1.0), 0.2);
+        assert_eq!(p.cpu_ratio_under_throttle(2.0), 0.2);
+
+        assert!(load_params_str(r#"{ "cpu_ratio_min": -0.1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_histogram_delta() {
+        let mut p = Params {
+            anon_histogram: vec![4, 3, 2, 1],
+            ..Default::default()
+        };
+        let delta = |index, weight| HistogramDelta { index, weight };
+
+        p.apply_histogram_delta(&[delta(1, 7)]).unwrap();
+        assert_eq!(p.anon_histogram, vec![4, 7, 2, 1]);
+
+        // Slots past the end are created, zero-filling the gap.
+        p.apply_histogram_delta(&[delta(6, 5), delta(0, 0)])
+            .unwrap();
+        assert_eq!(p.anon_histogram, vec![0, 7, 2, 1, 0, 0, 5]);
+
+        // Later deltas win.
+        p.apply_histogram_delta(&[delta(2, 9), delta(2, 8)])
+            .unwrap();
+        assert_eq!(p.anon_histogram[2], 8);
+
+        // Zeroing everything is rejected and leaves the histogram alone.
+        let before = p.anon_histogram.clone();
+        let zero_all: Vec<_> = (0..before.len()).map(|i| delta(i, 0)).collect();
+        assert!(p.apply_histogram_delta(&zero_all).is_err());
+        assert_eq!(p.anon_histogram, before);
+
+        // An empty histogram can be built up from scratch.
+        let mut p = Params::default();
+        p.apply_histogram_delta(&[delta(2, 1)]).unwrap();
+        assert_eq!(p.anon_histogram, vec![0, 0, 1]);
+    }
+
+    #[test]
+    fn test_anon_histogram() {
+        // Empty disables the histogram.
+        let p = load_params_str("{}").unwrap();
+        assert!(p.anon_histogram.is_empty());
+        assert!(p.anon_histogram_normalized().is_none());
+
+        // All zeros is rejected and can't be normalized.
+        assert!(load_params_str(r#"{ "anon_histogram": [0, 0, 0] }"#).is_err());
+        let p = Params {
+            anon_histogram: vec![0, 0, 0],
+            ..Default::default()
+        };
+        assert!(p.anon_histogram_normalized().is_none());
+
+        // Weights summing past u64::MAX still normalize.
+        let p = Params {
+            anon_histogram: vec![u64::MAX, u64::MAX],
+            ..Default::default()
+        };
+        assert_eq!(p.anon_histogram_normalized(), Some(vec![0.5, 0.5]));
+
+        // file_histogram gets the same checks.
+        assert!(load_params_str(r#"{ "file_histogram": [0, 0] }"#).is_err());
+        let p = Params {
+            file_histogram: vec![u64::MAX, 0, u64::MAX],
+            ..Default::default()
+        };
+        assert_eq!(p.file_histogram_normalized(), Some(vec![0.5, 0.0, 0.5]));
+
+        let p = load_params_str(r#"{ "anon_histogram": [1, 0, 3, 6] }"#).unwrap();
+        let probs = p.anon_histogram_normalized().unwrap();
+        let slot_bytes = (p.chunk_pages * *PAGE_SIZE) as u64;
+        assert!(p.check_anon_histogram(4 * slot_bytes).is_ok());
+        assert!(p.check_anon_histogram(3 * slot_bytes + 1).is_ok());
+        let e = p.check_anon_histogram(3 * slot_bytes).unwrap_err();
+        println!("{}", &e);
+        assert!(e.to_string().contains("4 slots"));
+        assert!(Params::default().check_anon_histogram(0).is_ok());
+        assert_eq!(probs, vec![0.1, 0.0, 0.3, 0.6]);
+        assert!((probs.iter().sum::<f64>() - 1.0).abs() < 0.000_001);
+    }
+
+    #[test]
+    fn test_addr_frac() {
+        let close = |a: f64, b: f64| (a - b).abs() < 0.000_001;
+
+        let p = Params {
+            rps_max: 1000,
+            file_addr_rps_base_frac: 0.2,
+            anon_addr_rps_base_frac: 0.6,
+            ..Default::default()
+        };
+        assert!(close(p.file_addr_frac(0), 0.2));
+        assert!(close(p.anon_addr_frac(0), 0.6));
+        assert!(close(p.file_addr_frac(500), 0.6));
+        assert!(close(p.anon_addr_frac(500), 0.8));
+        assert!(close(p.file_addr_frac(1000), 1.0));
+        assert!(close(p.anon_addr_frac(1000), 1.0));
+        assert!(close(p.file_addr_frac(2000), 1.0));
+
+        // rps_max of 0 disables scaling.
+        let p = Params { rps_max: 0, ..p };
+        assert!(close(p.file_addr_frac(0), 1.0));
+        assert!(close(p.anon_addr_frac(500), 1.0));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Params::default().validate().is_empty());
+
+        let p = Params {
+            concurrency_max: 0,
+            lat_target: -1.0,
+            rps_target: 2000,
+            rps_max: 1000,
+            file_addr_dist: FileAddrDist::Zipf { s: -1.0 },
+            file_direct: true,
+            file_frac: 0.8,
+            ..Default::default()
+        };
+        let warns = p.validate();
+        println!("{:#?}", &warns);
+        assert_eq!(warns.len(), 5);
+        assert!(warns[0].contains("concurrency_max"));
+        assert!(warns[1].contains("lat_target"));
+        assert!(warns[2].contains("rps_target 2000"));
+        assert!(warns[2].contains("rps_max 1000"));
+        assert!(warns[3].contains("Zipf"));
+        assert!(warns[4].contains("file_direct"));
+    }
+
+    #[test]
+    fn test_max_achievable_rps() {
+        // 16 workers each sleeping 20ms can't do more than 800 rps.
+        let p = Params {
+            concurrency_max: 16,
+            sleep_mean: 0.02,
+            rps_target: 10000,
+            ..Default::default()
+        };
+        assert_eq!(p.max_achievable_rps(), 800);
+        let warns = p.validate();
+        println!("{:#?}", &warns);
+        assert_eq!(warns.len(), 1);
+        assert!(warns[0].contains("unreachable"));
+        assert!(warns[0].contains("800"));
+
+        let p = Params {
+            rps_target: 500,
+            ..p
+        };
+        assert!(p.validate().is_empty());
+
+        // Without sleeps, lat_target bounds each request.
+        let p = Params {
+            sleep_mean: 0.0,
+            lat_target: 0.1,
+            ..p
+        };
+        assert_eq!(p.max_achievable_rps(), 160);
+    }
+
+    #[test]
+    fn test_pid_reset() {
+        let mut p = load_params_str(r#"{ "pid_reset": true }"#).unwrap();
+        assert!(!p.as_json().unwrap()[PARAMS_DOC.len()..].contains("pid_reset"));
+        assert!(p.take_pid_reset());
+        assert!(!p.take_pid_reset());
+
+        p.request_pid_reset();
+        assert!(p.is_pid_reset_requested());
+        assert!(p.take_pid_reset());
+
+        // Large setpoint jumps are detected by the dispatcher against the
+        // params it's running, loading only leaves explicit requests.
+        let mut prev = Params::default();
+        let mut p = Params {
+            lat_target: prev.lat_target * 1.5,
+            ..Default::default()
+        };
+        p.loaded(Some(&mut prev)).unwrap();
+        assert!(!p.is_setpoint_jump(&prev));
+
+        let mut p = Params {
+            rps_target: prev.rps_target / 4,
+            ..Default::default()
+        };
+        p.loaded(Some(&mut prev)).unwrap();
+        assert!(p.is_setpoint_jump(&prev));
+        assert!(!p.is_pid_reset_requested());
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut running = load_params_str(r#"{ "rps_target": 1000 }"#).unwrap();
+        assert!(!running.is_paused());
+        assert!(Params::default().rps_target > 0);
+
+        // Pausing and resuming keep the PID controller states.
+        let mut paused = load_params_str(r#"{ "rps_target": 0 }"#).unwrap();
+        assert!(paused.is_paused());
+        paused.loaded(Some(&mut running)).unwrap();
+        assert!(!paused.is_setpoint_jump(&running));
+
+        let mut resumed = running.clone();
+        resumed.loaded(Some(&mut paused)).unwrap();
+        assert!(!resumed.is_setpoint_jump(&paused));
+
+        // An explicit reset still goes through.
+        let mut p = load_params_str(r#"{ "rps_target": 0, "pid_reset": true }"#).unwrap();
+        p.loaded(Some(&mut running)).unwrap();
+        assert!(p.take_pid_reset());
+
+        // Neither does a pause mask a latency target jump.
+        let mut p = Params {
+            rps_target: 0,
+            lat_target: running.lat_target * 4.0,
+            ..Default::default()
+        };
+        p.loaded(Some(&mut running)).unwrap();
+        assert!(p.is_setpoint_jump(&running));
+    }
+
+    #[test]
+    fn test_autotune_lat() {
+        let close = |a: f64, b: f64| (a - b).abs() < 0.000_01;
+
+        // Ku = 4 * 0.1 / (pi * 0.2) = 0.63662, Tu = 10
+        let mut p = Params::default();
+        p.autotune_lat(10.0, 0.2).unwrap();
+        assert!(close(p.lat_pid.kp, 0.38197));
+        assert!(close(p.lat_pid.ki, 0.07639));
+        assert!(close(p.lat_pid.kd, 0.47746));
+
+        // Per-step gains follow control_period.
+        let mut p = Params {
+            control_period: 2.0,
+            ..Default::default()
+        };
+        p.autotune_lat(10.0, 0.2).unwrap();
+        assert!(close(p.lat_pid.kp, 0.38197));
+        assert!(close(p.lat_pid.ki, 0.15279));
+        assert!(close(p.lat_pid.kd, 0.23873));
+
+        assert!(p.autotune_lat(0.0, 0.2).is_err());
+        assert!(p.autotune_lat(10.0, -1.0).is_err());
+    }
+
+    #[test]
+    fn test_params_builder() {
+        assert_eq!(ParamsBuilder::new().build().unwrap(), Params::default());
+
+        let built = ParamsBuilder::new()
+            .rps_target(500)
+            .rps_max(1000)
+            .lat_target(0.05)
+            .lat_target_pct(0.95)
+            .mem_frac(1.5)
+            .file_frac(0.0)
+            .concurrency_max(32)
+            .build()
+            .unwrap();
+        let loaded = load_params_str(
+            r#"{
+                "rps_target": 500,
+                "rps_max": 1000,
+                "lat_target": 0.05,
+                "lat_target_pct": 0.95,
+                "mem_frac": 1.5,
+                "file_frac": 0.0,
+                "concurrency_max": 32
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(built, loaded);
+        assert_eq!(built.mem_frac, 1.0);
+        assert_eq!(built.file_frac, Params::FILE_FRAC_MIN);
+
+        assert!(ParamsBuilder::new().lat_target_pct(95.0).build().is_err());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let p = Params::default();
+        let fp = p.fingerprint();
+        assert_eq!(p.clone().fingerprint(), fp);
+        assert_eq!(Params::default().fingerprint(), fp);
+
+        let mut q = p.clone();
+        q.rps_target += 1;
+        assert_ne!(q.fingerprint(), fp);
+
+        let mut q = p.clone();
+        q.anon_histogram = vec![1, 2];
+        let hist_fp = q.fingerprint();
+        assert_ne!(hist_fp, fp);
+        q.anon_histogram = vec![2, 1];
+        assert_ne!(q.fingerprint(), hist_fp);
+
+        let mut q = p.clone();
+        q.lat_pid.kd += 0.01;
+        assert_ne!(q.fingerprint(), fp);
+
+        // Runtime-only state doesn't count.
+        let mut q = p.clone();
+        q.request_pid_reset();
+        assert_eq!(q.fingerprint(), fp);
+    }
+
+    #[test]
+    fn test_preamble_overrides() {
+        let p = Params::default();
+        assert_eq!(p.preamble_of().unwrap(), PARAMS_DOC);
+        assert!(p.as_json().unwrap().starts_with(PARAMS_DOC));
+
+        let mut p = Params {
+            rps_target: 1000,
+            ..Default::default()
+        };
+        p.lat_pid.kp = 0.5;
+        let pre = p.preamble_of().unwrap();
+        assert!(pre.starts_with(PARAMS_DOC));
+        let overrides: Vec<&str> = pre
+            .lines()
+            .filter(|line| line.starts_with("// overridden: "))
+            .collect();
+        assert_eq!(
+            overrides,
+            vec![
+                "// overridden: lat_pid.kp=0.5",
+                "// overridden: rps_target=1000"
+            ]
+        );
+
+        // The block is part of the preamble and doesn't affect loading.
+        let loaded = load_params_str(&p.as_json().unwrap()).unwrap();
+        assert_eq!(loaded.rps_target, 1000);
+        assert_eq!(loaded.lat_pid.kp, 0.5);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Params::default();
+        assert!(old.diff(&old.clone()).is_empty());
+
+        let mut new = old.clone();
+        new.rps_target = 1234;
+        new.lat_pid.kd = 0.5;
+        new.anon_histogram = vec![1; 32];
+
+        let diffs = old.diff(&new);
+        println!("{:#?}", &diffs);
+        assert_eq!(diffs.len(), 3);
+        assert!(diffs.contains(&(
+            "rps_target".into(),
+            old.rps_target.to_string(),
+            "1234".into()
+        )));
+        assert!(diffs.contains(&(
+            "lat_pid.kd".into(),
+            old.lat_pid.kd.to_string(),
+            "0.5".into()
+        )));
+        assert!(diffs.contains(&(
+            "anon_histogram".into(),
+            "[]".into(),
+            "[32 slots, sum 32]".into()
+        )));
+
+        // Unbounded clamps aren't serialized.
+        new.lat_pid.i_max = 0.1;
+        assert!(old
+            .diff(&new)
+            .contains(&("lat_pid.i_max".into(), "-".into(), "0.1".into())));
+    }
+
+    #[test]
+    fn test_apply_env_overrides() {
+        // Each case uses its own prefix as the tests run in parallel.
+        std::env::set_var("RD_HASHD_TEST_ENV1_RPS_TARGET", "1234");
+        std::env::set_var("RD_HASHD_TEST_ENV1_LAT_TARGET", "50ms");
+        std::env::set_var("RD_HASHD_TEST_ENV1_MEM_FRAC", "1.5");
+        std::env::set_var("RD_HASHD_TEST_ENV1_HASH_ALGO", "Sha256");
+        let mut p = Params::default();
+        p.apply_env_overrides("RD_HASHD_TEST_ENV1_").unwrap();
+        assert_eq!(p.rps_target, 1234);
+        assert_eq!(p.lat_target, 0.05);
+        assert_eq!(p.hash_algo, HashAlgo::Sha256);
+        // Clamped like a loaded file.
+        assert_eq!(p.mem_frac, 1.0);
+        // The rest is left alone.
+        assert_eq!(p.file_frac, Params::default().file_frac);
+
+        std::env::set_var("RD_HASHD_TEST_ENV2_RPS_TARGET", "lots");
+        let mut p = Params::default();
+        let err = p.apply_env_overrides("RD_HASHD_TEST_ENV2_").unwrap_err();
+        println!("{:#}", &err);
+        assert!(err.to_string().contains("RD_HASHD_TEST_ENV2_RPS_TARGET"));
+        assert_eq!(p, Params::default());
+
+        std::env::set_var("RD_HASHD_TEST_ENV3_NO_SUCH_FIELD", "1");
+        let err = p.apply_env_overrides("RD_HASHD_TEST_ENV3_").unwrap_err();
+        assert!(err.to_string().contains("RD_HASHD_TEST_ENV3_NO_SUCH_FIELD"));
+
+        // Nothing set, nothing changes.
+        p.apply_env_overrides("RD_HASHD_TEST_ENV4_").unwrap();
+        assert_eq!(p.fingerprint(), Params::default().fingerprint());
+
+        // Optional fields can be set and reset.
+        std::env::set_var("RD_HASHD_TEST_ENV5_RNG_SEED", "5");
+        p.apply_env_overrides("RD_HASHD_TEST_ENV5_").unwrap();
+        assert_eq!(p.rng_seed, Some(5));
+        std::env::set_var("RD_HASHD_TEST_ENV6_RNG_SEED", "null");
+        p.apply_env_overrides("RD_HASHD_TEST_ENV6_").unwrap();
+        assert_eq!(p.rng_seed, None);
+    }
+
+    #[test]
+    fn test_trace_path() {
+        let p = Params::default();
+        assert!(!p.has_trace());
+        assert!(!p.as_json().unwrap()[PARAMS_DOC.len()..].contains("trace_path"));
+
+        let path = std::env::temp_dir().join(format!(
+            "rd-hashd-intf-test-trace-{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(&path, "# chunk size write\n0 4096 0\n7 64K 1\n").unwrap();
+        let p = load_params_str(&format!(
+            r#"{{ "trace_path": {:?}, "file_histogram": [1, 2] }}"#,
+            &path
+        ))
+        .unwrap();
+        assert!(p.has_trace());
+        assert_eq!(p.trace_path.as_ref(), Some(&path));
+
+        // The trace is verified on load.
+        std::fs::write(&path, "0 4096 maybe\n").unwrap();
+        let e = load_params_str(&format!(r#"{{ "trace_path": {:?} }}"#, &path)).unwrap_err();
+        println!("{:#}", &e);
+        assert!(format!("{:#}", &e).contains("line 1"));
+
+        // Reloads and patches which keep trace_path don't read it again.
+        let mut q = p.clone();
+        q.loaded(Some(&mut p.clone())).unwrap();
+        let mut q = p.clone();
+        q.apply_patch(&ParamsPatch {
+            rps_target: Some(100),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut r = Params::default();
+        assert!(r.clone().loaded(Some(&mut q)).is_ok());
+        assert!(q.clone().loaded(None).is_err());
+        assert!(q.clone().loaded(Some(&mut r)).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(load_params_str(&format!(r#"{{ "trace_path": {:?} }}"#, &path)).is_err());
+    }
+
+    #[test]
+    fn test_think_service_split() {
+        // By default, all of the sleep is latency.
+        let p = Params {
+            sleep_mean: 0.02,
+            ..Default::default()
+        };
+        assert!(!p.has_service_time());
+        assert_eq!(p.think_service_split(0.02, 0.005), (0.0, 0.02));
+
+        // With service time, only it counts and the sleep is think time.
+        let p = load_params_str(r#"{ "sleep_mean": "20ms", "service_mean": "5ms" }"#).unwrap();
+        assert!(p.has_service_time());
+        assert_eq!(p.service_mean, 0.005);
+        assert_eq!(p.think_service_split(0.02, 0.005), (0.02, 0.005));
+        let (_, lat0) = p.think_service_split(0.0, 0.005);
+        let (_, lat1) = p.think_service_split(1.0, 0.005);
+        assert_eq!(lat0, lat1);
+        let (_, lat2) = p.think_service_split(0.02, 0.01);
+        assert!(lat2 > lat1);
+
+        // Each worker cycles through both.
+        let p = Params {
+            concurrency_max: 10,
+            ..p
+        };
+        assert_eq!(p.max_achievable_rps(), 400);
+
+        assert!(load_params_str(r#"{ "service_mean": -1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_assertions() {
+        let p = load_params_str(
+            r#"{ "rps_target": 800, "lat_target": 0.1, "assertions": [
+                   { "field": "Rps", "op": ">=", "threshold": 0.9, "of_target": true },
+                   { "field": "LatP99", "op": "<", "threshold": 0.25 } ] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            p.assertions,
+            vec![
+                ParamAssertion {
+                    field: AssertField::Rps,
+                    op: AssertOp::Ge,
+                    threshold: 0.9,
+                    of_target: true
+                },
+                ParamAssertion {
+                    field: AssertField::LatP99,
+                    op: AssertOp::Lt,
+                    threshold: 0.25,
+                    of_target: false
+                },
+            ]
+        );
+        let (rps, lat) = (&p.assertions[0], &p.assertions[1]);
+        assert_eq!(rps.threshold_for(&p), 720.0);
+        assert!(rps.holds(&p, 720.0));
+        assert!(!rps.holds(&p, 719.0));
+        assert!(!rps.holds(&p, f64::NAN));
+        assert_eq!(lat.threshold_for(&p), 0.25);
+        assert!(lat.holds(&p, 0.2));
+        assert!(!lat.holds(&p, 0.25));
+
+        let json = p.as_json().unwrap();
+        assert!(json.contains(r#""op": ">=""#));
+        assert_eq!(load_params_str(&json).unwrap().assertions, p.assertions);
+        assert!(!Params::default().as_json().unwrap()[PARAMS_DOC.len()..].contains("assertions"));
+
+        for bad in &[
+            r#"{ "field": "Rps", "op": "=", "threshold": 1 }"#,
+            r#"{ "field": "Joules", "op": ">", "threshold": 1 }"#,
+            r#"{ "field": "Rps", "op": ">" }"#,
+        ] {
+            assert!(load_params_str(&format!(r#"{{ "assertions": [{}] }}"#, bad)).is_err());
+        }
+        let mut p = Params::default();
+        assert!(p
+            .apply_patch(&ParamsPatch {
+                assertions: Some(vec![ParamAssertion {
+                    field: AssertField::Lat,
+                    op: AssertOp::Le,
+                    threshold: f64::INFINITY,
+                    of_target: false,
+                }]),
+                ..Default::default()
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_degraded() {
+        let p = load_params_str(r#"{ "mem_frac": 0.5, "control_period": 2.0 }"#).unwrap();
+        assert!(!p.degraded());
+        assert!(p.degraded_reasons().is_empty());
+        assert!(!Params::default().degraded());
+
+        // Rounding noise is clamped quietly.
+        let p = load_params_str(r#"{ "file_frac": 1.0000000001 }"#).unwrap();
+        assert_eq!(p.file_frac, 1.0);
+        assert!(!p.degraded());
+
+        let mut p = load_params_str(
+            r#"{ "mem_frac": 1.5, "anon_write_frac": -0.2, "control_period": 0.0,
+                 "file_total_frac": 0.0, "file_frac_min": 0.1, "file_frac": 0.05 }"#,
+        )
+        .unwrap();
+        println!("{:#?}", &p.degraded_reasons());
+        assert!(p.degraded());
+        assert_eq!(p.degraded_reasons().len(), 4);
+        for (reason, name) in p.degraded_reasons().iter().zip(&[
+            "control_period",
+            "mem_frac",
+            "anon_write_frac",
+            "file_total_frac",
+        ]) {
+            assert!(reason.starts_with(&format!("{} ", name)), "{}", reason);
+        }
+        assert_eq!((p.mem_frac, p.anon_write_frac), (1.0, 0.0));
+        assert_eq!(p.file_frac, 0.1);
+
+        // Patching a clamped field replaces its reason.
+        p.apply_patch(&ParamsPatch {
+            mem_frac: Some(0.5),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(p.degraded_reasons().len(), 3);
+        assert!(!p
+            .degraded_reasons()
+            .iter()
+            .any(|r| r.starts_with("mem_frac ")));
+        assert!(p.degraded());
+    }
+
+    #[test]
+    fn test_provenance() {
+        let p = Params::default();
+        assert_eq!(p.provenance()["rps_target"], Source::Default);
+
+        let mut p =
+            load_params_str(r#"{ "rps": 100, "mem_frac": 0.5, "file_frac": 0.2 }"#).unwrap();
+        assert_eq!(p.provenance()["rps_target"], Source::File);
+        assert_eq!(p.provenance()["mem_frac"], Source::File);
+        assert_eq!(p.provenance()["lat_target"], Source::Default);
+
+        std::env::set_var("RD_HASHD_TEST_PROV_MEM_FRAC", "0.7");
+        p.apply_env_overrides("RD_HASHD_TEST_PROV_").unwrap();
+        assert_eq!(p.mem_frac, 0.7);
+        assert_eq!(p.provenance()["mem_frac"], Source::Env);
+        assert_eq!(p.provenance()["file_frac"], Source::File);
+        assert_eq!(p.provenance()["lat_target"], Source::Default);
+
+        p.apply_patch(&ParamsPatch {
+            file_frac: Some(0.3),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(p.provenance()["file_frac"], Source::Patch);
+        assert_eq!(p.provenance()["mem_frac"], Source::Env);
+
+        // Doesn't affect comparisons.
+        let mut q = p.clone();
+        q.provenance = Default::default();
+        assert_eq!(p, q);
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let mut p = ParamsBuilder::new()
+            .rps_target(100)
+            .file_frac(0.5)
+            .build()
+            .unwrap();
+        let prev = p.clone();
+
+        let patch: ParamsPatch = serde_json::from_str(
+            r#"{
+                "rps_target": 200,
+                "lat_target": 0.02,
+                "file_frac": 3.0
+            }"#,
+        )
+        .unwrap();
+        assert!(patch.mem_frac.is_none());
+        p.apply_patch(&patch).unwrap();
+
+        assert_eq!(p.rps_target, 200);
+        assert_eq!(p.lat_target, 0.02);
+        assert_eq!(p.file_frac, 1.0);
+
+        let diffs = prev.diff(&p);
+        assert_eq!(diffs.len(), 3);
+        for field in &["rps_target", "lat_target", "file_frac"] {
+            assert!(diffs.iter().any(|d| d.0 == *field));
+        }
+
+        // An empty patch is a no-op.
+        p.apply_patch(&Default::default()).unwrap();
+        assert_eq!(prev.diff(&p).len(), 3);
+
+        // A failing patch changes nothing, provenance included.
+        let before = p.clone();
+        let patch: ParamsPatch =
+            serde_json::from_str(r#"{ "rps_target": 300, "file_sync_every": 0 }"#).unwrap();
+        assert!(p.apply_patch(&patch).is_err());
+        assert_eq!(p, before);
+        assert_eq!(p.provenance(), before.provenance());
+        assert_eq!(p.provenance()["file_sync_every"], Source::Default);
+
+        // Optional fields are set to None with null and left alone when
+        // omitted.
+        let patch: ParamsPatch = serde_json::from_str(
+            r#"{ "trace_path": "/tmp/trace", "anon_size_mean": "1M", "rng_seed": 7 }"#,
+        )
+        .unwrap();
+        let mut p = Params::default();
+        p.overwrite_from_patch(&patch, Source::Patch);
+        assert_eq!(p.trace_path, Some(std::path::PathBuf::from("/tmp/trace")));
+        assert_eq!(p.anon_size_mean, Some(1 << 20));
+
+        let patch: ParamsPatch = serde_json::from_str(
+            r#"{
+                "trace_path": null,
+                "anon_size_mean": null,
+                "lat_target_ramp": null,
+                "cpu_ratio_min": null,
+                "cpu_affinity": null,
+                "mem_total_override": null
+            }"#,
+        )
+        .unwrap();
+        let mut fields = patch.fields();
+        fields.sort();
+        assert_eq!(
+            fields,
+            vec![
+                "anon_size_mean",
+                "cpu_affinity",
+                "cpu_ratio_min",
+                "lat_target_ramp",
+                "mem_total_override",
+                "trace_path"
+            ]
+        );
+        p.apply_patch(&patch).unwrap();
+        assert_eq!((p.trace_path.as_ref(), p.anon_size_mean), (None, None));
+        assert_eq!(p.rng_seed, Some(7));
+        assert_eq!(p.provenance()["trace_path"], Source::Patch);
+        assert_eq!(Params::default().provenance()["rng_seed"], Source::Default);
+    }
+
+    #[test]
+    fn test_version() {
+        // v0 files don't have the key and get upgraded.
+        let p = load_params_str(r#"{ "rps_target": 100 }"#).unwrap();
+        assert_eq!(p.version, Params::VERSION);
+        assert_eq!(p.rps_target, 100);
+        assert!(p
+            .as_json()
+            .unwrap()
+            .contains(&format!("\"version\": {}", Params::VERSION)));
+
+        let p = load_params_str(&format!(r#"{{ "version": {} }}"#, Params::VERSION)).unwrap();
+        assert_eq!(p.version, Params::VERSION);
+
+        let e = load_params_str(&format!(r#"{{ "version": {} }}"#, Params::VERSION + 1))
+            .unwrap_err()
+            .to_string();
+        println!("{}", &e);
+        assert!(e.contains("newer"));
+    }
+
+    #[test]
+    fn test_toml() {
+        static SEQ: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "rd-hashd-intf-test-params-{}-{}.toml",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let mut p = ParamsBuilder::new().rps_target(1000).build().unwrap();
+        p.anon_histogram = vec![1, 0, 7, 3];
+        p.file_addr_dist = FileAddrDist::Zipf { s: 1.1 };
+        p.lat_pid.i_max = 0.5;
+        p.mem_pid.d_on_measurement = false;
+
+        p.save_toml(&path).unwrap();
+        let toml = std::fs::read_to_string(&path).unwrap();
+        assert!(toml.starts_with("#\n# rd-hashd runtime parameters\n"));
+        // The overridden fields are listed like in JSON.
+        let overridden: Vec<&str> = toml
+            .lines()
+            .filter(|line| line.starts_with("# overridden: "))
+            .collect();
+        assert!(overridden.contains(&"# overridden: rps_target=1000"));
+        assert!(overridden.contains(&"# overridden: lat_pid.i_max=0.5"));
+
+        let loaded = Params::load_toml(&path).unwrap();
+
+        // loaded() runs on TOML files too.
+        std::fs::write(&path, "file_sync_every = 0\n").unwrap();
+        assert!(Params::load_toml(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, p);
+        assert_eq!(loaded, load_params_str(&p.as_json().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema = Params::json_schema();
+        let props = schema["properties"].as_object().unwrap();
+
+        // Provenance covers every ParamsPatch field, including the unset
+        // nullable ones which aren't serialized.
+        let dfl = Params::default();
+        let fields = dfl.provenance();
+        for (name, _) in fields.iter() {
+            assert!(props.contains_key(name), "{} missing from schema", name);
+            assert!(
+                props[name]["description"].is_string(),
+                "{} undocumented",
+                name
+            );
+        }
+        // version isn't patchable.
+        assert!(props.contains_key("version"));
+        assert_eq!(props.len(), fields.len() + 1);
+
+        for (name, prop) in props.iter() {
+            if name.ends_with("_frac") {
+                assert!(prop["minimum"].as_f64().unwrap() >= 0.0, "{}", name);
+                assert_eq!(prop["maximum"].as_f64(), Some(1.0), "{}", name);
+            }
+        }
+        assert_eq!(props["mem_frac"]["minimum"], 0.0);
+        assert_eq!(props["rps_target"]["type"], "integer");
+        assert_eq!(
+            props["lat_target_pct"]["maximum"],
+            Params::LAT_TARGET_PCT_MAX
+        );
+        assert_eq!(props["lat_pid"]["properties"]["kp"]["default"], 0.1);
+    }
+
+    #[test]
+    fn test_human_units() {
+        let p = load_params_str(
+            r#"{
+                "control_period": "1.5s",
+                "lat_target": "75ms",
+                "sleep_mean": "20ms",
+                "file_size_mean": "1.2M",
+                "log_bps": "1200Ki"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(p.control_period, 1.5);
+        assert_eq!(p.lat_target, 0.075);
+        assert_eq!(p.sleep_mean, 0.02);
+        assert_eq!(p.file_size_mean, 1258291);
+        assert_eq!(p.log_bps, 1200 << 10);
+
+        // Bare numbers still work.
+        let p2 = load_params_str(r#"{ "sleep_mean": 0.02, "file_size_mean": 1258291 }"#).unwrap();
+        assert_eq!(p2.sleep_mean, p.sleep_mean);
+        assert_eq!(p2.file_size_mean, p.file_size_mean);
+
+        // Saved in canonical form, falling back to numbers if inexact.
+        let json = p.as_json().unwrap();
+        println!("{}", &json[PARAMS_DOC.len()..]);
+        assert!(json.contains(r#""control_period": "1500ms""#));
+        assert!(json.contains(r#""lat_target": "75ms""#));
+        assert!(json.contains(r#""sleep_mean": "20ms""#));
+        assert!(json.contains(r#""file_size_mean": 1258291"#));
+        assert!(json.contains(r#""log_bps": "1200K""#));
+        assert_eq!(load_params_str(&json).unwrap(), p);
+
+        assert!(load_params_str(r#"{ "sleep_mean": "20 parsecs" }"#).is_err());
+
+        let patch: ParamsPatch = serde_json::from_str(r#"{ "lat_target": "100ms" }"#).unwrap();
+        assert_eq!(patch.lat_target, Some(0.1));
+    }
+
+    #[test]
+    fn test_required_testfile_bytes() {
+        let mem_total_override = Some(16 << 30);
+
+        let p = Params {
+            mem_total_override,
+            ..Default::default()
+        };
+        assert_eq!(p.file_total_frac, 1.0);
+        // 16G * 0.8 * 0.25
+        assert_eq!(p.required_testfile_bytes(p.mem_total()), 3435974656);
+
+        let p = Params {
+            mem_frac: 0.5,
+            file_frac: 0.5,
+            file_total_frac: 0.25,
+            mem_total_override,
+            ..Default::default()
+        };
+        assert_eq!(p.required_testfile_bytes(p.mem_total()), 1 << 30);
+
+        // Rounded up to the page size.
+        let p = Params {
+            mem_total_override: Some(1 << 20 | 1),
+            ..p
+        };
+        let bytes = p.required_testfile_bytes(p.mem_total());
+        assert_eq!(bytes % *PAGE_SIZE as u64, 0);
+        assert!(bytes >= (1 << 16));
+    }
+
+    #[test]
+    fn test_params_view() {
+        let mut p = load_params_str(
+            r#"{ "lat_target": 0.075, "file_size_mean": 2097152, "log_bps": 1572864 }"#,
+        )
+        .unwrap();
+        let mut view = ParamsView::new(&mut p);
+        assert!((view.lat_target_ms() - 75.0).abs() < 0.000_001);
+        assert_eq!(view.file_size_mean_mib(), 2.0);
+        assert_eq!(view.log_mbps(), 1.5);
+
+        view.set_lat_target_ms(20.0);
+        view.set_file_size_mean_mib(0.5);
+        view.set_log_mbps(4.0);
+        assert!((view.lat_target_ms() - 20.0).abs() < 0.000_001);
+        assert_eq!(view.file_size_mean_mib(), 0.5);
+        assert_eq!(view.log_mbps(), 4.0);
+
+        assert!((p.lat_target - 0.02).abs() < 0.000_001);
+        assert_eq!(p.file_size_mean, 512 << 10);
+        assert_eq!(p.log_bps, 4 << 20);
+
+        // The new lat_target isn't overridden by the stale lat_targets.
+        let lat_target = p.lat_target;
+        p.apply_patch(&Default::default()).unwrap();
+        assert_eq!(p.lat_target, lat_target);
+    }
+
+    #[test]
+    fn test_params_summary() {
+        let p = load_params_str(
+            r#"{ "rps_target": 900, "rps_max": 1200, "lat_targets": [[0.9, 0.05], [0.99, 0.2]],
+                 "concurrency_min": 4, "concurrency_max": 64, "mem_frac": 0.5,
+                 "file_frac": 0.3, "anon_write_frac": 0.1,
+                 "anon_histogram": [11, 22, 33, 44] }"#,
+        )
+        .unwrap();
+        let sum = ParamsSummary::from(&p);
+        assert_eq!(
+            (
+                sum.rps_target,
+                sum.rps_max,
+                sum.concurrency_min,
+                sum.concurrency_max
+            ),
+            (900, 1200, 4, 64)
+        );
+        assert_eq!(sum.lat_targets, vec![(0.9, 0.05), (0.99, 0.2)]);
+        assert_eq!((sum.mem_frac, sum.file_frac), (0.5, 0.3));
+        assert_eq!((sum.file_write_frac, sum.anon_write_frac), (0.0, 0.1));
+        assert_eq!((sum.anon_histogram_len, sum.file_histogram_len), (4, 0));
+
+        let json = serde_json::to_string(&sum).unwrap();
+        println!("{}", &json);
+        assert!(json.contains(r#""anon_histogram_len":4"#));
+        assert!(!json.contains("33"));
+        let keys = match serde_json::to_value(&sum).unwrap() {
+            serde_json::Value::Object(map) => map.len(),
+            _ => panic!(),
+        };
+        assert_eq!(keys, 14);
+    }
+
+    #[test]
+    fn test_lat_error() {
+        let p = Params {
+            lat_target: 0.1,
+            ..Default::default()
+        };
+        // Weight 0 only looks at the target percentile, however bad the tail.
+        assert_eq!(p.lat_error(0.05, 10.0), 0.5);
+        assert_eq!(p.lat_error(0.2, f64::INFINITY), -1.0);
+        assert_eq!(p.lat_tail_blend(0.05, f64::NAN), 0.05);
+
+        let p = Params {
+            lat_tail_weight: 1.0,
+            ..p
+        };
+        assert_eq!(p.lat_error(0.05, 0.1), 0.0);
+        assert!((p.lat_error(0.05, 0.3) + 2.0).abs() < 0.000_001);
+
+        let p = Params {
+            lat_tail_weight: 0.5,
+            ..p
+        };
+        assert!((p.lat_error(0.05, 0.25) + 0.5).abs() < 0.000_001);
+
+        let p = load_params_str(r#"{ "lat_tail_weight": 3.0 }"#).unwrap();
+        assert_eq!(p.lat_tail_weight, 1.0);
+    }
+
+    #[test]
+    fn test_lat_targets() {
+        // The legacy single target form.
+        let p = load_params_str(r#"{ "lat_target_pct": 0.99, "lat_target": 0.05 }"#).unwrap();
+        assert!(p.lat_targets.is_empty());
+        assert_eq!(p.lat_target_list(), vec![(0.99, 0.05)]);
+        assert!(!p.as_json().unwrap()[PARAMS_DOC.len()..].contains("lat_targets"));
+
+        // Two targets, the first one is reflected in the legacy fields.
+        let p = load_params_str(r#"{ "lat_targets": [[0.95, 0.075], [0.99, 0.15]] }"#).unwrap();
+        assert_eq!(p.lat_target_pct, 0.95);
+        assert_eq!(p.lat_target, 0.075);
+        assert_eq!(p.lat_target_list(), vec![(0.95, 0.075), (0.99, 0.15)]);
+        assert_eq!(load_params_str(&p.as_json().unwrap()).unwrap(), p);
+
+        // Changing the legacy fields by any means survives reloading.
+        let mut q = p.clone();
+        q.apply_patch(&ParamsPatch {
+            lat_target: Some(0.1),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(q.lat_target_list(), vec![(0.95, 0.1), (0.99, 0.15)]);
+
+        let mut q = p.clone();
+        std::env::set_var("RD_HASHD_TEST_LAT_TARGETS_LAT_TARGET", "90ms");
+        q.apply_env_overrides("RD_HASHD_TEST_LAT_TARGETS_").unwrap();
+        assert_eq!(q.lat_target, 0.09);
+
+        let mut q = p.clone();
+        ParamField::LatTargetPct.set(&mut q, 0.9);
+        q.loaded(None).unwrap();
+        assert_eq!(q.lat_target_list(), vec![(0.9, 0.075), (0.99, 0.15)]);
+
+        let mut q = p.clone();
+        ParamsView::new(&mut q).set_lat_target_ms(60.0);
+        q.loaded(None).unwrap();
+        assert_eq!(q.lat_target, 0.06);
+
+        let e = load_params_str(r#"{ "lat_targets": [[0.95, 0.075], [99.0, 0.15]] }"#)
+            .unwrap_err()
+            .to_string();
+        println!("{}", &e);
+        assert!(e.starts_with("lat_targets[1] percentile 99 out of range"));
+    }
+
+    #[test]
+    fn test_cpu_affinity() {
+        let online = online_cpus().unwrap();
+
+        let p = load_params_str("{}").unwrap();
+        assert!(p.cpu_affinity.is_none());
+
+        let p = load_params_str(&format!(r#"{{ "cpu_affinity": [{}] }}"#, online[0])).unwrap();
+        assert_eq!(p.cpu_affinity, Some(vec![online[0]]));
+
+        let offline = online.iter().max().unwrap() + 1;
+        let e = load_params_str(&format!(r#"{{ "cpu_affinity": [{}] }}"#, offline)).unwrap_err();
+        println!("{}", &e);
+        assert!(e.to_string().contains("not online"));
+        assert!(load_params_str(r#"{ "cpu_affinity": [] }"#).is_err());
+    }
+
+    #[test]
+    fn test_sample_distributions() {
+        let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+        let p = Params::default();
+
+        let sizes = p.sample_file_sizes(16384, 1);
+        assert_eq!(sizes, p.sample_file_sizes(16384, 1));
+        assert_ne!(sizes, p.sample_file_sizes(16384, 2));
+        let sizes: Vec<f64> = sizes.iter().map(|&v| v as f64).collect();
+        let err = (mean(&sizes) - p.file_size_mean as f64).abs() / p.file_size_mean as f64;
+        println!("file size mean err={:.3}", err);
+        assert!(err < 0.05);
+
+        let lognormal = Params {
+            file_size_dist: FileSizeDist::LogNormal,
+            ..Default::default()
+        };
+        let sizes: Vec<f64> = lognormal
+            .sample_file_sizes(65536, 1)
+            .iter()
+            .map(|&v| v as f64)
+            .collect();
+        let err = (mean(&sizes) - p.file_size_mean as f64).abs() / p.file_size_mean as f64;
+        println!("lognormal file size mean err={:.3}", err);
+        assert!(err < 0.1);
+
+        let sleeps = p.sample_sleep(16384, 1);
+        assert_eq!(sleeps, p.sample_sleep(16384, 1));
+        let err = (mean(&sleeps) - p.sleep_mean).abs() / p.sleep_mean;
+        println!("sleep mean err={:.3}", err);
+        assert!(err < 0.05);
+        assert!(sleeps.iter().all(|&v| v >= 0.0 && v <= 2.0 * p.sleep_mean));
+
+        // A narrow normal barely blends into uniform and stays near slot 0.
+        let narrow = Params {
+            file_addr_stdev_ratio: 0.05,
+            ..Default::default()
+        };
+        let addrs = narrow.sample_file_addrs(16384, 1000, 1);
+        assert_eq!(addrs, narrow.sample_file_addrs(16384, 1000, 1));
+        assert!(addrs.iter().all(|&a| a < 1000));
+        let addrs: Vec<f64> = addrs.iter().map(|&v| v as f64).collect();
+        println!("narrow addr mean={:.1}", mean(&addrs));
+        assert!(mean(&addrs) < 100.0);
+
+        let zipf = Params {
+            file_addr_dist: FileAddrDist::Zipf { s: 1.0 },
+            ..Default::default()
+        };
+        let addrs = zipf.sample_file_addrs(16384, 64, 1);
+        let head = addrs.iter().filter(|&&a| a == 0).count();
+        let tail = addrs.iter().filter(|&&a| a == 63).count();
+        assert!(head > 10 * tail);
+
+        let hist = Params {
+            file_histogram: vec![0, 1, 0, 0],
+            ..Default::default()
+        };
+        let addrs = hist.sample_file_addrs(1024, 100, 1);
+        assert!(addrs.iter().all(|&a| a == 25));
+        assert!(hist.sample_file_addrs(16, 0, 1).is_empty());
     }
 }
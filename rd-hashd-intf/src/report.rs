@@ -103,6 +103,124 @@ impl<T: Into<f64>> ops::DivAssign<T> for Latencies {
     }
 }
 
+/// Latency histogram with fixed bucket boundaries. `bounds[i]` is the
+/// inclusive upper bound of `counts[i]` and the extra last count is for
+/// everything above the last bound. Unlike `Latencies`, histograms from
+/// different sources can be summed as long as the bounds match.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    pub bounds: Vec<f64>,
+    pub counts: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    pub fn new(bounds: Vec<f64>) -> Self {
+        let counts = vec![0; bounds.len() + 1];
+        Self { bounds, counts }
+    }
+
+    /// `nr_buckets` bounds spaced logarithmically over `[min, max]`.
+    pub fn log_spaced(nr_buckets: usize, min: f64, max: f64) -> Self {
+        let bounds = match nr_buckets {
+            0 => vec![],
+            1 => vec![max],
+            _ => {
+                let step = (max / min).ln() / (nr_buckets - 1) as f64;
+                (0..nr_buckets)
+                    .map(|i| min * (step * i as f64).exp())
+                    .collect()
+            }
+        };
+        Self::new(bounds)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bounds.is_empty()
+    }
+
+    pub fn nr_samples(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn insert(&mut self, lat: f64) {
+        if self.counts.len() != self.bounds.len() + 1 {
+            return;
+        }
+        let idx = self.bounds.partition_point(|bound| *bound < lat);
+        self.counts[idx] += 1;
+    }
+
+    /// Upper bound of the bucket containing the `p`'th percentile, `p` in
+    /// `[0.0, 1.0]`. Samples above the last bound are reported as the last
+    /// bound. 0.0 if there are no samples.
+    pub fn percentile(&self, p: f64) -> f64 {
+        let total = self.nr_samples();
+        if total == 0 || self.bounds.is_empty() {
+            return 0.0;
+        }
+        let rank = ((p.max(0.0).min(1.0) * total as f64).ceil() as u64).max(1);
+        let mut acc = 0;
+        for (idx, cnt) in self.counts.iter().enumerate() {
+            acc += cnt;
+            if acc >= rank {
+                return self.bounds[idx.min(self.bounds.len() - 1)];
+            }
+        }
+        self.bounds[self.bounds.len() - 1]
+    }
+}
+
+impl ops::AddAssign<&LatencyHistogram> for LatencyHistogram {
+    fn add_assign(&mut self, rhs: &LatencyHistogram) {
+        if self.is_empty() {
+            *self = rhs.clone();
+        } else if self.bounds == rhs.bounds {
+            for (lhs, rhs) in self.counts.iter_mut().zip(rhs.counts.iter()) {
+                *lhs += rhs;
+            }
+        }
+    }
+}
+
+/// Access distribution over `acc_dist_slots` equal-size slots of the file
+/// or anon area, in number of accessed pages per slot.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessDist {
+    pub slots: Vec<u64>,
+}
+
+impl AccessDist {
+    pub fn total(&self) -> u64 {
+        self.slots.iter().sum()
+    }
+
+    /// The `k` most accessed slots as `(index, count)` pairs, busiest first.
+    /// Ties are broken by the lower index.
+    pub fn top_k(&self, k: usize) -> Vec<(usize, u64)> {
+        let mut sorted: Vec<(usize, u64)> = self.slots.iter().cloned().enumerate().collect();
+        sorted.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        sorted.truncate(k);
+        sorted
+    }
+
+    /// Cumulative fraction of accesses up to and including each slot. All
+    /// zeros if there were no accesses.
+    pub fn cdf(&self) -> Vec<f64> {
+        let total = self.total();
+        let mut acc = 0;
+        self.slots
+            .iter()
+            .map(|cnt| {
+                acc += cnt;
+                match total {
+                    0 => 0.0,
+                    _ => acc as f64 / total as f64,
+                }
+            })
+            .collect()
+    }
+}
+
 const STAT_DOC: &str = "\
 //  rps: Request per second in the last control period
 //  concurrency: Current number of active worker threads
@@ -113,8 +231,19 @@ const STAT_DOC: &str = "\
 //  nr_done: Total number of hashes calculated
 //  nr_workers: Number of worker threads
 //  nr_idle_workers: Number of idle workers
+//  queue_depth: Number of requests waiting in the dispatch queue
+//               (params.queue_max)
+//  nr_dropped: Total number of requests dropped because the dispatch queue
+//              was full
+//  nr_read_mismatches: Total number of file pages which failed verification
+//                      (params.verify_reads)
+//  assertion_violations: Number of control periods which violated each of
+//                        params.assertions since they were last changed
 //  lat.p*: Latency percentiles
 //  lat.ctl: Latency percentile used for rps control (params.lat_target_pct)
+//  lat_hist.bounds: Latency histogram bucket upper bounds (params.lat_hist_buckets)
+//  lat_hist.counts: Completions in each bucket since the last report, the last
+//                   one counts the ones above the last bound
 ";
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -128,12 +257,21 @@ pub struct Stat {
     pub nr_done: u64,
     pub nr_workers: usize,
     pub nr_idle_workers: usize,
+    #[serde(default)]
+    pub queue_depth: usize,
+    #[serde(default)]
+    pub nr_dropped: u64,
     pub lat: Latencies,
+    pub lat_hist: LatencyHistogram,
 
     pub file_size: u64,
     pub file_dist: Vec<u64>,
     pub anon_size: usize,
     pub anon_dist: Vec<u64>,
+    #[serde(default)]
+    pub nr_read_mismatches: u64,
+    #[serde(default)]
+    pub assertion_violations: Vec<u64>,
 }
 
 impl ops::AddAssign<&Stat> for Stat {
@@ -147,11 +285,47 @@ impl ops::AddAssign<&Stat> for Stat {
         self.nr_done += rhs.nr_done;
         self.nr_workers += rhs.nr_workers;
         self.nr_idle_workers += rhs.nr_idle_workers;
+        self.queue_depth += rhs.queue_depth;
+        self.nr_dropped += rhs.nr_dropped;
+        self.nr_read_mismatches += rhs.nr_read_mismatches;
+        if self.assertion_violations.len() < rhs.assertion_violations.len() {
+            self.assertion_violations
+                .resize(rhs.assertion_violations.len(), 0);
+        }
+        for (cnt, rcnt) in self
+            .assertion_violations
+            .iter_mut()
+            .zip(rhs.assertion_violations.iter())
+        {
+            *cnt += rcnt;
+        }
         self.lat += &rhs.lat;
+        self.lat_hist += &rhs.lat_hist;
     }
 }
 
 impl Stat {
+    /// File access distribution since the last report, None if
+    /// `acc_dist_slots` is 0.
+    pub fn file_access_dist(&self) -> Option<AccessDist> {
+        Self::access_dist(&self.file_dist)
+    }
+
+    /// Anon access distribution since the last report, None if
+    /// `acc_dist_slots` is 0.
+    pub fn anon_access_dist(&self) -> Option<AccessDist> {
+        Self::access_dist(&self.anon_dist)
+    }
+
+    fn access_dist(slots: &[u64]) -> Option<AccessDist> {
+        match slots.is_empty() {
+            true => None,
+            false => Some(AccessDist {
+                slots: slots.to_vec(),
+            }),
+        }
+    }
+
     pub fn avg<T: Into<f64>>(&mut self, div: T)
     where
         Latencies: ops::DivAssign<f64>,
@@ -166,6 +340,12 @@ impl Stat {
         self.nr_done = (self.nr_done as f64 / divf64).round() as u64;
         self.nr_workers = (self.nr_workers as f64 / divf64).round() as usize;
         self.nr_idle_workers = (self.nr_idle_workers as f64 / divf64).round() as usize;
+        self.queue_depth = (self.queue_depth as f64 / divf64).round() as usize;
+        self.nr_dropped = (self.nr_dropped as f64 / divf64).round() as u64;
+        self.nr_read_mismatches = (self.nr_read_mismatches as f64 / divf64).round() as u64;
+        for cnt in self.assertion_violations.iter_mut() {
+            *cnt = (*cnt as f64 / divf64).round() as u64;
+        }
         self.lat /= divf64;
     }
 }
@@ -224,3 +404,83 @@ impl JsonSave for Report {
         Some(REPORT_DOC_HEADER.to_string() + STAT_DOC + "//\n")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessDist, LatencyHistogram, Stat};
+
+    #[test]
+    fn test_access_dist() {
+        let dist = AccessDist {
+            slots: vec![1, 5, 0, 2, 5, 7],
+        };
+        assert_eq!(dist.total(), 20);
+        assert_eq!(dist.top_k(3), vec![(5, 7), (1, 5), (4, 5)]);
+        assert_eq!(dist.top_k(0), vec![]);
+        assert_eq!(dist.top_k(10).len(), 6);
+
+        let cdf = dist.cdf();
+        assert_eq!(cdf, vec![0.05, 0.3, 0.3, 0.4, 0.65, 1.0]);
+
+        let dist = AccessDist { slots: vec![0; 4] };
+        assert_eq!(dist.cdf(), vec![0.0; 4]);
+        assert_eq!(AccessDist::default().cdf(), Vec::<f64>::new());
+
+        let stat = Stat {
+            file_dist: vec![1, 2],
+            ..Default::default()
+        };
+        assert_eq!(stat.file_access_dist().unwrap().slots, vec![1, 2]);
+        assert_eq!(stat.anon_access_dist(), None);
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut hist = LatencyHistogram::log_spaced(41, 0.0001, 10.0);
+        assert_eq!(hist.bounds.len(), 41);
+        assert_eq!(hist.counts.len(), 42);
+        assert!((hist.bounds[0] - 0.0001).abs() < 1e-12);
+        assert!((hist.bounds[40] - 10.0).abs() < 1e-9);
+        assert_eq!(hist.percentile(0.5), 0.0);
+
+        // 1ms to 100ms in 0.1ms steps.
+        for i in 10..=1000 {
+            hist.insert(i as f64 / 10000.0);
+        }
+        assert_eq!(hist.nr_samples(), 991);
+
+        // Each bucket spans a factor of 10^(5/40), the reported bound should
+        // be at or above the exact value but within one bucket.
+        let ratio = 10f64.powf(5.0 / 40.0);
+        for (p, exact) in &[(0.5, 0.0505), (0.95, 0.0951), (0.99, 0.0991)] {
+            let v = hist.percentile(*p);
+            println!("p{}: exact={} hist={}", p * 100.0, exact, v);
+            assert!(v >= *exact && v <= exact * ratio * 1.000_001);
+        }
+        assert!(hist.percentile(0.0) <= 0.001 * ratio);
+        assert!(hist.percentile(1.0) >= 0.1);
+
+        // Overflow is reported as the last bound.
+        let mut hist = LatencyHistogram::new(vec![0.01, 0.1]);
+        hist.insert(0.005);
+        hist.insert(0.01);
+        hist.insert(5.0);
+        assert_eq!(hist.counts, vec![2, 0, 1]);
+        assert_eq!(hist.percentile(0.5), 0.01);
+        assert_eq!(hist.percentile(0.99), 0.1);
+
+        // Summing.
+        let mut sum = LatencyHistogram::default();
+        sum += &hist;
+        sum += &hist;
+        assert_eq!(sum.counts, vec![4, 0, 2]);
+        sum += &LatencyHistogram::new(vec![1.0]);
+        assert_eq!(sum.counts, vec![4, 0, 2]);
+
+        let json = serde_json::to_string(&sum).unwrap();
+        assert_eq!(
+            serde_json::from_str::<LatencyHistogram>(&json).unwrap(),
+            sum
+        );
+    }
+}
@@ -0,0 +1,314 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+
+//! Samplers for the size, sleep and address distributions described by
+//! `Params`. rd-hashd draws from these and `Params::sample_*()` use them to
+//! expose the same distributions for visualization.
+use log::{debug, warn};
+use rand_distr::{Distribution, LogNormal, Normal, Uniform};
+
+use super::params::erfc;
+use super::{FileSizeDist, Params};
+use rd_util::*;
+
+/// Normal distribution with clamps. The portion of the distribution which is
+/// cut off by the clamps uniformly raise the distribution within the clamps
+/// such that it gradually transforms into uniform distribution as stdev
+/// increases. `with_uniform_frac()` sets the uniform portion explicitly.
+pub struct ClampedNormal {
+    normal: Normal<f64>,
+    uniform: Uniform<f64>,
+    left: f64,
+    right: f64,
+    uniform_frac: Option<f64>,
+}
+
+impl ClampedNormal {
+    // Give up and go uniform after this many out-of-range normal samples.
+    const REJECT_TRIES: usize = 64;
+
+    /// Like `new()` but also truncated `trunc_stdevs` stdevs away from the
+    /// mean. 0 doesn't truncate further.
+    pub fn truncated(mean: f64, stdev: f64, left: f64, right: f64, trunc_stdevs: f64) -> Self {
+        if trunc_stdevs > 0.0 {
+            let (tl, tr) = (
+                left.max(mean - trunc_stdevs * stdev),
+                right.min(mean + trunc_stdevs * stdev),
+            );
+            if tl <= tr {
+                return Self::new(mean, stdev, tl, tr);
+            }
+        }
+        Self::new(mean, stdev, left, right)
+    }
+
+    pub fn new(mean: f64, stdev: f64, left: f64, right: f64) -> Self {
+        assert!(left <= right, "ClampedNormal left={} right={}", left, right);
+        Self {
+            normal: Normal::new(mean, stdev).unwrap(),
+            uniform: Uniform::new_inclusive(left, right),
+            left,
+            right,
+            uniform_frac: None,
+        }
+    }
+
+    /// Pick uniformly `frac` of the time and from the normal distribution
+    /// within the clamps otherwise. `None` keeps the single draw of `new()`.
+    /// If the clamps hold so little of the normal distribution that
+    /// rejection would take more than `REJECT_TRIES` draws on average, it's
+    /// practically flat within them and all picks are uniform.
+    pub fn with_uniform_frac(mut self, frac: Option<f64>) -> Self {
+        let flat = self.in_range_prob() * (Self::REJECT_TRIES as f64) < 1.0;
+        self.uniform_frac = frac.map(|frac| match flat {
+            true => 1.0,
+            false => frac,
+        });
+        self
+    }
+
+    /// The probability of a normal sample landing within the clamps.
+    fn in_range_prob(&self) -> f64 {
+        let (mean, stdev) = (self.normal.mean(), self.normal.std_dev());
+        if stdev <= 0.0 {
+            return (self.left <= mean && mean <= self.right) as u32 as f64;
+        }
+        let cdf = |x: f64| 0.5 * erfc((mean - x) / (stdev * std::f64::consts::SQRT_2));
+        cdf(self.right) - cdf(self.left)
+    }
+
+    pub fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        if let Some(frac) = self.uniform_frac {
+            if rng.gen::<f64>() < frac {
+                return self.uniform.sample(rng);
+            }
+            for _ in 0..Self::REJECT_TRIES {
+                let v = self.normal.sample(rng);
+                if self.left <= v && v <= self.right {
+                    return v;
+                }
+            }
+            return self.uniform.sample(rng);
+        }
+
+        let v = self.normal.sample(rng);
+        if self.left <= v && v <= self.right {
+            v
+        } else {
+            self.uniform.sample(rng)
+        }
+    }
+}
+
+/// Discrete distribution over `[0, n)` where each index is picked with
+/// probability proportional to its weight.
+pub struct WeightedSampler {
+    cdf: Vec<f64>,
+    uniform: Uniform<f64>,
+}
+
+impl WeightedSampler {
+    pub fn new<I: IntoIterator<Item = f64>>(weights: I) -> Self {
+        let mut cdf: Vec<f64> = weights
+            .into_iter()
+            .scan(0.0, |sum, w| {
+                *sum += w;
+                Some(*sum)
+            })
+            .collect();
+        let sum = *cdf.last().expect("WeightedSampler without weights");
+        assert!(sum > 0.0, "WeightedSampler weights sum={}", sum);
+        for c in cdf.iter_mut() {
+            *c /= sum;
+        }
+        Self {
+            cdf,
+            uniform: Uniform::new(0.0, 1.0),
+        }
+    }
+
+    /// Zipf distribution. Index `k` is picked with probability proportional
+    /// to `1 / (k + 1)^s`.
+    pub fn zipf(n: usize, s: f64) -> Self {
+        Self::new((1..=n.max(1)).map(|k| 1.0 / (k as f64).powf(s)))
+    }
+
+    pub fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        let v = self.uniform.sample(rng);
+        self.cdf.partition_point(|&c| c < v).min(self.cdf.len() - 1)
+    }
+}
+
+/// File access size distribution selected by `Params::file_size_dist` or
+/// the `Empirical` inverse CDF of `Params::file_size_percentiles`.
+pub enum FileSizeSampler {
+    Normal(ClampedNormal),
+    LogNormal(LogNormal<f64>, f64),
+    Empirical(Vec<(f64, f64)>, Uniform<f64>),
+}
+
+impl FileSizeSampler {
+    /// The top-level sampler, `Empirical` if `file_size_percentiles` is set.
+    pub fn for_params(params: &Params) -> Self {
+        if params.file_size_percentiles.is_empty() {
+            return Self::new(params, params.file_size_mean as f64);
+        }
+        debug!("file: size_percentiles={:?}", &params.file_size_percentiles);
+        Self::Empirical(
+            params
+                .file_size_percentiles
+                .iter()
+                .map(|&(pct, size)| (pct, size as f64))
+                .collect(),
+            Uniform::new_inclusive(0.0, 1.0),
+        )
+    }
+
+    /// Size at percentile `pct` of the inverse CDF `points`, interpolated
+    /// linearly between the points and flat beyond the ends.
+    fn inverse_cdf(points: &[(f64, f64)], pct: f64) -> f64 {
+        let idx = points.partition_point(|&(p, _)| p < pct);
+        if idx == 0 {
+            return points[0].1;
+        }
+        if idx == points.len() {
+            return points[idx - 1].1;
+        }
+        let ((p0, s0), (p1, s1)) = (points[idx - 1], points[idx]);
+        match p1 > p0 {
+            true => s0 + (s1 - s0) * (pct - p0) / (p1 - p0),
+            false => s1,
+        }
+    }
+
+    pub fn new(params: &Params, size_mean: f64) -> Self {
+        let mut size_stdev = size_mean * params.file_size_stdev_ratio;
+
+        debug!(
+            "file: size_dist={:?} size_mean={} size_stdev={}",
+            params.file_size_dist,
+            size_mean.round(),
+            size_stdev.round(),
+        );
+
+        if params.file_size_dist == FileSizeDist::LogNormal {
+            match LogNormal::from_mean_cv(size_mean.max(1.0), params.file_size_stdev_ratio) {
+                Ok(lognormal) => return Self::LogNormal(lognormal, *PAGE_SIZE as f64),
+                Err(e) => {
+                    warn!(
+                        "file: LogNormal size distribution failed ({:?}), using Normal",
+                        &e
+                    );
+                    if !size_stdev.is_finite() {
+                        size_stdev = 0.0;
+                    }
+                }
+            }
+        }
+        Self::Normal(ClampedNormal::truncated(
+            size_mean,
+            size_stdev,
+            *PAGE_SIZE as f64,
+            2.0 * size_mean,
+            params.size_trunc_stdevs,
+        ))
+    }
+
+    pub fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match self {
+            Self::Normal(normal) => normal.sample(rng),
+            Self::LogNormal(lognormal, min) => lognormal.sample(rng).max(*min),
+            Self::Empirical(points, uniform) => Self::inverse_cdf(points, uniform.sample(rng)),
+        }
+    }
+}
+
+/// Per-request workload class selection for `Params::classes`. Each class
+/// has its own file size distribution and file_frac.
+pub struct ClassSampler {
+    weights: WeightedSampler,
+    classes: Vec<(FileSizeSampler, f64)>,
+}
+
+impl ClassSampler {
+    /// None if `params.classes` is empty.
+    pub fn new(params: &Params) -> Option<Self> {
+        if params.classes.is_empty() {
+            return None;
+        }
+        debug!("dispatch: {} workload classes", params.classes.len());
+        Some(Self {
+            weights: WeightedSampler::new(params.classes.iter().map(|c| c.weight)),
+            classes: params
+                .classes
+                .iter()
+                .map(|c| {
+                    (
+                        FileSizeSampler::new(params, c.file_size_mean as f64),
+                        c.file_frac,
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    /// Pick a class and return its file size sampler and file_frac.
+    pub fn pick<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> (&FileSizeSampler, f64) {
+        let (sampler, file_frac) = &self.classes[self.weights.sample(rng)];
+        (sampler, *file_frac)
+    }
+}
+
+/// Sleep duration distribution, `Mixture` if `Params::sleep_modes` is set.
+pub enum SleepSampler {
+    Normal(ClampedNormal),
+    Mixture(WeightedSampler, Vec<ClampedNormal>),
+    Jittered(Box<SleepSampler>, Uniform<f64>),
+}
+
+impl SleepSampler {
+    pub fn new(params: &Params) -> Self {
+        let sampler = match params.sleep_modes.is_empty() {
+            true => Self::Normal(Self::normal(params.sleep_mean, params.sleep_stdev_ratio)),
+            false => Self::Mixture(
+                WeightedSampler::new(params.sleep_modes.iter().map(|m| m.0)),
+                params
+                    .sleep_modes
+                    .iter()
+                    .map(|&(_, mean, stdev_ratio)| Self::normal(mean, stdev_ratio))
+                    .collect(),
+            ),
+        };
+        let jitter_max = params.sleep_mean * params.sleep_jitter_frac;
+        match jitter_max > 0.0 {
+            true => Self::Jittered(Box::new(sampler), Uniform::new_inclusive(0.0, jitter_max)),
+            false => sampler,
+        }
+    }
+
+    /// Service time sampler, see `Params::think_service_split()`.
+    pub fn service(params: &Params) -> Self {
+        Self::Normal(Self::normal(
+            params.service_mean,
+            params.service_stdev_ratio,
+        ))
+    }
+
+    fn normal(sleep_mean: f64, sleep_stdev_ratio: f64) -> ClampedNormal {
+        let sleep_stdev = sleep_mean * sleep_stdev_ratio;
+
+        debug!(
+            "anon: sleep_mean={} sleep_stdev={:.2}",
+            sleep_mean, sleep_stdev
+        );
+
+        ClampedNormal::new(sleep_mean, sleep_stdev, 0.0, 2.0 * sleep_mean)
+    }
+
+    pub fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match self {
+            Self::Normal(normal) => normal.sample(rng),
+            Self::Mixture(modes, normals) => normals[modes.sample(rng)].sample(rng),
+            Self::Jittered(sampler, jitter) => sampler.sample(rng) + jitter.sample(rng),
+        }
+    }
+}
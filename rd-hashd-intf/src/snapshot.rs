@@ -0,0 +1,97 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::Params;
+use rd_util::*;
+
+/// Live state of a PID controller. `last_error` and `last_pv` are the error
+/// and process value of the previous step and `None` before the first one.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PidState {
+    pub integral: f64,
+    pub last_error: Option<f64>,
+    pub last_pv: Option<f64>,
+}
+
+/// `Params`, the PID controller states and the operating point they drove
+/// of a running rd-hashd so that a restart can resume from the same spot.
+/// `concurrency`, `concurrency_max` and the effective `mem_frac` are 0.0 in
+/// snapshots which predate them and left alone on restore. Loading validates
+/// `params` the same way as a params file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    pub params: Params,
+    pub lat_pid: PidState,
+    pub rps_pid: PidState,
+    pub mem_pid: PidState,
+    pub concurrency: f64,
+    pub concurrency_max: f64,
+    pub mem_frac: f64,
+}
+
+impl JsonLoad for Snapshot {
+    fn loaded(&mut self, prev: Option<&mut Self>) -> Result<()> {
+        self.params.loaded(prev.map(|prev| &mut prev.params))
+    }
+}
+
+impl JsonSave for Snapshot {}
+
+#[cfg(test)]
+mod tests {
+    use super::{PidState, Snapshot};
+    use rd_util::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn load_snapshot_str(body: &str) -> anyhow::Result<Snapshot> {
+        static SEQ: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "rd-hashd-intf-test-snapshot-{}-{}.json",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, body).unwrap();
+        let result = JsonConfigFile::<Snapshot>::load(&path).map(|f| f.data);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut snap = Snapshot::default();
+        snap.params.rps_target = 1234;
+        snap.lat_pid = PidState {
+            integral: -0.123_456_789_012_345_67,
+            last_error: Some(0.25),
+            last_pv: None,
+        };
+        snap.mem_pid.integral = 1.0 / 3.0;
+        snap.concurrency = 12.5;
+        snap.concurrency_max = 40.0;
+        snap.mem_frac = 0.6;
+
+        let loaded = load_snapshot_str(&snap.as_json().unwrap()).unwrap();
+        assert_eq!(loaded.lat_pid.integral, snap.lat_pid.integral);
+        assert_eq!(loaded.mem_pid.integral, snap.mem_pid.integral);
+        assert_eq!(loaded.lat_pid, snap.lat_pid);
+        assert_eq!(loaded.params.rps_target, 1234);
+        assert_eq!(
+            (loaded.concurrency, loaded.concurrency_max, loaded.mem_frac),
+            (12.5, 40.0, 0.6)
+        );
+
+        // Snapshots which predate the operating point load with zeros.
+        let loaded = load_snapshot_str(r#"{ "lat_pid": { "integral": 0.5 } }"#).unwrap();
+        assert_eq!(loaded.lat_pid.integral, 0.5);
+        assert_eq!(loaded.concurrency, 0.0);
+        assert_eq!(loaded.mem_frac, 0.0);
+
+        // The embedded params go through the usual validation.
+        let e = load_snapshot_str(r#"{ "params": { "lat_target_pct": 99.0 } }"#).unwrap_err();
+        println!("{}", &e);
+        assert!(e.to_string().contains("lat_target_pct"));
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+
+//! Recorded file access traces which `Params::trace_path` can point to.
+//!
+//! A trace is a text file with one `CHUNK_INDEX SIZE IS_WRITE` record per
+//! line. `CHUNK_INDEX` is the position of the first accessed chunk in the
+//! testfiles in units of `chunk_pages`, `SIZE` the number of bytes accessed
+//! from there, either plain or with a unit suffix, and `IS_WRITE` is `0` or
+//! `1`. Blank lines and lines starting with `#` are ignored.
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+use rd_util::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub chunk: u64,
+    pub size: usize,
+    pub is_write: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessTrace {
+    pub records: Vec<TraceRecord>,
+}
+
+impl AccessTrace {
+    fn parse_record(line: &str) -> Result<TraceRecord> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 {
+            bail!(
+                "expected CHUNK_INDEX SIZE IS_WRITE, got {} fields",
+                fields.len()
+            );
+        }
+        let chunk = fields[0]
+            .parse::<u64>()
+            .with_context(|| format!("invalid chunk index {:?}", fields[0]))?;
+        let size = parse_size(fields[1])? as usize;
+        if size == 0 {
+            bail!("size is zero");
+        }
+        let is_write = match fields[2] {
+            "0" => false,
+            "1" => true,
+            v => bail!("IS_WRITE should be 0 or 1, got {:?}", v),
+        };
+        Ok(TraceRecord {
+            chunk,
+            size,
+            is_write,
+        })
+    }
+
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut records = vec![];
+        for (idx, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            records.push(Self::parse_record(line).with_context(|| format!("line {}", idx + 1))?);
+        }
+        if records.is_empty() {
+            bail!("no records");
+        }
+        Ok(Self { records })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let input = std::fs::read_to_string(path)
+            .with_context(|| format!("reading access trace {:?}", path))?;
+        Self::parse(&input).with_context(|| format!("parsing access trace {:?}", path))
+    }
+
+    /// The `seq`'th record, wrapping around at the end.
+    pub fn get(&self, seq: u64) -> &TraceRecord {
+        &self.records[(seq % self.records.len() as u64) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessTrace, TraceRecord};
+
+    #[test]
+    fn test_parse() {
+        let trace = AccessTrace::parse(
+            "# chunk size write
+             0 4096 0
+
+             17 1M 1
+             3 8192 0
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            trace.records,
+            vec![
+                TraceRecord {
+                    chunk: 0,
+                    size: 4096,
+                    is_write: false
+                },
+                TraceRecord {
+                    chunk: 17,
+                    size: 1 << 20,
+                    is_write: true
+                },
+                TraceRecord {
+                    chunk: 3,
+                    size: 8192,
+                    is_write: false
+                },
+            ]
+        );
+        assert_eq!(trace.get(1).chunk, 17);
+        assert_eq!(trace.get(4).chunk, 17);
+
+        for bad in &["", "# nothing\n", "1 4096", "x 4096 0", "1 0 0", "1 4096 w"] {
+            assert!(AccessTrace::parse(bad).is_err(), "{:?}", bad);
+        }
+        let err = AccessTrace::parse("1 4096 0\n2 4096 2\n").unwrap_err();
+        assert!(format!("{:#}", err).contains("line 2"));
+    }
+}
@@ -3,7 +3,6 @@ use crossbeam::channel::{self, select, tick, Receiver, Sender};
 use linreg::linear_regression_of;
 use log::{debug, error, info, warn};
 use num::Integer;
-use pid::Pid;
 use std::collections::VecDeque;
 use std::fmt::Write;
 use std::rc::Rc;
@@ -12,10 +11,11 @@ use std::thread::{sleep, spawn, JoinHandle};
 use std::time::{Duration, Instant};
 use std::u32;
 
-use rd_hashd_intf::{params, Params, Phase, Report, Stat};
+use rd_hashd_intf::{params, FileSync, Params, Phase, Report, Stat};
 use rd_util::*;
 
 use super::hasher;
+use super::pid::Pid;
 use super::testfiles::TestFiles;
 use super::{create_logger, report_tick, Args, TestFilesProgressBar, TESTFILE_UNIT_SIZE};
 
@@ -182,6 +182,7 @@ impl Cfg {
                     kp: 0.25,
                     ki: 0.01,
                     kd: 0.01,
+                    ..Default::default()
                 },
                 rounds: 10,
                 converge: ConvergeCfg {
@@ -647,7 +648,7 @@ impl Bench {
     }
 
     fn time_hash(size: usize, params: &Params, tf: &TestFiles) -> f64 {
-        let mut hasher = hasher::Hasher::new(1.0, 0.0);
+        let mut hasher = hasher::Hasher::new(1.0, 0.0, false, params.hash_algo);
         let chunk_size = params.chunk_pages * *PAGE_SIZE;
         let chunks_per_unit = (tf.unit_size as usize).div_ceil(&chunk_size);
 
@@ -657,16 +658,22 @@ impl Bench {
             let path = tf.path((i / chunks_per_unit) as u64);
             let off = ((i % chunks_per_unit) * params.chunk_pages) as u64;
 
-            hasher.load(&path, off, chunk_size, false).expect(&format!(
-                "failed to load chunk {}, chunk_size={} chunks_per_unit={} path={:?} off={}",
-                i, chunk_size, chunks_per_unit, &path, off
-            ));
+            hasher
+                .load(&path, off, chunk_size, false, FileSync::None)
+                .expect(&format!(
+                    "failed to load chunk {}, chunk_size={} chunks_per_unit={} path={:?} off={}",
+                    i, chunk_size, chunks_per_unit, &path, off
+                ));
         }
-        hasher.sha1();
+        hasher.digest();
 
         Instant::now().duration_since(started_at).as_secs_f64()
     }
 
+    fn calc_time_per_byte(size: usize, params: &Params, tf: &TestFiles) -> f64 {
+        Self::time_hash(size, params, tf) / size as f64
+    }
+
     fn calc_file_size_mean(cfg: &CpuCfg, params: &Params, time_per_byte: f64) -> usize {
         let target_bytes = cfg.lat / time_per_byte;
         let cpu_ratio = params.cpu_ratio / (1.0 / (1.0 + params.anon_size_ratio));
@@ -685,14 +692,9 @@ impl Bench {
         file_size_mean
     }
 
-    // inverse of calc_file_size_mean()
-    pub fn calc_time_per_byte(cfg: &CpuCfg, params: &Params) -> f64 {
-        let cpu_ratio = params.cpu_ratio / (1.0 / (1.0 + params.anon_size_ratio));
-        let target_bytes = cpu_ratio * params.file_size_mean as f64;
-        cfg.lat / target_bytes
-    }
-
-    fn bench_cpu(&self, cfg: &CpuCfg) -> usize {
+    /// Returns the converged `file_size_mean` and the measured seconds to
+    /// hash a byte.
+    fn bench_cpu(&self, cfg: &CpuCfg) -> (usize, f64) {
         const TIME_HASH_SIZE: usize = 256 << 20;
         let mut params: Params = self.params.clone();
         let mut nr_rounds = 0;
@@ -715,9 +717,9 @@ impl Bench {
         // chunk_pages converges.
         let mut last_chunk_pages = 1;
         let mut nr_converges = 0;
+        let mut time_per_byte = 0.0;
         for _ in 0..10 {
-            let base_time = Self::time_hash(TIME_HASH_SIZE, &params, &tf);
-            let time_per_byte = base_time / TIME_HASH_SIZE as f64;
+            time_per_byte = Self::calc_time_per_byte(TIME_HASH_SIZE, &params, &tf);
             params.file_size_mean = Self::calc_file_size_mean(cfg, &self.params, time_per_byte);
 
             // chunk_pages calculation must be done with the original
@@ -741,16 +743,7 @@ impl Bench {
         params.file_size_mean = (params.file_size_mean as f64 * 1.05) as usize;
 
         let th = self.create_test_hasher(max_size, tf, &params, true);
-        let mut pid = Pid::new(
-            cfg.fsz_pid.kp,
-            cfg.fsz_pid.ki,
-            cfg.fsz_pid.kd,
-            1.0,
-            1.0,
-            1.0,
-            1.0,
-            1.0,
-        );
+        let mut pid = Pid::new(&cfg.fsz_pid, 1.0, 1.0, cfg.lat);
 
         // determine rps based on latency convergence
         while nr_rounds < cfg.rounds {
@@ -776,12 +769,12 @@ impl Bench {
                 break;
             }
 
-            let adj = pid.next_control_output(1.0 + err).output;
+            let adj = pid.next_control_output(result.0).output;
             params.file_size_mean = ((params.file_size_mean as f64 * (1.0 + adj)) as usize).max(1);
             th.disp_hist.lock().unwrap().disp.set_params(&params);
         }
 
-        params.file_size_mean
+        (params.file_size_mean, time_per_byte)
     }
 
     fn bench_cpu_saturation(&self, cfg: &CpuSatCfg) -> u32 {
@@ -1113,11 +1106,12 @@ impl Bench {
         //
         // cpu single bench
         //
-        self.params.file_size_mean = match (args.bench_cpu_single, args.bench_hash_size) {
-            (true, None) => self.bench_cpu(&cfg.cpu),
-            (false, None) => dfl_params.file_size_mean,
-            (_, Some(v)) => v,
-        };
+        (self.params.file_size_mean, self.params.cpu_time_per_byte) =
+            match (args.bench_cpu_single, args.bench_hash_size) {
+                (true, None) => self.bench_cpu(&cfg.cpu),
+                (false, None) => (dfl_params.file_size_mean, dfl_params.cpu_time_per_byte),
+                (_, Some(v)) => (v, dfl_params.cpu_time_per_byte),
+            };
         self.params.chunk_pages = match (args.bench_cpu_single, args.bench_chunk_pages) {
             (true, None) => Self::calc_chunk_pages(&cfg.cpu, &self.params),
             (false, None) => dfl_params.chunk_pages,
@@ -1126,7 +1120,7 @@ impl Bench {
         info!(
             "[ Single cpu result: hash size {:.2}M, anon access {:.2}M, chunk {} pages ]",
             to_mb(self.params.file_size_mean),
-            to_mb(self.params.file_size_mean as f64 * self.params.anon_size_ratio),
+            to_mb(self.params.anon_size_mean_bytes()),
             self.params.chunk_pages,
         );
 
@@ -1161,7 +1155,7 @@ impl Bench {
                     .data
                     .bench_file_frac
                     .unwrap()
-                    .max(rd_hashd_intf::Params::FILE_FRAC_MIN);
+                    .max(self.params.file_frac_min);
             }
 
             self.set_phase(Phase::BenchMemPrep);
@@ -1,29 +1,38 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use anyhow::Result;
 use crossbeam::channel::{self, select, Receiver, Sender};
-use log::{debug, error, trace, warn};
+use log::{debug, error, info, trace, warn};
 use num::Integer;
-use pid::Pid;
 use quantiles::ckms::CKMS;
 use rand::rngs::SmallRng;
-use rand::SeedableRng;
-use rand_distr::{Distribution, Normal, Uniform};
-use sha1::{Digest, Sha1};
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Uniform};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
 use std::convert::TryInto;
 use std::fs::OpenOptions;
 use std::io::{prelude::*, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 use std::thread::{sleep, spawn, JoinHandle};
 use std::time::{Duration, Instant};
 
-use rd_hashd_intf::{Latencies, Params, Stat};
+use rd_hashd_intf::sampler::{
+    ClampedNormal, ClassSampler, FileSizeSampler, SleepSampler, WeightedSampler,
+};
+use rd_hashd_intf::{
+    AccessTrace, AssertField, FileAddrDist, FileSync, HashAlgo, Latencies, LatencyHistogram,
+    Params, Snapshot, Stat, TraceRecord,
+};
 use rd_util::anon_area::AnonArea;
 use rd_util::*;
 
-use super::bench::{Bench, Cfg};
 use super::logger::Logger;
-use super::testfiles::TestFiles;
+use super::pid::Pid;
+use super::queue::RequestQueue;
+use super::testfiles::{ChecksumTable, TestFiles};
 use super::workqueue::WorkQueue;
 
 /// Load files and calculate sha1.
@@ -32,16 +41,89 @@ pub struct Hasher {
     off: usize,
     cpu_ratio: f64,
     fake_cpu_load_time_per_byte: f64,
+    file_direct: bool,
+    direct_buf: Vec<u8>,
+    hash_algo: HashAlgo,
+    full_pages: bool,
+    last_load_off: usize,
+}
+
+/// In-progress digest of one of the `HashAlgo`s.
+enum AlgoHasher {
+    Crc32(crc32fast::Hasher),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl AlgoHasher {
+    fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Crc32 => Self::Crc32(crc32fast::Hasher::new()),
+            HashAlgo::Sha1 => Self::Sha1(Sha1::new()),
+            HashAlgo::Sha256 => Self::Sha256(Sha256::new()),
+            HashAlgo::Sha512 => Self::Sha512(Sha512::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Crc32(h) => h.update(data),
+            Self::Sha1(h) => h.update(data),
+            Self::Sha256(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+        }
+    }
+
+    fn hex_digest(self) -> String {
+        match self {
+            Self::Crc32(h) => format!("{:08x}", h.finalize()),
+            Self::Sha1(h) => h.digest().to_string(),
+            Self::Sha256(h) => format!("{:x}", h.finalize()),
+            Self::Sha512(h) => format!("{:x}", h.finalize()),
+        }
+    }
 }
 
 impl Hasher {
-    pub fn new(cpu_ratio: f64, fake_cpu_load_time_per_byte: f64) -> Self {
+    pub fn new(
+        cpu_ratio: f64,
+        fake_cpu_load_time_per_byte: f64,
+        file_direct: bool,
+        hash_algo: HashAlgo,
+    ) -> Self {
         Hasher {
             buf: vec![],
             off: 0,
             cpu_ratio,
             fake_cpu_load_time_per_byte,
+            file_direct,
+            direct_buf: vec![],
+            hash_algo,
+            full_pages: false,
+            last_load_off: 0,
+        }
+    }
+
+    /// Read whole pages even when faking CPU load so that `last_load()`
+    /// can be verified.
+    pub fn with_full_pages(mut self) -> Self {
+        self.full_pages = true;
+        self
+    }
+
+    /// The bytes read by the last `load()`.
+    pub fn last_load(&self) -> &[u8] {
+        &self.buf[self.last_load_off..self.off]
+    }
+
+    /// Page-aligned bounce page for O_DIRECT reads.
+    fn direct_page(direct_buf: &mut Vec<u8>) -> &mut [u8] {
+        if direct_buf.is_empty() {
+            direct_buf.resize(*PAGE_SIZE * 2, 0);
         }
+        let start = (*PAGE_SIZE - direct_buf.as_ptr() as usize % *PAGE_SIZE) % *PAGE_SIZE;
+        &mut direct_buf[start..start + *PAGE_SIZE]
     }
 
     pub fn load<P: AsRef<Path>>(
@@ -50,19 +132,25 @@ impl Hasher {
         input_off: u64,
         mut input_size: usize,
         is_write: bool,
+        sync: FileSync,
     ) -> Result<usize> {
+        // Writes are only a few bytes per page and go through page cache.
+        let direct = self.file_direct && !is_write;
         let mut opts = OpenOptions::new();
         opts.read(true);
         if is_write {
             opts.write(true);
         }
+        if direct {
+            opts.custom_flags(libc::O_DIRECT);
+        }
         let mut f = opts.open(path)?;
         input_size = input_size.min((f.metadata()?.len() - input_off) as usize);
 
         let len = self.off + input_size;
         self.buf.resize(len, 0);
 
-        let bytes_per_page = if self.fake_cpu_load_time_per_byte == 0.0 {
+        let bytes_per_page = if self.fake_cpu_load_time_per_byte == 0.0 || self.full_pages {
             *PAGE_SIZE // load the whole page
         } else {
             8 // faking CPU load, load just the leading 8 bytes
@@ -71,7 +159,15 @@ impl Hasher {
         for idx in 0..((input_size + *PAGE_SIZE - 1) / *PAGE_SIZE) {
             let off = idx * *PAGE_SIZE;
             f.seek(SeekFrom::Start(input_off + off as u64))?;
-            f.read_exact(&mut self.buf[self.off + off..self.off + off + bytes_per_page])?;
+            if direct {
+                // Chunks are page-aligned, read whole pages and copy.
+                let page = Self::direct_page(&mut self.direct_buf);
+                f.read_exact(page)?;
+                self.buf[self.off + off..self.off + off + bytes_per_page]
+                    .copy_from_slice(&page[..bytes_per_page]);
+            } else {
+                f.read_exact(&mut self.buf[self.off + off..self.off + off + bytes_per_page])?;
+            }
 
             if is_write {
                 let val = u64::from_ne_bytes(
@@ -84,6 +180,13 @@ impl Hasher {
             }
         }
 
+        match sync {
+            FileSync::None => {}
+            FileSync::Fdatasync => f.sync_data()?,
+            FileSync::Fsync => f.sync_all()?,
+        }
+
+        self.last_load_off = self.off;
         self.off = len;
         Ok(input_size)
     }
@@ -101,20 +204,22 @@ impl Hasher {
         }
     }
 
-    /// Calculates sha1 of self.buf * self.cpu_ratio.  Hasher exists
-    /// to waste cpu and io and self.cpu_ratio controls the ratio
-    /// between cpu and io.
-    pub fn sha1(&mut self) -> Digest {
+    /// Calculates the hex digest of self.buf * self.cpu_ratio using
+    /// self.hash_algo.  Hasher exists to waste cpu and io and
+    /// self.cpu_ratio controls the ratio between cpu and io.  Returns an
+    /// empty string if faking CPU load.
+    pub fn digest(&mut self) -> String {
         if self.fake_cpu_load_time_per_byte > 0.0 {
-            // Sleep for the equivalent duration instead of actually calculating SHA1.
+            // Sleep for the equivalent duration instead of actually calculating the
+            // digest. This matches Params::fake_cpu_sleep().
             sleep(Duration::from_secs_f64(
                 self.buf.len() as f64 * self.cpu_ratio * self.fake_cpu_load_time_per_byte,
             ));
-            return Default::default();
+            return String::new();
         }
 
         let mut repeat = self.cpu_ratio;
-        let mut hasher = Sha1::new();
+        let mut hasher = AlgoHasher::new(self.hash_algo);
         let mut nr_bytes = 0;
 
         while repeat > 0.01 {
@@ -128,40 +233,13 @@ impl Hasher {
             nr_bytes += self.buf.len();
             hasher.update(&self.buf);
         }
-        trace!("hashed {} bytes, cpu_ratio={}", nr_bytes, self.cpu_ratio);
-        hasher.digest()
-    }
-}
-
-/// Normal distribution with clamps. The portion of the distribution which is
-/// cut off by the clamps uniformly raise the distribution within the clamps
-/// such that it gradually transforms into uniform distribution as stdev
-/// increases.
-struct ClampedNormal {
-    normal: Normal<f64>,
-    uniform: Uniform<f64>,
-    left: f64,
-    right: f64,
-}
-
-impl ClampedNormal {
-    fn new(mean: f64, stdev: f64, left: f64, right: f64) -> Self {
-        assert!(left <= right, "ClampedNormal left={} right={}", left, right);
-        Self {
-            normal: Normal::new(mean, stdev).unwrap(),
-            uniform: Uniform::new_inclusive(left, right),
-            left,
-            right,
-        }
-    }
-
-    fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> f64 {
-        let v = self.normal.sample(rng);
-        if self.left <= v && v <= self.right {
-            return v;
-        } else {
-            return self.uniform.sample(rng);
-        }
+        trace!(
+            "hashed {} bytes, cpu_ratio={} algo={:?}",
+            nr_bytes,
+            self.cpu_ratio,
+            self.hash_algo
+        );
+        hasher.hex_digest()
     }
 }
 
@@ -169,12 +247,14 @@ impl ClampedNormal {
 pub enum DispatchCmd {
     SetParams(Params),
     GetStat(Sender<Stat>),
+    GetSnapshot(Sender<Snapshot>),
+    Restore(Snapshot),
     FillAnon,
 }
 
 /// Hasher worker thread's completion for the dispatch thread.
 struct HashCompletion {
-    digest: Digest,
+    digest: String,
     started_at: Instant,
     file_dist: Vec<u64>,
     anon_dist: Vec<u64>,
@@ -187,34 +267,91 @@ struct HasherThread {
 
     file_max_frac: f64,
     file_frac: f64,
+    file_total_frac: f64,
     file_nr_chunks: usize,
     file_addr_stdev_ratio: f64,
+    file_addr_uniform_blend: Option<f64>,
+    addr_trunc_stdevs: f64,
+    file_zipf: Option<Arc<WeightedSampler>>,
+    file_hist: Option<Arc<WeightedSampler>>,
+    trace_rec: Option<TraceRecord>,
     file_addr_frac: f64,
+    file_addr_center: usize,
     file_write_frac: f64,
+    file_sync: FileSync,
+    file_sync_every: u32,
+    file_write_cost: u64,
+    file_nr_writes: Arc<AtomicU64>,
+    file_direct: bool,
+    verify_reads: bool,
+    nr_read_mismatches: Arc<AtomicU64>,
 
     anon_area: Arc<RwLock<AnonArea>>,
     anon_nr_chunks: usize,
     anon_addr_stdev_ratio: f64,
+    anon_addr_uniform_blend: Option<f64>,
+    anon_hist: Option<Arc<WeightedSampler>>,
     anon_addr_frac: f64,
+    anon_addr_center: usize,
     anon_write_frac: f64,
 
+    think_dur: f64,
     sleep_dur: f64,
     cpu_ratio: f64,
     fake_cpu_load_time_per_byte: f64,
+    hash_algo: HashAlgo,
 
     cmpl_tx: Sender<HashCompletion>,
 
     started_at: Instant,
+    rng_seed: u64,
     file_dist_slots: usize,
     anon_dist_slots: usize,
 }
 
 impl HasherThread {
+    /// `file_nr_writes` is in this fraction of a write so that fractional
+    /// `write_cost_ratio`s can be accounted.
+    const WRITE_COST_UNIT: u64 = 1000;
+
+    fn write_cost(write_cost_ratio: f64) -> u64 {
+        ((write_cost_ratio * Self::WRITE_COST_UNIT as f64).round() as u64).max(1)
+    }
+
+    /// Count the pages in `data` read from `page` which don't match
+    /// `table` into `nr_mismatches`.
+    fn verify_read(table: &ChecksumTable, page: u64, data: &[u8], nr_mismatches: &AtomicU64) {
+        let nr = table.nr_mismatches(page, data);
+        if nr > 0 {
+            debug!("hasher: {} pages from {} failed verification", nr, page);
+            nr_mismatches.fetch_add(nr, Ordering::Relaxed);
+        }
+    }
+
+    /// Account a file write costing `cost` and return whether it crossed a
+    /// `every` writes boundary and the file should be synced.
+    fn write_sync_due(nr_writes: &AtomicU64, cost: u64, every: u32) -> bool {
+        let every = every.max(1) as u64 * Self::WRITE_COST_UNIT;
+        let prev = nr_writes.fetch_add(cost, Ordering::Relaxed);
+        (prev + cost) / every > prev / every
+    }
+
+    /// Shift `page` by `center` chunks, wrapping around `nr_pages`.
+    fn drift_page(page: u64, center: usize, chunk_pages: usize, nr_pages: u64) -> u64 {
+        match center {
+            0 => page,
+            _ => (page + (center * chunk_pages) as u64) % nr_pages.max(1),
+        }
+    }
+
+    fn file_nr_addr_chunks(&self) -> usize {
+        self.file_nr_pages() as usize / self.chunk_pages
+    }
+
     /// Translate [-1.0, 1.0] `rel` to page index. Similar to
     /// AnonArea::rel_to_page().
     fn rel_to_file_page(&self, rel: f64) -> u64 {
-        let frac = self.mem_frac * self.file_frac / self.file_max_frac;
-        let nr_pages = ((self.tf.size as f64 * frac) as u64).min(self.tf.size) / *PAGE_SIZE as u64;
+        let nr_pages = self.file_nr_pages();
         let mut pg_idx = ((nr_pages / 2) as f64 * rel.abs()) as u64;
         pg_idx *= 2;
         if rel.is_sign_negative() {
@@ -223,6 +360,36 @@ impl HasherThread {
         pg_idx.min(nr_pages - 1)
     }
 
+    fn file_nr_pages(&self) -> u64 {
+        let frac = self.mem_frac * self.file_frac * self.file_total_frac / self.file_max_frac;
+        ((self.tf.size as f64 * frac) as u64).min(self.tf.size) / *PAGE_SIZE as u64
+    }
+
+    /// Number of testfiles covered by the current file access range. Zipf
+    /// ranks are assigned to these in order.
+    fn file_nr_units(&self) -> usize {
+        let pages_per_unit = self.tf.unit_size / *PAGE_SIZE as u64;
+        let nr_pages = (self.file_nr_pages() as f64 * self.file_addr_frac) as u64;
+        (((nr_pages + pages_per_unit - 1) / pages_per_unit) as usize).max(1)
+    }
+
+    /// Pick a random page in the testfile at Zipf rank `unit`.
+    fn zipf_to_file_page<R: rand::Rng + ?Sized>(&self, unit: usize, rng: &mut R) -> u64 {
+        let pages_per_unit = self.tf.unit_size / *PAGE_SIZE as u64;
+        let page = unit as u64 * pages_per_unit + rng.gen_range(0..pages_per_unit);
+        page.min(self.file_nr_pages().max(1) - 1)
+    }
+
+    /// First page of trace chunk `chunk`, wrapping around `nr_pages`.
+    fn trace_to_page(chunk: u64, chunk_pages: usize, nr_pages: u64) -> u64 {
+        (chunk * chunk_pages as u64) % nr_pages.max(1)
+    }
+
+    /// First page of the chunk_pages-sized region for histogram slot `slot`.
+    fn hist_to_page(slot: usize, chunk_pages: usize, nr_pages: u64) -> u64 {
+        ((slot * chunk_pages) as u64).min(nr_pages.max(1) - 1)
+    }
+
     fn file_page_to_idx_off(&self, page: u64) -> (u64, u64) {
         let pages_per_unit = self.tf.unit_size / *PAGE_SIZE as u64;
         (
@@ -253,8 +420,14 @@ impl HasherThread {
         anon_dist[slot] += cnt as u64;
     }
 
-    fn run(self) {
-        let mut rng = SmallRng::from_entropy();
+    fn run(mut self) {
+        // Think time precedes the request and isn't part of its latency.
+        if self.think_dur > 0.0 {
+            sleep(Duration::from_secs_f64(self.think_dur));
+            self.started_at = Instant::now();
+        }
+
+        let mut rng = SmallRng::seed_from_u64(self.rng_seed);
 
         let mut file_dist = Vec::<u64>::new();
         let mut anon_dist = Vec::<u64>::new();
@@ -264,25 +437,102 @@ impl HasherThread {
         let rw_uniform = Uniform::new_inclusive(0.0, 1.0);
 
         // Load hash input files.
-        let file_addr_normal = ClampedNormal::new(0.0, self.file_addr_stdev_ratio, -1.0, 1.0);
+        let file_addr_normal = ClampedNormal::truncated(
+            0.0,
+            self.file_addr_stdev_ratio,
+            -1.0,
+            1.0,
+            self.addr_trunc_stdevs,
+        )
+        .with_uniform_frac(self.file_addr_uniform_blend);
 
         trace!("hasher::run(): cpu_ratio={:.2}", self.cpu_ratio);
-        let mut rdh = Hasher::new(self.cpu_ratio, self.fake_cpu_load_time_per_byte);
-        for _ in 0..self.file_nr_chunks {
-            let rel = file_addr_normal.sample(&mut rng) * self.file_addr_frac;
-            let page = self.rel_to_file_page(rel);
+        let mut rdh = Hasher::new(
+            self.cpu_ratio,
+            self.fake_cpu_load_time_per_byte,
+            self.file_direct,
+            self.hash_algo,
+        );
+        let checksums = match self.verify_reads {
+            true => self.tf.checksums.as_ref(),
+            false => None,
+        };
+        if checksums.is_some() {
+            rdh = rdh.with_full_pages();
+        }
+        for i in 0..self.file_nr_chunks {
+            let page = match (
+                self.trace_rec,
+                self.file_hist.as_ref(),
+                self.file_zipf.as_ref(),
+            ) {
+                (Some(rec), _, _) => Self::trace_to_page(
+                    rec.chunk + i as u64,
+                    self.chunk_pages,
+                    self.tf.size / *PAGE_SIZE as u64,
+                ),
+                (None, Some(hist), _) => Self::hist_to_page(
+                    hist.sample(&mut rng),
+                    self.chunk_pages,
+                    self.tf.size / *PAGE_SIZE as u64,
+                ),
+                (None, None, zipf) => {
+                    let page = match zipf {
+                        Some(zipf) => self.zipf_to_file_page(zipf.sample(&mut rng), &mut rng),
+                        None => {
+                            let rel = file_addr_normal.sample(&mut rng) * self.file_addr_frac;
+                            self.rel_to_file_page(rel)
+                        }
+                    };
+                    Self::drift_page(
+                        page,
+                        self.file_addr_center,
+                        self.chunk_pages,
+                        self.file_nr_pages(),
+                    )
+                }
+            };
             let (file_idx, file_off) = self.file_page_to_idx_off(page);
             let path = self.tf.path(file_idx);
-            let is_write =
-                self.file_write_frac != 0.0 && rw_uniform.sample(&mut rng) <= self.file_write_frac;
-
-            match rdh.load(&path, file_off, *PAGE_SIZE * self.chunk_pages, is_write) {
-                Ok(size) => Self::file_dist_count(
-                    &mut file_dist,
-                    page,
-                    (size / *PAGE_SIZE) as u64,
-                    &self.tf,
-                ),
+            let is_write = match self.trace_rec {
+                Some(rec) => rec.is_write,
+                None => {
+                    self.file_write_frac != 0.0
+                        && rw_uniform.sample(&mut rng) <= self.file_write_frac
+                }
+            };
+            let sync = match (is_write, self.file_sync) {
+                (false, _) | (_, FileSync::None) => FileSync::None,
+                (true, sync) => {
+                    match Self::write_sync_due(
+                        &self.file_nr_writes,
+                        self.file_write_cost,
+                        self.file_sync_every,
+                    ) {
+                        true => sync,
+                        false => FileSync::None,
+                    }
+                }
+            };
+
+            match rdh.load(
+                &path,
+                file_off,
+                *PAGE_SIZE * self.chunk_pages,
+                is_write,
+                sync,
+            ) {
+                Ok(size) => {
+                    if let Some(table) = checksums {
+                        Self::verify_read(table, page, rdh.last_load(), &self.nr_read_mismatches);
+                    }
+                    Self::file_dist_count(
+                        &mut file_dist,
+                        page,
+                        (size / *PAGE_SIZE) as u64,
+                        &self.tf,
+                    )
+                }
                 Err(e) => error!("Failed to load {:?}:{} ({:?})", &path, file_off, &e),
             }
         }
@@ -290,12 +540,33 @@ impl HasherThread {
 
         // Generate anonymous accesses.
         let aa = self.anon_area.read().unwrap();
-        let anon_addr_normal = ClampedNormal::new(0.0, self.anon_addr_stdev_ratio, -1.0, 1.0);
+        let anon_addr_normal = ClampedNormal::truncated(
+            0.0,
+            self.anon_addr_stdev_ratio,
+            -1.0,
+            1.0,
+            self.addr_trunc_stdevs,
+        )
+        .with_uniform_frac(self.anon_addr_uniform_blend);
 
         for _ in 0..self.anon_nr_chunks {
-            let rel = anon_addr_normal.sample(&mut rng) * self.anon_addr_frac;
-            let page_base =
-                AnonArea::rel_to_page_idx(rel, aa.size() - (self.chunk_pages - 1) * *PAGE_SIZE);
+            let page_base = match self.anon_hist.as_ref() {
+                Some(hist) => Self::hist_to_page(
+                    hist.sample(&mut rng),
+                    self.chunk_pages,
+                    ((aa.size() / *PAGE_SIZE).saturating_sub(self.chunk_pages - 1)) as u64,
+                ) as usize,
+                None => {
+                    let rel = anon_addr_normal.sample(&mut rng) * self.anon_addr_frac;
+                    let size = aa.size() - (self.chunk_pages - 1) * *PAGE_SIZE;
+                    Self::drift_page(
+                        AnonArea::rel_to_page_idx(rel, size) as u64,
+                        self.anon_addr_center,
+                        self.chunk_pages,
+                        (size / *PAGE_SIZE) as u64,
+                    ) as usize
+                }
+            };
             let is_write =
                 self.anon_write_frac != 0.0 && rw_uniform.sample(&mut rng) <= self.anon_write_frac;
 
@@ -313,8 +584,8 @@ impl HasherThread {
         }
         sleep(Duration::from_secs_f64(self.sleep_dur / 3.0));
 
-        // Calculate sha1 and signal completion.
-        let digest = rdh.sha1();
+        // Calculate digest and signal completion.
+        let digest = rdh.digest();
         sleep(Duration::from_secs_f64(self.sleep_dur / 3.0));
 
         self.cmpl_tx
@@ -336,6 +607,7 @@ struct DispatchThread {
     tf: Arc<TestFiles>,
     params: Params,
     params_at: Instant,
+    params_seq: u64,
     logger: Option<Logger>,
     cmd_rx: Receiver<DispatchCmd>,
 
@@ -344,20 +616,48 @@ struct DispatchThread {
     cmpl_rx: Receiver<HashCompletion>,
 
     // Hash input file and anon area access patterns.
-    file_size_normal: ClampedNormal,
+    rng: SmallRng,
+    file_size_sampler: FileSizeSampler,
+    class_sampler: Option<ClassSampler>,
+    file_hist: Option<Arc<WeightedSampler>>,
+    file_zipf: Option<(usize, Arc<WeightedSampler>)>,
+    trace: Option<Arc<AccessTrace>>,
+    trace_seq: u64,
+    file_nr_writes: Arc<AtomicU64>,
+    nr_read_mismatches: Arc<AtomicU64>,
     anon_area: Arc<RwLock<AnonArea>>,
+    anon_hist: Option<Arc<WeightedSampler>>,
     anon_size_normal: ClampedNormal,
-    sleep_normal: ClampedNormal,
+    sleep_sampler: SleepSampler,
+    service_sampler: SleepSampler,
 
     // Latency percentile calculation.
     lat_min: f64,
     lat_max: f64,
     ckms: CKMS<f64>,
     ckms_at: Instant,
-
-    // Latency and rps PID controllers.
-    lat_pid: Pid<f64>,
-    rps_pid: Pid<f64>,
+    lat_hist: LatencyHistogram,
+
+    // Latency, rps and memory pressure PID controllers.
+    lat_pid: Pid,
+    rps_pid: Pid,
+    mem_pid: Pid,
+    mem_pressure_err: bool,
+    // The running ramps as (start, started_at), see Params::mem_frac_at().
+    mem_frac_ramp: Option<(f64, Instant)>,
+    file_frac_ramp: Option<(f64, Instant)>,
+    lat_target_ramp_at: Instant,
+    warmup_at: Instant,
+    stress_broken: bool,
+    control_period: f64,
+    control_scale: f64,
+    lat_err_prev: Option<f64>,
+    assertion_violations: Vec<u64>,
+    queue: Option<RequestQueue>,
+    nr_dropped: u64,
+    addr_drift_at: Instant,
+    cpu_throttle_counts: Option<Vec<u64>>,
+    cpu_throttle_err: bool,
 
     // Runtime parameters.
     lat: Latencies,
@@ -367,10 +667,19 @@ struct DispatchThread {
     nr_done: u64,
     last_nr_done: u64,
     rps: f64,
+    mem_frac: f64,
+    file_frac: f64,
     file_addr_frac: f64,
     anon_addr_frac: f64,
+    cpu_throttle_frac: f64,
     fake_cpu_load_time_per_byte: f64,
 
+    // Idle resident memory, see mem_balloon_bytes.
+    anon_comp: f64,
+    balloon: Option<AnonArea>,
+    balloon_cursor: usize,
+    balloon_at: Instant,
+
     file_dist: Vec<u64>,
     anon_dist: Vec<u64>,
 }
@@ -378,34 +687,63 @@ struct DispatchThread {
 impl DispatchThread {
     const WQ_IDLE_TIMEOUT: f64 = 60.0;
     const CKMS_ERROR: f64 = 0.001;
+    const MEM_FRAC_MIN: f64 = 0.01;
+    // Threads other than the workers, e.g. dispatch, reporting and logging.
+    const THREAD_LIMIT_RESERVE: u64 = 64;
 
-    fn anon_total(max_size: u64, params: &Params) -> usize {
-        (max_size as f64
-            * (params.mem_frac * (1.0 - params.file_frac))
-                .max(0.0)
-                .min(1.0)) as usize
+    fn anon_total(max_size: u64, mem_frac: f64, file_frac: f64) -> usize {
+        (max_size as f64 * (mem_frac * (1.0 - file_frac)).max(0.0).min(1.0)) as usize
     }
 
-    fn file_size_normal(params: &Params) -> ClampedNormal {
-        let size_mean = params.file_size_mean as f64;
-        let size_stdev = size_mean * params.file_size_stdev_ratio;
+    fn file_hist(params: &Params) -> Option<Arc<WeightedSampler>> {
+        let probs = params.file_histogram_normalized()?;
+        debug!("file: histogram with {} slots", probs.len());
+        Some(Arc::new(WeightedSampler::new(probs)))
+    }
 
-        debug!(
-            "file: size_mean={} size_stdev={}",
-            size_mean.round(),
-            size_stdev.round(),
-        );
+    /// The Zipf sampler over `nr_units` testfiles, rebuilt only when the
+    /// number changes and shared by the hash workers.
+    fn file_zipf(
+        cache: &mut Option<(usize, Arc<WeightedSampler>)>,
+        dist: FileAddrDist,
+        nr_units: usize,
+    ) -> Option<Arc<WeightedSampler>> {
+        let s = match dist {
+            FileAddrDist::Normal => return None,
+            FileAddrDist::Zipf { s } => s,
+        };
+        match cache {
+            Some((nr, _)) if *nr == nr_units => {}
+            _ => {
+                debug!("file: Zipf sampler over {} units, s={}", nr_units, s);
+                *cache = Some((nr_units, Arc::new(WeightedSampler::zipf(nr_units, s))));
+            }
+        }
+        cache.as_ref().map(|(_, zipf)| zipf.clone())
+    }
 
-        ClampedNormal::new(
-            size_mean,
-            size_stdev,
-            *PAGE_SIZE as f64,
-            2.0 * size_mean as f64,
-        )
+    fn trace(params: &Params) -> Option<Arc<AccessTrace>> {
+        let path = params.trace_path.as_ref()?;
+        match AccessTrace::load(path) {
+            Ok(trace) => {
+                debug!("file: replaying {} trace records", trace.records.len());
+                Some(Arc::new(trace))
+            }
+            Err(e) => {
+                error!("dispatch: Failed to load access trace ({:#})", &e);
+                None
+            }
+        }
+    }
+
+    fn anon_hist(params: &Params) -> Option<Arc<WeightedSampler>> {
+        let probs = params.anon_histogram_normalized()?;
+        debug!("anon: histogram with {} slots", probs.len());
+        Some(Arc::new(WeightedSampler::new(probs)))
     }
 
     fn anon_size_normal(params: &Params) -> ClampedNormal {
-        let size_mean = (params.file_size_mean as f64 * params.anon_size_ratio as f64).max(0.0);
+        let size_mean = params.anon_size_mean_bytes();
         let size_stdev = size_mean * params.anon_size_stdev_ratio;
 
         debug!(
@@ -414,42 +752,100 @@ impl DispatchThread {
             size_stdev.round(),
         );
 
-        ClampedNormal::new(
+        ClampedNormal::truncated(
             size_mean,
             size_stdev,
             *PAGE_SIZE as f64,
             2.0 * size_mean as f64,
+            params.size_trunc_stdevs,
         )
     }
 
-    fn sleep_normal(params: &Params) -> ClampedNormal {
-        let sleep_mean = params.sleep_mean;
-        let sleep_stdev = params.sleep_mean * params.sleep_stdev_ratio;
-
-        debug!(
-            "anon: sleep_mean={} sleep_stdev={:.2}",
-            sleep_mean, sleep_stdev
-        );
-
-        ClampedNormal::new(sleep_mean, sleep_stdev, 0.0, 2.0 * sleep_mean)
+    /// Sizes and sleeps are sampled from this and each hasher is seeded
+    /// from it so that the whole access sequence follows `rng_seed`.
+    fn rng(params: &Params) -> SmallRng {
+        match params.rng_seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => SmallRng::from_entropy(),
+        }
     }
 
-    fn pid_controllers(params: &Params) -> (Pid<f64>, Pid<f64>) {
+    fn pid_controllers(params: &Params) -> (Pid, Pid, Pid) {
         let lat = &params.lat_pid;
         let rps = &params.rps_pid;
+        let mem = &params.mem_pid;
 
         debug!(
-            "dispatch_pids: [lat kp={} ki={} kd={}] [rps kp={} ki={} kd={}]",
-            lat.kp, lat.ki, lat.kd, rps.kp, rps.ki, rps.kd
+            "dispatch_pids: [lat kp={} ki={} kd={}] [rps kp={} ki={} kd={}] [mem kp={} ki={} kd={}]",
+            lat.kp, lat.ki, lat.kd, rps.kp, rps.ki, rps.kd, mem.kp, mem.ki, mem.kd
         );
 
         (
-            Pid::new(lat.kp, lat.ki, lat.kd, 0.1, 0.1, 0.1, 1.0, 1.0),
-            Pid::new(rps.kp, rps.ki, rps.kd, 1.0, 1.0, 1.0, 1.0, 1.0),
+            Pid::new(lat, 0.1, 1.0, params.lat_target),
+            Pid::new(rps, 1.0, 1.0, params.base_rps_target() as f64),
+            Pid::new(mem, 0.1, 0.1, params.mem_pressure_target),
         )
     }
 
+    /// Scale `concurrency` according to the rps. If `ramp_max` is not zero,
+    /// no more than `ramp_max` workers are added in one step.
+    fn concurrency_step(
+        rps_pid: &mut Pid,
+        concurrency: f64,
+        rps: f64,
+        ramp_max: u32,
+        conc_min: f64,
+    ) -> f64 {
+        let adj = rps_pid.next_control_output(rps).output;
+        let mut next = (concurrency * (1.0 + adj)).max(conc_min);
+        if ramp_max > 0 {
+            next = next.min(concurrency + ramp_max as f64);
+        }
+        next
+    }
+
+    /// Scale `concurrency_max` by the latency controller output `adj`,
+    /// keeping it within `[conc_min, conc_max]`. Negative adjustment means
+    /// latency is in charge. concurrency_max might have diverged upwards in
+    /// the meantime. Jump down to the current concurrency level immediately.
+    fn concurrency_max_step(
+        concurrency_max: f64,
+        concurrency: f64,
+        adj: f64,
+        conc_min: f64,
+        conc_max: f64,
+    ) -> f64 {
+        let base = match adj < 0.0 {
+            true => concurrency_max.min(concurrency),
+            false => concurrency_max,
+        };
+        (base * (1.0 + adj)).max(conc_min).min(conc_max)
+    }
+
+    /// Scale `mem_frac` according to the memory pressure. The configured
+    /// `mem_frac` is the upper bound.
+    fn mem_frac_step(mem_pid: &mut Pid, mem_frac: f64, pressure: f64, mem_frac_max: f64) -> f64 {
+        let adj = mem_pid.next_control_output(pressure).output;
+        (mem_frac * (1.0 + adj))
+            .max(Self::MEM_FRAC_MIN)
+            .min(mem_frac_max)
+    }
+
     fn verify_params(&mut self) {
+        if let Some(limit) = thread_limit() {
+            let limit = limit
+                .saturating_sub(Self::THREAD_LIMIT_RESERVE)
+                .min(u32::MAX as u64);
+            let conc_max = self.params.effective_concurrency_max(limit.max(1) as u32);
+            if conc_max < self.params.concurrency_max {
+                warn!(
+                    "concurrency_max {} is over the system thread limit, clamping to {}",
+                    self.params.concurrency_max, conc_max
+                );
+                self.params.concurrency_max = conc_max;
+            }
+        }
+
         let file_max_frac = self.tf.size as f64 / self.max_size as f64;
         if self.params.file_frac > file_max_frac {
             warn!(
@@ -459,10 +855,20 @@ impl DispatchThread {
             self.params.file_frac = file_max_frac;
         }
 
+        if self.params.verify_reads && self.tf.checksums.is_none() {
+            warn!("verify_reads requires testfile checksums from --prepare-testfiles, ignoring");
+            self.params.verify_reads = false;
+        }
+
+        if let Err(e) = self.params.check_mem_balloon(self.max_size) {
+            warn!("{:#}", &e);
+        }
+
         self.file_dist = vec![];
         self.anon_dist = vec![];
         self.file_dist.resize(self.params.acc_dist_slots, 0);
         self.anon_dist.resize(self.params.acc_dist_slots, 0);
+        self.lat_hist = self.params.lat_histogram();
     }
 
     pub fn new(
@@ -474,30 +880,61 @@ impl DispatchThread {
         cmd_rx: Receiver<DispatchCmd>,
     ) -> Self {
         let (cmpl_tx, cmpl_rx) = channel::unbounded::<HashCompletion>();
-        let (lat_pid, rps_pid) = Self::pid_controllers(&params);
-        let anon_total = Self::anon_total(max_size, &params);
+        let (lat_pid, rps_pid, mem_pid) = Self::pid_controllers(&params);
+        let anon_total = Self::anon_total(max_size, params.mem_frac, params.file_frac);
         let now = Instant::now();
 
         let mut dt = Self {
             max_size,
             params_at: now,
+            params_seq: 0,
             cmd_rx,
             logger,
             wq: WorkQueue::new(Duration::from_secs_f64(Self::WQ_IDLE_TIMEOUT)),
 
             cmpl_tx,
             cmpl_rx,
-            file_size_normal: Self::file_size_normal(&params),
+            rng: Self::rng(&params),
+            file_size_sampler: FileSizeSampler::for_params(&params),
+            class_sampler: ClassSampler::new(&params),
+            file_hist: Self::file_hist(&params),
+            file_zipf: None,
+            trace: Self::trace(&params),
+            trace_seq: 0,
+            file_nr_writes: Arc::new(AtomicU64::new(0)),
+            nr_read_mismatches: Arc::new(AtomicU64::new(0)),
             anon_area: Arc::new(RwLock::new(AnonArea::new(anon_total, anon_comp))),
+            anon_hist: Self::anon_hist(&params),
             anon_size_normal: Self::anon_size_normal(&params),
-            sleep_normal: Self::sleep_normal(&params),
+            sleep_sampler: SleepSampler::new(&params),
+            service_sampler: SleepSampler::service(&params),
 
             lat_min: std::f64::MAX,
             lat_max: 0.0,
             ckms: CKMS::<f64>::new(Self::CKMS_ERROR),
             ckms_at: now,
+            lat_hist: Default::default(),
             lat_pid,
             rps_pid,
+            mem_pid,
+            mem_pressure_err: false,
+            mem_frac_ramp: None,
+            file_frac_ramp: None,
+            lat_target_ramp_at: Instant::now(),
+            warmup_at: now,
+            stress_broken: false,
+            control_period: params.next_control_period(0.0),
+            control_scale: 1.0,
+            lat_err_prev: None,
+            assertion_violations: vec![0; params.assertions.len()],
+            queue: match params.queue_max {
+                0 => None,
+                max => Some(RequestQueue::new(max, now)),
+            },
+            nr_dropped: 0,
+            addr_drift_at: now,
+            cpu_throttle_counts: None,
+            cpu_throttle_err: false,
 
             lat: Latencies::default(),
             concurrency_max: params.concurrency_max as f64,
@@ -506,10 +943,18 @@ impl DispatchThread {
             nr_done: 0,
             last_nr_done: 0,
             rps: 0.0,
+            mem_frac: params.mem_frac,
+            file_frac: params.file_frac,
             file_addr_frac: 1.0,
             anon_addr_frac: 1.0,
+            cpu_throttle_frac: 0.0,
             fake_cpu_load_time_per_byte: 0.0,
 
+            anon_comp,
+            balloon: None,
+            balloon_cursor: 0,
+            balloon_at: now,
+
             file_dist: vec![],
             anon_dist: vec![],
 
@@ -518,13 +963,84 @@ impl DispatchThread {
             params,
         };
         dt.verify_params();
+        // There are no previous params to fall back to.
+        let anon_bytes =
+            Self::anon_total(dt.max_size, dt.params.mem_frac, dt.params.file_frac) as u64;
+        if let Err(e) = dt.params.check_anon_histogram(anon_bytes) {
+            warn!("{:#}, ignoring anon_histogram", &e);
+            dt.params.anon_histogram.clear();
+            dt.anon_hist = None;
+        }
+        dt.log_params();
+        dt.update_balloon();
+        dt.wq.set_cpu_affinity(dt.params.cpu_affinity.clone());
         dt
     }
 
+    /// Create, resize or drop the balloon to match `mem_balloon_bytes`.
+    fn update_balloon(&mut self) {
+        let size = self.params.mem_balloon_bytes as usize;
+        match (size, self.balloon.as_mut()) {
+            (0, _) => self.balloon = None,
+            (size, Some(balloon)) => {
+                if balloon.size() != size {
+                    balloon.resize(size);
+                }
+            }
+            (size, None) => {
+                debug!("dispatch: Creating {} memory balloon", format_size(size));
+                self.balloon = Some(AnonArea::new(size, self.anon_comp));
+                self.balloon_cursor = 0;
+            }
+        }
+    }
+
+    /// Touch the share of the balloon pages due since the last call so that
+    /// all of it is touched once every `MEM_BALLOON_TOUCH_SECS`. Untouched
+    /// pages are filled, populated ones are only read so that they stay
+    /// clean.
+    fn touch_balloon(&mut self) {
+        let now = Instant::now();
+        let dur = now.duration_since(self.balloon_at).as_secs_f64();
+        self.balloon_at = now;
+
+        let balloon = match self.balloon.as_ref() {
+            Some(v) => v,
+            None => return,
+        };
+        let nr_pages = balloon.size() / *PAGE_SIZE;
+        let nr = (nr_pages as f64 * dur / Params::MEM_BALLOON_TOUCH_SECS).ceil() as usize;
+        for _ in 0..nr.min(nr_pages) {
+            let idx = self.balloon_cursor % nr_pages;
+            let page: &mut [u64] = balloon.access_page(idx);
+            if page[0] == 0 {
+                balloon.fill_page_with_random(idx);
+            } else {
+                std::hint::black_box(page[0]);
+            }
+            self.balloon_cursor = (idx + 1) % nr_pages;
+        }
+    }
+
+    /// Log a summary of the applied params and, at debug level, the full
+    /// params as JSON lines, see `Params::as_log_line()`.
+    fn log_params(&mut self) {
+        let now = chrono::Local::now();
+        info!(
+            "params-summary: {}",
+            self.params.as_summary_log_line(self.params_seq, now)
+        );
+        debug!(
+            "params-json: {}",
+            self.params.as_log_line(self.params_seq, now)
+        );
+        self.params_seq += 1;
+    }
+
     fn params_updated(&mut self) {
         self.fake_cpu_load_time_per_byte = match self.params.fake_cpu_load {
             true => {
-                let time_per_byte = Bench::calc_time_per_byte(&Cfg::new(1.0).cpu, &self.params);
+                let time_per_byte = self.params.hash_time_per_byte();
                 if time_per_byte != self.fake_cpu_load_time_per_byte {
                     warn!(
                         "Faking CPU load ({:.3}ns per byte)",
@@ -542,19 +1058,133 @@ impl DispatchThread {
         };
     }
 
-    fn update_params(&mut self, new_params: Params) {
-        let old_anon_total = Self::anon_total(self.max_size, &self.params);
-        let new_anon_total = Self::anon_total(self.max_size, &new_params);
+    /// Update the PID controllers with the new params. Controller states are
+    /// carried over unless reset is requested.
+    fn update_pid_controllers(&mut self, reset: bool) {
+        let params = &self.params;
+        if reset {
+            debug!("dispatch: Resetting PID controllers");
+            for pid in [&mut self.lat_pid, &mut self.rps_pid, &mut self.mem_pid].iter_mut() {
+                pid.reset();
+            }
+        }
+
+        self.lat_pid.params = params.lat_pid.clone();
+        self.lat_pid.setpoint = params.lat_target;
+        self.rps_pid.params = params.rps_pid.clone();
+        self.rps_pid.setpoint = params.base_rps_target() as f64;
+        self.mem_pid.params = params.mem_pid.clone();
+        self.mem_pid.setpoint = params.mem_pressure_target;
+    }
+
+    fn update_params(&mut self, mut new_params: Params) {
+        let mem_frac_ramp = Self::next_ramp(
+            self.mem_frac_ramp,
+            new_params.mem_frac_ramp_secs,
+            self.mem_frac,
+            self.params.mem_frac,
+            new_params.mem_frac,
+        );
+        let new_mem_frac = match mem_frac_ramp {
+            Some((start, at)) => new_params.mem_frac_at(start, at.elapsed().as_secs_f64()),
+            None => new_params.mem_frac,
+        };
+        let file_frac_ramp = Self::next_ramp(
+            self.file_frac_ramp,
+            new_params.file_frac_ramp_secs,
+            self.file_frac,
+            self.params.file_frac,
+            new_params.file_frac,
+        );
+        let new_file_frac = match file_frac_ramp {
+            Some((start, at)) => new_params.file_frac_at(start, at.elapsed().as_secs_f64()),
+            None => new_params.file_frac,
+        };
+
+        // The anon area passes through both ends of the ramps, the
+        // histogram must fit in the smaller.
+        let anon_bytes = Self::anon_total(self.max_size, new_mem_frac, new_file_frac).min(
+            Self::anon_total(self.max_size, new_params.mem_frac, new_params.file_frac),
+        ) as u64;
+        if let Err(e) = new_params.check_anon_histogram(anon_bytes) {
+            warn!("{:#}, rejecting params update", &e);
+            return;
+        }
+
+        if new_params.lat_target_ramp != self.params.lat_target_ramp {
+            self.lat_target_ramp_at = Instant::now();
+        }
+        Self::ramp_lat_target(&mut new_params, self.lat_target_ramp_at);
+        if new_params.stress_schedule != self.params.stress_schedule {
+            self.stress_broken = false;
+        }
+        if new_params.assertions != self.params.assertions {
+            self.assertion_violations = vec![0; new_params.assertions.len()];
+        }
+        match (self.queue.as_mut(), new_params.queue_max) {
+            (_, 0) => self.queue = None,
+            (Some(queue), max) => self.nr_dropped += queue.set_max(max),
+            (None, max) => self.queue = Some(RequestQueue::new(max, Instant::now())),
+        }
+        for (field, old, new) in self.params.diff(&new_params) {
+            info!("params: {} {} -> {}", field, old, new);
+        }
+        let pid_reset = new_params.take_pid_reset() || new_params.is_setpoint_jump(&self.params);
+        match (self.params.is_paused(), new_params.is_paused()) {
+            (false, true) => info!("dispatch: Paused"),
+            (true, false) => info!("dispatch: Resumed"),
+            _ => {}
+        }
+        if let Some((start, at)) = mem_frac_ramp {
+            if Some(at) != self.mem_frac_ramp.map(|(_, at)| at) {
+                debug!(
+                    "dispatch: Ramping mem_frac {:.2} -> {:.2} over {:.1}s",
+                    start, new_params.mem_frac, new_params.mem_frac_ramp_secs
+                );
+            }
+        }
+        if let Some((start, at)) = file_frac_ramp {
+            if Some(at) != self.file_frac_ramp.map(|(_, at)| at) {
+                debug!(
+                    "dispatch: Ramping file_frac {:.2} -> {:.2} over {:.1}s",
+                    start, new_params.file_frac, new_params.file_frac_ramp_secs
+                );
+            }
+        }
+        self.mem_frac_ramp = mem_frac_ramp;
+        self.file_frac_ramp = file_frac_ramp;
+        let old_anon_total = Self::anon_total(self.max_size, self.mem_frac, self.file_frac);
+        let new_anon_total = Self::anon_total(self.max_size, new_mem_frac, new_file_frac);
+        if new_params.rng_seed != self.params.rng_seed {
+            self.rng = Self::rng(&new_params);
+        }
+        if new_params.trace_path != self.params.trace_path {
+            self.trace = Self::trace(&new_params);
+            self.trace_seq = 0;
+        }
+        self.mem_frac = new_mem_frac;
+        self.file_frac = new_file_frac;
         self.params = new_params;
+        self.control_period = self.params.next_control_period(0.0);
+        self.lat_err_prev = None;
         self.verify_params();
+        if self.file_frac_ramp.is_none() {
+            self.file_frac = self.params.file_frac;
+        }
+        self.log_params();
+        self.update_balloon();
+        self.update_pid_controllers(pid_reset);
+        self.wq.set_cpu_affinity(self.params.cpu_affinity.clone());
         let params = &self.params;
 
-        self.file_size_normal = Self::file_size_normal(params);
+        self.file_size_sampler = FileSizeSampler::for_params(params);
+        self.class_sampler = ClassSampler::new(params);
+        self.file_hist = Self::file_hist(params);
+        self.file_zipf = None;
+        self.anon_hist = Self::anon_hist(params);
         self.anon_size_normal = Self::anon_size_normal(params);
-        self.sleep_normal = Self::sleep_normal(params);
-        let (lp, rp) = Self::pid_controllers(params);
-        self.lat_pid = lp;
-        self.rps_pid = rp;
+        self.sleep_sampler = SleepSampler::new(params);
+        self.service_sampler = SleepSampler::service(params);
 
         if new_anon_total != old_anon_total {
             let mut aa = self.anon_area.write().unwrap();
@@ -563,62 +1193,245 @@ impl DispatchThread {
 
         if let Some(logger) = self.logger.as_mut() {
             logger.set_padding(params.log_padding());
+            logger.set_policy(params.log_max_bytes, params.log_fsync_secs);
         }
 
         self.params_at = Instant::now();
         self.params_updated();
     }
 
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            params: self.params.clone(),
+            lat_pid: self.lat_pid.state(),
+            rps_pid: self.rps_pid.state(),
+            mem_pid: self.mem_pid.state(),
+            concurrency: self.concurrency,
+            concurrency_max: self.concurrency_max,
+            mem_frac: self.mem_frac,
+        }
+    }
+
+    /// Apply the snapshot's params and then resume the PID controllers and
+    /// the operating point from the saved states, overriding any reset the
+    /// params update implies. The restored values are clamped to what the
+    /// params allow. A restored mem_frac which differs from the configured
+    /// one ramps back to it if `mem_frac_ramp_secs` is set or is corrected
+    /// by the memory pressure controller.
+    fn restore(&mut self, mut snap: Snapshot) {
+        snap.params.take_pid_reset();
+        self.update_params(snap.params);
+        self.lat_pid.set_state(&snap.lat_pid);
+        self.rps_pid.set_state(&snap.rps_pid);
+        self.mem_pid.set_state(&snap.mem_pid);
+
+        if snap.concurrency_max > 0.0 {
+            self.concurrency_max = snap
+                .concurrency_max
+                .min(self.params.concurrency_max as f64)
+                .max(1.0);
+        }
+        if snap.concurrency > 0.0 {
+            self.concurrency = snap.concurrency.min(self.concurrency_max).max(1.0);
+        }
+        let mem_frac = snap.mem_frac.min(self.params.mem_frac);
+        if mem_frac > 0.0 && mem_frac != self.mem_frac {
+            let new_anon_total = Self::anon_total(self.max_size, mem_frac, self.file_frac);
+            match self.params.check_anon_histogram(new_anon_total as u64) {
+                Ok(()) => {
+                    let old_anon_total =
+                        Self::anon_total(self.max_size, self.mem_frac, self.file_frac);
+                    self.mem_frac = mem_frac;
+                    if self.params.mem_frac_ramp_secs > 0.0 {
+                        self.mem_frac_ramp = Some((mem_frac, Instant::now()));
+                    }
+                    if new_anon_total != old_anon_total {
+                        self.anon_area.write().unwrap().resize(new_anon_total);
+                    }
+                }
+                Err(e) => warn!("{:#}, not restoring mem_frac {:.2}", &e, mem_frac),
+            }
+        }
+        debug!(
+            "dispatch: Restored controller states from snapshot, concurrency={:.1}/{:.1} mem_frac={:.2}",
+            self.concurrency, self.concurrency_max, self.mem_frac
+        );
+    }
+
     fn launch_hashers(&mut self) {
         // Fire off hash workers to fill up the target concurrency.
-        let mut rng = SmallRng::from_entropy();
+        if self.params.is_paused() {
+            if let Some(queue) = self.queue.as_mut() {
+                queue.restart(Instant::now());
+            }
+            return;
+        }
+        if let Some(queue) = self.queue.as_mut() {
+            self.nr_dropped += queue.arrive(self.rps_pid.setpoint, Instant::now());
+        }
+        let rng = &mut self.rng;
+        let drift = match self.params.addr_drift_rate > 0.0 {
+            true => {
+                let aa = self.anon_area.read().unwrap();
+                let nr_pages = (aa.size() / *PAGE_SIZE).saturating_sub(self.params.chunk_pages - 1);
+                Some((
+                    self.addr_drift_at.elapsed().as_secs_f64(),
+                    nr_pages / self.params.chunk_pages,
+                ))
+            }
+            false => None,
+        };
 
         while self.nr_in_flight < self.concurrency as u32 {
-            let chunk_size = *PAGE_SIZE * self.params.chunk_pages;
+            // With the queue, requests start when they arrive, not when
+            // launched, and there's no think time.
+            let (started_at, queued) = match self.queue.as_mut() {
+                Some(queue) => match queue.pop() {
+                    Some(at) => (at, true),
+                    None => break,
+                },
+                None => (Instant::now(), false),
+            };
+            let chunk_size = self.params.chunk_bytes();
 
             // Determine file and anon access chunk counts. Indices are
             // determined by each hash worker to avoid overloading the
             // dispatch thread.
-            let file_size = self.file_size_normal.sample(&mut rng).round() as usize;
-            let file_nr_chunks = file_size.div_ceil(&chunk_size).max(1);
-            let anon_size = self.anon_size_normal.sample(&mut rng).round() as usize;
+            let trace_seq = self.trace_seq;
+            let trace_rec = self.trace.as_ref().map(|trace| *trace.get(trace_seq));
+            self.trace_seq += 1;
+            let (file_size, file_frac) = match trace_rec {
+                Some(rec) => (rec.size, self.file_frac),
+                None => {
+                    let (file_size_sampler, file_frac) = match self.class_sampler.as_ref() {
+                        Some(cs) => cs.pick(rng),
+                        None => (&self.file_size_sampler, self.file_frac),
+                    };
+                    (file_size_sampler.sample(rng).round() as usize, file_frac)
+                }
+            };
+            // No file accesses if file_frac_min allowed file_frac to reach 0.
+            // Trace records are always replayed.
+            let file_nr_chunks = match file_frac > 0.0 || trace_rec.is_some() {
+                true => file_size.div_ceil(&chunk_size).max(1),
+                false => 0,
+            };
+            let anon_size = self.anon_size_normal.sample(rng).round() as usize;
             let anon_nr_chunks = anon_size.div_ceil(&chunk_size);
+            let sleep_dur = self.sleep_sampler.sample(rng);
+            let service_dur = match self.params.has_service_time() {
+                true => self.service_sampler.sample(rng),
+                false => 0.0,
+            };
+            let (think_dur, sleep_dur) = self.params.think_service_split(sleep_dur, service_dur);
+            let think_dur = match queued {
+                true => 0.0,
+                false => think_dur,
+            };
 
-            let hasher_thread = HasherThread {
+            let mut hasher_thread = HasherThread {
                 tf: self.tf.clone(),
-                mem_frac: self.params.mem_frac,
+                mem_frac: self.mem_frac,
                 chunk_pages: self.params.chunk_pages,
 
                 file_max_frac: self.tf.size as f64 / self.max_size as f64,
-                file_frac: self.params.file_frac,
+                file_frac,
+                file_total_frac: self.params.file_total_frac,
                 file_nr_chunks,
                 file_addr_stdev_ratio: self.params.file_addr_stdev_ratio,
+                file_addr_uniform_blend: self
+                    .params
+                    .addr_uniform_blend(self.params.file_addr_stdev_ratio),
+                addr_trunc_stdevs: self.params.addr_trunc_stdevs,
+                file_zipf: None,
+                file_hist: self.file_hist.clone(),
+                trace_rec,
                 file_addr_frac: self.file_addr_frac,
+                file_addr_center: 0,
                 file_write_frac: self.params.file_write_frac,
+                file_sync: self.params.file_sync,
+                file_sync_every: self.params.file_sync_every,
+                file_write_cost: HasherThread::write_cost(self.params.write_cost_ratio),
+                file_nr_writes: self.file_nr_writes.clone(),
+                file_direct: self.params.file_direct,
+                verify_reads: self.params.verify_reads,
+                nr_read_mismatches: self.nr_read_mismatches.clone(),
 
                 anon_area: self.anon_area.clone(),
                 anon_nr_chunks,
                 anon_addr_stdev_ratio: self.params.anon_addr_stdev_ratio,
+                anon_addr_uniform_blend: self
+                    .params
+                    .addr_uniform_blend(self.params.anon_addr_stdev_ratio),
+                anon_hist: self.anon_hist.clone(),
                 anon_addr_frac: self.anon_addr_frac,
+                anon_addr_center: 0,
                 anon_write_frac: self.params.anon_write_frac,
 
-                sleep_dur: self.sleep_normal.sample(&mut rng),
-                cpu_ratio: self.params.cpu_ratio,
+                think_dur,
+                sleep_dur,
+                cpu_ratio: self.params.cpu_ratio_under_throttle(self.cpu_throttle_frac),
                 fake_cpu_load_time_per_byte: self.fake_cpu_load_time_per_byte,
+                hash_algo: self.params.hash_algo,
 
                 cmpl_tx: self.cmpl_tx.clone(),
 
-                started_at: Instant::now(),
+                started_at,
+                rng_seed: rng.gen(),
                 file_dist_slots: self.file_dist.len(),
                 anon_dist_slots: self.anon_dist.len(),
             };
 
+            if self.file_hist.is_none() && trace_rec.is_none() {
+                hasher_thread.file_zipf = Self::file_zipf(
+                    &mut self.file_zipf,
+                    self.params.file_addr_dist,
+                    hasher_thread.file_nr_units(),
+                );
+            }
+
+            if let Some((elapsed, anon_span)) = drift {
+                let file_span = hasher_thread.file_nr_addr_chunks();
+                hasher_thread.file_addr_center = self.params.addr_center_at(elapsed, file_span);
+                hasher_thread.anon_addr_center = self.params.addr_center_at(elapsed, anon_span);
+            }
+
             self.wq.queue(move || hasher_thread.run());
 
             self.nr_in_flight += 1;
         }
     }
 
+    /// Account a finished hash worker.
+    fn complete(&mut self, cmpl: HashCompletion) {
+        let HashCompletion {
+            digest,
+            started_at,
+            file_dist,
+            anon_dist,
+        } = cmpl;
+        self.nr_in_flight -= 1;
+        self.nr_done += 1;
+        let dur = Instant::now().duration_since(started_at).as_secs_f64();
+        self.lat_min = self.lat_min.min(dur);
+        self.lat_max = self.lat_max.max(dur);
+        self.ckms.insert(dur);
+        self.lat_hist.insert(dur);
+        if let Some(logger) = self.logger.as_mut() {
+            logger.log(&format!("{} {:.2}ms", digest, dur * TO_MSEC));
+        }
+        if file_dist.len() == self.file_dist.len() {
+            for i in 0..file_dist.len() {
+                self.file_dist[i] += file_dist[i];
+            }
+        }
+        if anon_dist.len() == self.anon_dist.len() {
+            for i in 0..anon_dist.len() {
+                self.anon_dist[i] += anon_dist[i];
+            }
+        }
+    }
+
     fn reset_lat_rps(&mut self, now: Instant) {
         self.lat_min = std::f64::MAX;
         self.lat_max = 0.0;
@@ -627,11 +1440,25 @@ impl DispatchThread {
         self.last_nr_done = self.nr_done;
     }
 
+    /// The latency fed to the latency PID controller. With multiple targets,
+    /// the most violated one is scaled to `lat_target`.
+    fn lat_ctl(ckms: &CKMS<f64>, params: &Params) -> f64 {
+        params
+            .lat_target_list()
+            .iter()
+            .map(|(pct, target)| ckms.query(*pct).unwrap().1 / target)
+            .fold(std::f64::MIN, f64::max)
+            * params.lat_target
+    }
+
     fn refresh_lat_rps(&mut self, now: Instant) -> bool {
         let dur = now.duration_since(self.ckms_at);
-        if dur.as_secs_f64() < self.params.control_period {
+        if dur.as_secs_f64() < self.control_period {
             return false;
         }
+        // The controller gains are per control_period, scale them for the
+        // period which just elapsed.
+        self.control_scale = self.control_period / self.params.control_period;
 
         if self.nr_done > self.last_nr_done {
             self.lat.min = self.lat_min;
@@ -648,13 +1475,16 @@ impl DispatchThread {
             self.lat.p99_99 = self.ckms.query(0.9999).unwrap().1;
             self.lat.p99_999 = self.ckms.query(0.99999).unwrap().1;
             self.lat.max = self.lat_max;
-            self.lat.ctl = self.ckms.query(self.params.lat_target_pct).unwrap().1;
+            self.lat.ctl = self
+                .params
+                .lat_tail_blend(Self::lat_ctl(&self.ckms, &self.params), self.lat.p99_9);
+            self.adapt_control_period(dur.as_secs_f64());
         } else {
             self.lat = Default::default();
             if self.nr_in_flight > 0 {
                 warn!(
                     "No completion in {} with {} requests in flight, con={:.1}/{:.1}",
-                    format_duration(self.params.control_period),
+                    format_duration(self.control_period),
                     self.nr_in_flight,
                     self.concurrency,
                     self.concurrency_max
@@ -669,30 +1499,78 @@ impl DispatchThread {
         true
     }
 
+    /// Bump the `violations` count of each of `params.assertions` which the
+    /// `measured` values don't satisfy.
+    fn check_assertions<F>(params: &Params, violations: &mut [u64], measured: F)
+    where
+        F: Fn(AssertField) -> f64,
+    {
+        for (assertion, cnt) in params.assertions.iter().zip(violations.iter_mut()) {
+            let val = measured(assertion.field);
+            if !assertion.holds(params, val) {
+                debug!(
+                    "dispatch: Assertion {:?} {:?} {} violated by {}",
+                    assertion.field,
+                    assertion.op,
+                    assertion.threshold_for(params),
+                    val
+                );
+                *cnt += 1;
+            }
+        }
+    }
+
+    /// Pick the next control period from how fast the latency error moved
+    /// over the last `dur` seconds. See `Params::next_control_period()`.
+    fn adapt_control_period(&mut self, dur: f64) {
+        let err = (self.params.lat_target - self.lat.ctl) / self.params.lat_target;
+        let slope = match self.lat_err_prev {
+            Some(prev) if dur > 0.0 => (err - prev) / dur,
+            _ => 0.0,
+        };
+        self.lat_err_prev = Some(err);
+        self.control_period = self.params.next_control_period(slope);
+    }
+
     /// Two pid controllers work in conjunction to determine the concurrency
     /// level. The latency one caps the max concurrency to keep latency within
     /// the target. The rps one tries to converge on the target rps.
     fn update_control(&mut self) {
-        let out = self
-            .lat_pid
-            .next_control_output(self.lat.ctl / self.params.lat_target);
-        let adj = out.output;
-
-        // Negative adjustment means latency is in charge. concurrency_max
-        // might have diverged upwards in the meantime. Jump down to the
-        // current concurrency level immediately.
-        if adj < 0.0 {
-            self.concurrency_max = f64::min(self.concurrency_max, self.concurrency);
-        }
-        self.concurrency_max = (self.concurrency_max * (1.0 + adj))
-            .max(1.0)
-            .min(self.params.concurrency_max as f64);
-
-        let adj = self
-            .rps_pid
-            .next_control_output(self.rps / self.params.rps_target as f64)
-            .output;
-        self.concurrency = (self.concurrency * (1.0 + adj)).max(1.0);
+        self.touch_balloon();
+
+        // While paused, there are no measurements to act on. Freeze the
+        // controllers and the memory footprint until resumed.
+        if self.params.is_paused() {
+            return;
+        }
+        Self::ramp_lat_target(&mut self.params, self.lat_target_ramp_at);
+        self.lat_pid.setpoint = self.params.lat_target;
+        self.apply_warmup();
+        self.apply_rps_schedule();
+        self.update_cpu_throttle();
+
+        for pid in [&mut self.lat_pid, &mut self.rps_pid, &mut self.mem_pid].iter_mut() {
+            pid.period_scale = self.control_scale;
+        }
+        let out = self.lat_pid.next_control_output(self.lat.ctl);
+        let conc_min = self
+            .params
+            .effective_concurrency_min(self.params.concurrency_max) as f64;
+        self.concurrency_max = Self::concurrency_max_step(
+            self.concurrency_max,
+            self.concurrency,
+            out.output,
+            conc_min,
+            self.params.concurrency_max as f64,
+        );
+
+        self.concurrency = Self::concurrency_step(
+            &mut self.rps_pid,
+            self.concurrency,
+            self.rps,
+            self.params.concurrency_ramp_max,
+            conc_min,
+        );
 
         // If concurrency is being limited by concurrency_max, latency is in
         // control; otherwise rps. Reset the other's integral term to prevent
@@ -711,15 +1589,54 @@ impl DispatchThread {
             self.lat_pid.reset_integral_term();
         }
 
-        let rps_max = self.params.rps_max as f64;
-        let file_base = self.params.file_addr_rps_base_frac;
-        let anon_base = self.params.anon_addr_rps_base_frac;
-        self.file_addr_frac = (file_base + (1.0 - file_base) * (self.rps / rps_max)).min(1.0);
-        self.anon_addr_frac = (anon_base + (1.0 - anon_base) * (self.rps / rps_max)).min(1.0);
+        let file_frac = self.ramped_file_frac();
+        if self.file_frac != file_frac {
+            let old_anon_total = Self::anon_total(self.max_size, self.mem_frac, self.file_frac);
+            self.file_frac = file_frac;
+            let new_anon_total = Self::anon_total(self.max_size, self.mem_frac, self.file_frac);
+            if new_anon_total != old_anon_total {
+                self.anon_area.write().unwrap().resize(new_anon_total);
+            }
+        }
+
+        let mem_frac_max = self.ramped_mem_frac();
+        if self.params.mem_pressure_target > 0.0 {
+            self.update_mem_control(mem_frac_max);
+        } else if self.mem_frac != mem_frac_max {
+            let old_anon_total = Self::anon_total(self.max_size, self.mem_frac, self.file_frac);
+            self.mem_frac = mem_frac_max;
+            let new_anon_total = Self::anon_total(self.max_size, self.mem_frac, self.file_frac);
+            if new_anon_total != old_anon_total {
+                self.anon_area.write().unwrap().resize(new_anon_total);
+            }
+        }
+
+        let rps = self.rps.round() as u32;
+        self.file_addr_frac = self.params.file_addr_frac(rps);
+        self.anon_addr_frac = self.params.anon_addr_frac(rps);
+
+        let (rps, lat, lat_p99, concurrency, mem_frac) = (
+            self.rps,
+            self.lat.ctl,
+            self.lat.p99,
+            self.concurrency,
+            self.mem_frac,
+        );
+        Self::check_assertions(
+            &self.params,
+            &mut self.assertion_violations,
+            |field| match field {
+                AssertField::Rps => rps,
+                AssertField::Lat => lat,
+                AssertField::LatP99 => lat_p99,
+                AssertField::Concurrency => concurrency,
+                AssertField::MemFrac => mem_frac,
+            },
+        );
 
         debug!(
             "p50={:.1} p84={:.1} p90={:.1} p95={:.1} p99={:.1} ctl={:.1} rps={:.1} con={:.1}/{:.1} \
-             ffrac={:.2} aafrac={:.2}",
+             mfrac={:.2} ffrac={:.2} aafrac={:.2}",
             self.lat.p50 * TO_MSEC,
             self.lat.p84 * TO_MSEC,
             self.lat.p90 * TO_MSEC,
@@ -729,19 +1646,190 @@ impl DispatchThread {
             self.rps,
             self.concurrency,
             self.concurrency_max,
+            self.mem_frac,
             self.file_addr_frac,
             self.anon_addr_frac,
         );
     }
 
+    /// While warming up, ramp the rps setpoint up and run the latency and rps
+    /// controllers with reduced gains. The full gains are restored once done.
+    fn apply_warmup(&mut self) {
+        let params = &self.params;
+        if params.warmup_secs <= 0.0 {
+            return;
+        }
+        let elapsed = self.warmup_at.elapsed().as_secs_f64();
+        let target = params.warmup_rps_target(elapsed);
+        let (scale, setpoint) = match elapsed < params.warmup_secs {
+            true => (Params::WARMUP_PID_GAIN_SCALE, target.max(1)),
+            false => (1.0, target),
+        };
+        self.lat_pid.params = params.lat_pid.scaled_gains(scale);
+        self.rps_pid.params = params.rps_pid.scaled_gains(scale);
+        self.rps_pid.setpoint = setpoint as f64;
+    }
+
+    /// Follow the burst and stress schedules with the rps setpoint. The
+    /// controllers chase the jumps as they would a changed rps_target.
+    fn apply_rps_schedule(&mut self) {
+        let stress = !self.params.stress_schedule.is_empty();
+        if !self.params.has_bursts() && !stress {
+            return;
+        }
+        let elapsed = self.warmup_at.elapsed().as_secs_f64();
+        let target = self.params.effective_rps_target(elapsed);
+        if stress && !self.stress_broken && self.lat.ctl > self.params.lat_target {
+            info!(
+                "stress: Latency target broken at rps_target {} ({:.1}rps) {:.1}s in",
+                self.rps_pid.setpoint, self.rps, elapsed
+            );
+            self.stress_broken = true;
+        }
+        if target as f64 != self.rps_pid.setpoint {
+            debug!(
+                "dispatch: rps setpoint {} -> {}",
+                self.rps_pid.setpoint, target
+            );
+        }
+        self.rps_pid.setpoint = target.max(1) as f64;
+    }
+
+    /// Update `cpu_throttle_frac` to the fraction of CPUs whose thermal
+    /// throttle count went up since the last control period. Only tracked
+    /// if `cpu_ratio_min` is set.
+    fn update_cpu_throttle(&mut self) {
+        if self.params.cpu_ratio_min.is_none() {
+            self.cpu_throttle_counts = None;
+            self.cpu_throttle_frac = 0.0;
+            return;
+        }
+        let counts = match read_cpu_throttle_counts() {
+            Ok(v) => {
+                self.cpu_throttle_err = false;
+                v
+            }
+            Err(e) => {
+                if !self.cpu_throttle_err {
+                    warn!("Failed to read CPU throttle counts ({:?})", &e);
+                    self.cpu_throttle_err = true;
+                }
+                return;
+            }
+        };
+        if let Some(prev) = self.cpu_throttle_counts.as_ref() {
+            self.cpu_throttle_frac = Self::throttled_frac(prev, &counts);
+        }
+        self.cpu_throttle_counts = Some(counts);
+    }
+
+    fn throttled_frac(prev: &[u64], cur: &[u64]) -> f64 {
+        if prev.len() != cur.len() || cur.is_empty() {
+            return 0.0;
+        }
+        let nr = prev.iter().zip(cur.iter()).filter(|(p, c)| c > p).count();
+        nr as f64 / cur.len() as f64
+    }
+
+    /// Set `lat_target` and the first of `lat_targets` from the ramp which
+    /// started at `at`.
+    fn ramp_lat_target(params: &mut Params, at: Instant) {
+        if params.lat_target_ramp.is_none() {
+            return;
+        }
+        params.lat_target = params.lat_target_at(at.elapsed().as_secs_f64());
+        params.sync_lat_targets();
+    }
+
+    /// The ramp to follow after a params update. Changing the target from
+    /// `old_target` to `target` starts a new ramp from the current value
+    /// `cur`, otherwise the running `ramp`, if any, keeps going.
+    fn next_ramp(
+        ramp: Option<(f64, Instant)>,
+        secs: f64,
+        cur: f64,
+        old_target: f64,
+        target: f64,
+    ) -> Option<(f64, Instant)> {
+        match secs > 0.0 {
+            false => None,
+            true if target != old_target => Some((cur, Instant::now())),
+            true => ramp,
+        }
+    }
+
+    /// The configured mem_frac, interpolated if a ramp is in progress.
+    fn ramped_mem_frac(&mut self) -> f64 {
+        match self.mem_frac_ramp {
+            Some((start, at)) => {
+                let elapsed = at.elapsed().as_secs_f64();
+                if elapsed >= self.params.mem_frac_ramp_secs {
+                    debug!("dispatch: mem_frac ramp complete");
+                    self.mem_frac_ramp = None;
+                }
+                self.params.mem_frac_at(start, elapsed)
+            }
+            None => self.params.mem_frac,
+        }
+    }
+
+    /// The configured file_frac, interpolated if a ramp is in progress.
+    fn ramped_file_frac(&mut self) -> f64 {
+        match self.file_frac_ramp {
+            Some((start, at)) => {
+                let elapsed = at.elapsed().as_secs_f64();
+                if elapsed >= self.params.file_frac_ramp_secs {
+                    debug!("dispatch: file_frac ramp complete");
+                    self.file_frac_ramp = None;
+                }
+                self.params.file_frac_at(start, elapsed)
+            }
+            None => self.params.file_frac,
+        }
+    }
+
+    fn update_mem_control(&mut self, mem_frac_max: f64) {
+        let pressure = match read_self_mem_pressure() {
+            Ok(v) => {
+                self.mem_pressure_err = false;
+                v
+            }
+            Err(e) => {
+                if !self.mem_pressure_err {
+                    warn!("Failed to read memory pressure ({:?})", &e);
+                    self.mem_pressure_err = true;
+                }
+                return;
+            }
+        };
+
+        let old_anon_total = Self::anon_total(self.max_size, self.mem_frac, self.file_frac);
+        self.mem_frac =
+            Self::mem_frac_step(&mut self.mem_pid, self.mem_frac, pressure, mem_frac_max);
+        let new_anon_total = Self::anon_total(self.max_size, self.mem_frac, self.file_frac);
+
+        if new_anon_total != old_anon_total {
+            self.anon_area.write().unwrap().resize(new_anon_total);
+        }
+    }
+
     pub fn run(&mut self) {
         self.params_updated();
         loop {
             // Launch hashers to fill target concurrency.
             self.launch_hashers();
 
+            // Wake up for the next queue arrival too.
+            let arrival = match self.queue.as_ref() {
+                Some(queue) if !self.params.is_paused() => {
+                    channel::after(queue.next_arrival_in(self.rps_pid.setpoint))
+                }
+                _ => channel::never(),
+            };
+
             // Handle user commands and hasher completions.
             select! {
+                recv(arrival) -> _ => {},
                 recv(self.cmd_rx) -> cmd => {
                     match cmd {
                         Ok(DispatchCmd::SetParams(params)) => self.update_params(params),
@@ -752,8 +1840,11 @@ impl DispatchThread {
                             anon_dist.resize(self.params.acc_dist_slots, 0);
                             std::mem::swap(&mut self.file_dist, &mut file_dist);
                             std::mem::swap(&mut self.anon_dist, &mut anon_dist);
+                            let lat_hist = std::mem::replace(&mut self.lat_hist,
+                                                             self.params.lat_histogram());
 
                             ch.send(Stat { lat: self.lat.clone(),
+                                           lat_hist,
                                            rps: self.rps,
                                            concurrency: self.concurrency,
                                            concurrency_max: self.concurrency_max,
@@ -763,13 +1854,19 @@ impl DispatchThread {
                                            nr_done: self.nr_done,
                                            nr_workers: self.wq.nr_workers(),
                                            nr_idle_workers: self.wq.nr_idle_workers(),
+                                           queue_depth: self.queue.as_ref().map(|q| q.depth()).unwrap_or(0),
+                                           nr_dropped: self.nr_dropped,
                                            file_size: self.tf.size,
                                            file_dist,
                                            anon_size: self.anon_area.read().unwrap().size(),
                                            anon_dist,
+                                           nr_read_mismatches: self.nr_read_mismatches.load(Ordering::Relaxed),
+                                           assertion_violations: self.assertion_violations.clone(),
                             })
                                 .unwrap();
                         }
+                        Ok(DispatchCmd::GetSnapshot(ch)) => ch.send(self.snapshot()).unwrap(),
+                        Ok(DispatchCmd::Restore(snap)) => self.restore(snap),
                         Ok(DispatchCmd::FillAnon) => {
                             let aa = self.anon_area.read().unwrap();
                             for i in 0 .. aa.size() / *PAGE_SIZE {
@@ -784,28 +1881,7 @@ impl DispatchThread {
                 },
                 recv(self.cmpl_rx) -> cmpl => {
                     match cmpl {
-                        Ok(HashCompletion {digest, started_at, file_dist, anon_dist}) => {
-                            self.nr_in_flight -= 1;
-                            self.nr_done += 1;
-                            let dur = Instant::now().duration_since(started_at).as_secs_f64();
-                            self.lat_min = self.lat_min.min(dur);
-                            self.lat_max = self.lat_max.max(dur);
-                            self.ckms.insert(dur);
-                            if let Some(logger) = self.logger.as_mut() {
-                                logger.log(&format!("{} {:.2}ms",
-                                                    digest, dur * TO_MSEC));
-                            }
-                            if file_dist.len() == self.file_dist.len() {
-                                for i in 0..file_dist.len() {
-                                    self.file_dist[i] += file_dist[i];
-                                }
-                            }
-                            if anon_dist.len() == self.anon_dist.len() {
-                                for i in 0..anon_dist.len() {
-                                    self.anon_dist[i] += anon_dist[i];
-                                }
-                            }
-                        },
+                        Ok(cmpl) => self.complete(cmpl),
                         Err(err) => {
                             debug!("DispatchThread: cmpl_rx error ({:?})", err);
                             return;
@@ -880,6 +1956,24 @@ impl Dispatch {
         self.stat_rx.recv().unwrap()
     }
 
+    pub fn snapshot(&self) -> Snapshot {
+        let (tx, rx) = channel::bounded(1);
+        self.cmd_tx
+            .as_ref()
+            .unwrap()
+            .send(DispatchCmd::GetSnapshot(tx))
+            .unwrap();
+        rx.recv().unwrap()
+    }
+
+    pub fn restore(&mut self, snap: &Snapshot) {
+        self.cmd_tx
+            .as_ref()
+            .unwrap()
+            .send(DispatchCmd::Restore(snap.clone()))
+            .unwrap();
+    }
+
     pub fn fill_anon(&self) {
         self.cmd_tx
             .as_ref()
@@ -900,12 +1994,53 @@ impl Drop for Dispatch {
 
 #[cfg(test)]
 mod tests {
+    use super::{
+        ChecksumTable, ClassSampler, DispatchThread, FileSizeSampler, Hasher, HasherThread, Pid,
+        SleepSampler, TestFiles,
+    };
     use quantiles::ckms::CKMS;
     use rand::rngs::SmallRng;
-    use rand::SeedableRng;
+    use rand::{Rng, SeedableRng};
+    use rd_hashd_intf::params::PidParams;
+    use rd_hashd_intf::{
+        AssertField, AssertOp, FileSizeDist, FileSync, HashAlgo, ParamAssertion, Params, PidState,
+        WorkloadClass,
+    };
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
 
     const CKMS_ERROR: f64 = 0.001;
 
+    fn dispatch_thread(tf: TestFiles, max_size: u64, params: Params) -> DispatchThread {
+        let (_cmd_tx, cmd_rx) = crossbeam::channel::unbounded();
+        DispatchThread::new(max_size, tf, params, 0.0, None, cmd_rx)
+    }
+
+    /// `size` bytes of testfiles in a fresh directory named after `name`.
+    /// Remove the directory when done.
+    fn setup_testfiles(name: &str, size: u64) -> (TestFiles, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("rd-hashd-test-{}-{}", name, std::process::id()));
+        let mut tf = TestFiles::new(&dir, 1 << 20, size, 0.0);
+        tf.setup(|_| {}).unwrap();
+        (tf, dir)
+    }
+
+    /// Launch hash workers through the dispatch path until `nr` have
+    /// finished, then wait for the rest.
+    fn run_hashers(dt: &mut DispatchThread, nr: u64) {
+        while dt.nr_done < nr {
+            dt.launch_hashers();
+            let cmpl = dt.cmpl_rx.recv().unwrap();
+            dt.complete(cmpl);
+        }
+        while dt.nr_in_flight > 0 {
+            let cmpl = dt.cmpl_rx.recv().unwrap();
+            dt.complete(cmpl);
+        }
+    }
+
     #[test]
     fn test_clamped_normal() {
         let _ = ::env_logger::try_init();
@@ -952,4 +2087,927 @@ mod tests {
         assert!(p50 >= -0.1 && p50 <= 0.1);
         assert!(p75 >= 0.4 && p75 <= 0.6);
     }
+
+    #[test]
+    fn test_clamped_normal_uniform_frac() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let tail_frac = |n: &super::ClampedNormal, rng: &mut SmallRng| {
+            let nr = 16384;
+            let mut nr_tail = 0;
+            for _ in 0..nr {
+                let v = n.sample(rng);
+                assert!((-1.0..=1.0).contains(&v));
+                if v.abs() > 0.75 {
+                    nr_tail += 1;
+                }
+            }
+            nr_tail as f64 / nr as f64
+        };
+
+        // The default knee reproduces the plain clamped distribution.
+        let blend = Params::default().uniform_blend(0.6);
+        let plain = tail_frac(&super::ClampedNormal::new(0.0, 0.6, -1.0, 1.0), &mut rng);
+        let blended = tail_frac(
+            &super::ClampedNormal::new(0.0, 0.6, -1.0, 1.0).with_uniform_frac(Some(blend)),
+            &mut rng,
+        );
+        println!(
+            "blend={:.3} plain={:.3} blended={:.3}",
+            blend, plain, blended
+        );
+        assert!((plain - blended).abs() < 0.02);
+
+        // All uniform and all normal.
+        let all = super::ClampedNormal::new(0.0, 0.6, -1.0, 1.0).with_uniform_frac(Some(1.0));
+        assert!((tail_frac(&all, &mut rng) - 0.25).abs() < 0.02);
+        let none = super::ClampedNormal::new(0.0, 0.1, -1.0, 1.0).with_uniform_frac(Some(0.0));
+        assert_eq!(tail_frac(&none, &mut rng), 0.0);
+
+        // The default knee takes the single draw path.
+        let p = Params::default();
+        assert_eq!(p.addr_uniform_blend(0.6), None);
+        let p = Params {
+            dist_uniform_knee: 0.3,
+            ..p
+        };
+        assert_eq!(p.addr_uniform_blend(0.6), Some(p.uniform_blend(0.6)));
+
+        // Clamps too narrow for rejection go uniform.
+        let wide = super::ClampedNormal::new(0.0, 1000.0, -1.0, 1.0).with_uniform_frac(Some(0.0));
+        assert!((tail_frac(&wide, &mut rng) - 0.25).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_clamped_normal_truncated() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let range = |trunc: f64, rng: &mut SmallRng| {
+            let n = super::ClampedNormal::truncated(0.0, 0.5, -1.0, 1.0, trunc);
+            (0..4096).fold((0.0f64, 0.0f64), |(min, max), _| {
+                let v = n.sample(rng);
+                (min.min(v), max.max(v))
+            })
+        };
+
+        // 0 keeps the original bounds which 2 stdevs touch.
+        let (min, max) = range(0.0, &mut rng);
+        println!("trunc=0 min={:.3} max={:.3}", min, max);
+        assert!(min < -0.9 && max > 0.9);
+
+        // Narrower truncations narrow the range.
+        let (min, max) = range(1.0, &mut rng);
+        println!("trunc=1 min={:.3} max={:.3}", min, max);
+        assert!(min >= -0.5 && max <= 0.5);
+        assert!(min < -0.45 && max > 0.45);
+        let (min, max) = range(0.5, &mut rng);
+        println!("trunc=0.5 min={:.3} max={:.3}", min, max);
+        assert!(min >= -0.25 && max <= 0.25);
+
+        // Truncating wider than the bounds has no effect.
+        let (min, max) = range(10.0, &mut rng);
+        assert!(min >= -1.0 && max <= 1.0);
+    }
+
+    #[test]
+    fn test_concurrency_step() {
+        let params = PidParams {
+            kp: 10.0,
+            ..Default::default()
+        };
+
+        // rps far below the target makes the controller ask for 10x.
+        let mut pid = Pid::new(&params, 10.0, 10.0, 1000.0);
+        let next = DispatchThread::concurrency_step(&mut pid, 10.0, 100.0, 0, 1.0);
+        assert!(next > 50.0);
+
+        let mut pid = Pid::new(&params, 10.0, 10.0, 1000.0);
+        let next = DispatchThread::concurrency_step(&mut pid, 10.0, 100.0, 8, 1.0);
+        assert_eq!(next, 18.0);
+
+        // Shrinking isn't limited.
+        let mut pid = Pid::new(&params, 10.0, 10.0, 1000.0);
+        let next = DispatchThread::concurrency_step(&mut pid, 100.0, 2000.0, 8, 1.0);
+        assert_eq!(next, 1.0);
+    }
+
+    #[test]
+    fn test_concurrency_min() {
+        let params = Params {
+            concurrency_min: 4,
+            lat_pid: PidParams {
+                kp: 10.0,
+                ki: 1.0,
+                ..Default::default()
+            },
+            rps_pid: PidParams {
+                kp: 10.0,
+                ki: 1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (mut lat_pid, mut rps_pid, _) = DispatchThread::pid_controllers(&params);
+        let conc_min = params.effective_concurrency_min(params.concurrency_max) as f64;
+        assert_eq!(conc_min, 4.0);
+
+        // Latency 100x over the target and rps way over, both controllers
+        // push for the minimum as hard as they can.
+        let (mut conc, mut conc_max) = (64.0, 64.0);
+        for _ in 0..100 {
+            let adj = lat_pid
+                .next_control_output(params.lat_target * 100.0)
+                .output;
+            conc_max = DispatchThread::concurrency_max_step(
+                conc_max,
+                conc,
+                adj,
+                conc_min,
+                params.concurrency_max as f64,
+            );
+            conc = DispatchThread::concurrency_step(
+                &mut rps_pid,
+                conc,
+                params.rps_target as f64 * 100.0,
+                0,
+                conc_min,
+            )
+            .min(conc_max);
+            assert!(conc >= conc_min && conc_max >= conc_min);
+        }
+        assert_eq!((conc, conc_max), (conc_min, conc_min));
+
+        // The floor can't go over concurrency_max.
+        let capped = Params {
+            concurrency_min: 100,
+            concurrency_max: 10,
+            ..Default::default()
+        };
+        assert_eq!(capped.effective_concurrency_min(capped.concurrency_max), 10);
+        assert_eq!(Params::default().effective_concurrency_min(65536), 1);
+    }
+
+    #[test]
+    fn test_mem_frac_step() {
+        let params = Params {
+            mem_pressure_target: 0.1,
+            ..Default::default()
+        };
+        let mut pid = Pid::new(&params.mem_pid, 0.1, 0.1, params.mem_pressure_target);
+
+        // Pressure above the target should shrink the footprint.
+        let mut mem_frac = params.mem_frac;
+        for _ in 0..10 {
+            let next = DispatchThread::mem_frac_step(&mut pid, mem_frac, 0.5, params.mem_frac);
+            assert!(next < mem_frac);
+            mem_frac = next;
+        }
+        println!("mem_frac after high pressure: {:.3}", mem_frac);
+
+        // No pressure should grow it back up to but not beyond mem_frac. The
+        // accumulated integral term may delay the turnaround a bit.
+        let low = mem_frac;
+        for _ in 0..100 {
+            mem_frac = DispatchThread::mem_frac_step(&mut pid, mem_frac, 0.0, params.mem_frac);
+            assert!(mem_frac <= params.mem_frac);
+        }
+        assert!(mem_frac > low);
+        assert_eq!(mem_frac, params.mem_frac);
+    }
+
+    #[test]
+    fn test_verify_read() {
+        let page_size = *rd_util::PAGE_SIZE;
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut data = vec![0u8; 8 * page_size];
+        rng.fill(&mut data[..]);
+
+        let mut table = ChecksumTable::default();
+        table.push_pages(&data);
+        let cnt = AtomicU64::new(0);
+
+        // A clean read of pages [2, 6).
+        let chunk = data[2 * page_size..6 * page_size].to_vec();
+        HasherThread::verify_read(&table, 2, &chunk, &cnt);
+        assert_eq!(cnt.load(Ordering::Relaxed), 0);
+
+        // Writes bump the leading u64 of each page, which isn't covered.
+        let mut written = chunk.clone();
+        written[page_size] = written[page_size].wrapping_add(1);
+        HasherThread::verify_read(&table, 2, &written, &cnt);
+        assert_eq!(cnt.load(Ordering::Relaxed), 0);
+
+        // Flip a byte in the middle of the third page.
+        let mut corrupted = chunk.clone();
+        corrupted[2 * page_size + 100] ^= 0xff;
+        HasherThread::verify_read(&table, 2, &corrupted, &cnt);
+        assert_eq!(cnt.load(Ordering::Relaxed), 1);
+
+        // The same bytes at a different offset don't match either.
+        HasherThread::verify_read(&table, 3, &chunk, &cnt);
+        assert_eq!(cnt.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn test_write_sync_due() {
+        let count = |ratio: f64, every: u32, nr: usize| {
+            let nr_writes = AtomicU64::new(0);
+            let cost = HasherThread::write_cost(ratio);
+            (0..nr)
+                .filter(|_| HasherThread::write_sync_due(&nr_writes, cost, every))
+                .count()
+        };
+        assert_eq!(count(1.0, 1, 100), 100);
+        assert_eq!(count(1.0, 4, 100), 25);
+        // Each write counts 4 times and the sync comes 4 times as often.
+        assert_eq!(count(4.0, 4, 100), 100);
+        assert_eq!(count(4.0, 16, 100), 25);
+        assert_eq!(count(0.5, 1, 100), 50);
+        assert_eq!(count(2.5, 10, 100), 25);
+    }
+
+    #[test]
+    fn test_drift_page() {
+        assert_eq!(HasherThread::drift_page(5, 0, 4, 100), 5);
+        assert_eq!(HasherThread::drift_page(5, 10, 4, 100), 45);
+        assert_eq!(HasherThread::drift_page(90, 10, 4, 100), 30);
+        assert_eq!(HasherThread::drift_page(0, 1, 4, 0), 0);
+    }
+
+    #[test]
+    fn test_file_size_dist() {
+        let _ = ::env_logger::try_init();
+        let mut rng = SmallRng::from_entropy();
+
+        for dist in &[FileSizeDist::Normal, FileSizeDist::LogNormal] {
+            let params = Params {
+                file_size_dist: *dist,
+                ..Default::default()
+            };
+            let sampler = FileSizeSampler::new(&params, params.file_size_mean as f64);
+
+            let nr_samples = 16384;
+            let mut sum = 0.0;
+            for _ in 0..nr_samples {
+                let v = sampler.sample(&mut rng);
+                assert!(v >= *rd_util::PAGE_SIZE as f64);
+                sum += v;
+            }
+            let mean = sum / nr_samples as f64;
+            let err = (mean - params.file_size_mean as f64).abs() / params.file_size_mean as f64;
+            println!("{:?}: mean={:.0} err={:.3}", dist, mean, err);
+            assert!(err < 0.05);
+        }
+    }
+
+    #[test]
+    fn test_hash_algo() {
+        let data = vec![0x5au8; 3 * *rd_util::PAGE_SIZE];
+        for (algo, hex_len) in &[
+            (HashAlgo::Crc32, 8),
+            (HashAlgo::Sha1, 40),
+            (HashAlgo::Sha256, 64),
+            (HashAlgo::Sha512, 128),
+        ] {
+            let mut hasher = Hasher::new(1.0, 0.0, false, *algo);
+            hasher.append(&data);
+            let digest = hasher.digest();
+            println!("{:?}: {}", algo, &digest);
+            assert_eq!(digest.len(), *hex_len);
+
+            // Faking CPU load skips hashing whatever the algorithm.
+            let mut hasher = Hasher::new(1.0, 0.000_000_001, false, *algo);
+            hasher.append(&data);
+            assert_eq!(hasher.digest(), "");
+        }
+    }
+
+    #[test]
+    fn test_crc32_touches_same_bytes() {
+        let page_size = *rd_util::PAGE_SIZE;
+        let path = std::env::temp_dir().join(format!("rd-hashd-test-crc32-{}", std::process::id()));
+        let data: Vec<u8> = (0..4 * page_size).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let load = |algo: HashAlgo| {
+            let mut hasher = Hasher::new(1.0, 0.0, false, algo);
+            let size = hasher
+                .load(
+                    &path,
+                    page_size as u64,
+                    2 * page_size,
+                    false,
+                    FileSync::None,
+                )
+                .unwrap();
+            assert_eq!(size, 2 * page_size);
+            let buf = hasher.buf.clone();
+            (buf, hasher.digest())
+        };
+        let (sha_buf, sha_digest) = load(HashAlgo::Sha1);
+        let (crc_buf, crc_digest) = load(HashAlgo::Crc32);
+        std::fs::remove_file(&path).unwrap();
+
+        // Every byte of the range is read into the buffer either way.
+        assert_eq!(&sha_buf[..], &data[page_size..3 * page_size]);
+        assert_eq!(crc_buf, sha_buf);
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&data[page_size..3 * page_size]);
+        assert_eq!(crc_digest, format!("{:08x}", crc.finalize()));
+        assert_ne!(crc_digest, sha_digest);
+    }
+
+    #[test]
+    fn test_class_sampler() {
+        let mut rng = SmallRng::from_entropy();
+        assert!(ClassSampler::new(&Params::default()).is_none());
+
+        let class = |weight: f64, file_frac: f64| WorkloadClass {
+            weight,
+            file_size_mean: 4096,
+            file_frac,
+            rps_target: 100,
+        };
+        let params = Params {
+            classes: vec![class(1.0, 0.1), class(3.0, 0.9)],
+            ..Default::default()
+        };
+        let cs = ClassSampler::new(&params).unwrap();
+
+        let mut cnts = [0u64; 2];
+        for _ in 0..65536 {
+            let (_, file_frac) = cs.pick(&mut rng);
+            cnts[(file_frac > 0.5) as usize] += 1;
+        }
+        let ratio = cnts[1] as f64 / cnts[0] as f64;
+        println!("cnts={:?} ratio={:.3}", &cnts, ratio);
+        assert!((ratio - 3.0).abs() < 0.3);
+    }
+
+    #[test]
+    fn test_sleep_modes() {
+        let mut rng = SmallRng::from_entropy();
+
+        // Fast and slow paths which don't overlap, each clamped to [0, 2 * mean].
+        let params = Params {
+            sleep_modes: vec![(0.3, 0.001, 0.5), (0.7, 0.1, 0.2)],
+            ..Default::default()
+        };
+        let sampler = SleepSampler::new(&params);
+
+        let nr_samples = 65536;
+        let mut nr_fast = 0;
+        for _ in 0..nr_samples {
+            let v = sampler.sample(&mut rng);
+            assert!((0.0..=0.2).contains(&v));
+            if v <= 0.002 {
+                nr_fast += 1;
+            }
+        }
+        let fast_frac = nr_fast as f64 / nr_samples as f64;
+        println!("fast_frac={:.3}", fast_frac);
+        assert!((fast_frac - 0.3).abs() < 0.02);
+        assert!((1.0 - fast_frac - 0.7).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_sleep_jitter() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let variance = |params: &Params, rng: &mut SmallRng| {
+            let sampler = SleepSampler::new(params);
+            let samples: Vec<f64> = (0..16384).map(|_| sampler.sample(rng)).collect();
+            let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+            let var =
+                samples.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+            (mean, var)
+        };
+
+        let params = Params {
+            sleep_mean: 0.02,
+            sleep_stdev_ratio: 0.01,
+            ..Default::default()
+        };
+        let (mean, var) = variance(&params, &mut rng);
+        let (jmean, jvar) = variance(
+            &Params {
+                sleep_jitter_frac: 0.5,
+                ..params.clone()
+            },
+            &mut rng,
+        );
+        println!(
+            "mean={:.5} var={:.3e} jittered mean={:.5} var={:.3e}",
+            mean, var, jmean, jvar
+        );
+        // Uniform [0, 0.01] adds 0.005 to the mean and 0.01^2 / 12 to the variance.
+        assert!((jmean - mean - 0.005).abs() < 0.0005);
+        assert!(jvar > var * 10.0);
+        assert!((jvar - var - 0.0001 / 12.0).abs() < 0.0001 / 12.0 * 0.1);
+    }
+
+    #[test]
+    fn test_rng_seed() {
+        let size = 8 << 20;
+        let (_, dir) = setup_testfiles("rng-seed", size);
+
+        // The file and anon pages the hash workers accessed.
+        let run = |rng_seed: Option<u64>| -> (Vec<u64>, Vec<u64>) {
+            let params = Params {
+                rng_seed,
+                file_size_mean: 64 << 10,
+                anon_size_ratio: 1.0,
+                sleep_mean: 0.0,
+                acc_dist_slots: 256,
+                ..Default::default()
+            };
+            let tf = TestFiles::new(&dir, 1 << 20, size, 0.0);
+            let mut dt = dispatch_thread(tf, 4 * size, params);
+            run_hashers(&mut dt, 16);
+            (dt.file_dist.clone(), dt.anon_dist.clone())
+        };
+
+        let first = run(Some(42));
+        assert!(first.0.iter().sum::<u64>() > 0);
+        assert!(first.1.iter().sum::<u64>() > 0);
+        assert_eq!(first, run(Some(42)));
+        assert_ne!(first, run(Some(43)));
+        assert_ne!(run(None), run(None));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_think_time_latency() {
+        let size = 8 << 20;
+        let (_, dir) = setup_testfiles("think-time", size);
+
+        // With a service time, the 200ms sleep is think time before each
+        // request and must not show up in the recorded latencies.
+        let params = Params {
+            file_size_mean: 64 << 10,
+            anon_size_ratio: 1.0,
+            sleep_mean: 0.2,
+            sleep_stdev_ratio: 0.0,
+            service_mean: 0.001,
+            service_stdev_ratio: 0.0,
+            ..Default::default()
+        };
+        let tf = TestFiles::new(&dir, 1 << 20, size, 0.0);
+        let mut dt = dispatch_thread(tf, 4 * size, params);
+        let started_at = std::time::Instant::now();
+        run_hashers(&mut dt, 8);
+        let elapsed = started_at.elapsed().as_secs_f64();
+        println!("elapsed={:.3} lat_max={:.3}", elapsed, dt.lat_max);
+        assert!(elapsed >= 0.2);
+        assert!(dt.lat_max >= 0.001);
+        assert!(dt.lat_max < 0.1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_weighted_sampler_zipf() {
+        let mut rng = SmallRng::from_entropy();
+        let s = 1.0;
+        let zipf = super::WeightedSampler::zipf(64, s);
+
+        let nr_samples = 1 << 18;
+        let mut cnts = vec![0u64; 64];
+        for _ in 0..nr_samples {
+            cnts[zipf.sample(&mut rng)] += 1;
+        }
+
+        // freq(1) / freq(k) should be k^s.
+        for k in 2..=8 {
+            let ratio = cnts[0] as f64 / cnts[k - 1] as f64;
+            let expected = (k as f64).powf(s);
+            println!("k={} ratio={:.3} expected={:.3}", k, ratio, expected);
+            assert!((ratio - expected).abs() / expected < 0.1);
+        }
+    }
+
+    #[test]
+    fn test_file_zipf_cache() {
+        use super::{DispatchThread, FileAddrDist};
+        use std::sync::Arc;
+
+        let mut cache = None;
+        let zipf = FileAddrDist::Zipf { s: 1.0 };
+        assert!(DispatchThread::file_zipf(&mut cache, FileAddrDist::Normal, 64).is_none());
+
+        // The same size is shared, a different one is rebuilt.
+        let a = DispatchThread::file_zipf(&mut cache, zipf, 64).unwrap();
+        let b = DispatchThread::file_zipf(&mut cache, zipf, 64).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        let c = DispatchThread::file_zipf(&mut cache, zipf, 32).unwrap();
+        assert!(!Arc::ptr_eq(&a, &c));
+    }
+
+    #[test]
+    fn test_file_histogram() {
+        let mut rng = SmallRng::from_entropy();
+
+        // Without a histogram, file_addr_stdev_ratio and file_addr_dist apply.
+        assert!(DispatchThread::file_hist(&Params::default()).is_none());
+
+        // The histogram takes over even with an extreme addr stdev.
+        let params = Params {
+            file_addr_stdev_ratio: 100.0,
+            file_histogram: vec![0, 3, 0, 1],
+            ..Default::default()
+        };
+        let hist = DispatchThread::file_hist(&params).unwrap();
+
+        let mut cnts = vec![0u64; 4];
+        for _ in 0..65536 {
+            cnts[hist.sample(&mut rng)] += 1;
+        }
+        let ratio = cnts[1] as f64 / cnts[3] as f64;
+        println!("cnts={:?} ratio={:.3}", &cnts, ratio);
+        assert_eq!(cnts[0], 0);
+        assert_eq!(cnts[2], 0);
+        assert!((ratio - 3.0).abs() < 0.3);
+
+        // Each slot maps to the start of its chunk_pages-sized region.
+        assert_eq!(HasherThread::hist_to_page(3, 25, 1000), 75);
+        assert_eq!(HasherThread::hist_to_page(100, 25, 1000), 999);
+    }
+
+    #[test]
+    fn test_access_trace() {
+        assert!(DispatchThread::trace(&Params::default()).is_none());
+
+        let path =
+            std::env::temp_dir().join(format!("rd-hashd-test-trace-{}.txt", std::process::id()));
+        std::fs::write(&path, "5 8192 1\n41 4096 0\n").unwrap();
+        let params = Params {
+            trace_path: Some(path.clone()),
+            file_histogram: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let trace = DispatchThread::trace(&params).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(trace.records.len(), 2);
+        assert!(trace.get(0).is_write);
+        assert_eq!(trace.get(3).chunk, 41);
+
+        // Chunk indices map to chunk_pages-sized regions and wrap around.
+        assert_eq!(HasherThread::trace_to_page(5, 25, 1000), 125);
+        assert_eq!(HasherThread::trace_to_page(41, 25, 1000), 25);
+
+        // Replaying bypasses file_histogram. The histogram puts every
+        // access on page 1000 and the trace on page 0.
+        let size = 8 << 20;
+        let (_, dir) = setup_testfiles("access-trace", size);
+        std::fs::write(&path, "0 4096 0\n").unwrap();
+        let mut hist = vec![0; 1024];
+        hist[1000] = 1;
+        let run = |trace_path: Option<PathBuf>| -> Vec<u64> {
+            let params = Params {
+                trace_path,
+                file_histogram: hist.clone(),
+                chunk_pages: 1,
+                anon_size_ratio: 1.0,
+                sleep_mean: 0.0,
+                acc_dist_slots: 256,
+                ..Default::default()
+            };
+            let tf = TestFiles::new(&dir, 1 << 20, size, 0.0);
+            let mut dt = dispatch_thread(tf, 4 * size, params);
+            run_hashers(&mut dt, 8);
+            dt.file_dist.clone()
+        };
+        let page_slot = |page: usize| page * 256 / (size as usize / *rd_util::PAGE_SIZE);
+
+        let dist = run(None);
+        assert!(dist[page_slot(1000)] > 0);
+        assert_eq!(dist.iter().sum::<u64>(), dist[page_slot(1000)]);
+        let dist = run(Some(path.clone()));
+        assert!(dist[0] > 0);
+        assert_eq!(dist.iter().sum::<u64>(), dist[0]);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lat_ctl() {
+        let mut ckms = CKMS::<f64>::new(CKMS_ERROR);
+        for i in 1..=1000 {
+            ckms.insert(i as f64 / 1000.0);
+        }
+
+        // p50 = 0.5, p99 = 0.99
+        let mut params = Params {
+            lat_target_pct: 0.5,
+            lat_target: 1.0,
+            ..Default::default()
+        };
+        let ctl = DispatchThread::lat_ctl(&ckms, &params);
+        assert!((ctl - 0.5).abs() < 0.01);
+
+        // p99 at 0.99 against 0.5 is the most violated.
+        params.lat_targets = vec![(0.5, 1.0), (0.99, 0.5)];
+        let ctl = DispatchThread::lat_ctl(&ckms, &params);
+        println!("ctl={}", ctl);
+        assert!((ctl - 1.98).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_check_assertions() {
+        let params = Params {
+            rps_target: 1000,
+            lat_target: 0.1,
+            assertions: vec![
+                ParamAssertion {
+                    field: AssertField::Rps,
+                    op: AssertOp::Ge,
+                    threshold: 0.9,
+                    of_target: true,
+                },
+                ParamAssertion {
+                    field: AssertField::LatP99,
+                    op: AssertOp::Lt,
+                    threshold: 0.25,
+                    of_target: false,
+                },
+            ],
+            ..Default::default()
+        };
+        let mut violations = vec![0; 2];
+        let measure = |rps: f64, lat_p99: f64| {
+            move |field| match field {
+                AssertField::Rps => rps,
+                AssertField::LatP99 => lat_p99,
+                _ => panic!("unexpected {:?}", field),
+            }
+        };
+
+        // Satisfied, nothing counted.
+        DispatchThread::check_assertions(&params, &mut violations, measure(950.0, 0.2));
+        assert_eq!(violations, vec![0, 0]);
+
+        // rps below 900 violates the first one.
+        for _ in 0..3 {
+            DispatchThread::check_assertions(&params, &mut violations, measure(850.0, 0.2));
+        }
+        assert_eq!(violations, vec![3, 0]);
+
+        // And both.
+        DispatchThread::check_assertions(&params, &mut violations, measure(0.0, 0.25));
+        assert_eq!(violations, vec![4, 1]);
+        DispatchThread::check_assertions(&params, &mut violations, measure(900.0, 0.1));
+        assert_eq!(violations, vec![4, 1]);
+    }
+
+    #[test]
+    fn test_anon_histogram_update() {
+        let max_size = 64 << 20;
+        let params = Params::default();
+        let slot_bytes = (params.chunk_pages * *rd_util::PAGE_SIZE) as u64;
+        let anon_bytes = DispatchThread::anon_total(max_size, params.mem_frac, params.file_frac);
+        let nr_slots = anon_bytes as u64 / slot_bytes;
+        let tf = TestFiles::new(std::env::temp_dir(), 1 << 20, max_size / 2, 0.0);
+        let mut dt = dispatch_thread(tf, max_size, params.clone());
+
+        // Fits in the current anon area.
+        let mut fits = params.clone();
+        fits.anon_histogram = vec![1; nr_slots as usize];
+        dt.update_params(fits.clone());
+        assert_eq!(dt.params.anon_histogram, fits.anon_histogram);
+        assert!(dt.anon_hist.is_some());
+
+        // Shrinking mem_frac would leave the histogram hanging off the end,
+        // the whole update is rejected and the old params stay.
+        let mut shrunk = fits.clone();
+        shrunk.mem_frac = params.mem_frac / 2.0;
+        shrunk.rps_target = params.rps_target / 2;
+        dt.update_params(shrunk);
+        assert_eq!(dt.params.mem_frac, params.mem_frac);
+        assert_eq!(dt.params.rps_target, params.rps_target);
+        assert_eq!(dt.params.anon_histogram, fits.anon_histogram);
+        assert!(dt.anon_hist.is_some());
+
+        // Ditto if the anon area is only that small at the start of a
+        // ramp, here from where memory pressure control left mem_frac.
+        dt.mem_frac = params.mem_frac / 2.0;
+        let mut ramped = fits.clone();
+        ramped.mem_frac_ramp_secs = 60.0;
+        ramped.mem_frac = 1.0;
+        dt.update_params(ramped);
+        assert!(dt.mem_frac_ramp.is_none());
+        assert_eq!(dt.params.mem_frac, params.mem_frac);
+        assert_eq!(dt.mem_frac, params.mem_frac / 2.0);
+    }
+
+    #[test]
+    fn test_mem_frac_ramp_reload() {
+        let max_size = 64 << 20;
+        let tf = TestFiles::new(std::env::temp_dir(), 1 << 20, max_size / 2, 0.0);
+        let params = Params {
+            mem_frac: 0.8,
+            mem_frac_ramp_secs: 100.0,
+            ..Default::default()
+        };
+        let mut dt = dispatch_thread(tf, max_size, params.clone());
+        assert!(dt.mem_frac_ramp.is_none());
+
+        // Changing mem_frac starts a ramp from the current one.
+        let mut p = params.clone();
+        p.mem_frac = 0.4;
+        dt.update_params(p.clone());
+        let (start, at) = dt.mem_frac_ramp.unwrap();
+        assert_eq!(start, 0.8);
+
+        // An unrelated reload halfway through keeps the ramp going.
+        let halfway = at.checked_sub(Duration::from_secs(50)).unwrap();
+        dt.mem_frac_ramp = Some((0.8, halfway));
+        dt.mem_frac = dt.ramped_mem_frac();
+        assert!((dt.mem_frac - 0.6).abs() < 0.01);
+        p.rps_target /= 2;
+        dt.update_params(p.clone());
+        assert_eq!(dt.mem_frac_ramp, Some((0.8, halfway)));
+        assert!((dt.mem_frac - 0.6).abs() < 0.01);
+
+        // A new target ramps from where the ramp got to, not from the
+        // previous target.
+        let cur = dt.mem_frac;
+        p.mem_frac = 0.2;
+        dt.update_params(p.clone());
+        let (start, _) = dt.mem_frac_ramp.unwrap();
+        assert_eq!(start, cur);
+
+        // Disabling the ramp jumps to the target.
+        p.mem_frac_ramp_secs = 0.0;
+        dt.update_params(p);
+        assert!(dt.mem_frac_ramp.is_none());
+        assert_eq!(dt.mem_frac, 0.2);
+    }
+
+    #[test]
+    fn test_file_frac_ramp_reload() {
+        let max_size = 64 << 20;
+        let tf = TestFiles::new(std::env::temp_dir(), 1 << 20, max_size / 2, 0.0);
+        let params = Params {
+            file_frac: 0.2,
+            file_frac_ramp_secs: 100.0,
+            ..Default::default()
+        };
+        let mut dt = dispatch_thread(tf, max_size, params.clone());
+
+        let mut p = params.clone();
+        p.file_frac = 0.4;
+        dt.update_params(p.clone());
+        let (start, at) = dt.file_frac_ramp.unwrap();
+        assert_eq!(start, 0.2);
+        assert!((dt.file_frac - 0.2).abs() < 0.01);
+
+        // Halfway, an unrelated reload leaves the ramp alone.
+        let halfway = at.checked_sub(Duration::from_secs(50)).unwrap();
+        dt.file_frac_ramp = Some((0.2, halfway));
+        dt.file_frac = dt.ramped_file_frac();
+        assert!((dt.file_frac - 0.3).abs() < 0.01);
+        p.rps_target /= 2;
+        dt.update_params(p.clone());
+        assert_eq!(dt.file_frac_ramp, Some((0.2, halfway)));
+        assert!((dt.file_frac - 0.3).abs() < 0.01);
+
+        // A new target ramps from there.
+        let cur = dt.file_frac;
+        p.file_frac = 0.1;
+        dt.update_params(p);
+        assert_eq!(dt.file_frac_ramp.unwrap().0, cur);
+        assert!((dt.file_frac - cur).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let max_size = 64 << 20;
+        let params = Params {
+            concurrency_max: 32,
+            mem_frac: 0.8,
+            ..Default::default()
+        };
+        let tf = TestFiles::new(std::env::temp_dir(), 1 << 20, max_size / 2, 0.0);
+        let mut dt = dispatch_thread(tf, max_size, params.clone());
+        dt.lat_pid.set_state(&PidState {
+            integral: 0.5,
+            last_error: Some(0.1),
+            last_pv: None,
+        });
+        dt.concurrency = 7.5;
+        dt.concurrency_max = 20.0;
+        dt.mem_frac = 0.6;
+        let snap = dt.snapshot();
+        assert_eq!(
+            (snap.concurrency, snap.concurrency_max, snap.mem_frac),
+            (7.5, 20.0, 0.6)
+        );
+
+        // A restart resumes from the same operating point.
+        let tf = TestFiles::new(std::env::temp_dir(), 1 << 20, max_size / 2, 0.0);
+        let mut dt = dispatch_thread(tf, max_size, params.clone());
+        dt.restore(snap.clone());
+        assert_eq!(dt.lat_pid.state(), snap.lat_pid);
+        assert_eq!(
+            (dt.concurrency, dt.concurrency_max, dt.mem_frac),
+            (7.5, 20.0, 0.6)
+        );
+        assert!(dt.mem_frac_ramp.is_none());
+
+        // With a ramp, mem_frac heads back to the configured one.
+        let mut snap_ramp = snap.clone();
+        snap_ramp.params.mem_frac_ramp_secs = 100.0;
+        let tf = TestFiles::new(std::env::temp_dir(), 1 << 20, max_size / 2, 0.0);
+        let mut dt = dispatch_thread(tf, max_size, params.clone());
+        dt.restore(snap_ramp);
+        assert_eq!(dt.mem_frac, 0.6);
+        assert_eq!(dt.mem_frac_ramp.unwrap().0, 0.6);
+
+        // Restored values are clamped to what the params allow and missing
+        // ones are left alone.
+        let mut snap = snap;
+        snap.params.concurrency_max = 10;
+        snap.params.mem_frac = 0.5;
+        snap.concurrency = 0.0;
+        let tf = TestFiles::new(std::env::temp_dir(), 1 << 20, max_size / 2, 0.0);
+        let mut dt = dispatch_thread(tf, max_size, params);
+        let concurrency = dt.concurrency;
+        dt.restore(snap);
+        assert_eq!(dt.concurrency_max, 10.0);
+        assert_eq!(dt.concurrency, concurrency.min(10.0));
+        assert_eq!(dt.mem_frac, 0.5);
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let max_size = 64 << 20;
+        let params = Params {
+            rps_target: 1000,
+            ..Default::default()
+        };
+        let tf = TestFiles::new(std::env::temp_dir(), 1 << 20, max_size / 2, 0.0);
+        let mut dt = dispatch_thread(tf, max_size, params.clone());
+        let state = PidState {
+            integral: 0.5,
+            last_error: Some(0.1),
+            last_pv: Some(0.07),
+        };
+        dt.lat_pid.set_state(&state);
+        dt.rps_pid.set_state(&state);
+        dt.concurrency = 7.5;
+        dt.concurrency_max = 20.0;
+        let check = |dt: &DispatchThread| {
+            assert_eq!(dt.lat_pid.state(), state);
+            assert_eq!(dt.rps_pid.state(), state);
+            assert_eq!((dt.concurrency, dt.concurrency_max), (7.5, 20.0));
+        };
+
+        // While paused, nothing is launched and the controllers are frozen.
+        dt.update_params(Params {
+            rps_target: 0,
+            ..params.clone()
+        });
+        for _ in 0..3 {
+            dt.update_control();
+            dt.launch_hashers();
+        }
+        assert_eq!(dt.nr_in_flight, 0);
+        check(&dt);
+
+        // Resuming picks up where it left off.
+        dt.update_params(params);
+        check(&dt);
+    }
+
+    #[test]
+    fn test_setpoint_jump_reset() {
+        let max_size = 64 << 20;
+        let params = Params {
+            rps_target: 1000,
+            ..Default::default()
+        };
+        let tf = TestFiles::new(std::env::temp_dir(), 1 << 20, max_size / 2, 0.0);
+        let mut dt = dispatch_thread(tf, max_size, params.clone());
+        let state = PidState {
+            integral: 0.5,
+            last_error: Some(0.1),
+            last_pv: Some(0.07),
+        };
+        dt.lat_pid.set_state(&state);
+        dt.rps_pid.set_state(&state);
+
+        // A small step keeps the states, a jump from the running params
+        // resets them even though the update itself doesn't ask to.
+        let step = Params {
+            rps_target: 1500,
+            ..params.clone()
+        };
+        dt.update_params(step.clone());
+        assert_eq!(dt.rps_pid.state(), state);
+
+        let jump = Params {
+            rps_target: 6000,
+            ..params
+        };
+        assert!(!jump.is_pid_reset_requested());
+        dt.update_params(jump);
+        assert_eq!(dt.lat_pid.state(), PidState::default());
+        assert_eq!(dt.rps_pid.state(), PidState::default());
+    }
 }
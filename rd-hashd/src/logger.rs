@@ -14,7 +14,7 @@ use std::path::Path;
 use std::sync::atomic::{self, AtomicU64};
 use std::sync::Arc;
 use std::thread::{spawn, JoinHandle};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use rd_util::*;
 
@@ -24,11 +24,13 @@ struct LogWorker {
     log_rx: Receiver<String>,
     dir_path: String,
     padding: Arc<AtomicU64>,
-    unit_size: u64,
-    nr_to_keep: usize,
+    unit_size: Arc<AtomicU64>,
+    fsync_secs: Arc<AtomicU64>,
+    max_size: u64,
     rng: SmallRng,
     file: Option<File>,
     size: u64,
+    synced_at: Instant,
     old_logs: VecDeque<String>,
 }
 
@@ -50,10 +52,30 @@ impl LogWorker {
         )
     }
 
+    /// Whether the log at `size` should be rotated. `unit_size` of 0 never
+    /// rotates.
+    fn rotate_due(size: u64, unit_size: u64) -> bool {
+        unit_size > 0 && size >= unit_size
+    }
+
+    /// Whether to sync `since_sync` seconds after the last one. `fsync_secs`
+    /// of 0 never syncs.
+    fn fsync_due(since_sync: f64, fsync_secs: f64) -> bool {
+        fsync_secs > 0.0 && since_sync >= fsync_secs
+    }
+
+    fn nr_to_keep(max_size: u64, unit_size: u64) -> usize {
+        match unit_size {
+            0 => usize::MAX,
+            unit => ((max_size + unit - 1) / unit) as usize,
+        }
+    }
+
     fn new(
         dir_path: String,
         padding: Arc<AtomicU64>,
-        unit_size: u64,
+        unit_size: Arc<AtomicU64>,
+        fsync_secs: Arc<AtomicU64>,
         max_size: u64,
         log_rx: Receiver<String>,
     ) -> Result<Self> {
@@ -98,10 +120,12 @@ impl LogWorker {
             dir_path,
             padding,
             unit_size,
-            nr_to_keep: ((max_size + unit_size - 1) / unit_size) as usize,
+            fsync_secs,
+            max_size,
             rng: SmallRng::from_entropy(),
             file,
             size,
+            synced_at: Instant::now(),
             old_logs: VecDeque::from(old_logs),
         };
         lw.expire_old_logs();
@@ -109,7 +133,8 @@ impl LogWorker {
     }
 
     fn expire_old_logs(&mut self) {
-        while self.old_logs.len() >= self.nr_to_keep {
+        let unit_size = self.unit_size.load(atomic::Ordering::Relaxed);
+        while self.old_logs.len() >= Self::nr_to_keep(self.max_size, unit_size) {
             let path = match self.old_logs.pop_front() {
                 Some(v) => v,
                 None => break,
@@ -121,7 +146,8 @@ impl LogWorker {
     }
 
     fn rotate(&mut self) {
-        if self.size < self.unit_size || self.file.is_none() {
+        let unit_size = self.unit_size.load(atomic::Ordering::Relaxed);
+        if !Self::rotate_due(self.size, unit_size) || self.file.is_none() {
             return;
         }
 
@@ -187,6 +213,24 @@ impl LogWorker {
             self.file = None;
         }
         self.size += line.len() as u64;
+        self.maybe_sync();
+    }
+
+    fn maybe_sync(&mut self) {
+        let fsync_secs = f64::from_bits(self.fsync_secs.load(atomic::Ordering::Relaxed));
+        if !Self::fsync_due(self.synced_at.elapsed().as_secs_f64(), fsync_secs) {
+            return;
+        }
+        if let Some(file) = self.file.as_ref() {
+            if let Err(err) = file.sync_data() {
+                error!(
+                    "logger: failed to sync {:?} ({})",
+                    &Self::log_path(&self.dir_path),
+                    err
+                );
+            }
+        }
+        self.synced_at = Instant::now();
     }
 
     fn run(mut self) {
@@ -205,6 +249,8 @@ impl LogWorker {
 pub struct Logger {
     log_tx: Option<Sender<String>>,
     padding: Arc<AtomicU64>,
+    unit_size: Arc<AtomicU64>,
+    fsync_secs: Arc<AtomicU64>,
     worker_jh: Option<JoinHandle<()>>,
 }
 
@@ -213,6 +259,7 @@ impl Logger {
         dir_path: P,
         padding: u64,
         unit_size: u64,
+        fsync_secs: f64,
         max_size: u64,
         capacity: usize,
     ) -> Result<Self>
@@ -227,12 +274,23 @@ impl Logger {
 
         let (log_tx, log_rx) = channel::bounded(capacity);
         let padding = Arc::new(AtomicU64::new(padding));
-        let worker = LogWorker::new(dir_path, padding.clone(), unit_size, max_size, log_rx)?;
+        let unit_size = Arc::new(AtomicU64::new(unit_size));
+        let fsync_secs = Arc::new(AtomicU64::new(fsync_secs.to_bits()));
+        let worker = LogWorker::new(
+            dir_path,
+            padding.clone(),
+            unit_size.clone(),
+            fsync_secs.clone(),
+            max_size,
+            log_rx,
+        )?;
         let worker_jh = spawn(move || worker.run());
 
         Ok(Self {
             log_tx: Some(log_tx),
             padding,
+            unit_size,
+            fsync_secs,
             worker_jh: Some(worker_jh),
         })
     }
@@ -244,6 +302,18 @@ impl Logger {
         }
     }
 
+    /// Rotate once the log reaches `unit_size` and sync at most every
+    /// `fsync_secs`. 0 disables either.
+    pub fn set_policy(&self, unit_size: u64, fsync_secs: f64) {
+        if self.unit_size.swap(unit_size, atomic::Ordering::Relaxed) != unit_size {
+            info!("Logger: Updating rotation size to {:.2}M", to_mb(unit_size));
+        }
+        let bits = fsync_secs.to_bits();
+        if self.fsync_secs.swap(bits, atomic::Ordering::Relaxed) != bits {
+            info!("Logger: Updating fsync interval to {:.2}s", fsync_secs);
+        }
+    }
+
     pub fn log(&self, msg: &str) {
         let _ = self.log_tx.as_ref().unwrap().send(msg.into());
     }
@@ -255,3 +325,30 @@ impl Drop for Logger {
         self.worker_jh.take().unwrap().join().unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::LogWorker;
+
+    #[test]
+    fn test_rotate_due() {
+        assert!(!LogWorker::rotate_due(0, 1 << 20));
+        assert!(!LogWorker::rotate_due((1 << 20) - 1, 1 << 20));
+        assert!(LogWorker::rotate_due(1 << 20, 1 << 20));
+        assert!(!LogWorker::rotate_due(u64::MAX, 0));
+
+        assert_eq!(LogWorker::nr_to_keep(4 << 30, 1 << 30), 4);
+        assert_eq!(LogWorker::nr_to_keep(4 << 30, 3 << 30), 2);
+        assert_eq!(LogWorker::nr_to_keep(4 << 30, 0), usize::MAX);
+    }
+
+    #[test]
+    fn test_fsync_due() {
+        assert!(!LogWorker::fsync_due(0.5, 1.0));
+        assert!(LogWorker::fsync_due(1.0, 1.0));
+        assert!(LogWorker::fsync_due(30.0, 1.0));
+        // 0 disables syncing no matter how long it's been.
+        assert!(!LogWorker::fsync_due(0.0, 0.0));
+        assert!(!LogWorker::fsync_due(1e9, 0.0));
+    }
+}
@@ -3,17 +3,20 @@ use chrono::prelude::*;
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, error, info, warn};
 use std::fmt::Write;
+use std::path::Path;
 use std::process::exit;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::sleep;
 use std::time::{Duration, Instant, SystemTime};
 
-use rd_hashd_intf::{Args, Params, Phase, Report, Stat};
+use rd_hashd_intf::{Args, Params, Phase, Report, Snapshot, Stat};
 use rd_util::*;
 
 mod bench;
 mod hasher;
 mod logger;
+mod pid;
+mod queue;
 mod testfiles;
 mod workqueue;
 
@@ -26,8 +29,8 @@ lazy_static::lazy_static! {
 }
 
 const TESTFILE_UNIT_SIZE: u64 = 32 << 20;
-const LOGFILE_UNIT_SIZE: u64 = 1 << 30;
 const LOGGER_HOLD_SEC: f64 = 300.0;
+const PARAMS_ENV_PREFIX: &str = "RD_HASHD_";
 
 static ROTATIONAL: AtomicBool = AtomicBool::new(false);
 static ROTATIONAL_TESTFILES: AtomicBool = AtomicBool::new(false);
@@ -128,6 +131,21 @@ impl TestFilesProgressBar {
     }
 }
 
+/// Make it hard to miss that the running workload isn't what the params
+/// file asked for.
+fn warn_degraded(params: &Params) {
+    if !params.degraded() {
+        return;
+    }
+    warn!(
+        "Params DEGRADED, {} value(s) clamped and the workload may not match the config:",
+        params.degraded_reasons().len()
+    );
+    for reason in params.degraded_reasons().iter() {
+        warn!("  {}", reason);
+    }
+}
+
 fn create_logger(args: &Args, params: &Params) -> Option<Logger> {
     match args.log_dir.as_ref() {
         Some(log_dir) => {
@@ -141,7 +159,8 @@ fn create_logger(args: &Args, params: &Params) -> Option<Logger> {
             match Logger::new(
                 log_dir,
                 params.log_padding(),
-                LOGFILE_UNIT_SIZE,
+                params.log_max_bytes,
+                params.log_fsync_secs,
                 args.log_size,
                 (params.rps_max as f64 * LOGGER_HOLD_SEC) as usize,
             ) {
@@ -168,6 +187,31 @@ fn main() {
 
     debug!("arguments: {:#?}", args);
 
+    if args.lint {
+        let path = match args.params.as_ref() {
+            Some(p) => p,
+            None => {
+                error!("--params must be specified with --lint");
+                panic!();
+            }
+        };
+        let report = Params::lint(Path::new(path), args.lint_fix).expect("failed to lint params");
+        for (field, old, new) in report.adjustments.iter() {
+            println!("adjust: {} {} -> {}", field, old, new);
+        }
+        for msg in report.warnings.iter() {
+            println!("warn: {}", msg);
+        }
+        if report.fixed {
+            println!("rewrote {}", path);
+        }
+        exit(if report.is_clean() || report.fixed {
+            0
+        } else {
+            1
+        });
+    }
+
     let tf_path = match args.testfiles.as_ref() {
         Some(p) => p,
         None => {
@@ -184,14 +228,31 @@ fn main() {
     //
     // Load params and init stat.
     //
-    let mut params_file = JsonConfigFile::<Params>::load_or_create(args.params.as_ref())
-        .expect("failed to process params file");
+    // Params from stdin or a URL are loaded once and not reloaded.
+    let mut params_file = match args.params.as_deref() {
+        Some("-") => JsonConfigFile::<Params> {
+            data: Params::load_from_reader(std::io::stdin())
+                .expect("failed to read params from stdin"),
+            ..Default::default()
+        },
+        Some(url) if Params::is_url(url) => JsonConfigFile::<Params> {
+            data: Params::load_from_url(url).expect("failed to fetch params"),
+            ..Default::default()
+        },
+        path => {
+            JsonConfigFile::<Params>::load_or_create(path).expect("failed to process params file")
+        }
+    };
     let params = &mut params_file.data;
+    params
+        .apply_env_overrides(PARAMS_ENV_PREFIX)
+        .expect("failed to apply params environment overrides");
 
     if params.file_frac > args.file_max_frac {
         warn!("--file-max is lower than Params::file_frac, adjusting file_frac");
         params.file_frac = args.file_max_frac;
     }
+    warn_degraded(params);
 
     //
     // Create the testfiles root dir and determine whether we're on rotational
@@ -204,6 +265,9 @@ fn main() {
         args.compressibility,
     );
     tf.prep_base_dir().unwrap();
+    if params.verify_reads {
+        tf.enable_checksums();
+    }
 
     ROTATIONAL_TESTFILES.store(storage_info::is_path_rotational(tf_path), Ordering::Relaxed);
 
@@ -293,7 +357,7 @@ fn main() {
         "Starting hasher (maxcon={} lat={:.1}ms rps={} file={:.2}G anon={:.2}G)",
         params.concurrency_max,
         params.lat_target * TO_MSEC,
-        params.rps_target,
+        params.base_rps_target(),
         to_gb(fsize),
         to_gb(asize)
     );
@@ -306,6 +370,21 @@ fn main() {
         create_logger(args, &params),
     );
 
+    // Resume the PID controllers. The params file stays authoritative.
+    if let Some(path) = args.snapshot.as_ref() {
+        if std::path::Path::new(path).exists() {
+            match JsonConfigFile::<Snapshot>::load(path) {
+                Ok(snap_file) => {
+                    let mut snap = snap_file.data;
+                    snap.params = params.clone();
+                    dispatch.restore(&snap);
+                    info!("Restored controller states from {:?}", path);
+                }
+                Err(e) => warn!("Failed to load snapshot file {:?} ({:?})", path, &e),
+            }
+        }
+    }
+
     //
     // Monitor and report.
     //
@@ -316,6 +395,7 @@ fn main() {
     let mut stat_sum: Stat = Default::default();
     let mut nr_sums: u32 = 0;
     let mut last_summary_at = Instant::now();
+    let mut last_snapshot_at = Instant::now();
     loop {
         sleep(Duration::from_secs(1));
         let now = Instant::now();
@@ -358,8 +438,14 @@ fn main() {
             last_summary_at = now;
         }
 
+        let mut params_reloaded = false;
         match params_file.maybe_reload() {
             Ok(true) => {
+                params_reloaded = true;
+                if let Err(e) = params_file.data.apply_env_overrides(PARAMS_ENV_PREFIX) {
+                    warn!("Failed to apply params environment overrides ({:#})", &e);
+                }
+                warn_degraded(&params_file.data);
                 dispatch.set_params(&params_file.data);
                 report_file.data.params_modified = DateTime::from(params_file.loaded_mod);
                 info!(
@@ -374,6 +460,19 @@ fn main() {
             ),
         }
 
+        // The PID states drift every tick, write them out periodically and
+        // whenever the params change.
+        if let Some(path) = args.snapshot.as_ref() {
+            if params_reloaded
+                || now.duration_since(last_snapshot_at).as_secs() >= Args::SNAPSHOT_INTV_SECS
+            {
+                if let Err(e) = dispatch.snapshot().save(path) {
+                    warn!("Failed to update snapshot file {:?} ({:?})", path, &e);
+                }
+                last_snapshot_at = now;
+            }
+        }
+
         report_tick(&mut report_file, false);
     }
 }
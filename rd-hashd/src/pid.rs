@@ -0,0 +1,358 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+
+//! PID controller driven by `PidParams`.
+//!
+//! The error is calculated relative to the setpoint so that the same gains
+//! can be used regardless of the magnitude of the controlled value. Each of
+//! the P, I and D contributions and the final output are limited
+//! symmetrically. The integral term is accumulated as `error * ki` so that
+//! `ki` can be changed without an output jump, and the accumulated term is
+//! further clamped to `[i_min, i_max]` to prevent windup. The output is
+//! clamped to `[out_min, out_max]` so that a single controller can be kept
+//! from demanding the whole range.
+//!
+//! By default, `d_on_measurement` is set and the derivative is taken on the
+//! measurement, which is immune to setpoint changes. If cleared, the
+//! derivative is taken on the error, so a setpoint change shows up as a
+//! spike in the D term.
+//!
+//! Each term can be turned off with `p_enabled`, `i_enabled` and
+//! `d_enabled`. A disabled term contributes zero and its state, the integral
+//! or the previous error and measurement, is held cleared so that nothing
+//! stale is left when it's turned back on.
+//!
+//! The gains are per nominal control period. When a step covers
+//! `period_scale` of it, e.g. with an adaptive control period, the integral
+//! accumulates `period_scale` as much, the derivative is divided by it and
+//! the output is scaled by it so that the response over time stays the same
+//! however the period is divided up.
+use rd_hashd_intf::params::PidParams;
+use rd_hashd_intf::PidState;
+
+#[derive(Debug)]
+pub struct ControlOutput {
+    pub p: f64,
+    pub i: f64,
+    pub d: f64,
+    pub output: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Pid {
+    pub params: PidParams,
+    pub term_limit: f64,
+    pub output_limit: f64,
+    pub setpoint: f64,
+    pub period_scale: f64,
+
+    prev_measurement: Option<f64>,
+    prev_error: Option<f64>,
+    integral_term: f64,
+}
+
+fn apply_limit(limit: f64, value: f64) -> f64 {
+    limit.min(value.abs()) * value.signum()
+}
+
+impl Pid {
+    pub fn new(params: &PidParams, term_limit: f64, output_limit: f64, setpoint: f64) -> Self {
+        Self {
+            params: params.clone(),
+            term_limit,
+            output_limit,
+            setpoint,
+            period_scale: 1.0,
+            prev_measurement: None,
+            prev_error: None,
+            integral_term: 0.0,
+        }
+    }
+
+    pub fn reset_integral_term(&mut self) {
+        self.integral_term = 0.0;
+    }
+
+    /// Forget the accumulated integral and derivative history.
+    pub fn reset(&mut self) {
+        self.integral_term = 0.0;
+        self.prev_measurement = None;
+        self.prev_error = None;
+    }
+
+    pub fn state(&self) -> PidState {
+        PidState {
+            integral: self.integral_term,
+            last_error: self.prev_error,
+            last_pv: self.prev_measurement,
+        }
+    }
+
+    /// Resume from `state`, e.g. one saved in a snapshot.
+    pub fn set_state(&mut self, state: &PidState) {
+        self.integral_term = state.integral;
+        self.prev_error = state.last_error;
+        self.prev_measurement = state.last_pv;
+    }
+
+    pub fn next_control_output(&mut self, measurement: f64) -> ControlOutput {
+        let pp = &self.params;
+        let scale = self.period_scale;
+        let error = (self.setpoint - measurement) / self.setpoint;
+
+        let p = match pp.p_enabled {
+            true => apply_limit(self.term_limit, error * pp.kp),
+            false => 0.0,
+        };
+
+        self.integral_term = match pp.i_enabled {
+            true => apply_limit(self.term_limit, self.integral_term + error * pp.ki * scale)
+                .max(pp.i_min)
+                .min(pp.i_max),
+            false => 0.0,
+        };
+
+        let d = match pp.d_enabled {
+            true => {
+                let d = match (pp.d_on_measurement, self.prev_measurement, self.prev_error) {
+                    (true, Some(prev), _) => -(measurement - prev) / self.setpoint * pp.kd / scale,
+                    (false, _, Some(prev)) => (error - prev) * pp.kd / scale,
+                    _ => 0.0,
+                };
+                self.prev_measurement = Some(measurement);
+                self.prev_error = Some(error);
+                apply_limit(self.term_limit, d)
+            }
+            false => {
+                self.prev_measurement = None;
+                self.prev_error = None;
+                0.0
+            }
+        };
+
+        let output = apply_limit(self.output_limit, p + self.integral_term + d)
+            .max(pp.out_min)
+            .min(pp.out_max)
+            * scale;
+
+        ControlOutput {
+            p,
+            i: self.integral_term,
+            d,
+            output,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pid;
+    use rd_hashd_intf::params::PidParams;
+
+    #[test]
+    fn test_integral_windup() {
+        let params = PidParams {
+            kp: 0.1,
+            ki: 0.01,
+            kd: 0.01,
+            i_min: -0.05,
+            i_max: 0.02,
+            ..Default::default()
+        };
+        let mut pid = Pid::new(&params, 1.0, 1.0, 1.0);
+
+        // A long stretch of the measurement sitting far below the setpoint
+        // would keep growing the integral term without the clamp.
+        for _ in 0..1000 {
+            let out = pid.next_control_output(0.1);
+            assert!(out.i <= params.i_max);
+        }
+        assert_eq!(pid.next_control_output(0.1).i, params.i_max);
+
+        // Ditto in the other direction.
+        for _ in 0..1000 {
+            let out = pid.next_control_output(10.0);
+            assert!(out.i >= params.i_min);
+        }
+        assert_eq!(pid.next_control_output(10.0).i, params.i_min);
+
+        // Without the clamp, only the symmetric term limit applies.
+        let mut pid = Pid::new(
+            &PidParams {
+                ki: 0.01,
+                ..Default::default()
+            },
+            1.0,
+            1.0,
+            1.0,
+        );
+        for _ in 0..1000 {
+            pid.next_control_output(0.1);
+        }
+        assert_eq!(pid.next_control_output(0.1).i, 1.0);
+    }
+
+    #[test]
+    fn test_output_limits() {
+        let params = PidParams {
+            kp: 1.0,
+            out_min: -0.1,
+            out_max: 0.25,
+            ..Default::default()
+        };
+        let mut pid = Pid::new(&params, 1.0, 1.0, 1.0);
+
+        // Unclamped, these would produce 0.9 and -0.9.
+        assert_eq!(pid.next_control_output(0.1).output, 0.25);
+        assert_eq!(pid.next_control_output(1.9).output, -0.1);
+
+        // Within the range, the output is untouched.
+        let out = pid.next_control_output(0.9);
+        assert!((out.output - 0.1).abs() < 0.000_001);
+    }
+
+    #[test]
+    fn test_disabled_terms() {
+        let params = PidParams {
+            kp: 0.1,
+            ki: 0.01,
+            kd: 0.01,
+            i_enabled: false,
+            ..Default::default()
+        };
+        let mut pid = Pid::new(&params, 1.0, 1.0, 1.0);
+
+        // Sustained error would wind the integral all the way up.
+        for i in 0..1000 {
+            let out = pid.next_control_output(0.1 + (i % 2) as f64 * 0.01);
+            assert_eq!(out.i, 0.0);
+            assert_eq!(pid.state().integral, 0.0);
+            assert_eq!(out.output, out.p + out.d);
+        }
+
+        // Re-enabling starts from a clean accumulator.
+        pid.params.i_enabled = true;
+        let out = pid.next_control_output(0.1);
+        assert!((out.i - 0.009).abs() < 0.000_001);
+
+        // P-only.
+        let mut pid = Pid::new(
+            &PidParams {
+                i_enabled: false,
+                d_enabled: false,
+                ..params.clone()
+            },
+            1.0,
+            1.0,
+            1.0,
+        );
+        for _ in 0..10 {
+            let out = pid.next_control_output(0.5);
+            assert_eq!((out.i, out.d), (0.0, 0.0));
+            assert_eq!(out.output, 0.05);
+        }
+        assert_eq!(pid.state().last_error, None);
+
+        // Disabling P leaves only I.
+        let mut pid = Pid::new(
+            &PidParams {
+                p_enabled: false,
+                i_enabled: true,
+                kd: 0.0,
+                ..params
+            },
+            1.0,
+            1.0,
+            1.0,
+        );
+        let out = pid.next_control_output(0.5);
+        assert_eq!(out.p, 0.0);
+        assert!(out.i > 0.0);
+        assert_eq!(out.output, out.i);
+    }
+
+    #[test]
+    fn test_state() {
+        let params = PidParams {
+            kp: 0.1,
+            ki: 0.013,
+            kd: 0.07,
+            ..Default::default()
+        };
+        let mut pid = Pid::new(&params, 1.0, 1.0, 1.0);
+        for i in 0..17 {
+            pid.next_control_output(0.3 + i as f64 * 0.01);
+        }
+        let state = pid.state();
+
+        let mut restored = Pid::new(&params, 1.0, 1.0, 1.0);
+        restored.set_state(&state);
+        assert_eq!(restored.state(), state);
+        assert_eq!(restored.state().integral, pid.state().integral);
+
+        // Both continue identically.
+        let (a, b) = (
+            pid.next_control_output(0.5),
+            restored.next_control_output(0.5),
+        );
+        assert_eq!((a.p, a.i, a.d, a.output), (b.p, b.i, b.d, b.output));
+    }
+
+    #[test]
+    fn test_d_on_measurement() {
+        let run = |d_on_measurement: bool| -> f64 {
+            let params = PidParams {
+                kd: 1.0,
+                d_on_measurement,
+                ..Default::default()
+            };
+            let mut pid = Pid::new(&params, 10.0, 10.0, 1.0);
+            for _ in 0..10 {
+                pid.next_control_output(1.0);
+            }
+            pid.setpoint = 2.0;
+            pid.next_control_output(1.0).d
+        };
+
+        // Doubling the setpoint jumps the relative error from 0 to 0.5.
+        let classic = run(false);
+        let on_meas = run(true);
+        println!("d: classic={} on_measurement={}", classic, on_meas);
+        assert_eq!(classic, 0.5);
+        assert_eq!(on_meas, 0.0);
+    }
+
+    #[test]
+    fn test_period_scale() {
+        let params = PidParams {
+            kp: 0.5,
+            ki: 0.1,
+            kd: 0.2,
+            ..Default::default()
+        };
+        let mut full = Pid::new(&params, 10.0, 10.0, 1.0);
+        let mut half = Pid::new(&params, 10.0, 10.0, 1.0);
+        half.period_scale = 0.5;
+
+        // The measurement ramps by 0.2 per nominal period. Over the same
+        // time, the halved steps see the same derivative.
+        full.next_control_output(0.4);
+        half.next_control_output(0.4);
+        let a = full.next_control_output(0.6);
+        half.next_control_output(0.5);
+        let b = half.next_control_output(0.6);
+        println!("ramp: full={:?} half={:?}", &a, &b);
+        assert!((a.d - b.d).abs() < 0.000_001);
+
+        // With the measurement held, they accumulate the same integral while
+        // each halved step moves the output half as much.
+        let (fi, hi) = (a.i, b.i);
+        let a = full.next_control_output(0.6);
+        half.next_control_output(0.6);
+        let b = half.next_control_output(0.6);
+        println!("held: full={:?} half={:?}", &a, &b);
+        assert!((a.i - fi - (b.i - hi)).abs() < 0.000_001);
+        assert!((a.p - b.p).abs() < 0.000_001);
+        assert!((b.output - (b.p + b.i) * 0.5).abs() < 0.000_001);
+        assert_eq!(full.period_scale, 1.0);
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+
+//! Bounded dispatch queue driven by `Params::queue_max`.
+//!
+//! Without the queue, the dispatcher launches a new request whenever a
+//! worker is free and the offered load follows the achieved throughput.
+//! With it, requests arrive at the rps setpoint whether or not a worker is
+//! free and wait in the queue until one is. Once `queue_max` requests are
+//! waiting, further arrivals are dropped and counted, which models load
+//! shedding.
+//!
+//! A request's latency runs from its arrival, so the time spent waiting
+//! counts. Under overload the queue stays full and every request waits
+//! about `queue_max / rps` seconds on top of its own run time. The latency
+//! controller responds to this like any other latency increase. A larger
+//! `queue_max` sheds fewer requests but adds more latency.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub struct RequestQueue {
+    max: u32,
+    waiting: VecDeque<Instant>,
+    credit: f64,
+    last_at: Instant,
+}
+
+impl RequestQueue {
+    const WAKE_MIN: f64 = 0.001;
+    const WAKE_MAX: f64 = 1.0;
+
+    pub fn new(max: u32, now: Instant) -> Self {
+        Self {
+            max,
+            waiting: VecDeque::new(),
+            credit: 0.0,
+            last_at: now,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.waiting.len()
+    }
+
+    /// Change the capacity. Returns the number of waiting requests dropped
+    /// because they no longer fit.
+    pub fn set_max(&mut self, max: u32) -> u64 {
+        self.max = max;
+        let nr_drops = self.waiting.len().saturating_sub(max as usize);
+        self.waiting.truncate(max as usize);
+        nr_drops as u64
+    }
+
+    /// Forget the time since the last call, e.g. while paused, so that no
+    /// arrivals are accounted for it.
+    pub fn restart(&mut self, now: Instant) {
+        self.credit = 0.0;
+        self.last_at = now;
+    }
+
+    /// Queue the requests which arrived at `rate` per second since the last
+    /// call. Returns the number of requests dropped because the queue was
+    /// full.
+    pub fn arrive(&mut self, rate: f64, now: Instant) -> u64 {
+        let dur = now.saturating_duration_since(self.last_at).as_secs_f64();
+        self.last_at = now;
+        if rate.is_nan() || rate <= 0.0 {
+            return 0;
+        }
+
+        self.credit += rate * dur;
+        let nr_arrivals = self.credit.floor();
+        self.credit -= nr_arrivals;
+
+        let room = (self.max as usize).saturating_sub(self.waiting.len());
+        let nr_queued = (nr_arrivals as u64).min(room as u64);
+        for _ in 0..nr_queued {
+            self.waiting.push_back(now);
+        }
+        nr_arrivals as u64 - nr_queued
+    }
+
+    /// The arrival time of the oldest waiting request.
+    pub fn pop(&mut self) -> Option<Instant> {
+        self.waiting.pop_front()
+    }
+
+    /// How long until the next arrival at `rate`.
+    pub fn next_arrival_in(&self, rate: f64) -> Duration {
+        let secs = match rate > 0.0 {
+            true => (1.0 - self.credit) / rate,
+            false => Self::WAKE_MAX,
+        };
+        Duration::from_secs_f64(secs.max(Self::WAKE_MIN).min(Self::WAKE_MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestQueue;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_overload() {
+        let start = Instant::now();
+        let mut queue = RequestQueue::new(8, start);
+        let mut nr_drops = 0;
+
+        // 1000 rps offered while only 10 are taken off every 100ms.
+        for i in 1..=50 {
+            let now = start + Duration::from_millis(i * 100);
+            nr_drops += queue.arrive(1000.0, now);
+            assert!(queue.depth() <= 8);
+            for _ in 0..10 {
+                if let Some(at) = queue.pop() {
+                    assert!(at <= now);
+                }
+            }
+        }
+        println!("nr_drops={}", nr_drops);
+        assert_eq!(nr_drops, 50 * (100 - 8));
+
+        // Within capacity, nothing is dropped and the queue drains.
+        let mut queue = RequestQueue::new(8, start);
+        for i in 1..=50 {
+            let now = start + Duration::from_millis(i * 100);
+            assert_eq!(queue.arrive(25.0, now), 0);
+            while queue.pop().is_some() {}
+        }
+        assert_eq!(queue.depth(), 0);
+
+        // Fractional arrivals carry over.
+        let mut queue = RequestQueue::new(8, start);
+        queue.arrive(5.0, start + Duration::from_millis(100));
+        assert_eq!(queue.depth(), 0);
+        assert_eq!(queue.next_arrival_in(5.0), Duration::from_millis(100));
+        queue.arrive(5.0, start + Duration::from_millis(200));
+        assert_eq!(queue.depth(), 1);
+
+        // Shrinking drops the excess and restart() skips the time in between.
+        let mut queue = RequestQueue::new(8, start);
+        queue.arrive(100.0, start + Duration::from_secs(1));
+        assert_eq!(queue.set_max(3), 5);
+        assert_eq!(queue.depth(), 3);
+        queue.restart(start + Duration::from_secs(100));
+        assert_eq!(queue.arrive(100.0, start + Duration::from_secs(100)), 0);
+        assert_eq!(queue.depth(), 3);
+    }
+}
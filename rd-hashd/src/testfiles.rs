@@ -19,6 +19,38 @@ const FILE_DIGITS: usize = FILE_BITS / 4;
 const DIR_BITS: usize = 16;
 const DIR_DIGITS: usize = DIR_BITS / 4;
 
+/// CRC32 of each testfile page for `Params::verify_reads`. The leading 8
+/// bytes of each page are excluded as file writes increment them.
+#[derive(Debug, Default)]
+pub struct ChecksumTable {
+    sums: Vec<u32>,
+}
+
+impl ChecksumTable {
+    const SKIP: usize = 8;
+
+    fn page_sum(page: &[u8]) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&page[Self::SKIP.min(page.len())..]);
+        hasher.finalize()
+    }
+
+    /// Append the checksums of the pages in `data`.
+    pub fn push_pages(&mut self, data: &[u8]) {
+        self.sums
+            .extend(data.chunks(*PAGE_SIZE).map(Self::page_sum));
+    }
+
+    /// The number of pages in `data`, which starts at page `page_idx`, not
+    /// matching the table. Pages past the end of the table aren't checked.
+    pub fn nr_mismatches(&self, page_idx: u64, data: &[u8]) -> u64 {
+        data.chunks(*PAGE_SIZE)
+            .zip(self.sums.iter().skip(page_idx as usize))
+            .filter(|(page, &sum)| Self::page_sum(page) != sum)
+            .count() as u64
+    }
+}
+
 #[derive(Debug)]
 pub struct TestFiles {
     base_path: PathBuf,
@@ -27,6 +59,7 @@ pub struct TestFiles {
     pub nr_files: u64,
     pub comp: f64,
     prefix: String,
+    pub checksums: Option<ChecksumTable>,
 }
 
 impl TestFiles {
@@ -38,9 +71,15 @@ impl TestFiles {
             nr_files: size.div_ceil(&unit_size),
             comp,
             prefix: String::from(DFL_PREFIX),
+            checksums: None,
         }
     }
 
+    /// Build `checksums` during `setup()`. Existing testfiles are read back.
+    pub fn enable_checksums(&mut self) {
+        self.checksums = Some(Default::default());
+    }
+
     pub fn prep_base_dir(&self) -> Result<()> {
         let bp = &self.base_path;
 
@@ -105,6 +144,9 @@ impl TestFiles {
         }
 
         self.prep_base_dir()?;
+        if self.checksums.is_some() {
+            self.enable_checksums();
+        }
 
         for i in 0..self.nr_files {
             let (_di, fi, dname, fname) = self.idx_to_dfnames(i);
@@ -130,6 +172,9 @@ impl TestFiles {
                             && Self::read_comp(&fpath).unwrap_or(-1.0) == self.comp =>
                     {
                         trace!("testfiles: using existing {:?}", &fpath);
+                        if let Some(table) = self.checksums.as_mut() {
+                            table.push_pages(&fs::read(&fpath)?);
+                        }
                         continue;
                     }
                     _ => {}
@@ -153,6 +198,9 @@ impl TestFiles {
             fill_area_with_random(&mut buf, self.comp, &mut rng);
             buf[0..8].copy_from_slice(&self.comp.to_ne_bytes());
             f.write_all(&buf)?;
+            if let Some(table) = self.checksums.as_mut() {
+                table.push_pages(&buf);
+            }
 
             progress(i * self.unit_size);
         }
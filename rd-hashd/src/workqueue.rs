@@ -25,7 +25,7 @@
 //!     sleep(Duration::from_secs(1));
 //! }
 //! ```
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 use std::cell::RefCell;
 use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
@@ -36,6 +36,7 @@ use std::time::{Duration, Instant};
 /// A worker thread's self representation.
 struct Worker {
     id: usize,
+    cpu: Option<usize>,
     work_rx: Receiver<Box<dyn FnOnce() + Send>>,
     ack_tx: Sender<usize>,
     err_tx: Sender<usize>,
@@ -64,17 +65,22 @@ pub struct WorkQueue {
     ack_rx: Receiver<usize>,
     err_tx: Sender<usize>,
     err_rx: Receiver<usize>,
+
+    cpu_affinity: Option<Vec<usize>>,
+    nr_spawned: usize,
 }
 
 impl Worker {
     pub fn new(
         id: usize,
+        cpu: Option<usize>,
         work_rx: Receiver<Box<dyn FnOnce() + Send>>,
         ack_tx: Sender<usize>,
         err_tx: Sender<usize>,
     ) -> Self {
         Worker {
             id,
+            cpu,
             work_rx,
             ack_tx,
             err_tx,
@@ -83,6 +89,11 @@ impl Worker {
 
     pub fn run(self) {
         debug!("worker-{:x}: starting", self.id);
+        if let Some(cpu) = self.cpu {
+            if let Err(e) = rd_util::set_cpu_affinity(&[cpu]) {
+                warn!("worker-{:x}: {:#}", self.id, &e);
+            }
+        }
         for work in self.work_rx {
             trace!("worker-{:x}: executing {:p}", self.id, work);
             let work = std::panic::AssertUnwindSafe(work);
@@ -112,6 +123,35 @@ impl WorkQueue {
             ack_rx,
             err_tx,
             err_rx,
+            cpu_affinity: None,
+            nr_spawned: 0,
+        }
+    }
+
+    /// CPU for the `idx`'th spawned worker, assigned round-robin.
+    fn worker_cpu(cpus: &[usize], idx: usize) -> usize {
+        cpus[idx % cpus.len()]
+    }
+
+    /// Pin workers to `cpus` round-robin, or unpin if `None`. Workers which
+    /// are currently idle are dropped so that the rest of the pool is
+    /// recreated with the new affinity. Busy workers keep their affinity
+    /// until they idle out.
+    pub fn set_cpu_affinity(&mut self, cpus: Option<Vec<usize>>) {
+        if cpus == self.cpu_affinity {
+            return;
+        }
+        debug!("workqueue: cpu_affinity={:?}", &cpus);
+        self.cpu_affinity = cpus;
+        self.nr_spawned = 0;
+
+        let mut jhs = Vec::<JoinHandle<()>>::new();
+        while let Some(wref) = self.idle_workers.pop_back() {
+            self.workers.remove(&Self::wref_id(&wref));
+            jhs.push(wref.borrow_mut().join_handle.take().unwrap());
+        }
+        for jh in jhs {
+            jh.join().unwrap();
         }
     }
 
@@ -192,7 +232,13 @@ impl WorkQueue {
         let id = Self::wref_id(&wref);
         self.workers.insert(id, wref.clone());
 
-        let worker = Worker::new(id, work_rx, self.ack_tx.clone(), self.err_tx.clone());
+        let cpu = self
+            .cpu_affinity
+            .as_ref()
+            .map(|cpus| Self::worker_cpu(cpus, self.nr_spawned));
+        self.nr_spawned += 1;
+
+        let worker = Worker::new(id, cpu, work_rx, self.ack_tx.clone(), self.err_tx.clone());
         wref.borrow_mut().join_handle = Some(spawn(|| worker.run()));
         wref
     }
@@ -336,4 +382,11 @@ mod tests {
 
         panic!("Boooooooooom!");
     }
+
+    #[test]
+    fn test_worker_cpu() {
+        let cpus = [2, 5, 7];
+        let assigned: Vec<usize> = (0..5).map(|i| WorkQueue::worker_cpu(&cpus, i)).collect();
+        assert_eq!(assigned, vec![2, 5, 7, 2, 5]);
+    }
 }
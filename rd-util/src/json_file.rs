@@ -12,7 +12,73 @@ fn read_json<P: AsRef<Path>>(path: P) -> Result<(String, String)> {
     let mut f = fs::OpenOptions::new().read(true).open(path)?;
     let mut buf = String::new();
     f.read_to_string(&mut buf)?;
+    Ok(split_json_preamble(&buf))
+}
+
+/// Blank out `//` line and `/* */` block comments outside of strings,
+/// keeping the newlines so that line numbers in parse errors still match.
+/// An unterminated block comment is left alone for the parser to reject.
+pub fn strip_json_comments(buf: &str) -> String {
+    let mut out = String::with_capacity(buf.len());
+    let mut chars = buf.char_indices().peekable();
+    let mut in_str = false;
+
+    while let Some((idx, c)) = chars.next() {
+        if in_str {
+            out.push(c);
+            match c {
+                '\\' => {
+                    if let Some((_, e)) = chars.next() {
+                        out.push(e);
+                    }
+                }
+                '"' => in_str = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek().map(|(_, n)| *n)) {
+            ('"', _) => {
+                in_str = true;
+                out.push(c);
+            }
+            ('/', Some('/')) => {
+                while let Some((_, n)) = chars.peek() {
+                    if *n == '\n' {
+                        break;
+                    }
+                    out.push(' ');
+                    chars.next();
+                }
+                out.push(' ');
+            }
+            ('/', Some('*')) => match buf[idx + 2..].find("*/") {
+                Some(len) => {
+                    let end = idx + 2 + len + 2;
+                    out.push(' ');
+                    for (i, n) in chars.by_ref() {
+                        out.push(if n == '\n' { '\n' } else { ' ' });
+                        if i + n.len_utf8() >= end {
+                            break;
+                        }
+                    }
+                }
+                None => {
+                    out.push_str(&buf[idx..]);
+                    break;
+                }
+            },
+            _ => out.push(c),
+        }
+    }
+    out
+}
 
+/// Split `buf` into the leading `//` and `#` comment lines and the JSON
+/// body. Comment lines in the body are blanked and inline comments are
+/// stripped with `strip_json_comments()` so that JSONC, e.g. a saved file
+/// with comments added by hand, loads.
+pub fn split_json_preamble(buf: &str) -> (String, String) {
     let mut preamble = String::new();
     let mut body = String::new();
     let mut seen_body = false;
@@ -29,7 +95,7 @@ fn read_json<P: AsRef<Path>>(path: P) -> Result<(String, String)> {
             body = body + line + "\n"
         }
     }
-    Ok((preamble, body))
+    (preamble, strip_json_comments(&body))
 }
 
 pub trait JsonLoad
@@ -54,6 +120,12 @@ where
         None
     }
 
+    /// Preamble to emit when saving `self`. Defaults to `Self::preamble()`,
+    /// override to add instance-specific comments.
+    fn preamble_of(&self) -> Option<String> {
+        Self::preamble()
+    }
+
     fn maybe_create_dfl<P: AsRef<Path>>(path_in: P) -> Result<bool> {
         let path = path_in.as_ref();
 
@@ -83,7 +155,7 @@ where
         if !serialized.ends_with("\n") {
             serialized += "\n";
         }
-        match Self::preamble() {
+        match self.preamble_of() {
             Some(pre) => Ok(pre + &serialized),
             None => Ok(serialized),
         }
@@ -32,14 +32,17 @@ pub mod journal_tailer;
 pub mod json_file;
 pub mod storage_info;
 pub mod systemd;
+pub mod toml_file;
 
 pub use iocost::{IoCostModelParams, IoCostQoSParams, IoCostSysSave};
 pub use journal_tailer::*;
 pub use json_file::{
-    JsonArgs, JsonArgsHelper, JsonConfigFile, JsonLoad, JsonRawFile, JsonReportFile, JsonSave,
+    split_json_preamble, strip_json_comments, JsonArgs, JsonArgsHelper, JsonConfigFile, JsonLoad,
+    JsonRawFile, JsonReportFile, JsonSave,
 };
 pub use storage_info::*;
 pub use systemd::TransientService;
+pub use toml_file::{TomlLoad, TomlSave};
 
 pub const TO_MSEC: f64 = 1000.0;
 pub const TO_PCT: f64 = 100.0;
@@ -197,6 +200,40 @@ pub fn nr_cpus() -> usize {
     }
 }
 
+/// Parse a kernel CPU list such as "0-3,8,10-11".
+pub fn parse_cpu_list(input: &str) -> Result<Vec<usize>> {
+    let mut cpus = vec![];
+    for tok in input.trim().split(',').filter(|t| !t.is_empty()) {
+        match tok.split_once('-') {
+            Some((first, last)) => cpus.extend(first.parse::<usize>()?..=last.parse::<usize>()?),
+            None => cpus.push(tok.parse::<usize>()?),
+        }
+    }
+    Ok(cpus)
+}
+
+pub fn online_cpus() -> Result<Vec<usize>> {
+    parse_cpu_list(&read_one_line("/sys/devices/system/cpu/online")?)
+}
+
+/// Restrict the calling thread to `cpus`.
+pub fn set_cpu_affinity(cpus: &[usize]) -> Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in cpus.iter() {
+            libc::CPU_SET(*cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) < 0 {
+            bail!(
+                "Failed to set CPU affinity to {:?} ({:?})",
+                cpus,
+                *libc::__errno_location()
+            );
+        }
+    }
+    Ok(())
+}
+
 pub const SWAPPINESS_PATH: &str = "/proc/sys/vm/swappiness";
 
 pub fn read_swappiness() -> Result<u32> {
@@ -227,6 +264,71 @@ pub fn read_zswap_enabled() -> Result<bool> {
     )
 }
 
+pub const THREADS_MAX_PATH: &str = "/proc/sys/kernel/threads-max";
+
+/// The maximum number of threads the calling process may create, the lower
+/// of the RLIMIT_NPROC soft limit and kernel.threads-max. None if neither
+/// limit could be determined.
+pub fn thread_limit() -> Option<u64> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    let nproc = match unsafe { libc::getrlimit(libc::RLIMIT_NPROC, &mut rlim) } {
+        0 if rlim.rlim_cur != libc::RLIM_INFINITY => Some(rlim.rlim_cur),
+        _ => None,
+    };
+    let threads_max = read_one_line(THREADS_MAX_PATH)
+        .ok()
+        .and_then(|line| line.trim().parse::<u64>().ok());
+    match (nproc, threads_max) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Per-CPU thermal throttle event counts in a stable order. Empty if the
+/// kernel doesn't expose them.
+pub fn read_cpu_throttle_counts() -> Result<Vec<u64>> {
+    let mut counts = vec![];
+    for path in glob("/sys/devices/system/cpu/cpu*/thermal_throttle/core_throttle_count")?
+        .filter_map(|x| x.ok())
+    {
+        let line = read_one_line(&path)?;
+        counts.push(
+            line.trim()
+                .parse::<u64>()
+                .with_context(|| format!("Parsing {:?} from {:?}", &line, &path))?,
+        );
+    }
+    Ok(counts)
+}
+
+/// Read the memory pressure "some" avg10 of the cgroup the calling process
+/// belongs to as a fraction.
+pub fn read_self_mem_pressure() -> Result<f64> {
+    let cgrp = fs::read_to_string("/proc/self/cgroup")
+        .context("Reading /proc/self/cgroup")?
+        .lines()
+        .find_map(|line| line.strip_prefix("0::").map(|x| x.to_string()))
+        .ok_or_else(|| anyhow!("cgroup2 membership not found"))?;
+    let path = format!("/sys/fs/cgroup{}/memory.pressure", cgrp.trim());
+
+    for line in fs::read_to_string(&path)
+        .with_context(|| format!("Reading {:?}", &path))?
+        .lines()
+    {
+        if let Ok(avg10) = scan_fmt!(
+            line,
+            "some avg10={f} avg60={*f} avg300={*f} total={*d}",
+            f64
+        ) {
+            return Ok(avg10 / TO_PCT);
+        }
+    }
+    bail!("{:?} doesn't contain \"some\" line", &path);
+}
+
 pub fn override_system_configuration(
     total_memory: Option<usize>,
     total_swap: Option<usize>,
@@ -555,14 +657,25 @@ pub fn parse_duration(input: &str) -> Result<f64> {
 
     let mut num = String::new();
     let mut sum = 0.0;
+    let mut last_unit = None;
     for ch in input.chars() {
         match ch {
             '_' => continue,
+            // Allow "ms", "us" and "ns".
+            's' if num.trim().is_empty() && matches!(last_unit, Some('n' | 'u' | 'm')) => {
+                last_unit = None;
+            }
             ch if UNITS.contains_key(&ch) => {
                 sum += num.trim().parse::<f64>()? * UNITS[&ch];
                 num.clear();
+                last_unit = Some(ch);
+            }
+            ch => {
+                num.push(ch);
+                if !ch.is_whitespace() {
+                    last_unit = None;
+                }
             }
-            ch => num.push(ch),
         }
     }
     if num.trim().len() > 0 {
@@ -594,15 +707,27 @@ pub fn parse_size(input: &str) -> Result<u64> {
 
     let mut num = String::new();
     let mut sum = 0;
+    let mut last_unit = None;
     for ch in input.chars() {
         let ch = ch.to_uppercase().to_string().chars().next().unwrap();
         match ch {
             '_' => continue,
+            // Allow binary prefixes and trailing "B" - "Ki", "KiB", "KB".
+            'I' if num.trim().is_empty() && matches!(last_unit, Some(u) if u != 'B') => {}
+            'B' if num.trim().is_empty() && last_unit.is_some() => {
+                last_unit = None;
+            }
             ch if UNITS.contains_key(&ch) => {
                 sum += parse_num(num.trim(), UNITS[&ch])?;
                 num.clear();
+                last_unit = Some(ch);
+            }
+            ch => {
+                num.push(ch);
+                if !ch.is_whitespace() {
+                    last_unit = None;
+                }
             }
-            ch => num.push(ch),
         }
     }
     if num.trim().len() > 0 {
@@ -954,6 +1079,9 @@ mod tests {
             (59918401.102, "1.9Y  1.1s  2000  u"),
             (1.27, "1.27"),
             (1.37, "100m1.27"),
+            (0.02, "20ms"),
+            (0.075, "75ms"),
+            (1.00025, "1s 250us"),
         ] {
             let result = super::parse_duration(pair.1).unwrap();
             assert_eq!(pair.0, result);
@@ -961,12 +1089,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(super::parse_cpu_list("0").unwrap(), vec![0]);
+        assert_eq!(
+            super::parse_cpu_list("0-3,8,10-11\n").unwrap(),
+            vec![0, 1, 2, 3, 8, 10, 11]
+        );
+        assert!(super::parse_cpu_list("0-x").is_err());
+    }
+
     #[test]
     fn test_parse_size() {
         for pair in &[
             (4404019, "4.2m"),
             (2164785152, "2G_16.5M"),
             (1659790359820, "1.5t  9.8  G   248281"),
+            (1228800, "1200Ki"),
+            (1258291, "1.2M"),
+            (1258291, "1.2MiB"),
+            (3072, "3KB"),
         ] {
             let result = super::parse_size(pair.1).unwrap();
             assert_eq!(pair.0, result);
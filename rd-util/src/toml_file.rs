@@ -0,0 +1,77 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use super::json_file::{JsonLoad, JsonSave};
+
+/// TOML counterpart of `JsonLoad`. `load_toml()` runs the `loaded()` hook
+/// on the result so that TOML files get the same verification.
+pub trait TomlLoad
+where
+    Self: JsonLoad,
+{
+    fn load_toml<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut f = fs::OpenOptions::new().read(true).open(path)?;
+        let mut buf = String::new();
+        f.read_to_string(&mut buf)?;
+        let mut obj = toml::from_str::<Self>(&buf)?;
+        obj.loaded(None)?;
+        Ok(obj)
+    }
+}
+
+/// TOML counterpart of `JsonSave`. The `//` comment lines of the JSON
+/// preamble are converted to `#` comments.
+pub trait TomlSave
+where
+    Self: JsonSave,
+{
+    fn as_toml(&self) -> Result<String> {
+        // Going through Value lets the serializer put tables after plain
+        // values regardless of the field order.
+        let value = toml::Value::try_from(self)?;
+        let mut serialized = toml::to_string_pretty(&value)?;
+        if !serialized.ends_with('\n') {
+            serialized += "\n";
+        }
+        match self.preamble_of() {
+            Some(pre) => {
+                let mut out = String::new();
+                for line in pre.lines() {
+                    match line.trim_start().strip_prefix("//") {
+                        Some(rest) => out += &format!("#{}\n", rest),
+                        None => out += &format!("{}\n", line),
+                    }
+                }
+                Ok(out + &serialized)
+            }
+            None => Ok(serialized),
+        }
+    }
+
+    fn save_toml<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path: &Path = path.as_ref();
+        let fname = match path.file_name() {
+            Some(v) => v,
+            None => bail!("can't save to null path"),
+        };
+
+        let mut tmp_path = PathBuf::from(path);
+        tmp_path.pop();
+        tmp_path.push(format!(".{}.toml-save-staging", &fname.to_string_lossy()));
+
+        let mut f = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .with_context(|| format!("opening staging file {:?}", &tmp_path))?;
+        f.write_all(self.as_toml()?.as_ref())
+            .with_context(|| format!("writing staging file {:?}", &tmp_path))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("moving {:?} to {:?}", &tmp_path, path))?;
+        Ok(())
+    }
+}